@@ -0,0 +1,369 @@
+/*
+ * analyzer.rs - Contains code related to static analysis of machine code,
+ * following the flow of control from the entry points to tell code apart from
+ * data, and writing the result as a listing that can be assembled again
+ */
+use crate::cpu::disassembler::*;
+use crate::cpu::memory::*;
+use crate::cpu::opcodes::*;
+use crate::cpu::registers::*;
+use crate::symbols::SymbolTable;
+
+use std::collections::{BTreeMap, BTreeSet};
+
+// number of bytes on each DB line of the listing
+const DATA_BYTES_PER_LINE: usize = 8;
+
+// column the instructions of the listing start at
+const LABEL_WIDTH: usize = 8;
+
+// Analyzer struct - where analysis starts from, and the names to give
+// addresses
+#[derive(Debug, Clone, Default)]
+pub struct Analyzer {
+    pub entries: BTreeSet<u16>,
+    pub coverage: BTreeSet<u16>, // instructions known to run, not labelled
+    pub symbols: SymbolTable,
+}
+
+impl Analyzer {
+    // creates a new instance of Analyzer with no entry points
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add_entry(&mut self, addr: u16) {
+        self.entries.insert(addr);
+    }
+
+    // adds the addresses of instructions seen executing, such as the coverage
+    // recorded by a Cpu, to start from as well as the entry points
+    pub fn seed(&mut self, coverage: impl IntoIterator<Item = u16>) {
+        self.coverage.extend(coverage);
+    }
+
+    // analyzes an image loaded at origin. analysis starts from the entry
+    // points (or origin if there are none) and any RST vectors in the image
+    pub fn analyze(&self, image: &[u8], origin: u16) -> Result<Analysis, String> {
+        let end = origin as usize + image.len();
+        if end > 0x10000 {
+            return Err(format!(
+                "Image of {} bytes does not fit in memory at {origin:04X}",
+                image.len()
+            ));
+        }
+
+        let mut memory = Memory::new();
+        for (i, byte) in image.iter().enumerate() {
+            memory.write(
+                RegisterValue::from(origin + i as u16),
+                RegisterValue::from(*byte),
+            )?;
+        }
+
+        let mut analysis = Analysis {
+            origin,
+            image: image.to_vec(),
+            entries: self.entries.clone(),
+            ..Default::default()
+        };
+        if analysis.entries.is_empty() {
+            analysis.entries.insert(origin);
+        }
+
+        let mut claimed = vec![false; image.len()]; // bytes that are code
+        let mut label_kinds: BTreeMap<u16, char> = BTreeMap::new();
+
+        let vectors = (0..8u16)
+            .map(|n| n * 8)
+            .filter(|addr| analysis.in_range(*addr));
+        let mut worklist: Vec<u16> = analysis.entries.iter().copied().chain(vectors).collect();
+        for addr in &worklist {
+            label_kinds.insert(*addr, 'L');
+        }
+        worklist.extend(&self.coverage);
+
+        while let Some(addr) = worklist.pop() {
+            if !analysis.in_range(addr) || claimed[(addr - origin) as usize] {
+                continue;
+            }
+
+            // unsupported opcodes end the path, they are most likely data
+            let instruction = disassemble(&memory, addr, Syntax::Intel)?;
+            let info = opcode_info(instruction.bytes[0]);
+            if !info.valid {
+                continue;
+            }
+
+            // an instruction can't run off the end of the image, or overlap
+            // one found already
+            let start = (addr - origin) as usize;
+            let n_bytes = instruction.n_bytes();
+            if start + n_bytes > image.len() || claimed[start..start + n_bytes].contains(&true) {
+                continue;
+            }
+            claimed[start..start + n_bytes].fill(true);
+
+            let operand = instruction.operand();
+            let (target, falls_through) = match info.flow {
+                Flow::Jump => (operand.map(|target| (target, 'L')), false),
+                Flow::ConditionalJump => (operand.map(|target| (target, 'L')), true),
+                Flow::Call | Flow::ConditionalCall => (operand.map(|target| (target, 'S')), true),
+                Flow::Restart(vector) => (Some((vector, 'S')), true),
+                Flow::Return | Flow::Halt => (None, false),
+                Flow::ConditionalReturn | Flow::Next => (None, true),
+
+                // PCHL goes somewhere that can't be known without running it
+                Flow::Indirect => {
+                    analysis.indirect_jumps.insert(addr);
+                    (None, false)
+                }
+            };
+
+            if let Some((target, kind)) = target.filter(|(target, _)| analysis.in_range(*target)) {
+                label_kinds.entry(target).or_insert(kind);
+                worklist.push(target);
+            }
+
+            if falls_through && start + n_bytes < image.len() {
+                worklist.push(addr + n_bytes as u16);
+            }
+
+            analysis.instructions.insert(addr, instruction);
+        }
+
+        // addresses used as data by 3-byte instructions are labelled too, or
+        // named if they are outside of the image
+        for instruction in analysis.instructions.values() {
+            let Some(operand) = instruction.operand().filter(|_| instruction.n_bytes() == 3) else {
+                continue;
+            };
+
+            if analysis.in_range(operand) {
+                label_kinds.entry(operand).or_insert('D');
+            } else if let Some(name) = self.symbols.name_at(operand) {
+                analysis.externals.insert(operand, name.to_string());
+            }
+        }
+
+        // a label can't go in the middle of an instruction
+        for (addr, kind) in label_kinds {
+            let offset = (addr - origin) as usize;
+
+            if analysis.instructions.contains_key(&addr) || !claimed[offset] {
+                let name = match self.symbols.name_at(addr) {
+                    Some(name) => name.to_string(),
+                    None => format!("{kind}{addr:04X}"),
+                };
+                analysis.labels.insert(addr, name);
+            }
+        }
+
+        Ok(analysis)
+    }
+}
+
+// Analysis struct - the code and labels found in an image
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Analysis {
+    pub origin: u16,
+    pub image: Vec<u8>,
+    pub entries: BTreeSet<u16>,
+    pub instructions: BTreeMap<u16, DisassembledInstruction>,
+    pub labels: BTreeMap<u16, String>,
+    pub externals: BTreeMap<u16, String>, // symbols outside of the image
+    pub indirect_jumps: BTreeSet<u16>,    // PCHL instructions
+}
+
+impl Analysis {
+    // returns whether an address is within the image
+    pub fn in_range(&self, addr: u16) -> bool {
+        let end = self.origin as usize + self.image.len();
+        (self.origin as usize..end).contains(&(addr as usize))
+    }
+
+    // returns the number of bytes found to be code
+    pub fn code_bytes(&self) -> usize {
+        self.instructions.values().map(|i| i.n_bytes()).sum()
+    }
+
+    // writes the analysis as a listing in Intel syntax, which assembles back
+    // to the same image
+    pub fn listing(&self) -> String {
+        let end = self.origin as usize + self.image.len();
+        let mut listing = format!(
+            "; static analysis of {:04X}-{:04X}, {} of {} bytes are code\n",
+            self.origin,
+            end.saturating_sub(1),
+            self.code_bytes(),
+            self.image.len()
+        );
+
+        let entries: Vec<String> = self.entries.iter().map(|a| format!("{a:04X}")).collect();
+        listing.push_str(&format!("; entry points: {}\n", entries.join(" ")));
+
+        for addr in &self.indirect_jumps {
+            listing.push_str(&format!(
+                "; indirect jump (PCHL) at {addr:04X}, its targets are unknown\n"
+            ));
+        }
+
+        if !self.externals.is_empty() {
+            listing.push('\n');
+            for (addr, name) in &self.externals {
+                listing.push_str(&format!("{name:<LABEL_WIDTH$}EQU {}\n", format_word(*addr)));
+            }
+        }
+
+        listing.push('\n');
+        listing.push_str(&self.line(None, &format!("ORG {}", format_word(self.origin))));
+
+        let mut addr = self.origin as usize;
+        while addr < end {
+            let label = self.labels.get(&(addr as u16));
+
+            if let Some(instruction) = self.instructions.get(&(addr as u16)) {
+                let mut text = self.operand_text(instruction);
+                if self.indirect_jumps.contains(&(addr as u16)) {
+                    text.push_str("\t; indirect jump");
+                }
+
+                listing.push_str(&self.line(label, &text));
+                addr += instruction.n_bytes();
+                continue;
+            }
+
+            // data runs until the next code or label
+            let mut data_end = addr + 1;
+            while data_end < end
+                && data_end - addr < DATA_BYTES_PER_LINE
+                && !self.instructions.contains_key(&(data_end as u16))
+                && !self.labels.contains_key(&(data_end as u16))
+            {
+                data_end += 1;
+            }
+
+            let offset = addr - self.origin as usize;
+            let bytes = &self.image[offset..offset + data_end - addr];
+            let values: Vec<String> = bytes.iter().map(|b| format_byte(*b)).collect();
+            let ascii: String = bytes
+                .iter()
+                .map(|b| match b.is_ascii_graphic() || *b == b' ' {
+                    true => *b as char,
+                    false => '.',
+                })
+                .collect();
+
+            listing.push_str(&self.line(label, &format!("DB {}\t; {ascii}", values.join(","))));
+            addr = data_end;
+        }
+
+        listing.push_str(&self.line(None, "END"));
+        listing
+    }
+
+    // formats a line of the listing, long labels go on a line of their own
+    fn line(&self, label: Option<&String>, text: &str) -> String {
+        match label {
+            Some(label) if label.len() + 1 < LABEL_WIDTH => {
+                format!("{:<LABEL_WIDTH$}{text}\n", format!("{label}:"))
+            }
+            Some(label) => format!("{label}:\n{:LABEL_WIDTH$}{text}\n", ""),
+            None => format!("{:LABEL_WIDTH$}{text}\n", ""),
+        }
+    }
+
+    // returns the text of an instruction, with an address operand replaced
+    // by a label or symbol
+    fn operand_text(&self, instruction: &DisassembledInstruction) -> String {
+        let Some(operand) = instruction.operand().filter(|_| instruction.n_bytes() == 3) else {
+            return instruction.text.clone();
+        };
+
+        // addresses inside of an instruction are given relative to the
+        // closest label before them
+        let name = match self.labels.get(&operand).or(self.externals.get(&operand)) {
+            Some(name) => Some(name.clone()),
+            None if self.in_range(operand) => self
+                .labels
+                .range(..operand)
+                .next_back()
+                .map(|(addr, name)| format!("{name}+{}", operand - addr)),
+            None => None,
+        };
+
+        match name {
+            Some(name) => instruction.text.replacen(&format_word(operand), &name, 1),
+            None => instruction.text.clone(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembler;
+
+    const PROGRAM: &str = "
+        ORG 100H
+        LXI H,TABLE
+        CALL PRINT
+        MVI A,1
+        JZ SKIP
+        PCHL
+SKIP:   JMP 0
+PRINT:  MVI C,9
+        CALL 5
+        RET
+TABLE:  DB 'HI$', 0C3H, 0
+        DW PRINT
+";
+
+    #[test]
+    fn analyzer_round_trip() {
+        let image = assembler::assemble(PROGRAM).unwrap().to_binary();
+
+        let mut analyzer = Analyzer::new();
+        analyzer.symbols.insert("BDOS", 0x0005);
+        let analysis = analyzer.analyze(&image, 0x0100).unwrap();
+
+        // the string and the 0C3H after it are data, not a JMP
+        assert_eq!(analysis.code_bytes(), 21);
+        assert!(analysis.indirect_jumps.contains(&0x010B));
+        assert_eq!(
+            analysis.labels.get(&0x010F).map(String::as_str),
+            Some("S010F")
+        );
+
+        let listing = analysis.listing();
+        assert!(listing.contains("BDOS    EQU 0005H\n"));
+        assert!(listing.contains("        CALL BDOS\n"));
+        assert!(listing.contains("L0100:  LXI H,D0115\n"));
+        assert!(listing.contains("D0115:  DB 48H,49H,24H,0C3H,00H,0FH,01H\t; HI$...."));
+
+        // the listing assembles back to the same image
+        assert_eq!(assembler::assemble(&listing).unwrap().to_binary(), image);
+    }
+
+    #[test]
+    fn analyzer_seeded_by_coverage() {
+        // the NOPs skipped by the JMP are only found by running the program
+        let image = assembler::assemble("ORG 0\nJMP 0005H\nDB 0\nDW 0\nHLT\n")
+            .unwrap()
+            .to_binary();
+
+        let analysis = Analyzer::new().analyze(&image, 0).unwrap();
+        assert!(!analysis.instructions.contains_key(&0x0003));
+
+        let mut analyzer = Analyzer::new();
+        analyzer.seed([0x0000, 0x0003, 0x0004]);
+        let analysis = analyzer.analyze(&image, 0).unwrap();
+        assert!(analysis.instructions.contains_key(&0x0003));
+        assert_eq!(
+            assembler::assemble(&analysis.listing())
+                .unwrap()
+                .to_binary(),
+            image
+        );
+    }
+}
@@ -1,14 +1,351 @@
 /*
  * arguments.rs -- Contains code related to command-line argument parsing.
  */
-use clap::Parser;
+use clap::{Parser, Subcommand};
+use i8080::cpu::disassembler::Syntax;
+use i8080::isis;
+use i8080::terminal::TerminalType;
+
+use std::num::ParseIntError;
 
 #[derive(Parser, Debug)]
+#[command(args_conflicts_with_subcommands = true)]
 pub struct Args {
+    // Tool to run instead of the emulator
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
+    // Syntax instructions are shown in, intel or zilog
+    #[arg(long, global = true, default_value = "intel")]
+    pub syntax: Syntax,
+
+    // Symbol files (.SYM, .MAP or label = addr) used to name addresses
+    #[arg(long, global = true)]
+    pub sym: Vec<String>,
+
+    // Assembler listing (.PRN or .LST) shown in the debug menu's source view
+    #[arg(long)]
+    pub prn: Option<String>,
+
     // Whether or not to show the debug menu
     #[arg(short, long)]
     pub debug: bool,
 
+    // Host file receiving CP/M list output (LST:)
+    #[arg(long)]
+    pub list: Option<String>,
+
+    // Host file receiving CP/M punch output (PUN:)
+    #[arg(long)]
+    pub punch: Option<String>,
+
+    // Host file supplying CP/M reader input (RDR:)
+    #[arg(long)]
+    pub reader: Option<String>,
+
+    // File to log every BDOS call to, followed by a summary at exit ("-" for stderr)
+    #[arg(long)]
+    pub bdos_trace: Option<String>,
+
+    // Host directory mapped to the CP/M drive A:
+    #[arg(long)]
+    pub drive: Option<String>,
+
+    // Whether or not to start the CP/M command prompt instead of a single program
+    #[arg(long)]
+    pub ccp: bool,
+
+    // Terminal the program expects (adm3a, vt52 or kaypro), its control codes
+    // are translated to ANSI and the cursor keys back
+    #[arg(long)]
+    pub terminal: Option<TerminalType>,
+
+    // Whether or not to run the program under ISIS-II instead of CP/M
+    #[arg(long, requires = "program", conflicts_with = "ccp")]
+    pub isis: bool,
+
+    // Extra ISIS-II drives, given as N=DIR for :FN: (:F0: is --drive)
+    #[arg(long, value_parser = parse_isis_drive)]
+    pub isis_drive: Vec<IsisDriveArg>,
+
+    // The command tail passed to the program
+    #[arg(long, default_value = "")]
+    pub args: String,
+
+    // Whether or not to run on bare metal, without CP/M or ISIS-II. nothing
+    // is set up besides the images loaded
+    #[arg(long, conflicts_with_all = ["isis", "ccp"])]
+    pub bare: bool,
+
+    // Address the program is loaded at, in hex. defaults to 100 under CP/M
+    // and 0 on bare metal
+    #[arg(long, value_parser = parse_address, conflicts_with = "isis")]
+    pub org: Option<u16>,
+
+    // Whether or not to load the program as a flat binary, even if it looks
+    // like an Intel HEX or S-record file
+    #[arg(long, conflicts_with = "isis")]
+    pub binary: bool,
+
+    // Extra images to load, given as FILE@ADDR (the address is in hex, and
+    // optional for Intel HEX and S-record files)
+    #[arg(long, value_parser = parse_image)]
+    pub load: Vec<ImageArg>,
+
+    // Address to start running at, in hex
+    #[arg(long, value_parser = parse_address, conflicts_with = "ccp")]
+    pub entry: Option<u16>,
+
+    // Initial stack pointer, in hex
+    #[arg(long, value_parser = parse_address, conflicts_with = "ccp")]
+    pub sp: Option<u16>,
+
+    // Initial register values, given as NAME=VALUE in hex (e.g. A=3F, HL=8000)
+    #[arg(long, value_parser = parse_register, conflicts_with = "ccp")]
+    pub reg: Vec<RegisterArg>,
+
+    // File to write the address of every instruction run to, which can seed
+    // the analyze command
+    #[arg(long, conflicts_with = "ccp")]
+    pub coverage: Option<String>,
+
+    // File to write a line to for each instruction executed, with the
+    // registers and flags before it ("-" for stdout)
+    #[arg(long)]
+    pub trace: Option<String>,
+
+    // Address ranges to trace, given as START-END in hex. everything is
+    // traced if none are given
+    #[arg(long, value_parser = parse_range, requires = "trace")]
+    pub trace_range: Vec<(u16, u16)>,
+
+    // Address to start tracing at, in hex
+    #[arg(long, value_parser = parse_address, requires = "trace")]
+    pub trace_start: Option<u16>,
+
+    // Address to stop tracing at, in hex
+    #[arg(long, value_parser = parse_address, requires = "trace")]
+    pub trace_stop: Option<u16>,
+
+    // Whether or not to start the monitor, a DDT-style command line debugger,
+    // instead of running the program straight away
+    #[arg(long, conflicts_with_all = ["ccp", "terminal"])]
+    pub monitor: bool,
+
+    // Whether or not to start the terminal UI, a full-screen debugger for
+    // terminals without a display, instead of running the program straight
+    // away
+    #[arg(long, conflicts_with_all = ["ccp", "terminal", "monitor", "debug"])]
+    pub tui: bool,
+
+    // Address to serve the Debug Adapter Protocol on, so an editor such as VS
+    // Code can debug the program: a port, host:port, or "-" for stdin and
+    // stdout
+    #[arg(long, conflicts_with_all = ["ccp", "terminal", "monitor", "debug", "tui"])]
+    pub dap: Option<String>,
+
+    // Address to serve remote control on, a JSON request per line for scripts
+    // to drive the emulator with: a port, host:port, or "-" for stdin and
+    // stdout
+    #[arg(long, conflicts_with_all = ["ccp", "terminal", "monitor", "debug", "tui", "dap"])]
+    pub remote: Option<String>,
+
     // The name of the file containing the program
-    pub program: String,
+    #[arg(required_unless_present_any = ["ccp", "load"])]
+    pub program: Option<String>,
+}
+
+// ImageArg struct - an image given with --load
+#[derive(Debug, Clone)]
+pub struct ImageArg {
+    pub path: String,
+    pub addr: Option<u16>,
+}
+
+// IsisDriveArg struct - an ISIS-II drive given with --isis-drive
+#[derive(Debug, Clone)]
+pub struct IsisDriveArg {
+    pub drive: usize,
+    pub dir: String,
+}
+
+// RegisterArg struct - a register value given with --reg
+#[derive(Debug, Clone)]
+pub struct RegisterArg {
+    pub name: String,
+    pub value: u16,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    // Disassembles a binary file
+    Disasm {
+        // The name of the file to disassemble
+        file: String,
+
+        // Address the file is loaded at, in hex
+        #[arg(default_value = "100", value_parser = parse_address)]
+        address: u16,
+    },
+
+    // Assembles a source file into a flat binary or Intel HEX file
+    Asm {
+        // The name of the source file
+        file: String,
+
+        // File to write the code to, defaults to the source file with a .bin
+        // (or .hex) extension
+        #[arg(short, long)]
+        output: Option<String>,
+
+        // Whether or not to write Intel HEX instead of a flat binary, also
+        // chosen by giving an output file ending in .hex
+        #[arg(long)]
+        hex: bool,
+
+        // File to write the listing to
+        #[arg(long)]
+        listing: Option<String>,
+
+        // File to write the symbol table to
+        #[arg(long)]
+        symbols: Option<String>,
+    },
+
+    // Separates the code of a binary from its data by following the flow of
+    // control, and writes a labelled listing that assembles back to it
+    Analyze {
+        // The name of the file to analyze
+        file: String,
+
+        // Address the file is loaded at, in hex
+        #[arg(default_value = "100", value_parser = parse_address)]
+        address: u16,
+
+        // Addresses to start from, in hex. defaults to the load address
+        #[arg(long, value_parser = parse_address)]
+        entry: Vec<u16>,
+
+        // Coverage file from a run (see --coverage) to start from as well
+        #[arg(long)]
+        coverage: Vec<String>,
+
+        // File to write the listing to, instead of standard output
+        #[arg(short, long)]
+        output: Option<String>,
+    },
+
+    // Runs a program alongside a reference trace captured from another
+    // emulator, stopping at the first instruction whose result differs
+    CompareTrace {
+        // The name of the program file
+        file: String,
+
+        // The reference trace, with a line of CSV or JSON for each
+        // instruction
+        reference: String,
+
+        // Whether or not to run the program on bare metal instead of under
+        // CP/M
+        #[arg(long)]
+        bare: bool,
+
+        // Address to load and start the program at, in hex. defaults to 100,
+        // or 0 with --bare
+        #[arg(long, value_parser = parse_address)]
+        org: Option<u16>,
+    },
+
+    // Links Microsoft REL modules into a flat binary
+    Link {
+        // The REL files to link, in order
+        #[arg(required = true)]
+        files: Vec<String>,
+
+        // Address to link the first module at, in hex
+        #[arg(long, default_value = "100", value_parser = parse_address)]
+        base: u16,
+
+        // File to write the code to, defaults to the first file with a .com
+        // extension
+        #[arg(short, long)]
+        output: Option<String>,
+
+        // File to write the symbol table to
+        #[arg(long)]
+        symbols: Option<String>,
+    },
+}
+
+// parses a hex number, with an optional 0x prefix or H suffix
+fn parse_hex(s: &str) -> Result<u16, ParseIntError> {
+    let digits = s.strip_prefix("0x").unwrap_or(s);
+    let digits = digits.strip_suffix(['h', 'H']).unwrap_or(digits);
+
+    u16::from_str_radix(digits, 16)
+}
+
+// parses a hex address
+pub fn parse_address(s: &str) -> Result<u16, String> {
+    parse_hex(s).map_err(|e| format!("Invalid address {s}: {e}"))
+}
+
+// parses an address range, START-END
+fn parse_range(s: &str) -> Result<(u16, u16), String> {
+    let invalid = || format!("Address ranges are given as START-END, not {s}");
+    let (start, end) = s.split_once('-').ok_or_else(invalid)?;
+    let (start, end) = (parse_address(start.trim())?, parse_address(end.trim())?);
+
+    // the range includes both ends, so it can't run backwards
+    match start <= end {
+        true => Ok((start, end)),
+        false => Err(invalid()),
+    }
+}
+
+// parses an image to load, FILE or FILE@ADDR
+fn parse_image(s: &str) -> Result<ImageArg, String> {
+    match s.rsplit_once('@') {
+        Some((path, addr)) => Ok(ImageArg {
+            path: path.to_string(),
+            addr: Some(parse_address(addr)?),
+        }),
+        None => Ok(ImageArg {
+            path: s.to_string(),
+            addr: None,
+        }),
+    }
+}
+
+// parses an ISIS-II drive, N=DIR
+fn parse_isis_drive(s: &str) -> Result<IsisDriveArg, String> {
+    let (drive, dir) = s
+        .split_once('=')
+        .ok_or_else(|| format!("ISIS-II drives are given as N=DIR, not {s}"))?;
+
+    match drive.trim().parse() {
+        Ok(drive) if drive < isis::DRIVES => Ok(IsisDriveArg {
+            drive,
+            dir: dir.to_string(),
+        }),
+        _ => Err(format!(
+            "Invalid drive {drive}: ISIS-II drives are 0 to {}",
+            isis::DRIVES - 1
+        )),
+    }
+}
+
+// parses a register value, NAME=VALUE
+fn parse_register(s: &str) -> Result<RegisterArg, String> {
+    let (name, value) = s
+        .split_once('=')
+        .ok_or_else(|| format!("Register values are given as NAME=VALUE, not {s}"))?;
+
+    let (name, value) = (name.trim(), value.trim());
+
+    Ok(RegisterArg {
+        name: name.to_string(),
+        value: parse_hex(value)
+            .map_err(|e| format!("Invalid value for register {name}: {value}: {e}"))?,
+    })
 }
@@ -0,0 +1,1084 @@
+/*
+ * assembler.rs - Contains code related to assembling Intel 8080 assembly
+ * language into machine code. the first pass finds the value of every
+ * symbol, the second generates the code and the listing
+ * See the Intel 8080/8085 Assembly Language Programming Manual (9800301)
+ */
+pub mod expression;
+
+use crate::cpu::disassembler::{self, Syntax};
+use crate::cpu::*;
+use expression::*;
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs;
+use std::path::Path;
+
+// maximum depth of nested INCLUDEs and macro expansions
+const MAX_NESTING: usize = 16;
+
+// number of data bytes in each Intel HEX record
+const HEX_RECORD_LEN: usize = 16;
+
+// number of bytes shown on each line of the listing
+const LISTING_BYTES: usize = 4;
+
+// directives that are accepted but have no effect on the output
+const IGNORED_DIRECTIVES: [&str; 4] = ["TITLE", "PAGE", "EJECT", "NAME"];
+
+// Chunk struct - a contiguous run of generated code
+#[derive(Debug, Clone, PartialEq)]
+pub struct Chunk {
+    pub addr: u16,
+    pub data: Vec<u8>,
+}
+
+// Assembly struct - the output of the assembler
+#[derive(Debug, Clone, Default)]
+pub struct Assembly {
+    pub chunks: Vec<Chunk>, // in the order they were generated
+    pub symbols: BTreeMap<String, u16>,
+    pub start: Option<u16>, // given by END
+    pub listing: String,
+}
+
+impl Assembly {
+    // returns the lowest and one past the highest address written to
+    fn extent(&self) -> Option<(usize, usize)> {
+        let chunks = self.chunks.iter().filter(|chunk| !chunk.data.is_empty());
+
+        let start = chunks.clone().map(|chunk| chunk.addr as usize).min()?;
+        let end = chunks
+            .map(|chunk| chunk.addr as usize + chunk.data.len())
+            .max()?;
+
+        Some((start, end))
+    }
+
+    // returns the code as a flat binary starting at the lowest address, with
+    // gaps filled with 0s (a CP/M .COM file when assembled at 100H)
+    pub fn to_binary(&self) -> Vec<u8> {
+        let Some((start, end)) = self.extent() else {
+            return Vec::new();
+        };
+
+        let mut binary = vec![0u8; end - start];
+        for chunk in &self.chunks {
+            let offset = chunk.addr as usize - start;
+            binary[offset..offset + chunk.data.len()].copy_from_slice(&chunk.data);
+        }
+
+        binary
+    }
+
+    // returns the code as Intel HEX. the end-of-file record holds the start
+    // address, if there is one
+    pub fn to_intel_hex(&self) -> String {
+        let mut hex = String::new();
+
+        for chunk in &self.chunks {
+            for (i, data) in chunk.data.chunks(HEX_RECORD_LEN).enumerate() {
+                let addr = chunk.addr.wrapping_add((i * HEX_RECORD_LEN) as u16);
+                hex.push_str(&hex_record(addr, 0x00, data));
+            }
+        }
+
+        hex.push_str(&hex_record(self.start.unwrap_or(0), 0x01, &[]));
+        hex
+    }
+
+    // returns the symbol table, one "ADDR NAME" line per symbol in the
+    // format of a .SYM file
+    pub fn symbol_table(&self) -> String {
+        self.symbols
+            .iter()
+            .map(|(name, value)| format!("{value:04X} {name}\n"))
+            .collect()
+    }
+
+    // writes the code into the memory of a Cpu
+    pub fn load_into(&self, cpu: &mut Cpu) -> Result<(), String> {
+        for chunk in &self.chunks {
+            if chunk.addr as usize + chunk.data.len() > 0x10000 {
+                return Err(format!(
+                    "Code at {:04X} runs past the end of memory",
+                    chunk.addr
+                ));
+            }
+
+            cpu.load_to_memory(chunk.data.clone(), chunk.addr)?;
+        }
+
+        Ok(())
+    }
+}
+
+// assembles source code. INCLUDEd files are found relative to the current
+// directory. errors are returned one per line, with the line they are on
+pub fn assemble(source: &str) -> Result<Assembly, String> {
+    assemble_source(source, "<source>", Path::new("."))
+}
+
+// assembles a source file. INCLUDEd files are found relative to its directory
+pub fn assemble_file(path: &Path) -> Result<Assembly, String> {
+    let source =
+        fs::read_to_string(path).map_err(|e| format!("Could not read {}: {e}", path.display()))?;
+    let dir = path.parent().unwrap_or(Path::new("."));
+
+    assemble_source(&source, &path.display().to_string(), dir)
+}
+
+// assembles source code straight into the memory of a Cpu, the program
+// counter is set to the start address if the source gives one
+pub fn assemble_into(cpu: &mut Cpu, source: &str) -> Result<Assembly, String> {
+    let assembly = assemble(source)?;
+    assembly.load_into(cpu)?;
+
+    if let Some(start) = assembly.start {
+        cpu.set_pc(start)?;
+    }
+
+    Ok(assembly)
+}
+
+fn assemble_source(source: &str, file: &str, dir: &Path) -> Result<Assembly, String> {
+    let opcodes = opcode_table();
+    let lines = source_lines(source, file);
+
+    // the first pass only finds the value of every symbol
+    let mut first = Pass::new(&opcodes, HashMap::new(), false);
+    first.assemble_lines(&lines, dir, 0);
+
+    let mut second = Pass::new(&opcodes, first.symbols, true);
+    second.assemble_lines(&lines, dir, 0);
+
+    if !second.errors.is_empty() {
+        return Err(second.errors.join("\n"));
+    }
+
+    Ok(Assembly {
+        chunks: second.chunks,
+        symbols: second.symbols.into_iter().collect(),
+        start: second.start,
+        listing: second.listing,
+    })
+}
+
+// OpcodeForm struct - how an opcode is written, e.g. MVI with the operands
+// ["A", "{n}"]
+struct OpcodeForm {
+    opcode: u8,
+    operands: Vec<String>,
+    n_bytes: usize,
+}
+
+// builds the table of instructions the assembler knows from the forms the
+// disassembler writes, so the two always agree
+fn opcode_table() -> HashMap<String, Vec<OpcodeForm>> {
+    let mut table: HashMap<String, Vec<OpcodeForm>> = HashMap::new();
+
+    for opcode in 0..=0xFFu8 {
+        let Some((template, n_bytes)) = disassembler::opcode_template(opcode, Syntax::Intel) else {
+            continue;
+        };
+
+        let (mnemonic, operands) = template.split_once(' ').unwrap_or((&template, ""));
+
+        table
+            .entry(mnemonic.to_string())
+            .or_default()
+            .push(OpcodeForm {
+                opcode,
+                operands: split_operands(operands),
+                n_bytes,
+            });
+    }
+
+    table
+}
+
+// SourceLine struct - a line of source, along with where it came from
+#[derive(Debug, Clone)]
+struct SourceLine {
+    file: String,
+    number: usize,
+    text: String,
+}
+
+fn source_lines(source: &str, file: &str) -> Vec<SourceLine> {
+    source
+        .lines()
+        .enumerate()
+        .map(|(i, text)| SourceLine {
+            file: file.to_string(),
+            number: i + 1,
+            text: text.to_string(),
+        })
+        .collect()
+}
+
+// Statement struct - the fields of a line of source
+#[derive(Debug, Default, PartialEq)]
+struct Statement {
+    label: Option<String>,
+    operation: Option<String>, // upper case
+    operands: String,
+}
+
+// Macro struct - a macro definition
+#[derive(Debug, Clone)]
+struct Macro {
+    params: Vec<String>,
+    body: Vec<String>,
+}
+
+// Pass struct - the state of one pass over the source
+struct Pass<'a> {
+    opcodes: &'a HashMap<String, Vec<OpcodeForm>>,
+    generate: bool, // whether this is the second pass
+    symbols: HashMap<String, u16>,
+    defined: HashSet<String>,     // symbols defined so far in this pass
+    redefinable: HashSet<String>, // symbols defined with SET
+    macros: HashMap<String, Macro>,
+    n_locals: usize,              // numbers the LOCAL labels of macros
+    local_names: HashSet<String>, // names given to LOCAL labels, starting with ??
+    location: u32,                // reaches 10000H once code fills the end of memory
+    chunks: Vec<Chunk>,
+    conditions: Vec<bool>, // whether each nested IF is being assembled
+    start: Option<u16>,
+    ended: bool,
+    listing: String,
+    errors: Vec<String>,
+}
+
+impl<'a> Pass<'a> {
+    fn new(
+        opcodes: &'a HashMap<String, Vec<OpcodeForm>>,
+        symbols: HashMap<String, u16>,
+        generate: bool,
+    ) -> Self {
+        Self {
+            opcodes,
+            generate,
+            symbols,
+            defined: HashSet::new(),
+            redefinable: HashSet::new(),
+            macros: HashMap::new(),
+            n_locals: 0,
+            local_names: HashSet::new(),
+            location: 0,
+            chunks: Vec::new(),
+            conditions: Vec::new(),
+            start: None,
+            ended: false,
+            listing: String::new(),
+            errors: Vec::new(),
+        }
+    }
+
+    // returns whether the lines being read are assembled, i.e. not inside a
+    // false IF
+    fn active(&self) -> bool {
+        self.conditions.iter().all(|condition| *condition)
+    }
+
+    // returns whether a word is an instruction, directive or macro
+    fn is_operation(&self, word: &str) -> bool {
+        let word = word.to_ascii_uppercase();
+
+        self.opcodes.contains_key(&word)
+            || self.macros.contains_key(&word)
+            || IGNORED_DIRECTIVES.contains(&word.as_str())
+            || [
+                "ORG", "EQU", "SET", "DB", "DW", "DS", "END", "IF", "ELSE", "ENDIF", "MACRO",
+                "ENDM", "LOCAL", "INCLUDE",
+            ]
+            .contains(&word.as_str())
+    }
+
+    // splits a line into its label, operation and operands
+    fn parse_line(&self, text: &str) -> Statement {
+        let text = strip_comment(text);
+        let trimmed = text.trim_start();
+
+        let first_len = trimmed
+            .find(|c: char| c.is_whitespace() || c == ':')
+            .unwrap_or(trimmed.len());
+        let (first, rest) = trimmed.split_at(first_len);
+
+        if first.is_empty() && rest.is_empty() {
+            return Statement::default();
+        }
+
+        // a label either ends with a colon, starts in the first column, or is
+        // the name given to EQU, SET or MACRO
+        let (label, rest) = if let Some(rest) = rest.strip_prefix(':') {
+            (Some(first), rest)
+        } else if !text.starts_with(char::is_whitespace) && !self.is_operation(first) {
+            (Some(first), rest)
+        } else {
+            let second = rest.split_whitespace().next().unwrap_or("");
+
+            match second.to_ascii_uppercase().as_str() {
+                "EQU" | "SET" | "MACRO" => (Some(first), rest),
+                _ => (None, trimmed),
+            }
+        };
+
+        let rest = rest.trim_start();
+        let operation_len = rest.find(char::is_whitespace).unwrap_or(rest.len());
+        let (operation, operands) = rest.split_at(operation_len);
+
+        Statement {
+            label: label.map(|label| label.to_ascii_uppercase()),
+            operation: (!operation.is_empty()).then(|| operation.to_ascii_uppercase()),
+            operands: operands.trim().to_string(),
+        }
+    }
+
+    // assembles a list of lines, INCLUDEs are found relative to dir
+    fn assemble_lines(&mut self, lines: &[SourceLine], dir: &Path, depth: usize) {
+        let mut i = 0;
+
+        while i < lines.len() && !self.ended {
+            let line = &lines[i];
+            i += 1;
+
+            let statement = self.parse_line(&line.text);
+            let operation = statement.operation.as_deref().unwrap_or("");
+
+            // macro definitions take the lines up to the matching ENDM
+            if operation == "MACRO" && self.active() {
+                let start = i;
+                let mut nesting = 0;
+
+                while i < lines.len() {
+                    match self.parse_line(&lines[i].text).operation.as_deref() {
+                        Some("MACRO") => nesting += 1,
+                        Some("ENDM") if nesting == 0 => break,
+                        Some("ENDM") => nesting -= 1,
+                        _ => {}
+                    }
+                    i += 1;
+                }
+
+                let body: Vec<String> = lines[start..i].iter().map(|l| l.text.clone()).collect();
+
+                let result = match (&statement.label, i < lines.len()) {
+                    (None, _) => Err(String::from("MACRO needs a name")),
+                    (_, false) => Err(String::from("MACRO without ENDM")),
+                    (Some(name), true) => {
+                        let params = split_operands(&statement.operands)
+                            .iter()
+                            .map(|param| param.to_ascii_uppercase())
+                            .collect();
+
+                        self.macros.insert(name.clone(), Macro { params, body });
+                        Ok(())
+                    }
+                };
+
+                self.list(line, None, &[], depth);
+                for body_line in &lines[start..i.min(lines.len())] {
+                    self.list(body_line, None, &[], depth);
+                }
+                if let Some(endm) = lines.get(i) {
+                    self.list(endm, None, &[], depth);
+                }
+                i += 1;
+
+                if let Err(e) = result {
+                    self.error(line, &e);
+                }
+                continue;
+            }
+
+            let addr = self.location as u16;
+
+            match self.assemble_statement(&statement, line, dir, depth) {
+                Ok(Listed::Code(bytes)) => self.list(line, Some(addr), &bytes, depth),
+                Ok(Listed::Value(value)) => {
+                    let value = format!("={value:04X}");
+                    self.list_text(line, &value, depth);
+                }
+                Ok(Listed::Nothing) => self.list(line, None, &[], depth),
+                Ok(Listed::Expanded) => {}
+                Err(e) => {
+                    self.list(line, None, &[], depth);
+                    self.error(line, &e);
+                }
+            }
+        }
+
+        if depth == 0 && !self.conditions.is_empty() {
+            if let Some(line) = lines.last() {
+                self.error(line, "IF without ENDIF");
+            }
+        }
+    }
+
+    // assembles a single statement, returns what to show in the listing
+    fn assemble_statement(
+        &mut self,
+        statement: &Statement,
+        line: &SourceLine,
+        dir: &Path,
+        depth: usize,
+    ) -> Result<Listed, String> {
+        let operation = statement.operation.as_deref().unwrap_or("");
+        let operands = &statement.operands;
+
+        // conditional assembly is followed even in lines that are skipped
+        match operation {
+            "IF" => {
+                // the IF is pushed even if its expression can't be evaluated,
+                // so that its ENDIF still matches it
+                let value = match self.active() {
+                    true => self.defined_value(operands),
+                    false => Ok(0),
+                };
+                self.conditions
+                    .push(value.as_ref().is_ok_and(|value| *value != 0));
+
+                value.map_err(|e| format!("IF requires a defined expression: {e}"))?;
+                return Ok(Listed::Nothing);
+            }
+
+            "ELSE" => {
+                let condition = self.conditions.pop().ok_or("ELSE without IF")?;
+                self.conditions.push(!condition);
+                return Ok(Listed::Nothing);
+            }
+
+            "ENDIF" => {
+                self.conditions.pop().ok_or("ENDIF without IF")?;
+                return Ok(Listed::Nothing);
+            }
+
+            _ if !self.active() => return Ok(Listed::Nothing),
+            _ => {}
+        }
+
+        match operation {
+            "EQU" | "SET" => {
+                let name = statement.label.as_ref().ok_or("EQU needs a name")?;
+                let value = match self.value(operands) {
+                    Ok(value) => value,
+
+                    // a forward reference can be resolved in the second pass
+                    Err(_) if !self.generate => return Ok(Listed::Nothing),
+                    Err(e) => return Err(e),
+                };
+
+                if operation == "SET" {
+                    self.redefinable.insert(name.clone());
+                }
+
+                self.define(name, value)?;
+                return Ok(Listed::Value(value));
+            }
+
+            // LOCAL is handled when a macro is expanded
+            "LOCAL" => return Err(String::from("LOCAL outside of a macro")),
+            "ENDM" => return Err(String::from("ENDM without MACRO")),
+            _ => {}
+        }
+
+        if let Some(label) = &statement.label {
+            self.define(label, self.location as u16)?;
+        }
+
+        match operation {
+            "" => Ok(Listed::Nothing),
+
+            "ORG" => {
+                let addr = self.defined_value(operands)?;
+                self.location = addr as u32;
+                Ok(Listed::Value(addr))
+            }
+
+            "DB" => {
+                let mut bytes = Vec::new();
+
+                for operand in split_operands(operands) {
+                    match string_literal(&operand) {
+                        Some(string) if string.len() != 1 => bytes.extend(string),
+                        _ => bytes.push(self.byte_value(&operand)?),
+                    }
+                }
+
+                self.emit(&bytes)?;
+                Ok(Listed::Code(bytes))
+            }
+
+            "DW" => {
+                let mut bytes = Vec::new();
+
+                for operand in split_operands(operands) {
+                    bytes.extend(self.value(&operand)?.to_le_bytes());
+                }
+
+                self.emit(&bytes)?;
+                Ok(Listed::Code(bytes))
+            }
+
+            "DS" => {
+                let size = self.defined_value(operands)?;
+                self.advance(size as usize)?;
+                Ok(Listed::Code(Vec::new()))
+            }
+
+            "END" => {
+                if !operands.is_empty() {
+                    self.start = Some(self.value(operands)?);
+                }
+
+                self.ended = true;
+                Ok(Listed::Nothing)
+            }
+
+            "INCLUDE" => {
+                if depth >= MAX_NESTING {
+                    return Err(String::from("INCLUDEs are nested too deeply"));
+                }
+
+                let name = string_literal(operands)
+                    .map(|name| String::from_utf8_lossy(&name).into_owned())
+                    .unwrap_or_else(|| operands.clone());
+                let path = dir.join(&name);
+                let source = fs::read_to_string(&path)
+                    .map_err(|e| format!("Could not read {}: {e}", path.display()))?;
+
+                self.list(line, None, &[], depth);
+
+                let included = source_lines(&source, &path.display().to_string());
+                let included_dir = path.parent().unwrap_or(dir).to_path_buf();
+                self.assemble_lines(&included, &included_dir, depth + 1);
+
+                Ok(Listed::Expanded)
+            }
+
+            _ if IGNORED_DIRECTIVES.contains(&operation) => Ok(Listed::Nothing),
+
+            _ if self.macros.contains_key(operation) => {
+                if depth >= MAX_NESTING {
+                    return Err(String::from("Macro expansions are nested too deeply"));
+                }
+
+                let expansion = self.expand_macro(operation, operands, line);
+
+                self.list(line, None, &[], depth);
+                self.assemble_lines(&expansion, dir, depth + 1);
+
+                Ok(Listed::Expanded)
+            }
+
+            _ => {
+                let bytes = self.encode(operation, &split_operands(operands))?;
+                self.emit(&bytes)?;
+                Ok(Listed::Code(bytes))
+            }
+        }
+    }
+
+    // encodes an instruction
+    fn encode(&self, mnemonic: &str, operands: &[String]) -> Result<Vec<u8>, String> {
+        let forms = self
+            .opcodes
+            .get(mnemonic)
+            .ok_or_else(|| format!("Unknown instruction: {mnemonic}"))?;
+
+        for form in forms.iter().filter(|f| f.operands.len() == operands.len()) {
+            let mut immediate = None;
+            let mut matches = true;
+
+            for (pattern, operand) in form.operands.iter().zip(operands) {
+                if pattern == "{n}" {
+                    immediate = Some(operand);
+                } else if pattern.eq_ignore_ascii_case(operand) {
+                    continue;
+                } else if let Ok(number) = pattern.parse::<u16>() {
+                    // RST takes an expression for its vector number
+                    matches &= self.value(operand)? == number;
+                } else {
+                    matches = false;
+                }
+            }
+
+            if !matches {
+                continue;
+            }
+
+            let mut bytes = vec![form.opcode];
+
+            match (form.n_bytes, immediate) {
+                (2, Some(operand)) => bytes.push(self.byte_value(operand)?),
+                (3, Some(operand)) => bytes.extend(self.value(operand)?.to_le_bytes()),
+                _ => {}
+            }
+
+            return Ok(bytes);
+        }
+
+        // RST with a value that doesn't match any vector
+        if mnemonic == "RST" {
+            return Err(String::from("RST needs a vector from 0 to 7"));
+        }
+
+        Err(format!(
+            "Invalid operands for {mnemonic}: {}",
+            operands.join(",")
+        ))
+    }
+
+    // expands a macro call into the lines of its body, with the parameters
+    // and LOCAL labels replaced
+    fn expand_macro(&mut self, name: &str, operands: &str, line: &SourceLine) -> Vec<SourceLine> {
+        let definition = self.macros[name].clone();
+        let args = split_operands(operands);
+
+        let mut replacements: HashMap<String, String> = definition
+            .params
+            .iter()
+            .enumerate()
+            .map(|(i, param)| (param.clone(), args.get(i).cloned().unwrap_or_default()))
+            .collect();
+
+        let mut expansion = Vec::new();
+
+        for text in &definition.body {
+            let statement = self.parse_line(text);
+
+            // every LOCAL label is given a name of its own, numbered across
+            // all expansions
+            if statement.operation.as_deref() == Some("LOCAL") {
+                for local in split_operands(&statement.operands) {
+                    self.n_locals += 1;
+                    let unique = format!("??{:04}", self.n_locals);
+                    self.local_names.insert(unique.clone());
+                    replacements.insert(local.to_ascii_uppercase(), unique);
+                }
+                continue;
+            }
+
+            expansion.push(SourceLine {
+                file: line.file.clone(),
+                number: line.number,
+                text: replace_identifiers(text, &replacements),
+            });
+        }
+
+        expansion
+    }
+
+    // defines a symbol, labels can only be defined once. names starting with
+    // ?? are kept for LOCAL labels, so they can't clash with the source's
+    fn define(&mut self, name: &str, value: u16) -> Result<(), String> {
+        if name.starts_with("??") && !self.local_names.contains(name) {
+            return Err(format!("Names starting with ?? are reserved: {name}"));
+        }
+
+        let redefinable = self.redefinable.contains(name);
+
+        if !self.defined.insert(name.to_string()) && !redefinable {
+            return Err(format!("Duplicate symbol: {name}"));
+        }
+
+        // a label that moved between passes means the code changed size
+        if self.generate && !redefinable {
+            if let Some(first_value) = self.symbols.get(name) {
+                if *first_value != value {
+                    return Err(format!("Phase error: {name}"));
+                }
+            }
+        }
+
+        self.symbols.insert(name.to_string(), value);
+        Ok(())
+    }
+
+    // evaluates an expression. in the first pass, symbols that haven't been
+    // defined yet are taken as 0
+    fn value(&self, expression: &str) -> Result<u16, String> {
+        match evaluate(expression, &self.symbols, self.location as u16) {
+            Ok(value) => Ok(value),
+            Err(ExpressionError::Undefined(_)) if !self.generate => Ok(0),
+            Err(e) => Err(e.to_string()),
+        }
+    }
+
+    // evaluates an expression that has to be known in the first pass (ORG,
+    // DS and IF), so it may only use symbols defined before it
+    fn defined_value(&self, expression: &str) -> Result<u16, String> {
+        let symbols: HashMap<String, u16> = self
+            .symbols
+            .iter()
+            .filter(|(name, _)| self.defined.contains(*name))
+            .map(|(name, value)| (name.clone(), *value))
+            .collect();
+
+        evaluate(expression, &symbols, self.location as u16).map_err(|e| e.to_string())
+    }
+
+    // evaluates an 8-bit value, which may be negative
+    fn byte_value(&self, expression: &str) -> Result<u8, String> {
+        match self.value(expression)? {
+            value @ (0..=0xFF | 0xFF80..=0xFFFF) => Ok(value as u8),
+            value => Err(format!("Value doesn't fit in a byte: {value:04X}H")),
+        }
+    }
+
+    // moves the location counter past code or reserved space, which can't go
+    // past the end of memory
+    fn advance(&mut self, size: usize) -> Result<(), String> {
+        let end = self.location as usize + size;
+        if end > 0x10000 {
+            return Err(String::from("Runs past the end of memory at 0FFFFH"));
+        }
+
+        self.location = end as u32;
+        Ok(())
+    }
+
+    // writes bytes at the location counter
+    fn emit(&mut self, bytes: &[u8]) -> Result<(), String> {
+        if bytes.is_empty() {
+            return Ok(());
+        }
+
+        let addr = self.location;
+        self.advance(bytes.len())?;
+
+        if self.generate {
+            match self.chunks.last_mut() {
+                Some(chunk) if chunk.addr as u32 + chunk.data.len() as u32 == addr => {
+                    chunk.data.extend(bytes)
+                }
+                _ => self.chunks.push(Chunk {
+                    addr: addr as u16,
+                    data: bytes.to_vec(),
+                }),
+            }
+        }
+
+        Ok(())
+    }
+
+    // adds a line to the listing, with the code generated for it
+    fn list(&mut self, line: &SourceLine, addr: Option<u16>, bytes: &[u8], depth: usize) {
+        let addr_text = addr.map(|addr| format!("{addr:04X}")).unwrap_or_default();
+        let first: String = bytes
+            .iter()
+            .take(LISTING_BYTES)
+            .map(|b| format!("{b:02X}"))
+            .collect();
+
+        self.list_text(line, &format!("{addr_text:<4} {first}"), depth);
+
+        // code that doesn't fit goes on the following lines
+        for (i, rest) in bytes.chunks(LISTING_BYTES).enumerate().skip(1) {
+            let addr = addr.unwrap_or(0).wrapping_add((i * LISTING_BYTES) as u16);
+            let rest: String = rest.iter().map(|b| format!("{b:02X}")).collect();
+            self.listing.push_str(&format!("{addr:04X} {rest}\n"));
+        }
+    }
+
+    fn list_text(&mut self, line: &SourceLine, code: &str, depth: usize) {
+        if self.generate {
+            // lines from INCLUDEs and macros are marked with a +
+            let marker = if depth > 0 { '+' } else { ' ' };
+
+            self.listing.push_str(&format!(
+                "{code:<13} {:>5}{marker} {}\n",
+                line.number, line.text
+            ));
+        }
+    }
+
+    // records an error on a line, only in the second pass so each is only
+    // reported once
+    fn error(&mut self, line: &SourceLine, message: &str) {
+        if self.generate {
+            self.errors
+                .push(format!("{}:{}: {message}", line.file, line.number));
+        }
+    }
+}
+
+// Listed enum - what the listing shows for a statement
+enum Listed {
+    Code(Vec<u8>), // the address and generated code
+    Value(u16),    // the value of EQU, SET or ORG
+    Nothing,
+    Expanded, // the lines of an INCLUDE or macro were listed instead
+}
+
+// removes a comment from a line, semicolons in strings don't start one
+fn strip_comment(text: &str) -> &str {
+    let mut quote = None;
+
+    for (i, c) in text.char_indices() {
+        match (c, quote) {
+            ('\'' | '"', None) => quote = Some(c),
+            (c, Some(q)) if c == q => quote = None,
+            (';', None) => return &text[..i],
+            _ => {}
+        }
+    }
+
+    text
+}
+
+// splits operands at commas that aren't in strings or parentheses
+fn split_operands(operands: &str) -> Vec<String> {
+    let mut split = Vec::new();
+    let mut current = String::new();
+    let mut quote = None;
+    let mut parens = 0;
+
+    for c in operands.chars() {
+        match (c, quote) {
+            ('\'' | '"', None) => quote = Some(c),
+            (c, Some(q)) if c == q => quote = None,
+            ('(', None) => parens += 1,
+            (')', None) => parens -= 1,
+            (',', None) if parens == 0 => {
+                split.push(current.trim().to_string());
+                current.clear();
+                continue;
+            }
+            _ => {}
+        }
+
+        current.push(c);
+    }
+
+    if !current.trim().is_empty() || !split.is_empty() {
+        split.push(current.trim().to_string());
+    }
+
+    split
+}
+
+// returns the contents of an operand that is only a quoted string
+fn string_literal(operand: &str) -> Option<Vec<u8>> {
+    let quote = operand.chars().next().filter(|c| matches!(c, '\'' | '"'))?;
+    let inner = operand.strip_prefix(quote)?.strip_suffix(quote)?;
+
+    // a lone quote inside means this is more than one string
+    let doubled = format!("{quote}{quote}");
+    if inner.replace(&doubled, "").contains(quote) {
+        return None;
+    }
+
+    Some(inner.replace(&doubled, &quote.to_string()).into_bytes())
+}
+
+// replaces whole identifiers outside of strings
+fn replace_identifiers(text: &str, replacements: &HashMap<String, String>) -> String {
+    let mut replaced = String::new();
+    let mut chars = text.char_indices().peekable();
+    let mut quote = None;
+
+    while let Some((i, c)) = chars.next() {
+        match quote {
+            Some(q) => {
+                if c == q {
+                    quote = None;
+                }
+                replaced.push(c);
+            }
+
+            None if c == '\'' || c == '"' => {
+                quote = Some(c);
+                replaced.push(c);
+            }
+
+            // stop at comments, they are left as they are
+            None if c == ';' => {
+                replaced.push_str(&text[i..]);
+                break;
+            }
+
+            None if is_identifier_start(c) => {
+                let mut end = i + c.len_utf8();
+                while let Some((j, next)) = chars.peek() {
+                    if !is_identifier_char(*next) {
+                        break;
+                    }
+                    end = j + next.len_utf8();
+                    chars.next();
+                }
+
+                let identifier = &text[i..end];
+                match replacements.get(&identifier.to_ascii_uppercase()) {
+                    Some(replacement) => replaced.push_str(replacement),
+                    None => replaced.push_str(identifier),
+                }
+            }
+
+            None => replaced.push(c),
+        }
+    }
+
+    replaced
+}
+
+// formats an Intel HEX record
+fn hex_record(addr: u16, record_type: u8, data: &[u8]) -> String {
+    let mut bytes = vec![data.len() as u8];
+    bytes.extend(addr.to_be_bytes());
+    bytes.push(record_type);
+    bytes.extend(data);
+
+    let checksum = bytes
+        .iter()
+        .fold(0u8, |sum, b| sum.wrapping_add(*b))
+        .wrapping_neg();
+    bytes.push(checksum);
+
+    let hex: String = bytes.iter().map(|b| format!("{b:02X}")).collect();
+    format!(":{hex}\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpu::registers::*;
+
+    #[test]
+    fn assemble_program() {
+        let source = "
+; prints a string through the BDOS
+BDOS    EQU     5
+PRINT   EQU     9
+        ORG     100H
+START:  MVI     C,PRINT
+        LXI     D,MSG
+        CALL    BDOS
+        JMP     0
+MSG:    DB      'HI',0DH,0AH,'$'
+        DW      START, MSG+1
+        DS      2
+        RST     7
+        END     START
+";
+        let assembly = assemble(source).unwrap();
+
+        assert_eq!(
+            assembly.to_binary(),
+            [
+                0x0E, 0x09, 0x11, 0x0B, 0x01, 0xCD, 0x05, 0x00, 0xC3, 0x00, 0x00, b'H', b'I', 0x0D,
+                0x0A, b'$', 0x00, 0x01, 0x0C, 0x01, 0x00, 0x00, 0xFF,
+            ]
+        );
+        assert_eq!(assembly.start, Some(0x100));
+        assert_eq!(assembly.symbols.get("MSG"), Some(&0x010B));
+        assert!(assembly.symbol_table().contains("010B MSG\n"));
+        assert!(assembly.listing.contains("0100 0E09"));
+        assert!(assembly.to_intel_hex().ends_with(":00010001FE\n"));
+    }
+
+    #[test]
+    fn assemble_macros_and_conditionals() {
+        let source = "
+DEBUG   EQU     0
+PUTC    MACRO   CHAR
+        LOCAL   SKIP
+        MVI     E,CHAR
+        JMP     SKIP
+SKIP:   NOP
+        ENDM
+        ORG     0
+        PUTC    'A'
+        PUTC    'B'
+        IF      DEBUG
+        HLT
+        ELSE
+        NOP
+        ENDIF
+";
+        let assembly = assemble(source).unwrap();
+
+        assert_eq!(
+            assembly.to_binary(),
+            [0x1E, b'A', 0xC3, 0x05, 0x00, 0x00, 0x1E, b'B', 0xC3, 0x0B, 0x00, 0x00, 0x00]
+        );
+
+        // LOCAL labels stay unique however many a macro has
+        let labels: Vec<String> = (0..150).map(|i| format!("L{i}")).collect();
+        let body: String = labels
+            .iter()
+            .map(|label| format!("{label}: NOP\n"))
+            .collect();
+        let source = format!(
+            "MANY MACRO\n LOCAL {}\n{body} ENDM\n MANY\n MANY\n",
+            labels.join(",")
+        );
+        assert_eq!(assemble(&source).unwrap().to_binary(), [0x00; 300]);
+    }
+
+    #[test]
+    fn assemble_errors() {
+        let errors = assemble("  MVI A,FOO\n  MOV A\n  BOGUS\n  MVI A,300\n").unwrap_err();
+        let errors: Vec<&str> = errors.lines().collect();
+
+        assert_eq!(
+            errors,
+            [
+                "<source>:1: Undefined symbol: FOO",
+                "<source>:2: Invalid operands for MOV: A",
+                "<source>:3: Unknown instruction: BOGUS",
+                "<source>:4: Value doesn't fit in a byte: 012CH",
+            ]
+        );
+
+        // code and reserved space can't wrap around to the start of memory,
+        // even once memory is filled right up to the end
+        let errors = assemble("  ORG 0FFFFH\n  LXI H,0\n  ORG 0FFF0H\n  DS 20H\n").unwrap_err();
+        assert_eq!(
+            errors.lines().collect::<Vec<_>>(),
+            [
+                "<source>:2: Runs past the end of memory at 0FFFFH",
+                "<source>:4: Runs past the end of memory at 0FFFFH",
+            ]
+        );
+        assert_eq!(
+            assemble("  ORG 0FFFEH\n  DW 1\n  NOP\n").unwrap_err(),
+            "<source>:3: Runs past the end of memory at 0FFFFH"
+        );
+
+        // an IF that can't be evaluated still pairs with its ENDIF, and the
+        // names of LOCAL labels can't be defined by the source
+        let errors = assemble(
+            "  IF LATER
+  HLT
+  ENDIF
+LATER EQU 1
+??0101: NOP
+",
+        )
+        .unwrap_err();
+        let errors: Vec<&str> = errors.lines().collect();
+
+        assert_eq!(
+            errors,
+            [
+                "<source>:1: IF requires a defined expression: Undefined symbol: LATER",
+                "<source>:5: Names starting with ?? are reserved: ??0101",
+            ]
+        );
+    }
+
+    #[test]
+    fn assemble_into_cpu() {
+        let mut cpu = Cpu::new();
+        assemble_into(&mut cpu, "ORG 200H\nGO: MVI A,42\nHLT\nEND GO").unwrap();
+
+        while cpu.is_running() {
+            cpu.execute_next().unwrap();
+        }
+
+        assert_eq!(cpu.alu.accumulator(), RegisterValue::from(42u8));
+    }
+}
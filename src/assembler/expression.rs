@@ -0,0 +1,382 @@
+/*
+ * expression.rs - Contains code related to evaluating the expressions used as
+ * operands by the assembler, following the operators and precedence of the
+ * Intel 8080 assembler
+ */
+use std::collections::HashMap;
+
+// Token enum - a single token of an expression
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Number(u16),
+    Identifier(String), // upper case, also used for the word operators
+    String(Vec<u8>),
+    Operator(&'static str),
+    LeftParen,
+    RightParen,
+    Location, // $
+}
+
+// ExpressionError enum - why an expression couldn't be evaluated. an undefined
+// symbol may just be a forward reference, so it is told apart from errors
+#[derive(Debug, Clone, PartialEq)]
+pub enum ExpressionError {
+    Undefined(String),
+    Invalid(String),
+}
+
+impl std::fmt::Display for ExpressionError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ExpressionError::Undefined(symbol) => write!(f, "Undefined symbol: {symbol}"),
+            ExpressionError::Invalid(message) => write!(f, "{message}"),
+        }
+    }
+}
+
+// evaluates an expression, with $ as the given location counter. all
+// arithmetic is 16-bit and wraps around, comparisons give 0FFFFH for true
+pub fn evaluate(
+    expression: &str,
+    symbols: &HashMap<String, u16>,
+    location: u16,
+) -> Result<u16, ExpressionError> {
+    let tokens = tokenize(expression)?;
+    let mut parser = Parser {
+        tokens,
+        pos: 0,
+        symbols,
+        location,
+    };
+
+    let value = parser.or_expression()?;
+
+    match parser.tokens.get(parser.pos) {
+        None => Ok(value),
+        Some(token) => Err(invalid(format!("Unexpected {token:?} in expression"))),
+    }
+}
+
+// returns whether a character can start a symbol name
+pub fn is_identifier_start(c: char) -> bool {
+    c.is_ascii_alphabetic() || matches!(c, '?' | '@' | '_' | '.')
+}
+
+// returns whether a character can be part of a symbol name
+pub fn is_identifier_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || matches!(c, '?' | '@' | '_' | '.' | '$')
+}
+
+// parses a number with an optional radix suffix: H (hex), B (binary), O or Q
+// (octal), or D (decimal, the default)
+pub fn parse_number(s: &str) -> Result<u16, ExpressionError> {
+    let upper = s.to_ascii_uppercase();
+
+    let (digits, radix) = match upper.chars().last() {
+        Some('H') => (&upper[..upper.len() - 1], 16),
+        Some('B') => (&upper[..upper.len() - 1], 2),
+        Some('O' | 'Q') => (&upper[..upper.len() - 1], 8),
+        Some('D') => (&upper[..upper.len() - 1], 10),
+        _ => (&upper[..], 10),
+    };
+
+    u32::from_str_radix(digits, radix)
+        .ok()
+        .filter(|value| *value <= 0xFFFF)
+        .map(|value| value as u16)
+        .ok_or_else(|| invalid(format!("Invalid number: {s}")))
+}
+
+fn invalid(message: String) -> ExpressionError {
+    ExpressionError::Invalid(message)
+}
+
+// splits an expression into tokens
+fn tokenize(expression: &str) -> Result<Vec<Token>, ExpressionError> {
+    let chars: Vec<char> = expression.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+
+    while i < chars.len() {
+        let c = chars[i];
+
+        match c {
+            _ if c.is_whitespace() => i += 1,
+
+            '0'..='9' => {
+                let start = i;
+                while i < chars.len() && chars[i].is_ascii_alphanumeric() {
+                    i += 1;
+                }
+
+                let number: String = chars[start..i].iter().collect();
+                tokens.push(Token::Number(parse_number(&number)?));
+            }
+
+            '$' if !chars.get(i + 1).is_some_and(|c| is_identifier_char(*c)) => {
+                tokens.push(Token::Location);
+                i += 1;
+            }
+
+            _ if is_identifier_start(c) || c == '$' => {
+                let start = i;
+                while i < chars.len() && is_identifier_char(chars[i]) {
+                    i += 1;
+                }
+
+                let identifier: String = chars[start..i].iter().collect();
+                tokens.push(Token::Identifier(identifier.to_ascii_uppercase()));
+            }
+
+            // strings are quoted with ' (or "), a doubled quote is a quote
+            '\'' | '"' => {
+                let mut string = Vec::new();
+                i += 1;
+
+                loop {
+                    match chars.get(i) {
+                        None => return Err(invalid(String::from("Unterminated string"))),
+                        Some(quote) if *quote == c && chars.get(i + 1) == Some(&c) => {
+                            string.push(c as u8);
+                            i += 2;
+                        }
+                        Some(quote) if *quote == c => {
+                            i += 1;
+                            break;
+                        }
+                        Some(other) => {
+                            string.push(*other as u8);
+                            i += 1;
+                        }
+                    }
+                }
+
+                tokens.push(Token::String(string));
+            }
+
+            '(' => {
+                tokens.push(Token::LeftParen);
+                i += 1;
+            }
+
+            ')' => {
+                tokens.push(Token::RightParen);
+                i += 1;
+            }
+
+            _ => {
+                let next = chars.get(i + 1).copied();
+
+                let (operator, len) = match (c, next) {
+                    ('<', Some('=')) => ("LE", 2),
+                    ('>', Some('=')) => ("GE", 2),
+                    ('<', Some('>')) => ("NE", 2),
+                    ('<', _) => ("LT", 1),
+                    ('>', _) => ("GT", 1),
+                    ('=', _) => ("EQ", 1),
+                    ('+', _) => ("+", 1),
+                    ('-', _) => ("-", 1),
+                    ('*', _) => ("*", 1),
+                    ('/', _) => ("/", 1),
+                    _ => return Err(invalid(format!("Unexpected character in expression: {c}"))),
+                };
+
+                tokens.push(Token::Operator(operator));
+                i += len;
+            }
+        }
+    }
+
+    Ok(tokens)
+}
+
+// Parser struct - recursive-descent evaluator over a list of tokens
+struct Parser<'a> {
+    tokens: Vec<Token>,
+    pos: usize,
+    symbols: &'a HashMap<String, u16>,
+    location: u16,
+}
+
+impl Parser<'_> {
+    // consumes the next token if it is one of the given operators. word
+    // operators are only recognised where an operator is expected
+    fn operator(&mut self, operators: &[&'static str]) -> Option<&'static str> {
+        let operator = match self.tokens.get(self.pos) {
+            Some(Token::Operator(op)) => operators.iter().find(|o| *o == op)?,
+            Some(Token::Identifier(word)) => operators.iter().find(|o| **o == word.as_str())?,
+            _ => return None,
+        };
+
+        self.pos += 1;
+        Some(operator)
+    }
+
+    // OR and XOR, the lowest precedence
+    fn or_expression(&mut self) -> Result<u16, ExpressionError> {
+        let mut value = self.and_expression()?;
+
+        while let Some(operator) = self.operator(&["OR", "XOR"]) {
+            let rhs = self.and_expression()?;
+            value = match operator {
+                "OR" => value | rhs,
+                _ => value ^ rhs,
+            };
+        }
+
+        Ok(value)
+    }
+
+    fn and_expression(&mut self) -> Result<u16, ExpressionError> {
+        let mut value = self.not_expression()?;
+
+        while self.operator(&["AND"]).is_some() {
+            value &= self.not_expression()?;
+        }
+
+        Ok(value)
+    }
+
+    fn not_expression(&mut self) -> Result<u16, ExpressionError> {
+        match self.operator(&["NOT"]) {
+            Some(_) => Ok(!self.not_expression()?),
+            None => self.comparison(),
+        }
+    }
+
+    fn comparison(&mut self) -> Result<u16, ExpressionError> {
+        let mut value = self.additive()?;
+
+        while let Some(operator) = self.operator(&["EQ", "NE", "LT", "LE", "GT", "GE"]) {
+            let rhs = self.additive()?;
+            let result = match operator {
+                "EQ" => value == rhs,
+                "NE" => value != rhs,
+                "LT" => value < rhs,
+                "LE" => value <= rhs,
+                "GT" => value > rhs,
+                _ => value >= rhs,
+            };
+
+            value = if result { 0xFFFF } else { 0 };
+        }
+
+        Ok(value)
+    }
+
+    fn additive(&mut self) -> Result<u16, ExpressionError> {
+        let mut value = self.multiplicative()?;
+
+        while let Some(operator) = self.operator(&["+", "-"]) {
+            let rhs = self.multiplicative()?;
+            value = match operator {
+                "+" => value.wrapping_add(rhs),
+                _ => value.wrapping_sub(rhs),
+            };
+        }
+
+        Ok(value)
+    }
+
+    fn multiplicative(&mut self) -> Result<u16, ExpressionError> {
+        let mut value = self.unary()?;
+
+        while let Some(operator) = self.operator(&["*", "/", "MOD", "SHL", "SHR"]) {
+            let rhs = self.unary()?;
+
+            value = match operator {
+                "*" => value.wrapping_mul(rhs),
+                "/" | "MOD" if rhs == 0 => return Err(invalid(String::from("Division by zero"))),
+                "/" => value / rhs,
+                "MOD" => value % rhs,
+                "SHL" => value.checked_shl(rhs as u32).unwrap_or(0),
+                _ => value.checked_shr(rhs as u32).unwrap_or(0),
+            };
+        }
+
+        Ok(value)
+    }
+
+    fn unary(&mut self) -> Result<u16, ExpressionError> {
+        match self.operator(&["-", "+", "HIGH", "LOW"]) {
+            Some("-") => Ok(self.unary()?.wrapping_neg()),
+            Some("+") => self.unary(),
+            Some("HIGH") => Ok(self.unary()? >> 8),
+            Some("LOW") => Ok(self.unary()? & 0xFF),
+            _ => self.primary(),
+        }
+    }
+
+    fn primary(&mut self) -> Result<u16, ExpressionError> {
+        let token = self
+            .tokens
+            .get(self.pos)
+            .cloned()
+            .ok_or_else(|| invalid(String::from("Missing operand in expression")))?;
+        self.pos += 1;
+
+        match token {
+            Token::Number(value) => Ok(value),
+            Token::Location => Ok(self.location),
+
+            // one or two characters, the first in the high byte
+            Token::String(string) => match string[..] {
+                [c] => Ok(c as u16),
+                [high, low] => Ok(u16::from_be_bytes([high, low])),
+                _ => Err(invalid(String::from(
+                    "Strings in expressions must be 1 or 2 characters",
+                ))),
+            },
+
+            Token::Identifier(name) => self
+                .symbols
+                .get(&name)
+                .copied()
+                .ok_or(ExpressionError::Undefined(name)),
+
+            Token::LeftParen => {
+                let value = self.or_expression()?;
+
+                match self.tokens.get(self.pos) {
+                    Some(Token::RightParen) => {
+                        self.pos += 1;
+                        Ok(value)
+                    }
+                    _ => Err(invalid(String::from("Missing ) in expression"))),
+                }
+            }
+
+            token => Err(invalid(format!("Unexpected {token:?} in expression"))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn expression_evaluate() {
+        let mut symbols = HashMap::new();
+        symbols.insert(String::from("BDOS"), 0x0005);
+        let eval = |s| evaluate(s, &symbols, 0x0100);
+
+        assert_eq!(eval("0FFH"), Ok(0xFF));
+        assert_eq!(eval("1010B + 7Q + 10D"), Ok(10 + 7 + 10));
+        assert_eq!(eval("2 + 3 * 4"), Ok(14));
+        assert_eq!(eval("(2 + 3) * 4"), Ok(20));
+        assert_eq!(eval("$ + BDOS"), Ok(0x0105));
+        assert_eq!(eval("HIGH 1234H"), Ok(0x12));
+        assert_eq!(eval("LOW 1234H SHL 4"), Ok(0x340));
+        assert_eq!(eval("'A' OR 20H"), Ok(0x61));
+        assert_eq!(eval("'AB'"), Ok(0x4142));
+        assert_eq!(eval("-1"), Ok(0xFFFF));
+        assert_eq!(eval("BDOS EQ 5 AND NOT 0"), Ok(0xFFFF));
+        assert_eq!(eval("17 MOD 5"), Ok(2));
+        assert_eq!(
+            eval("NOWHERE + 1"),
+            Err(ExpressionError::Undefined(String::from("NOWHERE")))
+        );
+        assert!(matches!(eval("1 +"), Err(ExpressionError::Invalid(_))));
+    }
+}
@@ -0,0 +1,246 @@
+/*
+ * commands.rs - Contains the tools that can be run from the command line
+ * instead of the emulator
+ */
+use crate::arguments::{parse_address, Command};
+use i8080::analyzer::Analyzer;
+use i8080::assembler;
+use i8080::compare;
+use i8080::cp_m;
+use i8080::cpu::disassembler::{self, Syntax};
+use i8080::cpu::memory::*;
+use i8080::cpu::registers::*;
+use i8080::cpu::*;
+use i8080::linker;
+use i8080::loader;
+use i8080::symbols::SymbolTable;
+
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+
+// runs the given tool
+pub fn run_command(command: Command, syntax: Syntax, symbols: &SymbolTable) -> Result<(), String> {
+    match command {
+        Command::Disasm { file, address } => disasm(&file, address, syntax, symbols),
+        Command::Asm {
+            file,
+            output,
+            hex,
+            listing,
+            symbols,
+        } => asm(&file, output, hex, listing, symbols),
+        Command::Analyze {
+            file,
+            address,
+            entry,
+            coverage,
+            output,
+        } => analyze(&file, address, &entry, &coverage, output, symbols),
+        Command::Link {
+            files,
+            base,
+            output,
+            symbols,
+        } => link(&files, base, output, symbols),
+        Command::CompareTrace {
+            file,
+            reference,
+            bare,
+            org,
+        } => compare_trace(&file, &reference, bare, org, syntax),
+    }
+}
+
+// prints the disassembly of a file loaded at the given address, with a label
+// line before each instruction that has a symbol
+fn disasm(file: &str, address: u16, syntax: Syntax, symbols: &SymbolTable) -> Result<(), String> {
+    let data = fs::read(file).map_err(|e| format!("Could not read {file}: {e}"))?;
+
+    if address as usize + data.len() > 0x10000 {
+        return Err(format!("{file} doesn't fit in memory at {address:04X}"));
+    }
+
+    let len = data.len();
+    let mut cpu = Cpu::new();
+    cpu.load_to_memory(data, address)?;
+
+    let mut stdout = std::io::stdout().lock();
+    for instruction in disassembler::disassemble_range(&cpu.memory, address, len, syntax)? {
+        let label = match symbols.name_at(instruction.addr) {
+            Some(name) => format!("{name}:\n"),
+            None => String::new(),
+        };
+
+        match writeln!(stdout, "{label}{}", instruction.with_symbols(symbols)) {
+            Err(e) if e.kind() == std::io::ErrorKind::BrokenPipe => break,
+            result => result.map_err(|e| e.to_string())?,
+        }
+    }
+
+    Ok(())
+}
+
+// assembles a source file, writing the code and optionally the listing and
+// symbol table
+fn asm(
+    file: &str,
+    output: Option<String>,
+    hex: bool,
+    listing: Option<String>,
+    symbols: Option<String>,
+) -> Result<(), String> {
+    let assembly = assembler::assemble_file(Path::new(file))?;
+
+    let hex = hex
+        || output
+            .as_ref()
+            .is_some_and(|output| output.to_lowercase().ends_with(".hex"));
+    let output = output.unwrap_or_else(|| {
+        let extension = if hex { "hex" } else { "bin" };
+        Path::new(file)
+            .with_extension(extension)
+            .display()
+            .to_string()
+    });
+
+    let write = |path: &str, contents: &[u8]| {
+        fs::write(path, contents).map_err(|e| format!("Could not write {path}: {e}"))
+    };
+
+    match hex {
+        true => write(&output, assembly.to_intel_hex().as_bytes())?,
+        false => write(&output, &assembly.to_binary())?,
+    }
+
+    if let Some(listing) = listing {
+        write(&listing, assembly.listing.as_bytes())?;
+    }
+
+    if let Some(symbols) = symbols {
+        write(&symbols, assembly.symbol_table().as_bytes())?;
+    }
+
+    Ok(())
+}
+
+// analyzes a file loaded at the given address, starting from the entry
+// points and the addresses in the coverage files
+fn analyze(
+    file: &str,
+    address: u16,
+    entries: &[u16],
+    coverage: &[String],
+    output: Option<String>,
+    symbols: &SymbolTable,
+) -> Result<(), String> {
+    let data = fs::read(file).map_err(|e| format!("Could not read {file}: {e}"))?;
+
+    let mut analyzer = Analyzer::new();
+    analyzer.symbols = symbols.clone();
+    analyzer.entries.extend(entries);
+
+    for path in coverage {
+        let text = fs::read_to_string(path).map_err(|e| format!("Could not read {path}: {e}"))?;
+        let addrs = text
+            .split_whitespace()
+            .map(parse_address)
+            .collect::<Result<Vec<u16>, String>>()?;
+
+        analyzer.seed(addrs);
+    }
+
+    let listing = analyzer.analyze(&data, address)?.listing();
+
+    match output {
+        Some(output) => {
+            fs::write(&output, listing).map_err(|e| format!("Could not write {output}: {e}"))
+        }
+        None => {
+            print!("{listing}");
+            Ok(())
+        }
+    }
+}
+
+// links REL files at the given address, writing the code from there to the
+// end of the last segment and optionally the symbol table
+fn link(
+    files: &[String],
+    base: u16,
+    output: Option<String>,
+    symbols: Option<String>,
+) -> Result<(), String> {
+    let objects = files
+        .iter()
+        .map(|file| fs::read(file).map_err(|e| format!("Could not read {file}: {e}")))
+        .collect::<Result<Vec<_>, String>>()?;
+
+    let mut cpu = Cpu::new();
+    let program = linker::link_into(&mut cpu, &objects, base)?;
+
+    // the output only holds the linked segments, absolute code elsewhere
+    // would be lost
+    if let Some((name, addr)) = program.absolute.first() {
+        return Err(format!(
+            "{name} loads absolute code at {addr:04X}, outside the linked program at {base:04X}-{:04X}",
+            program.end.saturating_sub(1)
+        ));
+    }
+
+    let code = (base as u32..program.end)
+        .map(|addr| {
+            let value = cpu
+                .memory
+                .read(RegisterValue::from(addr as u16), MemorySize::Integer8)?;
+            Ok(u8::try_from(value)?)
+        })
+        .collect::<Result<Vec<u8>, String>>()?;
+
+    let output = output.unwrap_or_else(|| {
+        Path::new(&files[0])
+            .with_extension("com")
+            .display()
+            .to_string()
+    });
+    fs::write(&output, code).map_err(|e| format!("Could not write {output}: {e}"))?;
+
+    if let Some(symbols) = symbols {
+        fs::write(&symbols, program.symbol_table())
+            .map_err(|e| format!("Could not write {symbols}: {e}"))?;
+    }
+
+    Ok(())
+}
+
+// runs a program alongside a reference trace, under CP/M or on bare metal,
+// and reports the first instruction whose result differs
+fn compare_trace(
+    file: &str,
+    reference: &str,
+    bare: bool,
+    org: Option<u16>,
+    syntax: Syntax,
+) -> Result<(), String> {
+    let program = fs::read(file).map_err(|e| format!("Could not read {file}: {e}"))?;
+    let trace =
+        fs::File::open(reference).map_err(|e| format!("Could not read {reference}: {e}"))?;
+
+    let mut cpu = Cpu::new();
+    if !bare {
+        cp_m::add_cpm_bdos(&mut cpu, cp_m::Bdos::new(cp_m::CpmDevices::new()));
+        cp_m::load_transient(&mut cpu, Vec::new(), "")?;
+    }
+
+    let org = org.unwrap_or(if bare { 0 } else { cp_m::TPA_START });
+    let start = loader::load_image(&mut cpu, &program, org)?.unwrap_or(org);
+    cpu.set_pc(start)?;
+
+    match compare::compare_trace(&mut cpu, &mut std::io::BufReader::new(trace), syntax)? {
+        Some(divergence) => Err(divergence.to_string()),
+        None => {
+            println!("The whole trace matched");
+            Ok(())
+        }
+    }
+}
@@ -0,0 +1,309 @@
+/*
+ * compare.rs - Contains code related to comparing a run against a reference
+ * trace captured from another emulator, one instruction at a time, to find
+ * the first instruction whose result differs. each line of the trace is the
+ * state before an instruction runs, starting with the state the program
+ * starts in, as either CSV or a JSON object:
+ *   PC,A,F,B,C,D,E,H,L,SP[,CYCLES]          in hex, e.g. 0100,00,02,...,FFFE
+ *   {"pc":256,"a":0,"f":"0x02","sp":"FFFEH","cycles":0}
+ * fields that are left empty or out aren't compared. JSON values are numbers
+ * or hex strings. blank lines, lines starting with # and a CSV header line
+ * starting with PC are skipped. only the flag bits of F are compared, as
+ * emulators differ on the others, and cycles are counted from the first line
+ */
+use crate::cpu::disassembler::*;
+use crate::cpu::registers::*;
+use crate::cpu::*;
+use crate::json::Json;
+
+use std::fmt;
+use std::io::BufRead;
+
+// the bits of F that are compared: S, Z, AC, P and CY
+const FLAGS_MASK: u8 = 0xD5;
+
+// the fields of a line, in CSV order
+pub const FIELDS: [&str; 11] = ["PC", "A", "F", "B", "C", "D", "E", "H", "L", "SP", "CYCLES"];
+
+// TraceState struct - the state of a Cpu before an instruction runs, as
+// given by a line of a trace. fields the trace doesn't give are None
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct TraceState {
+    pub fields: [Option<usize>; FIELDS.len()], // in the order of FIELDS
+}
+
+impl TraceState {
+    // returns the state of a Cpu, with its cycles counted from start_cycles
+    pub fn of(cpu: &Cpu, start_cycles: usize) -> Self {
+        let read = |register| Some(u16::from(cpu.reg_array.read_reg(register)) as usize);
+        let psw = read(Register::PSW).unwrap_or_default();
+
+        Self {
+            fields: [
+                read(Register::PC),
+                Some(psw >> 8),
+                Some(psw & 0xFF),
+                read(Register::B),
+                read(Register::C),
+                read(Register::D),
+                read(Register::E),
+                read(Register::H),
+                read(Register::L),
+                read(Register::SP),
+                Some(cpu.total_cycles - start_cycles),
+            ],
+        }
+    }
+
+    // parses a line of a trace, None if it is blank, a comment or a header
+    pub fn parse(line: &str) -> Result<Option<Self>, String> {
+        let line = line.trim();
+        let header = line
+            .get(..2)
+            .is_some_and(|start| start.eq_ignore_ascii_case("PC"));
+        if line.is_empty() || line.starts_with('#') || header {
+            return Ok(None);
+        }
+
+        let mut state = Self::default();
+        match line.starts_with('{') {
+            true => {
+                let json = Json::parse(line)?;
+                let Json::Object(pairs) = &json else {
+                    return Err(String::from("Expected a JSON object"));
+                };
+
+                for (key, value) in pairs {
+                    let index = field_index(key).ok_or_else(|| format!("Unknown field {key}"))?;
+                    state.fields[index] = match value {
+                        Json::Null => None,
+                        Json::String(text) => Some(parse_hex(text)?),
+                        value => Some(
+                            value
+                                .as_i64()
+                                .and_then(|n| usize::try_from(n).ok())
+                                .ok_or_else(|| format!("Invalid value for {key}"))?,
+                        ),
+                    };
+                }
+            }
+            false => {
+                let values: Vec<&str> = line.split(',').collect();
+                if values.len() > FIELDS.len() {
+                    return Err(format!("Expected at most {} fields", FIELDS.len()));
+                }
+
+                for (i, value) in values.iter().enumerate() {
+                    state.fields[i] = match value.trim() {
+                        "" => None,
+                        // cycles are in decimal, like the cycle counts of the
+                        // trace written by --trace
+                        value if i == FIELDS.len() - 1 => Some(
+                            value
+                                .parse()
+                                .map_err(|_| format!("Invalid cycles {value}"))?,
+                        ),
+                        value => Some(parse_hex(value)?),
+                    };
+                }
+            }
+        }
+
+        Ok(Some(state))
+    }
+
+    // returns the names of the fields given by both states that differ
+    pub fn differences(&self, other: &Self) -> Vec<&'static str> {
+        FIELDS
+            .iter()
+            .zip(self.fields.iter().zip(other.fields.iter()))
+            .filter(|(name, values)| match values {
+                (Some(a), Some(b)) if **name == "F" => {
+                    a & FLAGS_MASK as usize != b & FLAGS_MASK as usize
+                }
+                (Some(a), Some(b)) => a != b,
+                _ => false,
+            })
+            .map(|(name, _)| *name)
+            .collect()
+    }
+}
+
+// Divergence struct - the first line of a reference trace the Cpu's state
+// didn't match
+#[derive(Debug, Clone, PartialEq)]
+pub struct Divergence {
+    pub line: usize,              // line number in the trace
+    pub instructions: usize,      // instructions run before it
+    pub previous: Option<String>, // the instruction run last, disassembled
+    pub expected: TraceState,     // from the trace
+    pub actual: TraceState,       // of the Cpu
+    pub differences: Vec<&'static str>,
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Diverged at line {} of the trace, after {} instructions",
+            self.line, self.instructions
+        )?;
+        match &self.previous {
+            Some(previous) => writeln!(f, ", the last being\n  {previous}")?,
+            None => writeln!(f)?,
+        }
+
+        writeln!(f)?;
+        writeln!(f, "{:<8}{:<12}Emulator", "", "Reference")?;
+        for (i, name) in FIELDS.iter().enumerate() {
+            let format = |value: Option<usize>| match value {
+                None => String::from("-"),
+                Some(value) if *name == "CYCLES" => value.to_string(),
+                Some(value) if matches!(*name, "PC" | "SP") => format!("{value:04X}"),
+                Some(value) => format!("{value:02X}"),
+            };
+            let mark = match self.differences.contains(name) {
+                true => "<",
+                false => "",
+            };
+
+            let line = format!(
+                "{name:<8}{:<12}{:<12}{mark}",
+                format(self.expected.fields[i]),
+                format(self.actual.fields[i])
+            );
+            writeln!(f, "{}", line.trim_end())?;
+        }
+        Ok(())
+    }
+}
+
+// runs a Cpu one instruction at a time alongside a reference trace, until the
+// trace ends or the Cpu's state differs from it. the Cpu should be in the
+// state the trace starts in. None if the whole trace matched
+pub fn compare_trace(
+    cpu: &mut Cpu,
+    reference: &mut dyn BufRead,
+    syntax: Syntax,
+) -> Result<Option<Divergence>, String> {
+    let start_cycles = cpu.total_cycles;
+    let mut first_cycles = None;
+    let mut instructions = 0;
+    let mut previous = None;
+
+    for (i, line) in reference.lines().enumerate() {
+        let line = line.map_err(|e| format!("Could not read the trace: {e}"))?;
+        let Some(mut expected) =
+            TraceState::parse(&line).map_err(|e| format!("Line {}: {e}", i + 1))?
+        else {
+            continue;
+        };
+
+        // cycles are counted from the first line that gives them
+        if let Some(cycles) = &mut expected.fields[FIELDS.len() - 1] {
+            let first = *first_cycles.get_or_insert(*cycles);
+            *cycles = cycles
+                .checked_sub(first)
+                .ok_or_else(|| format!("Line {}: cycles go backwards", i + 1))?;
+        }
+
+        if !cpu.is_running() {
+            return Err(format!(
+                "The program halted before line {} of the trace",
+                i + 1
+            ));
+        }
+
+        let actual = TraceState::of(cpu, start_cycles);
+        let differences = expected.differences(&actual);
+        if !differences.is_empty() {
+            return Ok(Some(Divergence {
+                line: i + 1,
+                instructions,
+                previous,
+                expected,
+                actual,
+                differences,
+            }));
+        }
+
+        let pc = u16::from(cpu.reg_array.read_reg(Register::PC));
+        previous = disassemble(&cpu.memory, pc, syntax)
+            .ok()
+            .map(|instruction| instruction.to_string());
+        cpu.execute_next()
+            .map_err(|e| format!("Error at {pc:04X} on line {} of the trace: {e}", i + 1))?;
+        instructions += 1;
+    }
+
+    Ok(None)
+}
+
+fn field_index(name: &str) -> Option<usize> {
+    FIELDS
+        .iter()
+        .position(|field| field.eq_ignore_ascii_case(name))
+}
+
+// parses a hex value, with an optional 0x prefix or H suffix
+fn parse_hex(text: &str) -> Result<usize, String> {
+    let digits = text.strip_prefix("0x").unwrap_or(text);
+    let digits = digits.strip_suffix(['h', 'H']).unwrap_or(digits);
+
+    usize::from_str_radix(digits, 16).map_err(|_| format!("Invalid value {text}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn compare_against_reference() {
+        // MVI A,41H; INR A; HLT
+        let mut cpu = Cpu::new();
+        cpu.load_to_memory(vec![0x3E, 0x41, 0x3C, 0x76], 0x0100)
+            .unwrap();
+        cpu.set_pc(0x0100).unwrap();
+
+        // CSV and JSON lines, with a header, a comment and fields left out.
+        // the reference thinks INR A gave 43H
+        let reference = "PC,A,F,B,C,D,E,H,L,SP,CYCLES\n\
+            # captured elsewhere\n\
+            0100,00,02,00,00,00,00,00,00,0000,1000\n\
+            {\"pc\":\"0x102\",\"a\":65,\"cycles\":1007}\n\
+            0104,43,,,,,,,,,1012\n";
+        let divergence = compare_trace(&mut cpu, &mut reference.as_bytes(), Syntax::Intel)
+            .unwrap()
+            .unwrap();
+
+        assert_eq!((divergence.line, divergence.instructions), (5, 2));
+        assert_eq!(divergence.differences, ["PC", "A"]);
+        assert_eq!(
+            divergence.previous.as_deref(),
+            Some("0102  3C        INR A")
+        );
+
+        let report = divergence.to_string();
+        assert!(report.contains("\nPC      0104        0103        <\n"));
+        assert!(report.contains("\nA       43          42          <\n"));
+        assert!(report.contains("\nB       -           00\n"));
+        assert!(report.contains("\nCYCLES  12          12\n"));
+
+        // the whole trace matches once the reference is right, and a trace
+        // that goes on after the program halts is an error
+        let mut cpu = Cpu::new();
+        cpu.load_to_memory(vec![0x3E, 0x41, 0x3C, 0x76], 0x0100)
+            .unwrap();
+        cpu.set_pc(0x0100).unwrap();
+        assert_eq!(
+            compare_trace(&mut cpu, &mut "0100\n0102,41\n".as_bytes(), Syntax::Intel),
+            Ok(None)
+        );
+        assert_eq!(
+            compare_trace(&mut cpu, &mut "0103,42\n0104\n".as_bytes(), Syntax::Intel),
+            Err(String::from(
+                "The program halted before line 2 of the trace"
+            ))
+        );
+    }
+}
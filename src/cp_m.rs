@@ -1,29 +1,350 @@
 /*
  * cp_m.rs - Contains code related to implementing functions
  * in CP/M.
+ * See the CP/M 2.2 manual, section 5: http://www.gaby.de/cpm/manuals/archive/cpm22htm/ch5.htm
  */
+pub mod ccp;
+pub mod drive;
+pub mod trace;
+
 use crate::cpu::memory::*;
 use crate::cpu::registers::*;
 use crate::cpu::*;
+use crate::terminal::{Console, Keyboard};
+use drive::*;
+use trace::*;
+
+use std::fs::File;
+use std::io::{ErrorKind, Read, Write};
+use std::sync::{Arc, Mutex};
+
+// address of the IOBYTE in the CP/M zero page
+pub const IOBYTE_ADDR: u16 = 0x0003;
+
+// address of the current drive/user byte in the CP/M zero page
+pub const DRIVE_USER_ADDR: u16 = 0x0004;
+
+// address of the BDOS entry point
+pub const BDOS_ENTRY: u16 = 0x0005;
+
+// base address of the (emulated) BDOS, i.e. the top of the TPA
+pub const BDOS_BASE: u16 = 0xFE00;
+
+// start of the TPA, where transient programs are loaded and started
+pub const TPA_START: u16 = 0x0100;
+
+// addresses of the default FCBs and DMA buffer (which also holds the command tail)
+pub const DEFAULT_FCB: u16 = 0x005C;
+pub const DEFAULT_FCB2: u16 = 0x006C;
+pub const DEFAULT_DMA: u16 = 0x0080;
+
+// default IOBYTE: LST: = LPT:, PUN: = PTP:, RDR: = PTR:, CON: = CRT:
+pub const DEFAULT_IOBYTE: u8 = 0b10_01_01_01;
+
+// character returned by the reader when there is no more input (^Z)
+const READER_EOF: u8 = 0x1A;
+
+// CpmDevices struct - host-side streams backing the CP/M logical devices.
+// a device without a stream discards output, or reads as end-of-file
+#[derive(Default)]
+pub struct CpmDevices {
+    pub console: Option<Box<dyn Console>>, // CON: input, host stdin if None
+    pub list: Option<Box<dyn Write + Send>>, // LST:
+    pub punch: Option<Box<dyn Write + Send>>, // PUN:
+    pub reader: Option<Box<dyn Read + Send>>, // RDR:
+}
+
+impl CpmDevices {
+    // creates a new instance of CpmDevices with no streams attached
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // creates a new instance of CpmDevices, opening the given host files.
+    // the list and punch files are created (or truncated), the reader file
+    // must already exist
+    pub fn from_paths(
+        list: Option<&str>,
+        punch: Option<&str>,
+        reader: Option<&str>,
+    ) -> Result<Self, String> {
+        let create = |path: &str| -> Result<Box<dyn Write + Send>, String> {
+            let file = File::create(path).map_err(|e| format!("Could not create {path}: {e}"))?;
+            Ok(Box::new(file))
+        };
+
+        let list = list.map(create).transpose()?;
+        let punch = punch.map(create).transpose()?;
+        let reader = reader
+            .map(|path| -> Result<Box<dyn Read + Send>, String> {
+                let file = File::open(path).map_err(|e| format!("Could not open {path}: {e}"))?;
+                Ok(Box::new(file))
+            })
+            .transpose()?;
+
+        Ok(Self {
+            console: None,
+            list,
+            punch,
+            reader,
+        })
+    }
+}
+
+// LogicalDevice enum - the CP/M logical devices whose physical assignment is
+// selected by a field of the IOBYTE
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum LogicalDevice {
+    Console,
+    Reader,
+    Punch,
+    List,
+}
+
+impl LogicalDevice {
+    // returns the 2-bit physical device assigned to self by the given IOBYTE
+    pub fn assignment(&self, iobyte: u8) -> u8 {
+        use LogicalDevice::*;
+
+        let shift = match self {
+            Console => 0,
+            Reader => 2,
+            Punch => 4,
+            List => 6,
+        };
+
+        (iobyte >> shift) & 0b11
+    }
+
+    // returns whether the device is assigned to the console (TTY:, or CRT: for
+    // LST:) by the given IOBYTE, as opposed to a host file
+    pub fn is_console(&self, iobyte: u8) -> bool {
+        use LogicalDevice::*;
+
+        match self {
+            Console => true,
+            Reader | Punch => self.assignment(iobyte) == 0,
+            List => self.assignment(iobyte) <= 1,
+        }
+    }
+}
+
+// ConsoleLine enum - what reading a line of console input gave
+#[derive(Debug, Clone, PartialEq)]
+pub enum ConsoleLine {
+    Line(Vec<u8>),
+    Waiting, // the input ran out partway, the line is finished once there is more
+    End,     // the input is exhausted
+}
+
+// Bdos struct - holds the state kept by the BDOS between calls
+pub struct Bdos {
+    pub devices: CpmDevices,
+    pub drive: Option<HostDrive>, // drive A:, no disk functions if None
+    pub dma_addr: u16,
+    pub trace: Option<BdosTrace>, // logs every call if Some
+    console_cr: bool,             // the last line ended with CR, so an LF after it is skipped
+    console_line: Vec<u8>,        // the start of a line read before the input ran out
+    keyboard: Keyboard,           // the host's stdin, when there is no CON: stream
+}
+
+impl Bdos {
+    // creates a new instance of Bdos using the given devices, with no drive
+    pub fn new(devices: CpmDevices) -> Self {
+        Self {
+            devices,
+            drive: None,
+            dma_addr: DEFAULT_DMA,
+            trace: None,
+            console_cr: false,
+            console_line: Vec::new(),
+            keyboard: Keyboard::new(),
+        }
+    }
+
+    // reads a single character from the console, end-of-file reads as ^Z.
+    // None if there is no input yet, as with injected input that has run out
+    pub fn read_console(&mut self) -> Option<u8> {
+        match self.devices.console.as_mut() {
+            Some(console) => read_console_byte(console),
+            None => read_console_byte(&mut self.keyboard),
+        }
+    }
+
+    // returns whether a character can be read from the console without
+    // waiting for one
+    pub fn console_ready(&mut self) -> bool {
+        match self.devices.console.as_mut() {
+            Some(console) => console.key_ready(),
+            None => self.keyboard.key_ready(),
+        }
+    }
+
+    // returns the console status as the BDOS does, 0FFH if a character is
+    // ready and 0 if not
+    fn console_status(&mut self) -> u8 {
+        match self.console_ready() {
+            true => 0xFF,
+            false => 0,
+        }
+    }
+
+    // reads a line of console input, without its end. a line ends with CR
+    // (what a terminal sends for Enter), LF or both
+    pub fn read_console_line(&mut self) -> ConsoleLine {
+        loop {
+            let after_cr = std::mem::replace(&mut self.console_cr, false);
+            match self.read_console() {
+                None => {
+                    self.console_cr = after_cr;
+                    return ConsoleLine::Waiting;
+                }
+                Some(READER_EOF) if self.console_line.is_empty() => return ConsoleLine::End,
+                Some(READER_EOF) => break,
+                Some(b'\n') if after_cr => {}
+                Some(character @ (b'\r' | b'\n')) => {
+                    self.console_cr = character == b'\r';
+                    break;
+                }
+                Some(character) => self.console_line.push(character),
+            }
+        }
+
+        ConsoleLine::Line(std::mem::take(&mut self.console_line))
+    }
+
+    // returns whether console input has to be echoed by the BDOS. the host
+    // terminal already echoes what is typed on stdin, but injected input
+    // would otherwise never show up in the output
+    pub fn echoes_input(&self) -> bool {
+        self.devices.console.is_some()
+    }
+
+    // handles a call to the BDOS entry point: the function number is passed
+    // in C, and the argument (if any) in E or DE. results are returned in A
+    pub fn call(&mut self, cpu: &mut Cpu) -> Result<(), String> {
+        let function = u8::try_from(cpu.reg_array.read_reg(Register::C))?;
+
+        // the arguments have to be decoded before the call can modify them
+        let Some(mut trace) = self.trace.take() else {
+            return self.dispatch(cpu, function);
+        };
+
+        let call = BdosCall::before(cpu, function, self.dma_addr);
+        let result = self.dispatch(cpu, function);
+        let logged = call.and_then(|call| trace.record(call.after(cpu)));
 
-pub fn add_cpm_bdos(cpu: &mut Cpu) {
-    cpu.add_subroutine_handler(0x0005u16, |cpu| {
-        // 0x05 subroutine:
-        //  if C == 9:
-        //      output string starting at (DE) until $ character is found
-        //  if C == 2:
-        //      output single character in E
-
-        match cpu.reg_array.read_reg(Register::C) {
-            // C == 9: output string starting at (DE) until $ character is found
-            RegisterValue::Integer8(9) => {
+        self.trace = Some(trace);
+        result.and(logged)
+    }
+
+    // performs the given BDOS function
+    fn dispatch(&mut self, cpu: &mut Cpu, function: u8) -> Result<(), String> {
+        let e_val = cpu.reg_array.read_reg(Register::E);
+        let de_val = u16::from(cpu.reg_array.read_reg(Register::DE));
+
+        match function {
+            // 0: system reset, ends the program
+            0 => {
+                cpu.running = false;
+            }
+
+            // 1: read a character from the console into A
+            1 => {
+                let Some(character) = self.read_console() else {
+                    return cpu.wait_for_input();
+                };
+
+                if self.echoes_input() {
+                    console_out(cpu, character)?;
+                }
+
+                return_value(cpu, character)?;
+            }
+
+            // 2: output single character in E
+            2 => {
+                cpu.write_to_port(RegisterValue::from(0u8), e_val)?;
+            }
+
+            // 3: read a single character from RDR: into A
+            3 => {
+                let iobyte = read_iobyte(cpu)?;
+
+                let character = if LogicalDevice::Reader.is_console(iobyte) {
+                    let Some(character) = self.read_console() else {
+                        return cpu.wait_for_input();
+                    };
+                    character
+                } else {
+                    self.devices.reader.as_mut().map_or(READER_EOF, read_byte)
+                };
+
+                return_value(cpu, character)?;
+            }
+
+            // 4: output single character in E to PUN:
+            4 => {
+                let iobyte = read_iobyte(cpu)?;
+
+                if LogicalDevice::Punch.is_console(iobyte) {
+                    cpu.write_to_port(RegisterValue::from(0u8), e_val)?;
+                } else if let Some(punch) = self.devices.punch.as_mut() {
+                    write_byte(punch, e_val)?;
+                }
+            }
+
+            // 5: output single character in E to LST:
+            5 => {
+                let iobyte = read_iobyte(cpu)?;
+
+                if LogicalDevice::List.is_console(iobyte) {
+                    cpu.write_to_port(RegisterValue::from(0u8), e_val)?;
+                } else if let Some(list) = self.devices.list.as_mut() {
+                    write_byte(list, e_val)?;
+                }
+            }
+
+            // 6: direct console I/O, E == 0xFF reads a character (0 if none
+            // has been typed), E == 0xFE returns the console status,
+            // otherwise E is output
+            6 => match u8::try_from(e_val)? {
+                0xFF => {
+                    let character = match self.console_ready() {
+                        true => self.read_console().unwrap_or(0),
+                        false => 0,
+                    };
+                    return_value(cpu, character)?;
+                }
+
+                0xFE => {
+                    let status = self.console_status();
+                    return_value(cpu, status)?;
+                }
+
+                _ => cpu.write_to_port(RegisterValue::from(0u8), e_val)?,
+            },
+
+            // 7: return the IOBYTE in A
+            7 => {
+                let iobyte = read_iobyte(cpu)?;
+                return_value(cpu, iobyte)?;
+            }
+
+            // 8: set the IOBYTE to E
+            8 => {
+                cpu.memory.write(RegisterValue::from(IOBYTE_ADDR), e_val)?;
+            }
+
+            // 9: output string starting at (DE) until $ character is found
+            9 => {
                 // store mutable copy of DE, which will be used as the pointer
                 let mut str_pointer = cpu.reg_array.read_reg(Register::DE);
 
                 // until a $ character is found, output the string
                 loop {
                     // get the current character
-                    let current_char = cpu.memory.read(str_pointer, MemorySize::Integer8).unwrap();
+                    let current_char = cpu.memory.read(str_pointer, MemorySize::Integer8)?;
 
                     // break condition: character is '$'
                     if current_char == RegisterValue::from(b'$') {
@@ -31,20 +352,509 @@ pub fn add_cpm_bdos(cpu: &mut Cpu) {
                     }
 
                     // write to port, increase pointer
-                    cpu.write_to_port(RegisterValue::from(0u8), current_char)
-                        .unwrap();
-                    str_pointer = str_pointer.try_add(RegisterValue::from(1u16)).unwrap();
+                    cpu.write_to_port(RegisterValue::from(0u8), current_char)?;
+                    str_pointer = str_pointer.try_add(RegisterValue::from(1u16))?;
+                }
+            }
+
+            // 10: read a line from the console into the buffer at (DE). the
+            // first byte holds the buffer size, the second the line length
+            10 => {
+                let max_len = peek(&cpu.memory, de_val)?;
+                let line = match self.read_console_line() {
+                    ConsoleLine::Line(line) => line,
+                    ConsoleLine::Waiting => return cpu.wait_for_input(),
+                    ConsoleLine::End => Vec::new(),
+                };
+                let len = line.len().min(max_len as usize) as u8;
+
+                for (i, character) in line.iter().take(len as usize).enumerate() {
+                    poke(
+                        &mut cpu.memory,
+                        de_val.wrapping_add(2 + i as u16),
+                        *character,
+                    )?;
                 }
+
+                if self.echoes_input() {
+                    for i in 0..len as u16 {
+                        console_out(cpu, peek(&cpu.memory, de_val.wrapping_add(2 + i))?)?;
+                    }
+
+                    console_out(cpu, b'\r')?;
+                }
+
+                poke(&mut cpu.memory, de_val.wrapping_add(1), len)?;
+            }
+
+            // 11: console status, 0FFH if a character is ready
+            11 => {
+                let status = self.console_status();
+                return_value(cpu, status)?;
             }
 
-            // C == 2: output single character in E
-            RegisterValue::Integer8(2) => {
-                let e_val = cpu.reg_array.read_reg(Register::E);
-                cpu.write_to_port(RegisterValue::from(0u8), e_val).unwrap();
+            // 12: return the version number, CP/M 2.2
+            12 => return_value16(cpu, 0x0022)?,
+
+            // 13: reset the disk system
+            13 => {
+                self.dma_addr = DEFAULT_DMA;
+                return_value(cpu, 0)?;
+            }
+
+            // 14: select disk, only A: exists
+            14 => return_value(cpu, 0)?,
+
+            // 15-23: FCB-based file functions
+            15..=23 | 33..=36 => {
+                let result = match self.drive.as_mut() {
+                    Some(drive) => {
+                        let (memory, dma) = (&mut cpu.memory, self.dma_addr);
+
+                        match function {
+                            15 => drive.open(memory, de_val)?,
+                            16 => drive.close(memory, de_val)?,
+                            17 => drive.search_first(memory, de_val, dma)?,
+                            18 => drive.search_next(memory, dma)?,
+                            19 => drive.delete(memory, de_val)?,
+                            20 => drive.read_sequential(memory, de_val, dma)?,
+                            21 => drive.write_sequential(memory, de_val, dma)?,
+                            22 => drive.make(memory, de_val)?,
+                            23 => drive.rename(memory, de_val)?,
+                            33 => drive.read_random(memory, de_val, dma)?,
+                            34 => drive.write_random(memory, de_val, dma)?,
+                            35 => drive.file_size(memory, de_val)?,
+                            _ => drive.set_random_record(memory, de_val)?,
+                        }
+                    }
+
+                    None => 0xFF,
+                };
+
+                return_value(cpu, result)?;
+            }
+
+            // 24: return the login vector, only A: is logged in
+            24 => return_value16(cpu, 0x0001)?,
+
+            // 25: return the current disk, always A:
+            25 => return_value(cpu, 0)?,
+
+            // 26: set the DMA address to DE
+            26 => {
+                self.dma_addr = de_val;
+            }
+
+            // 32: get (E == 0xFF) or set the user number
+            32 => {
+                let user = self.drive.as_ref().map_or(0, |drive| drive.user);
+
+                match u8::try_from(e_val)? {
+                    0xFF => return_value(cpu, user)?,
+                    e => {
+                        if let Some(drive) = self.drive.as_mut() {
+                            drive.user = e & 0x0F;
+                        }
+                    }
+                }
             }
 
             // otherwise, do nothing
             _ => {}
         }
-    });
+
+        Ok(())
+    }
+}
+
+// installs the BDOS on the given Cpu, returns the BDOS state so that it can
+// be shared with the CCP
+pub fn add_cpm_bdos(cpu: &mut Cpu, bdos: Bdos) -> Arc<Mutex<Bdos>> {
+    let bdos = Arc::new(Mutex::new(bdos));
+    let bdos_thr = Arc::clone(&bdos);
+
+    // the IOBYTE lives in the zero page, initialize it to the default mapping
+    poke(&mut cpu.memory, IOBYTE_ADDR, DEFAULT_IOBYTE).unwrap();
+
+    // programs end by jumping to 0, which warm boots CP/M
+    cpu.warm_boot_stops = true;
+
+    cpu.add_subroutine_handler(BDOS_ENTRY, move |cpu| bdos_thr.lock().unwrap().call(cpu));
+
+    bdos
+}
+
+// loads a transient program (.COM file) into the TPA and sets up the zero
+// page and stack the way the CCP would before running it. the command tail
+// holds the arguments to the program
+pub fn load_transient(cpu: &mut Cpu, program: Vec<u8>, command_tail: &str) -> Result<(), String> {
+    if program.len() > (BDOS_BASE - TPA_START) as usize {
+        return Err(format!(
+            "Program of {} bytes does not fit in the TPA",
+            program.len()
+        ));
+    }
+
+    // a jump to 0 (warm boot) stops the Cpu, and the BDOS entry point jumps
+    // to the top of the TPA, where programs look to find the memory size
+    cpu.load_to_memory(vec![0xC3, 0x00, 0x00], 0x0000)?;
+    cpu.load_to_memory(vec![0xC3, 0x00, 0x00], BDOS_ENTRY)?;
+    cpu.memory.write(
+        RegisterValue::from(BDOS_ENTRY + 1),
+        RegisterValue::from(BDOS_BASE),
+    )?;
+
+    // the default FCBs are filled in from the first two arguments
+    let mut args = command_tail.split_whitespace();
+    for addr in DEFAULT_FCB..DEFAULT_DMA {
+        poke(&mut cpu.memory, addr, 0)?;
+    }
+    write_default_fcb(&mut cpu.memory, DEFAULT_FCB, args.next())?;
+    write_default_fcb(&mut cpu.memory, DEFAULT_FCB2, args.next())?;
+
+    // the command tail is stored at the default DMA address, prefixed by its
+    // length and followed by a 0
+    let command_tail = command_tail.trim().to_ascii_uppercase();
+    let command_tail = if command_tail.is_empty() {
+        command_tail
+    } else {
+        format!(" {command_tail}")
+    };
+    let command_tail = &command_tail.as_bytes()[..command_tail.len().min(126)];
+
+    poke(&mut cpu.memory, DEFAULT_DMA, command_tail.len() as u8)?;
+    cpu.load_to_memory(command_tail.to_vec(), DEFAULT_DMA + 1)?;
+    poke(
+        &mut cpu.memory,
+        DEFAULT_DMA + 1 + command_tail.len() as u16,
+        0,
+    )?;
+
+    // load the program, and start it with a return address of 0 on the stack
+    cpu.load_to_memory(program, TPA_START)?;
+    cpu.reg_array
+        .write_reg(Register::SP, RegisterValue::from(BDOS_BASE))?;
+    cpu.push_to_stack(RegisterValue::from(0u16))?;
+    cpu.set_pc(TPA_START)?;
+    cpu.running = true;
+
+    Ok(())
+}
+
+// fills in a default FCB from a command-line argument
+fn write_default_fcb(memory: &mut Memory, fcb: u16, arg: Option<&str>) -> Result<(), String> {
+    let blank = CpmFileName {
+        name: [b' '; 8],
+        ext: [b' '; 3],
+    };
+
+    let arg = arg.unwrap_or("");
+    let file_name = CpmFileName::parse(arg).unwrap_or(blank);
+
+    // the drive byte is 0 for the default drive, or 1 for A:, 2 for B:, ...
+    let drive = match arg.as_bytes() {
+        [drive, b':', ..] if drive.is_ascii_alphabetic() => drive.to_ascii_uppercase() - b'A' + 1,
+        _ => 0,
+    };
+
+    poke(memory, fcb, drive)?;
+    file_name.write_fcb(memory, fcb)
+}
+
+// reads a single byte from memory
+fn peek(memory: &Memory, addr: u16) -> Result<u8, String> {
+    let value = memory.read(RegisterValue::from(addr), MemorySize::Integer8)?;
+
+    Ok(u8::try_from(value)?)
+}
+
+// writes a single byte to memory
+fn poke(memory: &mut Memory, addr: u16, value: u8) -> Result<(), String> {
+    memory.write(RegisterValue::from(addr), RegisterValue::from(value))
+}
+
+// outputs a single character to the console (port 0)
+fn console_out(cpu: &mut Cpu, value: u8) -> Result<(), String> {
+    cpu.write_to_port(RegisterValue::from(0u8), RegisterValue::from(value))
+}
+
+// reads the current value of the IOBYTE from memory
+fn read_iobyte(cpu: &Cpu) -> Result<u8, String> {
+    peek(&cpu.memory, IOBYTE_ADDR)
+}
+
+// returns a BDOS result to the caller, which is passed back in both A and L
+fn return_value(cpu: &mut Cpu, value: u8) -> Result<(), String> {
+    cpu.alu.write_accumulator(RegisterValue::from(value))?;
+    cpu.reg_array
+        .write_reg(Register::L, RegisterValue::from(value))
+}
+
+// returns a 16-bit BDOS result to the caller in HL, with A = L and B = H
+fn return_value16(cpu: &mut Cpu, value: u16) -> Result<(), String> {
+    let (higher, lower) = ((value >> 8) as u8, (value & 0xFF) as u8);
+
+    return_value(cpu, lower)?;
+    cpu.reg_array
+        .write_reg(Register::H, RegisterValue::from(higher))?;
+    cpu.reg_array
+        .write_reg(Register::B, RegisterValue::from(higher))
+}
+
+// reads a single byte from a host stream, end-of-file (or an error) reads as ^Z
+fn read_byte(stream: &mut impl Read) -> u8 {
+    let mut buf = [0u8];
+
+    match stream.read(&mut buf) {
+        Ok(1) => buf[0],
+        _ => READER_EOF,
+    }
+}
+
+// reads a single byte of console input like read_byte, but None if the
+// stream would block until there is more
+fn read_console_byte(stream: &mut impl Read) -> Option<u8> {
+    let mut buf = [0u8];
+
+    match stream.read(&mut buf) {
+        Ok(1) => Some(buf[0]),
+        Err(e) if e.kind() == ErrorKind::WouldBlock => None,
+        _ => Some(READER_EOF),
+    }
+}
+
+// writes a single character to a host stream
+fn write_byte(stream: &mut impl Write, value: RegisterValue) -> Result<(), String> {
+    let value = u8::try_from(value)?;
+
+    stream
+        .write_all(&[value])
+        .map_err(|e| format!("Could not write to device: {e}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Write implementation over a shared buffer, so a test can inspect what
+    // was written after handing the writer over to the BDOS
+    #[derive(Clone, Default)]
+    pub(super) struct SharedBuf(pub(super) Arc<Mutex<Vec<u8>>>);
+
+    impl Write for SharedBuf {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    // runs a program loaded at 0x100 until it halts
+    fn run_program(cpu: &mut Cpu, program: Vec<u8>) {
+        cpu.load_to_memory(program, 0x100).unwrap();
+        cpu.set_pc(0x100).unwrap();
+
+        while cpu.is_running() {
+            cpu.execute_next().unwrap();
+        }
+    }
+
+    #[test]
+    fn cpm_list_and_punch_output() {
+        let (list, punch) = (SharedBuf::default(), SharedBuf::default());
+
+        let mut devices = CpmDevices::new();
+        devices.list = Some(Box::new(list.clone()));
+        devices.punch = Some(Box::new(punch.clone()));
+
+        let mut cpu = Cpu::new();
+        add_cpm_bdos(&mut cpu, Bdos::new(devices));
+
+        // MVI C,5; MVI E,'L'; CALL 5; MVI C,4; MVI E,'P'; CALL 5; HLT
+        run_program(
+            &mut cpu,
+            vec![
+                0x0E, 0x05, 0x1E, b'L', 0xCD, 0x05, 0x00, 0x0E, 0x04, 0x1E, b'P', 0xCD, 0x05, 0x00,
+                0x76,
+            ],
+        );
+
+        assert_eq!(*list.0.lock().unwrap(), b"L");
+        assert_eq!(*punch.0.lock().unwrap(), b"P");
+    }
+
+    #[test]
+    fn cpm_reader_input() {
+        let mut devices = CpmDevices::new();
+        devices.reader = Some(Box::new(&b"R"[..]));
+
+        let mut cpu = Cpu::new();
+        add_cpm_bdos(&mut cpu, Bdos::new(devices));
+
+        // MVI C,3; CALL 5; MOV B,A; MVI C,3; CALL 5; HLT
+        run_program(
+            &mut cpu,
+            vec![
+                0x0E, 0x03, 0xCD, 0x05, 0x00, 0x47, 0x0E, 0x03, 0xCD, 0x05, 0x00, 0x76,
+            ],
+        );
+
+        // the second read is past the end of the file
+        assert_eq!(
+            cpu.reg_array.read_reg(Register::B),
+            RegisterValue::from(b'R')
+        );
+        assert_eq!(cpu.alu.accumulator(), RegisterValue::from(READER_EOF));
+    }
+
+    #[test]
+    fn cpm_iobyte_assignment() {
+        let list = SharedBuf::default();
+        let console = Arc::new(Mutex::new(Vec::new()));
+        let console_thr = console.clone();
+
+        let mut devices = CpmDevices::new();
+        devices.list = Some(Box::new(list.clone()));
+
+        let mut cpu = Cpu::new();
+        add_cpm_bdos(&mut cpu, Bdos::new(devices));
+        cpu.set_port_handler_fn(move |_, value| {
+            console_thr
+                .lock()
+                .unwrap()
+                .push(u8::try_from(value).unwrap());
+        });
+
+        // MVI C,7; CALL 5; MOV B,A; MVI C,8; MVI E,0; CALL 5;
+        // MVI C,5; MVI E,'X'; CALL 5; HLT
+        run_program(
+            &mut cpu,
+            vec![
+                0x0E, 0x07, 0xCD, 0x05, 0x00, 0x47, 0x0E, 0x08, 0x1E, 0x00, 0xCD, 0x05, 0x00, 0x0E,
+                0x05, 0x1E, b'X', 0xCD, 0x05, 0x00, 0x76,
+            ],
+        );
+
+        // with LST: assigned to TTY:, list output goes to the console instead
+        assert_eq!(
+            cpu.reg_array.read_reg(Register::B),
+            RegisterValue::from(DEFAULT_IOBYTE)
+        );
+        assert!(list.0.lock().unwrap().is_empty());
+        assert_eq!(*console.lock().unwrap(), b"X");
+    }
+
+    #[test]
+    fn cpm_read_console_buffer_at_top_of_memory() {
+        let mut devices = CpmDevices::new();
+        devices.console = Some(Box::new(&b"HELLO\r"[..]));
+
+        let mut cpu = Cpu::new();
+        add_cpm_bdos(&mut cpu, Bdos::new(devices));
+        cpu.set_port_handler_fn(|_, _| {});
+        poke(&mut cpu.memory, 0xFFFD, 8).unwrap();
+
+        // MVI C,10; LXI D,0FFFDH; CALL 5; HLT. the line wraps around to the
+        // start of memory
+        run_program(
+            &mut cpu,
+            vec![0x0E, 0x0A, 0x11, 0xFD, 0xFF, 0xCD, 0x05, 0x00, 0x76],
+        );
+
+        assert_eq!(peek(&cpu.memory, 0xFFFE).unwrap(), 5);
+        assert_eq!(peek(&cpu.memory, 0xFFFF).unwrap(), b'H');
+        assert_eq!(peek(&cpu.memory, 0x0003).unwrap(), b'O');
+    }
+
+    #[test]
+    fn cpm_console_waits_for_input() {
+        let input = crate::remote::ConsoleInput::default();
+        let mut devices = CpmDevices::new();
+        devices.console = Some(Box::new(input.clone()));
+
+        let mut cpu = Cpu::new();
+        add_cpm_bdos(&mut cpu, Bdos::new(devices));
+        cpu.set_port_handler_fn(|_, _| {});
+        poke(&mut cpu.memory, 0x0200, 8).unwrap();
+
+        // MVI C,10; LXI D,0200H; CALL 5; MVI C,1; CALL 5; HLT
+        cpu.load_to_memory(
+            vec![
+                0x0E, 0x0A, 0x11, 0x00, 0x02, 0xCD, 0x05, 0x00, 0x0E, 0x01, 0xCD, 0x05, 0x00, 0x76,
+            ],
+            0x100,
+        )
+        .unwrap();
+        cpu.set_pc(0x100).unwrap();
+
+        // the program pauses at the call until the whole line is there, and
+        // again until there is a character for function 1
+        let budget = Budget::Instructions(100);
+        let pc = |cpu: &Cpu| u16::from(cpu.reg_array.read_reg(Register::PC));
+        assert_eq!(
+            cpu.run_until(budget, |_, _| false),
+            Ok(StopReason::WaitingForInput)
+        );
+        assert_eq!(pc(&cpu), 0x0105);
+
+        input.push(b"HI");
+        assert_eq!(
+            cpu.run_until(budget, |_, _| false),
+            Ok(StopReason::WaitingForInput)
+        );
+        assert_eq!(pc(&cpu), 0x0105);
+
+        input.push(b"!\r");
+        assert_eq!(
+            cpu.run_until(budget, |_, _| false),
+            Ok(StopReason::WaitingForInput)
+        );
+        assert_eq!(pc(&cpu), 0x010A);
+
+        input.push(b"X");
+        assert_eq!(cpu.run_until(budget, |_, _| false), Ok(StopReason::Halted));
+        assert_eq!(peek(&cpu.memory, 0x0201).unwrap(), 3);
+        assert_eq!(
+            (0x0202..0x0205)
+                .map(|addr| peek(&cpu.memory, addr).unwrap())
+                .collect::<Vec<_>>(),
+            b"HI!"
+        );
+        assert_eq!(cpu.alu.accumulator(), RegisterValue::from(b'X'));
+    }
+
+    #[test]
+    fn cpm_console_status() {
+        let input = crate::remote::ConsoleInput::default();
+        let mut devices = CpmDevices::new();
+        devices.console = Some(Box::new(input.clone()));
+
+        let mut cpu = Cpu::new();
+        add_cpm_bdos(&mut cpu, Bdos::new(devices));
+        cpu.set_port_handler_fn(|_, _| {});
+
+        // MVI C,11; CALL 5; MOV B,A; MVI C,6; MVI E,0FFH; CALL 5; MOV D,A; HLT
+        let program = vec![
+            0x0E, 0x0B, 0xCD, 0x05, 0x00, 0x47, 0x0E, 0x06, 0x1E, 0xFF, 0xCD, 0x05, 0x00, 0x57,
+            0x76,
+        ];
+        let results = |cpu: &Cpu| {
+            (
+                u8::try_from(cpu.reg_array.read_reg(Register::B)).unwrap(),
+                u8::try_from(cpu.reg_array.read_reg(Register::D)).unwrap(),
+            )
+        };
+
+        // with nothing typed, neither call waits for input
+        run_program(&mut cpu, program.clone());
+        assert!(!cpu.is_waiting_for_input());
+        assert_eq!(results(&cpu), (0, 0));
+
+        input.push(b"X");
+        cpu.running = true;
+        run_program(&mut cpu, program);
+        assert_eq!(results(&cpu), (0xFF, b'X'));
+    }
 }
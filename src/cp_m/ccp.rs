@@ -0,0 +1,325 @@
+/*
+ * ccp.rs - Contains code related to the console command processor (CCP),
+ * the CP/M command prompt. the CCP runs on the host rather than as 8080 code,
+ * and loads transient programs from the host drive into the Cpu
+ * See the CP/M 2.2 manual, section 1.6: http://www.gaby.de/cpm/manuals/archive/cpm22htm/ch1.htm
+ */
+use super::drive::*;
+use super::*;
+
+use std::fs;
+
+// runs the CCP until the console input is exhausted, returning to the prompt
+// whenever a transient program warm boots
+pub fn run_ccp(cpu: &mut Cpu, bdos: &Arc<Mutex<Bdos>>) -> Result<(), String> {
+    loop {
+        let user = bdos.lock().unwrap().drive.as_ref().map_or(0, |d| d.user);
+        poke(&mut cpu.memory, DRIVE_USER_ADDR, user << 4)?;
+
+        print(cpu, "\r\nA>")?;
+
+        let Some(line) = read_line(cpu, bdos)? else {
+            print(cpu, "\r\n")?;
+            return Ok(());
+        };
+
+        let line = line.trim().to_ascii_uppercase();
+        let (command, args) = line.split_once(' ').unwrap_or((&line, ""));
+        let args = args.trim();
+
+        match command {
+            "" => {}
+            "A:" => {}
+            "DIR" => dir(cpu, bdos, args)?,
+            "ERA" => era(cpu, bdos, args)?,
+            "REN" => ren(cpu, bdos, args)?,
+            "TYPE" => type_file(cpu, bdos, args)?,
+            "SAVE" => save(cpu, bdos, args)?,
+            "USER" => user_command(cpu, bdos, args)?,
+            _ => transient(cpu, bdos, command, args)?,
+        }
+    }
+}
+
+// reads a line of console input, returns None once the input is exhausted
+fn read_line(cpu: &mut Cpu, bdos: &Arc<Mutex<Bdos>>) -> Result<Option<String>, String> {
+    let mut bdos = bdos.lock().unwrap();
+    // the CCP reads the host's console, which never waits for input
+    let ConsoleLine::Line(line) = bdos.read_console_line() else {
+        return Ok(None);
+    };
+    let line: String = line.iter().map(|c| *c as char).collect();
+
+    if bdos.echoes_input() {
+        print(cpu, &line)?;
+    }
+
+    Ok(Some(line))
+}
+
+// prints a string to the console
+fn print(cpu: &mut Cpu, s: &str) -> Result<(), String> {
+    s.bytes().try_for_each(|b| console_out(cpu, b))
+}
+
+// parses a file name argument, printing an error for a bad one
+fn parse_file_name(cpu: &mut Cpu, arg: &str) -> Result<Option<CpmFileName>, String> {
+    let file_name = CpmFileName::parse(arg);
+
+    if file_name.is_none() {
+        print(cpu, &format!("\r\n{arg}?"))?;
+    }
+
+    Ok(file_name)
+}
+
+// DIR [afn]: lists the files matching the pattern, four to a line
+fn dir(cpu: &mut Cpu, bdos: &Arc<Mutex<Bdos>>, args: &str) -> Result<(), String> {
+    let pattern = if args.is_empty() { "*.*" } else { args };
+    let Some(pattern) = parse_file_name(cpu, pattern)? else {
+        return Ok(());
+    };
+
+    let files = match bdos.lock().unwrap().drive.as_ref() {
+        Some(drive) => drive.list(&pattern),
+        None => Vec::new(),
+    };
+
+    if files.is_empty() {
+        return print(cpu, "\r\nNO FILE");
+    }
+
+    for (i, file_name) in files.iter().enumerate() {
+        let name = String::from_utf8_lossy(&file_name.name).into_owned();
+        let ext = String::from_utf8_lossy(&file_name.ext).into_owned();
+
+        let separator = if i % 4 == 0 { "\r\nA" } else { " " };
+        print(cpu, &format!("{separator}: {name} {ext}"))?;
+    }
+
+    Ok(())
+}
+
+// ERA afn: deletes the files matching the pattern, asking first if it is *.*
+fn era(cpu: &mut Cpu, bdos: &Arc<Mutex<Bdos>>, args: &str) -> Result<(), String> {
+    let Some(pattern) = parse_file_name(cpu, args)? else {
+        return Ok(());
+    };
+
+    if pattern.name == [b'?'; 8] && pattern.ext == [b'?'; 3] {
+        print(cpu, "\r\nALL (Y/N)?")?;
+
+        let answer = read_line(cpu, bdos)?.unwrap_or_default();
+        if !answer.trim().eq_ignore_ascii_case("Y") {
+            return Ok(());
+        }
+    }
+
+    // like the BDOS, a file that couldn't be deleted is reported as NO FILE
+    let result = match bdos.lock().unwrap().drive.as_ref() {
+        Some(drive) => drive.delete_files(&pattern),
+        None => BDOS_NO_FILE,
+    };
+
+    if result == BDOS_NO_FILE {
+        return print(cpu, "\r\nNO FILE");
+    }
+
+    Ok(())
+}
+
+// REN new=old: renames a file
+fn ren(cpu: &mut Cpu, bdos: &Arc<Mutex<Bdos>>, args: &str) -> Result<(), String> {
+    let Some((new_name, old_name)) = args.split_once('=') else {
+        return print(cpu, &format!("\r\n{args}?"));
+    };
+
+    let Some(new_name) = parse_file_name(cpu, new_name.trim())? else {
+        return Ok(());
+    };
+    let Some(old_name) = parse_file_name(cpu, old_name.trim())? else {
+        return Ok(());
+    };
+
+    let bdos = bdos.lock().unwrap();
+    let Some(drive) = bdos.drive.as_ref() else {
+        return print(cpu, "\r\nNO FILE");
+    };
+
+    if drive.find(&new_name).is_some() {
+        return print(cpu, "\r\nFILE EXISTS");
+    }
+
+    if drive.rename_file(&old_name, &new_name) == BDOS_NO_FILE {
+        return print(cpu, "\r\nNO FILE");
+    }
+
+    Ok(())
+}
+
+// TYPE ufn: prints a text file up to the first ^Z
+fn type_file(cpu: &mut Cpu, bdos: &Arc<Mutex<Bdos>>, args: &str) -> Result<(), String> {
+    let Some(file_name) = parse_file_name(cpu, args)? else {
+        return Ok(());
+    };
+
+    let path = bdos
+        .lock()
+        .unwrap()
+        .drive
+        .as_ref()
+        .and_then(|d| d.find(&file_name));
+
+    let Some(path) = path else {
+        return print(cpu, "\r\nNO FILE");
+    };
+
+    let contents = fs::read(path).map_err(|e| format!("Could not read {file_name}: {e}"))?;
+
+    print(cpu, "\r\n")?;
+    contents
+        .iter()
+        .take_while(|b| **b != READER_EOF)
+        .try_for_each(|b| console_out(cpu, *b))
+}
+
+// SAVE n ufn: writes n pages (256 bytes each) of the TPA to a file
+fn save(cpu: &mut Cpu, bdos: &Arc<Mutex<Bdos>>, args: &str) -> Result<(), String> {
+    let (pages, file_name) = args.split_once(' ').unwrap_or((args, ""));
+
+    let Ok(pages) = pages.parse::<u16>() else {
+        return print(cpu, &format!("\r\n{pages}?"));
+    };
+    let Some(file_name) = parse_file_name(cpu, file_name.trim())? else {
+        return Ok(());
+    };
+
+    let len = pages as usize * 256;
+    if len > (BDOS_BASE - TPA_START) as usize {
+        return print(cpu, "\r\nNO SPACE");
+    }
+
+    let data = (0..len as u16)
+        .map(|i| peek(&cpu.memory, TPA_START + i))
+        .collect::<Result<Vec<u8>, String>>()?;
+
+    let bdos = bdos.lock().unwrap();
+    let Some(drive) = bdos.drive.as_ref() else {
+        return print(cpu, "\r\nNO SPACE");
+    };
+
+    let path = drive.create(&file_name)?;
+    fs::write(path, data).map_err(|e| format!("Could not write {file_name}: {e}"))
+}
+
+// USER n: selects user area n (0-15)
+fn user_command(cpu: &mut Cpu, bdos: &Arc<Mutex<Bdos>>, args: &str) -> Result<(), String> {
+    match args.parse::<u8>() {
+        Ok(user) if user <= 15 => {
+            if let Some(drive) = bdos.lock().unwrap().drive.as_mut() {
+                drive.user = user;
+            }
+
+            Ok(())
+        }
+
+        _ => print(cpu, &format!("\r\n{args}?")),
+    }
+}
+
+// loads a .COM file from the drive and runs it until it warm boots
+fn transient(
+    cpu: &mut Cpu,
+    bdos: &Arc<Mutex<Bdos>>,
+    command: &str,
+    args: &str,
+) -> Result<(), String> {
+    let file_name = CpmFileName::parse(command)
+        .filter(|f| !f.is_ambiguous() && (f.ext == *b"   " || f.ext == *b"COM"))
+        .map(|mut f| {
+            f.ext = *b"COM";
+            f
+        });
+
+    let path = {
+        let bdos = bdos.lock().unwrap();
+        file_name.and_then(|f| bdos.drive.as_ref().and_then(|d| d.find(&f)))
+    };
+
+    let Some(path) = path else {
+        return print(cpu, &format!("\r\n{command}?"));
+    };
+
+    let program = fs::read(path).map_err(|e| format!("Could not read {command}: {e}"))?;
+
+    bdos.lock().unwrap().dma_addr = DEFAULT_DMA;
+    if let Err(e) = load_transient(cpu, program, args) {
+        return print(cpu, &format!("\r\n{e}"));
+    }
+
+    print(cpu, "\r\n")?;
+
+    // an error ends the program like a warm boot would, after reporting it
+    while cpu.is_running() {
+        if let Err(e) = cpu.execute_next() {
+            print(cpu, &format!("\r\n{e}"))?;
+            cpu.running = false;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ccp_builtins_and_transient() {
+        let root = std::env::temp_dir().join(format!("i8080-ccp-{}", std::process::id()));
+        fs::create_dir_all(&root).unwrap();
+
+        // HELLO.COM: MVI C,9; LXI D,msg; CALL 5; RET; msg: "HI$"
+        fs::write(
+            root.join("hello.com"),
+            [
+                0x0E, 0x09, 0x11, 0x09, 0x01, 0xCD, 0x05, 0x00, 0xC9, b'H', b'I', b'$',
+            ],
+        )
+        .unwrap();
+
+        let mut devices = CpmDevices::new();
+        devices.console = Some(Box::new(
+            &b"hello\rren greet.com=hello.com\r\ndir\ngreet\rera *.*\r\ny\rdir\r"[..],
+        ));
+
+        let mut bdos = Bdos::new(devices);
+        bdos.drive = Some(HostDrive::new(&root));
+
+        let output = Arc::new(Mutex::new(String::new()));
+        let output_thr = output.clone();
+
+        let mut cpu = Cpu::new();
+        let bdos = add_cpm_bdos(&mut cpu, bdos);
+        cpu.set_port_handler_fn(move |_, value| {
+            let value = u8::try_from(value).unwrap();
+            output_thr.lock().unwrap().push(value as char);
+        });
+
+        run_ccp(&mut cpu, &bdos).unwrap();
+
+        let output = output.lock().unwrap().replace('\r', "");
+        assert_eq!(
+            output,
+            "\nA>hello\nHI\
+             \nA>ren greet.com=hello.com\
+             \nA>dir\nA: GREET    COM\
+             \nA>greet\nHI\
+             \nA>era *.*\nALL (Y/N)?y\
+             \nA>dir\nNO FILE\
+             \nA>\n"
+        );
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
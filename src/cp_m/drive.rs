@@ -0,0 +1,731 @@
+/*
+ * drive.rs - Contains code related to backing a CP/M disk drive with a
+ * directory on the host, and the FCB-based BDOS file functions on top of it
+ * See the CP/M 2.2 manual, section 5: http://www.gaby.de/cpm/manuals/archive/cpm22htm/ch5.htm
+ */
+use super::{peek, poke};
+use crate::cpu::memory::*;
+
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::PathBuf;
+
+// size of a CP/M record in bytes
+pub const RECORD_SIZE: usize = 128;
+
+// number of records in a single extent
+const RECORDS_PER_EXTENT: usize = 128;
+
+// number of extents addressed by EX before S2 is incremented
+const EXTENTS_PER_MODULE: usize = 32;
+
+// byte offsets of the fields in a file control block
+const FCB_NAME: u16 = 1;
+const FCB_EX: u16 = 12;
+const FCB_S2: u16 = 14;
+const FCB_RC: u16 = 15;
+const FCB_RENAME: u16 = 16;
+const FCB_CR: u16 = 32;
+const FCB_R0: u16 = 33;
+
+// BDOS return codes
+const BDOS_OK: u8 = 0x00;
+const BDOS_EOF: u8 = 0x01;
+pub const BDOS_NO_FILE: u8 = 0xFF;
+
+// CpmFileName struct - an 8.3 file name as stored in an FCB, upper case and
+// padded with spaces. a '?' in a pattern matches any character
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CpmFileName {
+    pub name: [u8; 8],
+    pub ext: [u8; 3],
+}
+
+impl CpmFileName {
+    // parses a file name as typed at the command line (e.g. "b:foo.asm",
+    // "*.COM"), ignoring any drive prefix
+    pub fn parse(s: &str) -> Option<Self> {
+        let s = match s.split_once(':') {
+            Some((drive, rest)) if drive.len() == 1 => rest,
+            Some(_) => return None,
+            None => s,
+        };
+
+        let (name, ext) = s.split_once('.').unwrap_or((s, ""));
+        if name.is_empty() || ext.contains('.') {
+            return None;
+        }
+
+        Some(Self {
+            name: Self::parse_field(name)?,
+            ext: Self::parse_field(ext)?,
+        })
+    }
+
+    // turns part of a typed file name into a space padded field, '*' fills
+    // the remainder of the field with '?'
+    fn parse_field<const N: usize>(s: &str) -> Option<[u8; N]> {
+        let mut field = [b' '; N];
+
+        for (i, c) in s.bytes().enumerate() {
+            if c == b'*' {
+                field[i..].fill(b'?');
+                break;
+            }
+
+            if i >= N || !c.is_ascii_graphic() || b"<>.,;:=[]".contains(&c) {
+                return None;
+            }
+
+            field[i] = c.to_ascii_uppercase();
+        }
+
+        Some(field)
+    }
+
+    // reads the file name out of the FCB at the given address, attribute bits
+    // (the high bit of each character) are ignored
+    pub fn from_fcb(memory: &Memory, fcb: u16) -> Result<Self, String> {
+        let mut name = [0u8; 8];
+        let mut ext = [0u8; 3];
+
+        for (i, c) in name.iter_mut().chain(ext.iter_mut()).enumerate() {
+            *c = (peek(memory, fcb.wrapping_add(FCB_NAME + i as u16))? & 0x7F).to_ascii_uppercase();
+        }
+
+        Ok(Self { name, ext })
+    }
+
+    // writes the file name into the FCB at the given address
+    pub fn write_fcb(&self, memory: &mut Memory, fcb: u16) -> Result<(), String> {
+        for (i, c) in self.name.iter().chain(self.ext.iter()).enumerate() {
+            poke(memory, fcb.wrapping_add(FCB_NAME + i as u16), *c)?;
+        }
+
+        Ok(())
+    }
+
+    // returns whether self, used as a pattern, matches the given file name
+    pub fn matches(&self, other: &Self) -> bool {
+        let pattern = self.name.iter().chain(self.ext.iter());
+        let name = other.name.iter().chain(other.ext.iter());
+
+        pattern.zip(name).all(|(p, c)| *p == b'?' || p == c)
+    }
+
+    // returns whether self contains any wildcard characters
+    pub fn is_ambiguous(&self) -> bool {
+        self.name.contains(&b'?') || self.ext.contains(&b'?')
+    }
+
+    // converts a host file name into a CpmFileName, if it can be represented
+    // as an 8.3 name
+    fn from_host(host_name: &str) -> Option<Self> {
+        let file_name = Self::parse(host_name)?;
+
+        if file_name.is_ambiguous() {
+            None
+        } else {
+            Some(file_name)
+        }
+    }
+}
+
+impl fmt::Display for CpmFileName {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = String::from_utf8_lossy(&self.name);
+        let ext = String::from_utf8_lossy(&self.ext);
+
+        if ext.trim_end().is_empty() {
+            write!(f, "{}", name.trim_end())
+        } else {
+            write!(f, "{}.{}", name.trim_end(), ext.trim_end())
+        }
+    }
+}
+
+// HostDrive struct - a CP/M drive backed by a directory on the host. user
+// area 0 is the directory itself, other user areas are subdirectories named
+// after the user number
+pub struct HostDrive {
+    root: PathBuf,
+    pub user: u8,
+    search_results: Vec<CpmFileName>,
+}
+
+impl HostDrive {
+    // creates a new instance of HostDrive rooted at the given host directory
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self {
+            root: root.into(),
+            user: 0,
+            search_results: Vec::new(),
+        }
+    }
+
+    // returns the host directory holding the files of the current user area
+    pub fn directory(&self) -> PathBuf {
+        if self.user == 0 {
+            self.root.clone()
+        } else {
+            self.root.join(self.user.to_string())
+        }
+    }
+
+    // returns all files in the current user area that match the given
+    // pattern, sorted by name
+    pub fn list(&self, pattern: &CpmFileName) -> Vec<CpmFileName> {
+        let Ok(entries) = fs::read_dir(self.directory()) else {
+            return Vec::new();
+        };
+
+        let mut files: Vec<CpmFileName> = entries
+            .flatten()
+            .filter(|entry| entry.file_type().is_ok_and(|t| t.is_file()))
+            .filter_map(|entry| CpmFileName::from_host(&entry.file_name().to_string_lossy()))
+            .filter(|file_name| pattern.matches(file_name))
+            .collect();
+
+        files.sort_by_key(|file_name| file_name.to_string());
+        files.dedup();
+        files
+    }
+
+    // returns the host path of an existing file, matching case-insensitively
+    pub fn find(&self, file_name: &CpmFileName) -> Option<PathBuf> {
+        fs::read_dir(self.directory())
+            .ok()?
+            .flatten()
+            .find(|entry| {
+                CpmFileName::from_host(&entry.file_name().to_string_lossy())
+                    .is_some_and(|host_name| host_name == *file_name)
+            })
+            .map(|entry| entry.path())
+    }
+
+    // returns the host path for a file, whether or not it exists yet
+    pub fn path_for(&self, file_name: &CpmFileName) -> PathBuf {
+        self.find(file_name)
+            .unwrap_or_else(|| self.directory().join(file_name.to_string()))
+    }
+
+    // creates a new, empty file (and the user area directory if needed)
+    pub fn create(&self, file_name: &CpmFileName) -> Result<PathBuf, String> {
+        fs::create_dir_all(self.directory()).map_err(|e| e.to_string())?;
+
+        let path = self.path_for(file_name);
+        File::create(&path).map_err(|e| format!("Could not create {file_name}: {e}"))?;
+
+        Ok(path)
+    }
+
+    // returns the size of a file in records, rounded up
+    fn size_in_records(path: &PathBuf) -> usize {
+        let len = fs::metadata(path).map_or(0, |m| m.len() as usize);
+
+        len.div_ceil(RECORD_SIZE)
+    }
+
+    // BDOS 15: open file
+    pub fn open(&mut self, memory: &mut Memory, fcb: u16) -> Result<u8, String> {
+        let file_name = CpmFileName::from_fcb(memory, fcb)?;
+
+        let Some(path) = self.find(&file_name) else {
+            return Ok(BDOS_NO_FILE);
+        };
+
+        // the extent asked for in the FCB is kept, set the record count to match
+        let record = fcb_record(memory, fcb)?;
+        set_fcb_record(memory, fcb, record, Self::size_in_records(&path))?;
+
+        Ok(BDOS_OK)
+    }
+
+    // BDOS 16: close file, files are never held open so this only checks
+    // that the file exists
+    pub fn close(&mut self, memory: &mut Memory, fcb: u16) -> Result<u8, String> {
+        let file_name = CpmFileName::from_fcb(memory, fcb)?;
+
+        Ok(match self.find(&file_name) {
+            Some(_) => BDOS_OK,
+            None => BDOS_NO_FILE,
+        })
+    }
+
+    // BDOS 17: search for the first file matching the FCB, the directory
+    // entry is written to the DMA buffer
+    pub fn search_first(&mut self, memory: &mut Memory, fcb: u16, dma: u16) -> Result<u8, String> {
+        // a '?' in the drive byte matches every file
+        let pattern = if peek(memory, fcb)? == b'?' {
+            CpmFileName::parse("*.*").unwrap()
+        } else {
+            CpmFileName::from_fcb(memory, fcb)?
+        };
+
+        self.search_results = self.list(&pattern);
+        self.search_results.reverse();
+
+        self.search_next(memory, dma)
+    }
+
+    // BDOS 18: search for the next file matching the last search_first
+    pub fn search_next(&mut self, memory: &mut Memory, dma: u16) -> Result<u8, String> {
+        let Some(file_name) = self.search_results.pop() else {
+            return Ok(BDOS_NO_FILE);
+        };
+
+        // build a directory entry as though the whole file was in one extent
+        for i in 0..32 {
+            poke(memory, dma.wrapping_add(i), 0)?;
+        }
+
+        poke(memory, dma, self.user)?;
+        file_name.write_fcb(memory, dma)?;
+
+        let records = Self::size_in_records(&self.path_for(&file_name));
+        let last_record = records.saturating_sub(1);
+        set_fcb_record(
+            memory,
+            dma,
+            last_record - last_record % RECORDS_PER_EXTENT,
+            records,
+        )?;
+        poke(memory, dma.wrapping_add(FCB_CR), 0)?;
+
+        // the entry is always placed at the start of the DMA buffer
+        Ok(0)
+    }
+
+    // BDOS 19: delete all files matching the FCB
+    pub fn delete(&mut self, memory: &mut Memory, fcb: u16) -> Result<u8, String> {
+        let pattern = CpmFileName::from_fcb(memory, fcb)?;
+
+        Ok(self.delete_files(&pattern))
+    }
+
+    // deletes all files matching a pattern. a file the host won't delete is
+    // reported to the program like a missing one, rather than stopping it
+    pub fn delete_files(&self, pattern: &CpmFileName) -> u8 {
+        let files = self.list(pattern);
+        let deleted = files
+            .iter()
+            .filter(|file_name| fs::remove_file(self.path_for(file_name)).is_ok())
+            .count();
+
+        if files.is_empty() || deleted < files.len() {
+            BDOS_NO_FILE
+        } else {
+            BDOS_OK
+        }
+    }
+
+    // BDOS 20: read the next record into the DMA buffer
+    pub fn read_sequential(
+        &mut self,
+        memory: &mut Memory,
+        fcb: u16,
+        dma: u16,
+    ) -> Result<u8, String> {
+        let record = fcb_record(memory, fcb)?;
+        let result = self.read_record(memory, fcb, dma, record)?;
+
+        if result == BDOS_OK {
+            let file_name = CpmFileName::from_fcb(memory, fcb)?;
+            let records = Self::size_in_records(&self.path_for(&file_name));
+            set_fcb_record(memory, fcb, record + 1, records)?;
+        }
+
+        Ok(result)
+    }
+
+    // BDOS 21: write the DMA buffer to the next record
+    pub fn write_sequential(
+        &mut self,
+        memory: &mut Memory,
+        fcb: u16,
+        dma: u16,
+    ) -> Result<u8, String> {
+        let record = fcb_record(memory, fcb)?;
+        let result = self.write_record(memory, fcb, dma, record)?;
+
+        if result == BDOS_OK {
+            let file_name = CpmFileName::from_fcb(memory, fcb)?;
+            let records = Self::size_in_records(&self.path_for(&file_name));
+            set_fcb_record(memory, fcb, record + 1, records)?;
+        }
+
+        Ok(result)
+    }
+
+    // BDOS 22: make (create) a file
+    pub fn make(&mut self, memory: &mut Memory, fcb: u16) -> Result<u8, String> {
+        let file_name = CpmFileName::from_fcb(memory, fcb)?;
+
+        if file_name.is_ambiguous() {
+            return Ok(BDOS_NO_FILE);
+        }
+
+        self.create(&file_name)?;
+        set_fcb_record(memory, fcb, fcb_record(memory, fcb)?, 0)?;
+
+        Ok(BDOS_OK)
+    }
+
+    // BDOS 23: rename the file in the first half of the FCB to the name in
+    // the second half
+    pub fn rename(&mut self, memory: &mut Memory, fcb: u16) -> Result<u8, String> {
+        let old_name = CpmFileName::from_fcb(memory, fcb)?;
+        let new_name = CpmFileName::from_fcb(memory, fcb.wrapping_add(FCB_RENAME))?;
+
+        Ok(self.rename_file(&old_name, &new_name))
+    }
+
+    // renames a file, reporting a file the host won't rename like a missing one
+    pub fn rename_file(&self, old_name: &CpmFileName, new_name: &CpmFileName) -> u8 {
+        let Some(old_path) = self.find(old_name) else {
+            return BDOS_NO_FILE;
+        };
+
+        match fs::rename(old_path, self.path_for(new_name)) {
+            Ok(()) => BDOS_OK,
+            Err(_) => BDOS_NO_FILE,
+        }
+    }
+
+    // BDOS 33: read the record selected by the random record field
+    pub fn read_random(&mut self, memory: &mut Memory, fcb: u16, dma: u16) -> Result<u8, String> {
+        let record = random_record(memory, fcb)?;
+        let result = self.read_record(memory, fcb, dma, record)?;
+
+        // the sequential position is moved to the record, but not past it
+        let file_name = CpmFileName::from_fcb(memory, fcb)?;
+        let records = Self::size_in_records(&self.path_for(&file_name));
+        set_fcb_record(memory, fcb, record, records)?;
+
+        Ok(result)
+    }
+
+    // BDOS 34: write the record selected by the random record field
+    pub fn write_random(&mut self, memory: &mut Memory, fcb: u16, dma: u16) -> Result<u8, String> {
+        let record = random_record(memory, fcb)?;
+        let result = self.write_record(memory, fcb, dma, record)?;
+
+        let file_name = CpmFileName::from_fcb(memory, fcb)?;
+        let records = Self::size_in_records(&self.path_for(&file_name));
+        set_fcb_record(memory, fcb, record, records)?;
+
+        Ok(result)
+    }
+
+    // BDOS 35: set the random record field to the size of the file in records
+    pub fn file_size(&mut self, memory: &mut Memory, fcb: u16) -> Result<u8, String> {
+        let file_name = CpmFileName::from_fcb(memory, fcb)?;
+
+        let Some(path) = self.find(&file_name) else {
+            return Ok(BDOS_NO_FILE);
+        };
+
+        set_random_record(memory, fcb, Self::size_in_records(&path))?;
+
+        Ok(BDOS_OK)
+    }
+
+    // BDOS 36: set the random record field to the current sequential position
+    pub fn set_random_record(&mut self, memory: &mut Memory, fcb: u16) -> Result<u8, String> {
+        let record = fcb_record(memory, fcb)?;
+        set_random_record(memory, fcb, record)?;
+
+        Ok(BDOS_OK)
+    }
+
+    // reads a single record of the file named by the FCB into the DMA buffer,
+    // a partial record at the end of the file is padded with ^Z
+    fn read_record(
+        &self,
+        memory: &mut Memory,
+        fcb: u16,
+        dma: u16,
+        record: usize,
+    ) -> Result<u8, String> {
+        let file_name = CpmFileName::from_fcb(memory, fcb)?;
+
+        let Some(path) = self.find(&file_name) else {
+            return Ok(BDOS_EOF);
+        };
+
+        let mut file = File::open(&path).map_err(|e| format!("Could not open {file_name}: {e}"))?;
+        let mut buf = [0x1Au8; RECORD_SIZE];
+
+        file.seek(SeekFrom::Start((record * RECORD_SIZE) as u64))
+            .map_err(|e| e.to_string())?;
+
+        // a short read can return fewer bytes than are available, so keep
+        // reading until the record is full or the end of the file is reached
+        let mut n_read = 0;
+        while n_read < RECORD_SIZE {
+            match file.read(&mut buf[n_read..]) {
+                Ok(0) => break,
+                Ok(n) => n_read += n,
+                Err(e) => return Err(format!("Could not read {file_name}: {e}")),
+            }
+        }
+
+        if n_read == 0 {
+            return Ok(BDOS_EOF);
+        }
+
+        for (i, value) in buf.iter().enumerate() {
+            poke(memory, dma.wrapping_add(i as u16), *value)?;
+        }
+
+        Ok(BDOS_OK)
+    }
+
+    // writes the DMA buffer to a single record of the file named by the FCB
+    fn write_record(
+        &self,
+        memory: &mut Memory,
+        fcb: u16,
+        dma: u16,
+        record: usize,
+    ) -> Result<u8, String> {
+        let file_name = CpmFileName::from_fcb(memory, fcb)?;
+
+        let Some(path) = self.find(&file_name) else {
+            return Ok(BDOS_NO_FILE);
+        };
+
+        let mut buf = [0u8; RECORD_SIZE];
+        for (i, value) in buf.iter_mut().enumerate() {
+            *value = peek(memory, dma.wrapping_add(i as u16))?;
+        }
+
+        let mut file = OpenOptions::new()
+            .write(true)
+            .open(&path)
+            .map_err(|e| format!("Could not open {file_name}: {e}"))?;
+
+        file.seek(SeekFrom::Start((record * RECORD_SIZE) as u64))
+            .and_then(|_| file.write_all(&buf))
+            .map_err(|e| format!("Could not write {file_name}: {e}"))?;
+
+        Ok(BDOS_OK)
+    }
+}
+
+// returns the sequential record position held in the EX, S2 and CR fields
+fn fcb_record(memory: &Memory, fcb: u16) -> Result<usize, String> {
+    let ex = (peek(memory, fcb.wrapping_add(FCB_EX))? & 0x1F) as usize;
+    let s2 = (peek(memory, fcb.wrapping_add(FCB_S2))? & 0x3F) as usize;
+    let cr = peek(memory, fcb.wrapping_add(FCB_CR))? as usize;
+
+    Ok((s2 * EXTENTS_PER_MODULE + ex) * RECORDS_PER_EXTENT + cr)
+}
+
+// sets the EX, S2 and CR fields to a sequential record position, and RC to
+// the number of records of a file of the given size in that extent
+fn set_fcb_record(
+    memory: &mut Memory,
+    fcb: u16,
+    record: usize,
+    file_records: usize,
+) -> Result<(), String> {
+    let extent = record / RECORDS_PER_EXTENT;
+    let extent_start = extent * RECORDS_PER_EXTENT;
+    let rc = file_records
+        .saturating_sub(extent_start)
+        .min(RECORDS_PER_EXTENT);
+
+    poke(
+        memory,
+        fcb.wrapping_add(FCB_EX),
+        (extent % EXTENTS_PER_MODULE) as u8,
+    )?;
+    poke(
+        memory,
+        fcb.wrapping_add(FCB_S2),
+        (extent / EXTENTS_PER_MODULE) as u8,
+    )?;
+    poke(memory, fcb.wrapping_add(FCB_RC), rc as u8)?;
+    poke(
+        memory,
+        fcb.wrapping_add(FCB_CR),
+        (record % RECORDS_PER_EXTENT) as u8,
+    )
+}
+
+// returns the record selected by the R0, R1 random record field
+fn random_record(memory: &Memory, fcb: u16) -> Result<usize, String> {
+    let r0 = peek(memory, fcb.wrapping_add(FCB_R0))? as usize;
+    let r1 = peek(memory, fcb.wrapping_add(FCB_R0 + 1))? as usize;
+
+    Ok((r1 << 8) | r0)
+}
+
+// sets the R0, R1, R2 random record field
+fn set_random_record(memory: &mut Memory, fcb: u16, record: usize) -> Result<(), String> {
+    poke(memory, fcb.wrapping_add(FCB_R0), (record & 0xFF) as u8)?;
+    poke(
+        memory,
+        fcb.wrapping_add(FCB_R0 + 1),
+        ((record >> 8) & 0xFF) as u8,
+    )?;
+    poke(
+        memory,
+        fcb.wrapping_add(FCB_R0 + 2),
+        ((record >> 16) & 0xFF) as u8,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cpm_file_name_parse() {
+        let file_name = CpmFileName::parse("b:foo.asm").unwrap();
+        assert_eq!(&file_name.name, b"FOO     ");
+        assert_eq!(&file_name.ext, b"ASM");
+        assert_eq!(file_name.to_string(), "FOO.ASM");
+
+        let pattern = CpmFileName::parse("F*.*").unwrap();
+        assert_eq!(&pattern.name, b"F???????");
+        assert!(pattern.is_ambiguous());
+        assert!(pattern.matches(&file_name));
+        assert!(!pattern.matches(&CpmFileName::parse("BAR.ASM").unwrap()));
+
+        assert!(CpmFileName::parse("TOOLONGNAME.COM").is_none());
+        assert!(CpmFileName::parse("A.B.C").is_none());
+    }
+
+    #[test]
+    fn host_drive_write_read_sequential() {
+        let root = std::env::temp_dir().join(format!("i8080-drive-{}", std::process::id()));
+        fs::create_dir_all(&root).unwrap();
+
+        let mut drive = HostDrive::new(&root);
+        let mut memory = Memory::new();
+        let (fcb, dma) = (0x5Cu16, 0x80u16);
+
+        CpmFileName::parse("TEST.DAT")
+            .unwrap()
+            .write_fcb(&mut memory, fcb)
+            .unwrap();
+
+        // make the file and write two records to it
+        assert_eq!(drive.make(&mut memory, fcb).unwrap(), BDOS_OK);
+        for value in [0xAAu8, 0x55u8] {
+            for i in 0..RECORD_SIZE as u16 {
+                poke(&mut memory, dma.wrapping_add(i), value).unwrap();
+            }
+
+            assert_eq!(
+                drive.write_sequential(&mut memory, fcb, dma).unwrap(),
+                BDOS_OK
+            );
+        }
+
+        assert_eq!(fs::metadata(root.join("TEST.DAT")).unwrap().len(), 256);
+
+        // reopen, read both records back, then hit the end of the file
+        poke(&mut memory, fcb.wrapping_add(FCB_CR), 0).unwrap();
+        assert_eq!(drive.open(&mut memory, fcb).unwrap(), BDOS_OK);
+        assert_eq!(peek(&memory, fcb.wrapping_add(FCB_RC)).unwrap(), 2);
+
+        for value in [0xAAu8, 0x55u8] {
+            assert_eq!(
+                drive.read_sequential(&mut memory, fcb, dma).unwrap(),
+                BDOS_OK
+            );
+            assert_eq!(peek(&memory, dma).unwrap(), value);
+        }
+
+        assert_eq!(
+            drive.read_sequential(&mut memory, fcb, dma).unwrap(),
+            BDOS_EOF
+        );
+
+        // the file is found by a search, and removed by a delete
+        assert_eq!(drive.search_first(&mut memory, fcb, dma).unwrap(), 0);
+        assert_eq!(
+            CpmFileName::from_fcb(&memory, dma).unwrap().to_string(),
+            "TEST.DAT"
+        );
+        assert_eq!(drive.search_next(&mut memory, dma).unwrap(), BDOS_NO_FILE);
+
+        assert_eq!(drive.delete(&mut memory, fcb).unwrap(), BDOS_OK);
+        assert_eq!(drive.open(&mut memory, fcb).unwrap(), BDOS_NO_FILE);
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn host_drive_at_top_of_memory() {
+        let root = std::env::temp_dir().join(format!("i8080-drive-top-{}", std::process::id()));
+        fs::create_dir_all(&root).unwrap();
+
+        let mut drive = HostDrive::new(&root);
+        let mut memory = Memory::new();
+
+        // an FCB that wraps around to the start of memory
+        let (fcb, dma) = (0xFFF0u16, 0x0080u16);
+        CpmFileName::parse("TOP.DAT")
+            .unwrap()
+            .write_fcb(&mut memory, fcb)
+            .unwrap();
+        for i in 0..RECORD_SIZE as u16 {
+            poke(&mut memory, dma.wrapping_add(i), i as u8).unwrap();
+        }
+
+        assert_eq!(drive.make(&mut memory, fcb).unwrap(), BDOS_OK);
+        assert_eq!(
+            drive.write_sequential(&mut memory, fcb, dma).unwrap(),
+            BDOS_OK
+        );
+        assert_eq!(drive.set_random_record(&mut memory, fcb).unwrap(), BDOS_OK);
+        assert_eq!(peek(&memory, fcb.wrapping_add(FCB_R0)).unwrap(), 1);
+        assert_eq!(drive.close(&mut memory, fcb).unwrap(), BDOS_OK);
+
+        // and a DMA buffer that does
+        let (fcb, dma) = (0x005Cu16, 0xFFC0u16);
+        CpmFileName::parse("TOP.DAT")
+            .unwrap()
+            .write_fcb(&mut memory, fcb)
+            .unwrap();
+        assert_eq!(drive.open(&mut memory, fcb).unwrap(), BDOS_OK);
+        assert_eq!(
+            drive.read_sequential(&mut memory, fcb, dma).unwrap(),
+            BDOS_OK
+        );
+        assert_eq!(peek(&memory, 0xFFFF).unwrap(), 0x3F);
+        assert_eq!(peek(&memory, 0x003F).unwrap(), 0x7F);
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn host_drive_rename_failure() {
+        let root = std::env::temp_dir().join(format!("i8080-drive-ren-{}", std::process::id()));
+        fs::create_dir_all(root.join("NEW.TXT")).unwrap();
+        fs::write(root.join("OLD.TXT"), b"old").unwrap();
+
+        let mut drive = HostDrive::new(&root);
+        let mut memory = Memory::new();
+        let fcb = 0x5Cu16;
+
+        // the host won't replace a directory with a file, which the program
+        // sees as an error code rather than the emulator stopping
+        let old_name = CpmFileName::parse("OLD.TXT").unwrap();
+        let new_name = CpmFileName::parse("NEW.TXT").unwrap();
+        old_name.write_fcb(&mut memory, fcb).unwrap();
+        new_name
+            .write_fcb(&mut memory, fcb.wrapping_add(FCB_RENAME))
+            .unwrap();
+
+        assert_eq!(drive.rename(&mut memory, fcb).unwrap(), BDOS_NO_FILE);
+        assert!(root.join("OLD.TXT").is_file());
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -0,0 +1,288 @@
+/*
+ * trace.rs - Contains code related to tracing calls to the BDOS, logging
+ * each call with its decoded arguments and keeping per-function statistics
+ */
+use super::drive::*;
+use super::*;
+use crate::symbols::SymbolTable;
+
+use std::collections::BTreeMap;
+use std::fmt;
+
+// maximum number of characters of a string argument shown in the trace
+const MAX_STRING_LEN: usize = 64;
+
+// returns the name of a BDOS function, as given in the CP/M 2.2 manual
+pub fn function_name(function: u8) -> &'static str {
+    match function {
+        0 => "System Reset",
+        1 => "Console Input",
+        2 => "Console Output",
+        3 => "Reader Input",
+        4 => "Punch Output",
+        5 => "List Output",
+        6 => "Direct Console I/O",
+        7 => "Get I/O Byte",
+        8 => "Set I/O Byte",
+        9 => "Print String",
+        10 => "Read Console Buffer",
+        11 => "Get Console Status",
+        12 => "Return Version Number",
+        13 => "Reset Disk System",
+        14 => "Select Disk",
+        15 => "Open File",
+        16 => "Close File",
+        17 => "Search for First",
+        18 => "Search for Next",
+        19 => "Delete File",
+        20 => "Read Sequential",
+        21 => "Write Sequential",
+        22 => "Make File",
+        23 => "Rename File",
+        24 => "Return Login Vector",
+        25 => "Return Current Disk",
+        26 => "Set DMA Address",
+        27 => "Get Addr (Alloc)",
+        28 => "Write Protect Disk",
+        29 => "Get R/O Vector",
+        30 => "Set File Attributes",
+        31 => "Get Addr (DPB)",
+        32 => "Set/Get User Code",
+        33 => "Read Random",
+        34 => "Write Random",
+        35 => "Compute File Size",
+        36 => "Set Random Record",
+        37 => "Reset Drive",
+        40 => "Write Random Zero Fill",
+        _ => "Unknown",
+    }
+}
+
+// BdosCall struct - a single call to the BDOS, as recorded in the trace
+#[derive(Debug, Clone, PartialEq)]
+pub struct BdosCall {
+    pub caller: u16, // address of the CALL instruction
+    pub function: u8,
+    pub args: String, // human-readable decoded arguments
+    pub a: u8,        // returned in A
+    pub hl: u16,      // returned in HL
+}
+
+impl BdosCall {
+    // records the caller and decodes the arguments of a call that is about
+    // to be made
+    pub fn before(cpu: &Cpu, function: u8, dma_addr: u16) -> Result<Self, String> {
+        // the BDOS is entered in place of a 3 byte CALL, and PC is already
+        // past it
+        let caller = u16::from(cpu.reg_array.read_reg(Register::PC)).wrapping_sub(3);
+        let e_val = peek_reg(cpu, Register::E)?;
+        let de_val = u16::from(cpu.reg_array.read_reg(Register::DE));
+
+        let args = match function {
+            2 | 4 | 5 => format!("E={e_val:02X} {}", describe_char(e_val)),
+            6 => match e_val {
+                0xFF => String::from("E=FF (input)"),
+                0xFE => String::from("E=FE (status)"),
+                _ => format!("E={e_val:02X} {}", describe_char(e_val)),
+            },
+            8 => format!("E={e_val:02X}"),
+            9 => format!("DE={de_val:04X} {}", read_string(&cpu.memory, de_val)?),
+            10 | 26 => format!("DE={de_val:04X}"),
+            14 => format!("E={e_val:02X} ({}:)", (b'A' + (e_val & 0x0F)) as char),
+            15..=19 | 22 | 30 | 35 | 36 => {
+                format!("DE={de_val:04X} {}", describe_fcb(&cpu.memory, de_val)?)
+            }
+            20 | 21 | 33 | 34 | 40 => format!(
+                "DE={de_val:04X} {} DMA={dma_addr:04X}",
+                describe_fcb(&cpu.memory, de_val)?
+            ),
+            23 => format!(
+                "DE={de_val:04X} {} -> {}",
+                describe_fcb(&cpu.memory, de_val)?,
+                CpmFileName::from_fcb(&cpu.memory, de_val.wrapping_add(16))?
+            ),
+            32 if e_val == 0xFF => String::from("E=FF (get)"),
+            32 => format!("E={e_val:02X}"),
+            _ => String::new(),
+        };
+
+        Ok(Self {
+            caller,
+            function,
+            args,
+            a: 0,
+            hl: 0,
+        })
+    }
+
+    // records the values returned by the call
+    pub fn after(mut self, cpu: &Cpu) -> Self {
+        self.a = u8::try_from(cpu.alu.accumulator()).unwrap_or_default();
+        self.hl = u16::from(cpu.reg_array.read_reg(Register::HL));
+        self
+    }
+}
+
+impl fmt::Display for BdosCall {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:04X}: BDOS {:>2} {:<22} {:<40} -> A={:02X} HL={:04X}",
+            self.caller,
+            self.function,
+            function_name(self.function),
+            self.args,
+            self.a,
+            self.hl
+        )
+    }
+}
+
+// BdosTrace struct - writes a line for every BDOS call, and counts the calls
+// made to each function
+pub struct BdosTrace {
+    writer: Box<dyn Write + Send>,
+    counts: BTreeMap<u8, usize>,
+    pub symbols: SymbolTable, // used to name the callers
+}
+
+impl BdosTrace {
+    // creates a new instance of BdosTrace logging to the given writer
+    pub fn new(writer: Box<dyn Write + Send>) -> Self {
+        Self {
+            writer,
+            counts: BTreeMap::new(),
+            symbols: SymbolTable::new(),
+        }
+    }
+
+    // logs a call and adds it to the statistics
+    pub fn record(&mut self, call: BdosCall) -> Result<(), String> {
+        *self.counts.entry(call.function).or_default() += 1;
+
+        let caller = match self.symbols.describe(call.caller) {
+            Some(symbol) => format!(" ({symbol})"),
+            None => String::new(),
+        };
+
+        writeln!(self.writer, "{call}{caller}")
+            .map_err(|e| format!("Could not write BDOS trace: {e}"))
+    }
+
+    // returns the number of calls made to each function
+    pub fn counts(&self) -> &BTreeMap<u8, usize> {
+        &self.counts
+    }
+
+    // returns a table of the number of calls made to each function
+    pub fn summary(&self) -> String {
+        let mut summary = String::from("BDOS call summary:\n");
+
+        for (function, count) in &self.counts {
+            let name = function_name(*function);
+            summary.push_str(&format!("{function:>4} {name:<22} {count:>8}\n"));
+        }
+
+        let total: usize = self.counts.values().sum();
+        summary.push_str(&format!("{:>4} {:<22} {total:>8}\n", "", "Total"));
+
+        summary
+    }
+
+    // writes the summary to the trace
+    pub fn write_summary(&mut self) -> Result<(), String> {
+        let summary = self.summary();
+
+        self.writer
+            .write_all(summary.as_bytes())
+            .and_then(|_| self.writer.flush())
+            .map_err(|e| format!("Could not write BDOS trace: {e}"))
+    }
+}
+
+// reads an 8-bit register
+fn peek_reg(cpu: &Cpu, register: Register) -> Result<u8, String> {
+    Ok(u8::try_from(cpu.reg_array.read_reg(register))?)
+}
+
+// describes a character argument, printable characters are shown quoted
+fn describe_char(value: u8) -> String {
+    if value.is_ascii_graphic() || value == b' ' {
+        format!("'{}'", value as char)
+    } else {
+        String::new()
+    }
+}
+
+// describes the drive and file name in an FCB, e.g. "A:FOO.ASM"
+fn describe_fcb(memory: &Memory, fcb: u16) -> Result<String, String> {
+    let drive = match peek(memory, fcb)? {
+        0 => String::new(),
+        drive @ 1..=16 => format!("{}:", (b'A' + drive - 1) as char),
+        drive => format!("{drive:02X}:"),
+    };
+
+    Ok(format!("{drive}{}", CpmFileName::from_fcb(memory, fcb)?))
+}
+
+// reads a '$'-terminated string, quoted and escaped
+fn read_string(memory: &Memory, addr: u16) -> Result<String, String> {
+    let mut string = String::new();
+
+    for i in 0..=MAX_STRING_LEN as u16 {
+        let value = peek(memory, addr.wrapping_add(i))?;
+
+        if value == b'$' {
+            return Ok(format!("\"{string}\""));
+        }
+
+        string.extend((value as char).escape_default());
+    }
+
+    Ok(format!("\"{string}\"..."))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::tests::SharedBuf;
+    use super::*;
+
+    #[test]
+    fn bdos_trace_calls_and_counts() {
+        let log = SharedBuf::default();
+
+        let mut bdos = Bdos::new(CpmDevices::new());
+        bdos.trace = Some(BdosTrace::new(Box::new(log.clone())));
+
+        let mut cpu = Cpu::new();
+        let bdos = add_cpm_bdos(&mut cpu, bdos);
+
+        // MVI C,9; LXI D,msg; CALL 5; MVI C,12; CALL 5; MVI C,9; CALL 5; HLT;
+        // msg: "HI$"
+        let program = vec![
+            0x0E, 0x09, 0x11, 0x13, 0x01, 0xCD, 0x05, 0x00, 0x0E, 0x0C, 0xCD, 0x05, 0x00, 0x0E,
+            0x09, 0xCD, 0x05, 0x00, 0x76, b'H', b'I', b'$',
+        ];
+        cpu.load_to_memory(program, 0x100).unwrap();
+        cpu.set_pc(0x100).unwrap();
+
+        while cpu.is_running() {
+            cpu.execute_next().unwrap();
+        }
+
+        let log = String::from_utf8(log.0.lock().unwrap().clone()).unwrap();
+        let lines: Vec<&str> = log.lines().collect();
+
+        assert_eq!(lines.len(), 3);
+        assert!(lines[0].starts_with("0105: BDOS  9 Print String"));
+        assert!(lines[0].contains("DE=0113 \"HI\""));
+        assert!(lines[1].starts_with("010A: BDOS 12 Return Version Number"));
+        assert!(lines[1].ends_with("-> A=22 HL=0022"));
+
+        let bdos = bdos.lock().unwrap();
+        let trace = bdos.trace.as_ref().unwrap();
+        assert_eq!(trace.counts().get(&9), Some(&2));
+        assert_eq!(trace.counts().get(&12), Some(&1));
+        assert!(trace.summary().contains("Total"));
+    }
+}
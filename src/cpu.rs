@@ -4,35 +4,32 @@
  */
 
 pub mod alu;
+pub mod breakpoints;
+pub mod disassembler;
+pub mod expression;
 pub mod instruction;
 pub mod memory;
+pub mod opcodes;
 pub mod registers;
+pub mod trace;
 mod utils;
 
 use alu::*;
+use breakpoints::*;
 use instruction::*;
 use memory::*;
+use opcodes::*;
 use registers::*;
 
-use std::collections::HashMap;
-
-// holds the base number of clock cycles used by each opcode
-// note that for conditional call/ret, if the branch is taken, this number is increased by 6
-const CPU_INSTRUCTION_CLOCK_CYCLES: [usize; 256] = [
-    4, 10, 7, 5, 5, 5, 7, 4, 4, 10, 7, 5, 5, 5, 7, 4, 4, 10, 7, 5, 5, 5, 7, 4, 4, 10, 7, 5, 5, 5,
-    7, 4, 4, 10, 16, 5, 5, 5, 7, 4, 4, 10, 16, 5, 5, 5, 7, 4, 4, 10, 13, 5, 10, 10, 10, 4, 4, 10,
-    13, 5, 5, 5, 7, 4, 5, 5, 5, 5, 5, 5, 7, 5, 5, 5, 5, 5, 5, 5, 7, 5, 5, 5, 5, 5, 5, 5, 7, 5, 5,
-    5, 5, 5, 5, 5, 7, 5, 5, 5, 5, 5, 5, 5, 7, 5, 5, 5, 5, 5, 5, 5, 7, 5, 7, 7, 7, 7, 7, 7, 7, 7, 5,
-    5, 5, 5, 5, 5, 7, 5, 4, 4, 4, 4, 4, 4, 7, 4, 4, 4, 4, 4, 4, 4, 7, 4, 4, 4, 4, 4, 4, 4, 7, 4, 4,
-    4, 4, 4, 4, 4, 7, 4, 4, 4, 4, 4, 4, 4, 7, 4, 4, 4, 4, 4, 4, 4, 7, 4, 4, 4, 4, 4, 4, 4, 7, 4, 4,
-    4, 4, 4, 4, 4, 7, 4, 5, 10, 10, 10, 11, 11, 7, 11, 5, 10, 10, 10, 11, 17, 7, 11, 5, 10, 10, 10,
-    11, 11, 7, 11, 5, 10, 10, 10, 11, 17, 7, 11, 5, 10, 10, 18, 11, 11, 7, 11, 5, 5, 10, 4, 11, 17,
-    7, 11, 5, 10, 10, 4, 11, 11, 7, 11, 5, 5, 10, 4, 11, 17, 7, 11,
-];
+use std::collections::{BTreeSet, HashMap, VecDeque};
+use std::sync::Arc;
 
 // macro to help with debug output
 const DEBUG_OUTPUT: bool = false;
 
+// how many of the last instructions executed are kept in the history
+pub const HISTORY_LENGTH: usize = 16;
+
 macro_rules! dbg_print {
     ( $x:expr ) => {
         if DEBUG_OUTPUT {
@@ -48,6 +45,10 @@ macro_rules! dbg_println {
     };
 }
 
+// custom subroutine handler, called in place of a CALL to the address it is
+// registered at
+pub type SubroutineHandler = Arc<dyn Fn(&mut Cpu) -> Result<(), String> + Send + Sync>;
+
 // Cpu struct - holds all components of the CPU and has I/O functions
 pub struct Cpu {
     pub running: bool,
@@ -57,8 +58,40 @@ pub struct Cpu {
     pub memory: Memory,
     pub ports: [RegisterValue; 0x100],
     pub port_handler_fn: Option<Box<dyn Fn(RegisterValue, RegisterValue) + Send + 'static>>,
-    pub subroutines: HashMap<u16, fn(&mut Cpu)>,
+    pub subroutines: HashMap<u16, SubroutineHandler>,
     pub total_cycles: usize,
+    pub coverage: Option<BTreeSet<u16>>, // addresses of the instructions executed, if recorded
+    pub history: Option<VecDeque<u16>>,  // addresses of the last instructions executed, if recorded
+    pub trace: Option<trace::Trace>,     // writes a line for each instruction executed, if set
+    pub breakpoints: Breakpoints,
+    pub warm_boot_stops: bool, // a jump to 0 stops the Cpu, set when CP/M is installed
+    instruction_addr: u16,     // address of the instruction being executed
+    operand: Option<RegisterValue>, // fetched along with the opcode being executed
+    waiting_for_input: bool,   // the last instruction was a call waiting for input
+}
+
+// StopReason enum - why Cpu::run or a step returned
+#[derive(Debug, Clone, PartialEq)]
+pub enum StopReason {
+    Breakpoint(BreakpointHit),
+    Halted,
+    Completed,       // a step over, step out or run to address finished
+    BudgetExhausted, // the budget ran out first
+    WaitingForInput, // a call is waiting for console input, and is made again next time
+}
+
+// Budget enum - how far running or stepping may go before it gives up, so a
+// runaway program can't hang the host
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Budget {
+    Cycles(usize),
+    Instructions(usize),
+}
+
+impl Default for Cpu {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Cpu {
@@ -74,6 +107,14 @@ impl Cpu {
             port_handler_fn: None,
             subroutines: HashMap::new(),
             total_cycles: 0,
+            coverage: None,
+            history: None,
+            trace: None,
+            breakpoints: Breakpoints::new(),
+            warm_boot_stops: false,
+            instruction_addr: 0,
+            operand: None,
+            waiting_for_input: false,
         }
     }
 
@@ -82,6 +123,19 @@ impl Cpu {
         self.running
     }
 
+    // returns whether the last instruction was a call waiting for input
+    pub fn is_waiting_for_input(&self) -> bool {
+        self.waiting_for_input
+    }
+
+    // called by a subroutine handler that needs console input there is none
+    // of yet. the program counter goes back to the call, so it is made again
+    // the next time the Cpu runs
+    pub fn wait_for_input(&mut self) -> Result<(), String> {
+        self.waiting_for_input = true;
+        self.set_pc(self.instruction_addr)
+    }
+
     // reads a RegisterValue at the current program counter, also increments
     // the program counter by an appropriate amount
     fn read_next(&mut self, size: MemorySize) -> Result<RegisterValue, String> {
@@ -102,17 +156,40 @@ impl Cpu {
         Ok(value)
     }
 
-    // decodes the instruction at the current program counter into an Instruction enum
+    // decodes the instruction at the current program counter into an Instruction enum,
+    // fetching the operand that follows the opcode. the opcode table gives its length
     fn decode_next_instruction(&mut self) -> Result<(u8, Instruction), String> {
         let instruction = self.read_next(MemorySize::Integer8)?;
         let opcode = u8::try_from(instruction)?;
         let instruction = Instruction::decode(instruction)?;
 
-        dbg_println!("decode_next_instruction: {instruction:?}");
+        self.operand = match opcode_info(opcode).n_bytes {
+            2 => Some(self.read_next(MemorySize::Integer8)?),
+            3 => Some(self.read_next(MemorySize::Integer16)?),
+            _ => None,
+        };
+
+        let operand = self.operand;
+        dbg_println!("decode_next_instruction: {instruction:?} {operand:X?}");
 
         Ok((opcode, instruction))
     }
 
+    // returns the operand fetched with the instruction being executed. when
+    // there isn't one, as when a source is evaluated on its own, it is read
+    // from the program counter instead
+    fn read_operand(&mut self, size: MemorySize) -> Result<RegisterValue, String> {
+        match self.operand.take() {
+            Some(operand) if operand.n_bytes() == size.n_bytes() => Ok(operand),
+            Some(operand) => Err(format!(
+                "Attempted to read a {} byte operand, but {} bytes were fetched",
+                size.n_bytes(),
+                operand.n_bytes()
+            )),
+            None => self.read_next(size),
+        }
+    }
+
     // evaluates the value of a InstructionSource into a RegisterValue
     fn evaluate_source(&mut self, source: InstructionSource) -> Result<RegisterValue, String> {
         use InstructionSource::*;
@@ -124,19 +201,18 @@ impl Cpu {
 
                 match memory_source {
                     // if the memory source contains the address directly
-                    Address(addr) => self.memory.read(addr, size),
+                    Address(addr) => self.read_memory(addr, size),
 
                     // if the memory source address is contained in a register
                     Register(register) => {
                         let addr = self.reg_array.read_reg(register);
 
-                        self.memory.read(addr, size)
+                        self.read_memory(addr, size)
                     }
 
-                    // if the memory source address is the program counter
-                    // this will also increase the program counter by an
-                    // appropriate amount
-                    ProgramCounter => self.read_next(size),
+                    // if the memory source is the program counter, this is
+                    // the operand that follows the opcode
+                    ProgramCounter => self.read_operand(size),
                 }
             }
 
@@ -187,7 +263,7 @@ impl Cpu {
                 };
 
                 // write to the address
-                self.memory.write(addr, value)?;
+                self.write_memory(addr, value)?;
             }
 
             // if the source is a register
@@ -254,6 +330,59 @@ impl Cpu {
         Ok(())
     }
 
+    // reads memory on behalf of an instruction, checking the read against
+    // the breakpoints
+    fn read_memory(
+        &mut self,
+        addr: RegisterValue,
+        size: MemorySize,
+    ) -> Result<RegisterValue, String> {
+        let value = self.memory.read(addr, size)?;
+
+        if !self.breakpoints.is_empty() {
+            for (addr, value) in memory_bytes(addr, value) {
+                self.check_breakpoints(Access::Read { addr, value });
+            }
+        }
+
+        Ok(value)
+    }
+
+    // writes memory on behalf of an instruction, checking the write against
+    // the breakpoints
+    fn write_memory(&mut self, addr: RegisterValue, value: RegisterValue) -> Result<(), String> {
+        self.memory.write(addr, value)?;
+
+        if !self.breakpoints.is_empty() {
+            for (addr, value) in memory_bytes(addr, value) {
+                self.check_breakpoints(Access::Write { addr, value });
+            }
+        }
+
+        Ok(())
+    }
+
+    // checks an access made by the current instruction against the
+    // breakpoints. a hit is kept until it is taken
+    fn check_breakpoints(&mut self, access: Access) {
+        self.check_breakpoints_at(self.instruction_addr, access);
+    }
+
+    // checks an access made by the instruction at pc against the
+    // breakpoints, evaluating their conditions. a condition that can't be
+    // evaluated stops the program, so the error can be looked into
+    fn check_breakpoints_at(&mut self, pc: u16, access: Access) -> Option<BreakpointHit> {
+        let mut breakpoints = std::mem::take(&mut self.breakpoints);
+        let hit = breakpoints
+            .check_if(pc, access, |condition| {
+                condition.is_true(self).unwrap_or(true)
+            })
+            .cloned();
+
+        self.breakpoints = breakpoints;
+        hit
+    }
+
     // loads a vector of u8s to memory
     pub fn load_to_memory(&mut self, data: Vec<u8>, start_addr: u16) -> Result<(), String> {
         let writes = data.iter().enumerate().map(|(i, val)| {
@@ -273,8 +402,9 @@ impl Cpu {
     // executes an instruction, returns result with # of cycles. also modifies self::total_cycles
     pub fn execute(&mut self, opcode: u8, instruction: Instruction) -> Result<usize, String> {
         // holds the number of clock cycles used by the instruction
-        // conditional call/ret should increase this by 6 if branch taken
-        let mut cycles = CPU_INSTRUCTION_CLOCK_CYCLES[opcode as usize];
+        // conditional call/ret take longer if the branch is taken
+        let info = opcode_info(opcode);
+        let mut cycles = info.cycles;
 
         // make sure to update the status word before anything
         self.update_status_word()?;
@@ -289,10 +419,10 @@ impl Cpu {
 
             // loads a value from the immediate address to the destination
             Load(dest) => {
-                let addr = self.read_next(MemorySize::Integer16)?;
+                let addr = self.read_operand(MemorySize::Integer16)?;
 
                 let imm_size = MemorySize::from_bytes(dest.n_bytes()?)?;
-                let imm_val = self.memory.read(addr, imm_size)?;
+                let imm_val = self.read_memory(addr, imm_size)?;
 
                 dbg_println!("execute (Load): ({addr:X?}) = {imm_val:X?} -> {dest:?}");
 
@@ -302,7 +432,7 @@ impl Cpu {
             // stores a value to an immediate address
             Store(source) => {
                 let src_size = MemorySize::from_bytes(source.n_bytes()?)?;
-                let addr = self.read_next(MemorySize::Integer16)?;
+                let addr = self.read_operand(MemorySize::Integer16)?;
                 let dest = InstructionSource::Memory(MemorySource::Address(addr), src_size);
                 let value = self.evaluate_source(source)?;
                 dbg_println!("execute (Store): {value:X?} -> {dest:?}");
@@ -451,7 +581,7 @@ impl Cpu {
                     let new_pc = self.pop_from_stack(MemorySize::Integer16)?;
                     dbg_println!("execute (ReturnConditional): {new_pc:X?} -> PC");
                     self.reg_array.write_reg(Register::PC, new_pc)?;
-                    cycles += 6;
+                    cycles = info.taken_cycles;
                 } else {
                     dbg_println!("execute (ReturnConditional): branch not taken");
                 }
@@ -474,7 +604,7 @@ impl Cpu {
 
             // conditional jump
             JumpConditional(condition) => {
-                let addr = self.read_next(MemorySize::Integer16)?;
+                let addr = self.read_operand(MemorySize::Integer16)?;
                 if self.alu.flags().evaluate_condition(condition) {
                     dbg_println!("execute (JumpConditional): branch taken, {addr:X?} -> PC");
                     self.reg_array.write_reg(Register::PC, addr)?;
@@ -485,11 +615,11 @@ impl Cpu {
 
             // unconditional jump
             Jump => {
-                let addr = self.read_next(MemorySize::Integer16)?;
+                let addr = self.read_operand(MemorySize::Integer16)?;
 
                 dbg_println!("execute (Jump): {addr:X?} -> PC");
 
-                if addr == RegisterValue::from(0u16) {
+                if self.warm_boot_stops && addr == RegisterValue::from(0u16) {
                     self.running = false;
                 } else {
                     self.reg_array.write_reg(Register::PC, addr)?;
@@ -498,7 +628,7 @@ impl Cpu {
 
             // conditional call
             CallConditional(condition) => {
-                let addr = self.read_next(MemorySize::Integer16)?;
+                let addr = self.read_operand(MemorySize::Integer16)?;
                 if self.alu.flags().evaluate_condition(condition) {
                     dbg_println!(
                         "execute (CallConditional): branch taken, PC -> stack, {addr:X?} -> PC"
@@ -508,7 +638,7 @@ impl Cpu {
                     self.push_to_stack(pc_val)?;
 
                     self.reg_array.write_reg(Register::PC, addr)?;
-                    cycles += 6;
+                    cycles = info.taken_cycles;
                 } else {
                     dbg_println!("execute (CallConditional): branch not taken");
                 }
@@ -542,7 +672,7 @@ impl Cpu {
 
             // unconditional call
             Call => {
-                let addr = self.read_next(MemorySize::Integer16)?;
+                let addr = self.read_operand(MemorySize::Integer16)?;
                 dbg_println!("execute (Call): PC -> stack, {addr:X?} -> PC");
 
                 // handle custom subroutines
@@ -550,9 +680,9 @@ impl Cpu {
                 if self.subroutines.contains_key(&addr_u16) {
                     dbg_println!("Executing custom subroutine for {addr:X?}...");
 
-                    let subroutine_fn = self.subroutines.get(&addr_u16).unwrap();
+                    let subroutine_fn = Arc::clone(self.subroutines.get(&addr_u16).unwrap());
 
-                    subroutine_fn(self);
+                    subroutine_fn(self)?;
                 } else {
                     let pc_val = self.reg_array.read_reg(Register::PC);
                     self.push_to_stack(pc_val)?;
@@ -563,18 +693,26 @@ impl Cpu {
 
             // IO output
             IoOut => {
-                let port = self.read_next(MemorySize::Integer8)?;
+                let port = self.read_operand(MemorySize::Integer8)?;
                 let a_val = self.alu.accumulator();
 
                 self.write_to_port(port, a_val)?;
+                self.check_breakpoints(Access::PortOut {
+                    port: u8::try_from(port)?,
+                    value: u8::try_from(a_val)?,
+                });
             }
 
             // IO input
             IoIn => {
-                let port = self.read_next(MemorySize::Integer8)?;
+                let port = self.read_operand(MemorySize::Integer8)?;
                 let port_val = self.read_port(port)?;
 
                 self.alu.write_accumulator(port_val)?;
+                self.check_breakpoints(Access::PortIn {
+                    port: u8::try_from(port)?,
+                    value: u8::try_from(port_val)?,
+                });
             }
 
             // exchange instruction
@@ -622,6 +760,25 @@ impl Cpu {
     // executes the next instruction in memory
     pub fn execute_next(&mut self) -> Result<usize, String> {
         if self.running {
+            self.instruction_addr = u16::from(self.reg_array.read_reg(Register::PC));
+            self.waiting_for_input = false;
+            self.breakpoints.take_hit();
+
+            if let Some(coverage) = self.coverage.as_mut() {
+                coverage.insert(self.instruction_addr);
+            }
+            if let Some(history) = self.history.as_mut() {
+                if history.len() == HISTORY_LENGTH {
+                    history.pop_front();
+                }
+                history.push_back(self.instruction_addr);
+            }
+            if let Some(mut trace) = self.trace.take() {
+                let result = trace.record(self, self.instruction_addr);
+                self.trace = Some(trace);
+                result?;
+            }
+
             let (opcode, instruction) = self.decode_next_instruction()?;
             self.execute(opcode, instruction)
         } else {
@@ -641,7 +798,7 @@ impl Cpu {
         self.reg_array.write_reg(Register::SP, sp_val)?;
 
         // write value to (SP)
-        self.memory.write(sp_val, value)?;
+        self.write_memory(sp_val, value)?;
 
         Ok(())
     }
@@ -650,7 +807,7 @@ impl Cpu {
     pub fn pop_from_stack(&mut self, size: MemorySize) -> Result<RegisterValue, String> {
         // read from SP
         let mut sp_val = self.reg_array.read_reg(Register::SP);
-        let value = self.memory.read(sp_val, size)?;
+        let value = self.read_memory(sp_val, size)?;
 
         // increase SP by size
         let value_size = size.n_bytes() as u16;
@@ -702,8 +859,12 @@ impl Cpu {
     }
 
     // adds a custom subroutine handler
-    pub fn add_subroutine_handler(&mut self, subroutine_addr: u16, handler: fn(&mut Cpu)) {
-        self.subroutines.insert(subroutine_addr, handler);
+    pub fn add_subroutine_handler(
+        &mut self,
+        subroutine_addr: u16,
+        handler: impl Fn(&mut Cpu) -> Result<(), String> + Send + Sync + 'static,
+    ) {
+        self.subroutines.insert(subroutine_addr, Arc::new(handler));
     }
 
     // returns the total_cycles field
@@ -717,6 +878,34 @@ impl Cpu {
         self.reg_array.write_reg(Register::PC, new_pc)
     }
 
+    // writes a register given by name (A, any register pair, PSW, SP, PC or
+    // an 8-bit register), keeping the accumulator and flags in step with the
+    // PSW
+    pub fn set_register(&mut self, name: &str, value: u16) -> Result<(), String> {
+        use strum::IntoEnumIterator;
+
+        let name = name.to_ascii_uppercase();
+
+        if name == "A" {
+            let value = u8::try_from(value).map_err(|_| format!("{value:X} doesn't fit in A"))?;
+            self.alu.write_accumulator(RegisterValue::from(value))?;
+            return self.update_status_word();
+        }
+
+        let register = Register::iter()
+            .find(|register| register.get_human_readable_name() == name)
+            .ok_or_else(|| format!("Unknown register: {name}"))?;
+
+        let value = match self.reg_array.read_reg(register) {
+            RegisterValue::Integer8(_) => RegisterValue::from(
+                u8::try_from(value).map_err(|_| format!("{value:X} doesn't fit in {name}"))?,
+            ),
+            _ => RegisterValue::from(value),
+        };
+
+        self.write_to_source(InstructionSource::Register(register), value)
+    }
+
     // execute instructions for a given number of cycles, returns the number of
     // 'overshoot' cycles (i.e., how many cycles were executed minus the target
     // number)
@@ -730,10 +919,165 @@ impl Cpu {
         let overshoot = self.total_cycles - target_cycles;
         Ok(overshoot)
     }
+
+    // executes instructions until a breakpoint is hit, the program halts, or
+    // at least the given number of cycles have been executed
+    pub fn run(&mut self, cycles: usize) -> Result<StopReason, String> {
+        self.run_until(Budget::Cycles(cycles), |_, _| false)
+    }
+
+    // executes the next instruction, or the whole of a CALL or RST up until
+    // it returns to the following instruction. a recursive call returning to
+    // the same address deeper in the stack doesn't count
+    pub fn step_over(&mut self, budget: Budget) -> Result<StopReason, String> {
+        let pc = u16::from(self.reg_array.read_reg(Register::PC));
+        let sp = u16::from(self.reg_array.read_reg(Register::SP));
+        let info = opcode_info(u8::try_from(
+            self.memory
+                .read(RegisterValue::from(pc), MemorySize::Integer8)?,
+        )?);
+
+        if !matches!(
+            info.flow,
+            Flow::Call | Flow::ConditionalCall | Flow::Restart(_)
+        ) {
+            return self.run_until(budget, |_, _| true);
+        }
+
+        let return_addr = pc.wrapping_add(info.n_bytes as u16);
+        self.run_until(budget, |cpu, _| {
+            u16::from(cpu.reg_array.read_reg(Register::PC)) == return_addr
+                && stack_depth(cpu, sp) <= 0
+        })
+    }
+
+    // executes until the current subroutine returns. this is the first
+    // return that leaves the stack above where it is now, so any calls made
+    // (recursive or not) are run through, and values pushed since the
+    // subroutine was called are allowed for
+    pub fn step_out(&mut self, budget: Budget) -> Result<StopReason, String> {
+        let sp = u16::from(self.reg_array.read_reg(Register::SP));
+
+        self.run_until(budget, |cpu, flow| {
+            matches!(flow, Flow::Return | Flow::ConditionalReturn) && stack_depth(cpu, sp) < 0
+        })
+    }
+
+    // executes until the program counter reaches an address
+    pub fn run_to(&mut self, addr: u16, budget: Budget) -> Result<StopReason, String> {
+        self.run_until(budget, |cpu, _| {
+            u16::from(cpu.reg_array.read_reg(Register::PC)) == addr
+        })
+    }
+
+    // returns the addresses of the calls that the return addresses on the
+    // stack were pushed by, innermost first. a word on the stack is taken to
+    // be a return address if it follows a CALL or RST, which skips most
+    // pushed values. at most depth words are looked at
+    pub fn call_stack(&self, depth: usize) -> Vec<u16> {
+        let sp = u16::from(self.reg_array.read_reg(Register::SP)) as usize;
+        let read = |addr: usize, size| {
+            self.memory
+                .read(RegisterValue::from(addr as u16), size)
+                .map(u16::from)
+                .ok()
+        };
+
+        (sp..0xFFFF)
+            .step_by(2)
+            .take(depth)
+            .filter_map(|addr| {
+                let return_addr = read(addr, MemorySize::Integer16)?;
+
+                [3, 1].into_iter().find_map(|n_bytes| {
+                    let call = return_addr.wrapping_sub(n_bytes as u16);
+                    let info = opcode_info(read(call as usize, MemorySize::Integer8)? as u8);
+
+                    let is_call = matches!(
+                        info.flow,
+                        Flow::Call | Flow::ConditionalCall | Flow::Restart(_)
+                    );
+                    (is_call && info.n_bytes == n_bytes).then_some(call)
+                })
+            })
+            .collect()
+    }
+
+    // executes instructions until done returns true after one of them (it is
+    // given how the instruction can change the flow of the program), a
+    // breakpoint is hit, the program halts, a call waits for input or the
+    // budget runs out. an execute breakpoint at the starting address is
+    // passed over, so a program stopped at one can be resumed
+    pub fn run_until(
+        &mut self,
+        budget: Budget,
+        mut done: impl FnMut(&Cpu, Flow) -> bool,
+    ) -> Result<StopReason, String> {
+        let start_cycles = self.total_cycles;
+        let mut n_instructions = 0;
+
+        while self.running {
+            let pc = u16::from(self.reg_array.read_reg(Register::PC));
+
+            if n_instructions > 0 {
+                if let Some(hit) = self.check_breakpoints_at(pc, Access::Execute(pc)) {
+                    self.breakpoints.take_hit();
+                    return Ok(StopReason::Breakpoint(hit));
+                }
+            }
+
+            let exhausted = match budget {
+                Budget::Cycles(cycles) => self.total_cycles - start_cycles >= cycles,
+                Budget::Instructions(instructions) => n_instructions >= instructions,
+            };
+            if exhausted {
+                return Ok(StopReason::BudgetExhausted);
+            }
+
+            let opcode = self
+                .memory
+                .read(RegisterValue::from(pc), MemorySize::Integer8)?;
+            let flow = opcode_info(u8::try_from(opcode)?).flow;
+
+            self.execute_next()?;
+            n_instructions += 1;
+
+            // watchpoints stop the program after the instruction accessing
+            if let Some(hit) = self.breakpoints.take_hit() {
+                return Ok(StopReason::Breakpoint(hit));
+            }
+            if self.waiting_for_input {
+                return Ok(StopReason::WaitingForInput);
+            }
+            if self.running && done(self, flow) {
+                return Ok(StopReason::Completed);
+            }
+        }
+
+        Ok(StopReason::Halted)
+    }
+}
+
+// how far the stack has grown since it was at sp, negative once it has
+// shrunk past it. the stack often starts at 0000, so it wraps
+fn stack_depth(cpu: &Cpu, sp: u16) -> i16 {
+    sp.wrapping_sub(u16::from(cpu.reg_array.read_reg(Register::SP))) as i16
+}
+
+// splits a value in memory into its address and value of each byte
+fn memory_bytes(addr: RegisterValue, value: RegisterValue) -> Vec<(u16, u8)> {
+    let n_bytes = value.n_bytes() as u16;
+    let addr = u16::from(addr);
+    let value = u16::from(value).to_le_bytes();
+
+    (0..n_bytes)
+        .map(|i| (addr.wrapping_add(i), value[i as usize]))
+        .collect()
 }
 
 #[cfg(test)]
 mod tests {
+    use super::expression::Expression;
     use super::*;
 
     #[test]
@@ -959,4 +1303,174 @@ mod tests {
             RegisterValue::from(0x1234u16)
         );
     }
+
+    #[test]
+    fn cpu_set_register() {
+        let mut cpu = Cpu::new();
+
+        cpu.set_register("a", 0x42).unwrap();
+        cpu.set_register("HL", 0x1234).unwrap();
+        cpu.set_register("SP", 0xF000).unwrap();
+
+        assert_eq!(cpu.alu.accumulator(), RegisterValue::from(0x42u8));
+        assert_eq!(
+            cpu.reg_array.read_reg(Register::HL),
+            RegisterValue::from(0x1234u16)
+        );
+
+        // writing the PSW sets the accumulator and flags too
+        cpu.set_register("PSW", 0x1041).unwrap();
+        assert_eq!(cpu.alu.accumulator(), RegisterValue::from(0x10u8));
+        assert!(cpu.alu.flags().zero);
+
+        assert!(cpu.set_register("B", 0x100).is_err());
+        assert!(cpu.set_register("Q", 0).is_err());
+    }
+
+    #[test]
+    fn cpu_run_breakpoints() {
+        let mut cpu = Cpu::new();
+        crate::assembler::assemble_into(
+            &mut cpu,
+            "        ORG 100H\n        LXI H,2000H\nLOOP:   INR A\n        MOV M,A\n        CPI 3\n        JNZ LOOP\n        OUT 1\n        HLT\n",
+        )
+        .unwrap();
+        cpu.set_pc(0x0100).unwrap();
+
+        let loop_bp = cpu.breakpoints.add(BreakpointKind::Execute(0x0103));
+        let write_bp = cpu.breakpoints.add_temporary(BreakpointKind::Write {
+            start: 0x2000,
+            end: 0x2000,
+            value: Some(2),
+        });
+        let out_bp = cpu.breakpoints.add(BreakpointKind::PortOut(1));
+
+        // conditions are checked when the breakpoint would be hit
+        let symbols = crate::symbols::SymbolTable::new();
+        let never_bp = cpu.breakpoints.add(BreakpointKind::Write {
+            start: 0x2000,
+            end: 0x2000,
+            value: None,
+        });
+        let condition = |text| Some(Expression::parse(text, &symbols).unwrap());
+        cpu.breakpoints
+            .set_condition(out_bp, condition("A == 3 && !CY"))
+            .unwrap();
+        cpu.breakpoints
+            .set_condition(never_bp, condition("M == 7"))
+            .unwrap();
+
+        // the execute breakpoint at the current address is passed over when
+        // resuming
+        let StopReason::Breakpoint(hit) = cpu.run(1000).unwrap() else {
+            panic!("expected a breakpoint");
+        };
+        assert_eq!((hit.id, hit.pc), (loop_bp, 0x0103));
+        cpu.breakpoints.set_enabled(loop_bp, false).unwrap();
+
+        // watchpoints stop after the instruction writing
+        let StopReason::Breakpoint(hit) = cpu.run(1000).unwrap() else {
+            panic!("expected a breakpoint");
+        };
+        assert_eq!((hit.id, hit.pc), (write_bp, 0x0104));
+        assert_eq!(u16::from(cpu.reg_array.read_reg(Register::PC)), 0x0105);
+
+        let StopReason::Breakpoint(hit) = cpu.run(1000).unwrap() else {
+            panic!("expected a breakpoint");
+        };
+        assert_eq!(hit.access, Access::PortOut { port: 1, value: 3 });
+
+        assert_eq!(cpu.run(1000).unwrap(), StopReason::Halted);
+        assert_eq!(cpu.breakpoints.len(), 3);
+        assert_eq!(cpu.breakpoints.get(never_bp).unwrap().hits, 0);
+    }
+
+    #[test]
+    fn cpu_step_over_out() {
+        let recursive = || {
+            let mut cpu = Cpu::new();
+            crate::assembler::assemble_into(
+                &mut cpu,
+                "        ORG 100H\n        LXI SP,3000H\n        MVI A,3\n        CALL REC\n        HLT\nREC:    DCR A\n        RZ\n        PUSH PSW\n        CALL REC\n        POP PSW\n        RET\n",
+            )
+            .unwrap();
+            cpu.set_pc(0x0100).unwrap();
+            cpu
+        };
+        let pc = |cpu: &Cpu| u16::from(cpu.reg_array.read_reg(Register::PC));
+        let sp = |cpu: &Cpu| u16::from(cpu.reg_array.read_reg(Register::SP));
+        let budget = Budget::Cycles(10_000);
+
+        // stepping over the outer call runs the whole recursion
+        let mut cpu = recursive();
+        cpu.step_over(budget).unwrap();
+        cpu.step_over(budget).unwrap();
+        assert_eq!(pc(&cpu), 0x0105);
+        assert_eq!(cpu.step_over(budget).unwrap(), StopReason::Completed);
+        assert_eq!((pc(&cpu), sp(&cpu)), (0x0108, 0x3000));
+
+        // the recursive call returns to the same address deeper first
+        let mut cpu = recursive();
+        cpu.run_to(0x010C, budget).unwrap();
+        let frame_sp = sp(&cpu);
+        assert_eq!(cpu.step_over(budget).unwrap(), StopReason::Completed);
+        assert_eq!((pc(&cpu), sp(&cpu)), (0x010F, frame_sp));
+
+        // stepping out of the first level skips over the deeper ones
+        let mut cpu = recursive();
+        cpu.run_to(0x010A, budget).unwrap();
+        assert_eq!(cpu.call_stack(8), vec![0x0105]);
+        assert_eq!(cpu.step_out(budget).unwrap(), StopReason::Completed);
+        assert_eq!((pc(&cpu), sp(&cpu)), (0x0108, 0x3000));
+
+        let mut cpu = recursive();
+        assert_eq!(
+            cpu.run_to(0x0200, Budget::Instructions(3)).unwrap(),
+            StopReason::BudgetExhausted
+        );
+        assert_eq!(pc(&cpu), 0x0109);
+
+        // with the stack at 0000, returning wraps it back there
+        let mut cpu = Cpu::new();
+        crate::assembler::assemble_into(
+            &mut cpu,
+            "        ORG 100H\n        CALL SUB\n        HLT\nSUB:    PUSH PSW\n        POP PSW\n        RET\n",
+        )
+        .unwrap();
+        cpu.set_pc(0x0100).unwrap();
+        assert_eq!(cpu.step_over(budget).unwrap(), StopReason::Completed);
+        assert_eq!((pc(&cpu), sp(&cpu)), (0x0103, 0x0000));
+
+        cpu.set_pc(0x0100).unwrap();
+        cpu.run_to(0x0105, budget).unwrap();
+        assert_eq!(cpu.step_out(budget).unwrap(), StopReason::Completed);
+        assert_eq!((pc(&cpu), sp(&cpu)), (0x0103, 0x0000));
+
+        // stepping the HLT halts rather than completing the step
+        assert_eq!(cpu.step_over(budget).unwrap(), StopReason::Halted);
+    }
+
+    #[test]
+    fn cpu_jump_to_zero() {
+        // JMP 0 from 0100, with MVI A,1; HLT at 0
+        let program = || {
+            let mut cpu = Cpu::new();
+            cpu.load_to_memory(vec![0x3E, 0x01, 0x76], 0x0000).unwrap();
+            cpu.load_to_memory(vec![0xC3, 0x00, 0x00], 0x0100).unwrap();
+            cpu.set_pc(0x0100).unwrap();
+            cpu
+        };
+
+        // on bare metal it is an ordinary jump
+        let mut cpu = program();
+        cpu.run(100).unwrap();
+        assert_eq!(cpu.alu.accumulator(), RegisterValue::from(1u8));
+
+        // under CP/M it warm boots, which ends the program
+        let mut cpu = program();
+        cpu.warm_boot_stops = true;
+        cpu.execute_next().unwrap();
+        assert!(!cpu.is_running());
+        assert_eq!(cpu.alu.accumulator(), RegisterValue::from(0u8));
+    }
 }
@@ -16,6 +16,12 @@ pub struct AluFlags {
     pub aux_carry: bool, // aka half carry
 }
 
+impl Default for AluFlags {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl AluFlags {
     // creates a new instance of AluFlags with all values defaulting to false
     pub fn new() -> Self {
@@ -213,6 +219,12 @@ pub struct Alu {
     flags: AluFlags,            // 5-bit flags register
 }
 
+impl Default for Alu {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Alu {
     // creates a new empty instance of Alu
     pub fn new() -> Self {
@@ -360,7 +372,7 @@ impl Alu {
 
         self.flags.zero = result == 0;
         self.flags.sign = result & 0x80 != 0;
-        self.flags.parity = result.count_ones() % 2 == 0;
+        self.flags.parity = result.count_ones().is_multiple_of(2);
         self.flags.carry = x.checked_add(y).is_none();
 
         // auxiliary carry has to be found manually
@@ -385,7 +397,7 @@ impl Alu {
 
         self.flags.zero = result == 0;
         self.flags.sign = result & 0x80 != 0;
-        self.flags.parity = result.count_ones() % 2 == 0;
+        self.flags.parity = result.count_ones().is_multiple_of(2);
         self.flags.carry = x.checked_sub(y).is_none();
 
         // auxiliary carry has to be found manually
@@ -459,7 +471,7 @@ impl Alu {
 
         self.flags.zero = result == 0;
         self.flags.sign = result & 0x80 != 0;
-        self.flags.parity = result.count_ones() % 2 == 0;
+        self.flags.parity = result.count_ones().is_multiple_of(2);
         self.flags.carry = false;
         self.flags.aux_carry = false;
 
@@ -472,7 +484,7 @@ impl Alu {
 
         self.flags.zero = result == 0;
         self.flags.sign = result & 0x80 != 0;
-        self.flags.parity = result.count_ones() % 2 == 0;
+        self.flags.parity = result.count_ones().is_multiple_of(2);
         self.flags.carry = false;
         self.flags.aux_carry = false;
 
@@ -485,7 +497,7 @@ impl Alu {
 
         self.flags.zero = result == 0;
         self.flags.sign = result & 0x80 != 0;
-        self.flags.parity = result.count_ones() % 2 == 0;
+        self.flags.parity = result.count_ones().is_multiple_of(2);
         self.flags.carry = false;
         self.flags.aux_carry = false;
 
@@ -820,9 +832,7 @@ mod tests {
             ))
             .unwrap()
             .unwrap();
-        let result = alu
-            .evaluate(AluOperation::DecimalAdjust(RegisterValue::from(result)))
-            .unwrap();
+        let result = alu.evaluate(AluOperation::DecimalAdjust(result)).unwrap();
         assert_eq!(result.unwrap(), RegisterValue::from(0x8u8));
         assert_eq!(
             alu.flags(),
@@ -837,9 +847,7 @@ mod tests {
             ))
             .unwrap()
             .unwrap();
-        let result = alu
-            .evaluate(AluOperation::DecimalAdjust(RegisterValue::from(result)))
-            .unwrap();
+        let result = alu.evaluate(AluOperation::DecimalAdjust(result)).unwrap();
         assert_eq!(result.unwrap(), RegisterValue::from(0x42u8));
         assert_eq!(
             alu.flags(),
@@ -854,9 +862,7 @@ mod tests {
             ))
             .unwrap()
             .unwrap();
-        let result = alu
-            .evaluate(AluOperation::DecimalAdjust(RegisterValue::from(result)))
-            .unwrap();
+        let result = alu.evaluate(AluOperation::DecimalAdjust(result)).unwrap();
         assert_eq!(result.unwrap(), RegisterValue::from(0x00u8));
         assert_eq!(
             alu.flags(),
@@ -0,0 +1,375 @@
+/*
+ * breakpoints.rs - Contains code related to breakpoints, which stop a running
+ * program when it reaches an address, accesses memory or uses an I/O port
+ */
+use super::expression::Expression;
+use crate::symbols::SymbolTable;
+
+use std::collections::BTreeMap;
+use std::fmt;
+
+// BreakpointKind enum - what makes a breakpoint stop the program
+#[derive(Debug, Clone, PartialEq)]
+pub enum BreakpointKind {
+    // before the instruction at the address is executed
+    Execute(u16),
+
+    // after an instruction reads or writes a byte in the range start..=end,
+    // optionally only when the byte is the given value
+    Read {
+        start: u16,
+        end: u16,
+        value: Option<u8>,
+    },
+    Write {
+        start: u16,
+        end: u16,
+        value: Option<u8>,
+    },
+
+    // after an IN or OUT instruction uses the port
+    PortIn(u8),
+    PortOut(u8),
+}
+
+impl BreakpointKind {
+    // returns whether an access sets off a breakpoint of this kind
+    fn matches(&self, access: &Access) -> bool {
+        use BreakpointKind as Kind;
+
+        let in_range = |addr: u16, start: u16, end: u16, value: Option<u8>, actual: u8| {
+            (start..=end).contains(&addr) && value.is_none_or(|value| value == actual)
+        };
+
+        match (self, access) {
+            (Kind::Execute(addr), Access::Execute(pc)) => addr == pc,
+            (
+                Kind::Read { start, end, value },
+                Access::Read {
+                    addr,
+                    value: actual,
+                },
+            ) => in_range(*addr, *start, *end, *value, *actual),
+            (
+                Kind::Write { start, end, value },
+                Access::Write {
+                    addr,
+                    value: actual,
+                },
+            ) => in_range(*addr, *start, *end, *value, *actual),
+            (Kind::PortIn(port), Access::PortIn { port: actual, .. }) => port == actual,
+            (Kind::PortOut(port), Access::PortOut { port: actual, .. }) => port == actual,
+            _ => false,
+        }
+    }
+
+    // describes the breakpoint, with addresses shown by symbol
+    pub fn describe(&self, symbols: &SymbolTable) -> String {
+        let range = |start: u16, end: u16| match start == end {
+            true => symbols.format_addr(start),
+            false => format!(
+                "{}-{}",
+                symbols.format_addr(start),
+                symbols.format_addr(end)
+            ),
+        };
+        let condition = |value: Option<u8>| match value {
+            Some(value) => format!(" = {value:02X}"),
+            None => String::new(),
+        };
+
+        match self {
+            Self::Execute(addr) => format!("execute {}", symbols.format_addr(*addr)),
+            Self::Read { start, end, value } => {
+                format!("read {}{}", range(*start, *end), condition(*value))
+            }
+            Self::Write { start, end, value } => {
+                format!("write {}{}", range(*start, *end), condition(*value))
+            }
+            Self::PortIn(port) => format!("in port {port:02X}"),
+            Self::PortOut(port) => format!("out port {port:02X}"),
+        }
+    }
+}
+
+impl fmt::Display for BreakpointKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.describe(&SymbolTable::new()))
+    }
+}
+
+// Access enum - an access by the program that breakpoints are checked against
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Access {
+    Execute(u16),
+    Read { addr: u16, value: u8 },
+    Write { addr: u16, value: u8 },
+    PortIn { port: u8, value: u8 },
+    PortOut { port: u8, value: u8 },
+}
+
+// Breakpoint struct - a breakpoint, and how many times it has been hit
+#[derive(Debug, Clone, PartialEq)]
+pub struct Breakpoint {
+    pub id: usize,
+    pub kind: BreakpointKind,
+    pub enabled: bool,
+    pub temporary: bool,               // removed once it stops the program
+    pub hits: usize,                   // hits while enabled, including ignored ones
+    pub ignore: usize,                 // number of hits to let pass before stopping
+    pub condition: Option<Expression>, // only hit when this is true
+}
+
+// BreakpointHit struct - a breakpoint that stopped the program, and the
+// access that set it off
+#[derive(Debug, Clone, PartialEq)]
+pub struct BreakpointHit {
+    pub id: usize,
+    pub kind: BreakpointKind,
+    pub pc: u16, // address of the instruction that made the access
+    pub access: Access,
+}
+
+impl BreakpointHit {
+    // describes the hit, with addresses shown by symbol
+    pub fn describe(&self, symbols: &SymbolTable) -> String {
+        let access = match self.access {
+            Access::Execute(_) => String::new(),
+            Access::Read { addr, value } => {
+                format!(": read {value:02X} from {}", symbols.format_addr(addr))
+            }
+            Access::Write { addr, value } => {
+                format!(": wrote {value:02X} to {}", symbols.format_addr(addr))
+            }
+            Access::PortIn { port, value } => format!(": read {value:02X} from port {port:02X}"),
+            Access::PortOut { port, value } => format!(": wrote {value:02X} to port {port:02X}"),
+        };
+
+        format!(
+            "Breakpoint {} ({}) at {}{access}",
+            self.id,
+            self.kind.describe(symbols),
+            symbols.format_addr(self.pc)
+        )
+    }
+}
+
+impl fmt::Display for BreakpointHit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.describe(&SymbolTable::new()))
+    }
+}
+
+// Breakpoints struct - the breakpoints of a Cpu, numbered from 1
+#[derive(Debug, Clone, Default)]
+pub struct Breakpoints {
+    breakpoints: BTreeMap<usize, Breakpoint>,
+    next_id: usize,
+    hit: Option<BreakpointHit>, // set by an access, until it is taken
+}
+
+impl Breakpoints {
+    // creates a new empty instance of Breakpoints
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // adds a breakpoint, returns its id
+    pub fn add(&mut self, kind: BreakpointKind) -> usize {
+        self.next_id += 1;
+        self.breakpoints.insert(
+            self.next_id,
+            Breakpoint {
+                id: self.next_id,
+                kind,
+                enabled: true,
+                temporary: false,
+                hits: 0,
+                ignore: 0,
+                condition: None,
+            },
+        );
+        self.next_id
+    }
+
+    // adds a breakpoint that is removed once it stops the program
+    pub fn add_temporary(&mut self, kind: BreakpointKind) -> usize {
+        let id = self.add(kind);
+        self.breakpoints.get_mut(&id).unwrap().temporary = true;
+        id
+    }
+
+    pub fn remove(&mut self, id: usize) -> Result<Breakpoint, String> {
+        self.breakpoints
+            .remove(&id)
+            .ok_or_else(|| format!("No breakpoint {id}"))
+    }
+
+    pub fn clear(&mut self) {
+        self.breakpoints.clear();
+        self.hit = None;
+    }
+
+    pub fn set_enabled(&mut self, id: usize, enabled: bool) -> Result<(), String> {
+        self.get_mut(id)?.enabled = enabled;
+        Ok(())
+    }
+
+    // sets how many hits a breakpoint lets pass before it stops the program
+    pub fn set_ignore(&mut self, id: usize, ignore: usize) -> Result<(), String> {
+        self.get_mut(id)?.ignore = ignore;
+        Ok(())
+    }
+
+    // sets the condition a breakpoint is only hit when, None to always hit
+    pub fn set_condition(
+        &mut self,
+        id: usize,
+        condition: Option<Expression>,
+    ) -> Result<(), String> {
+        self.get_mut(id)?.condition = condition;
+        Ok(())
+    }
+
+    pub fn get(&self, id: usize) -> Option<&Breakpoint> {
+        self.breakpoints.get(&id)
+    }
+
+    fn get_mut(&mut self, id: usize) -> Result<&mut Breakpoint, String> {
+        self.breakpoints
+            .get_mut(&id)
+            .ok_or_else(|| format!("No breakpoint {id}"))
+    }
+
+    // returns the execute breakpoint at an address, if there is one
+    pub fn at(&self, addr: u16) -> Option<&Breakpoint> {
+        self.iter()
+            .find(|breakpoint| breakpoint.kind == BreakpointKind::Execute(addr))
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Breakpoint> {
+        self.breakpoints.values()
+    }
+
+    pub fn len(&self) -> usize {
+        self.breakpoints.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.breakpoints.is_empty()
+    }
+
+    // checks an access made by the instruction at pc against the enabled
+    // breakpoints, counting a hit on each one it sets off. the first one
+    // that isn't ignored is kept as the hit that stops the program.
+    // conditions are taken to be true
+    pub fn check(&mut self, pc: u16, access: Access) -> Option<&BreakpointHit> {
+        self.check_if(pc, access, |_| true)
+    }
+
+    // checks an access like check, with breakpoints that have a condition
+    // only set off when is_true says it is
+    pub fn check_if(
+        &mut self,
+        pc: u16,
+        access: Access,
+        is_true: impl Fn(&Expression) -> bool,
+    ) -> Option<&BreakpointHit> {
+        if self.breakpoints.is_empty() {
+            return None;
+        }
+
+        let mut stopped = None;
+        for breakpoint in self.breakpoints.values_mut() {
+            if !breakpoint.enabled
+                || !breakpoint.kind.matches(&access)
+                || !breakpoint.condition.as_ref().is_none_or(&is_true)
+            {
+                continue;
+            }
+
+            breakpoint.hits += 1;
+            if stopped.is_none() && breakpoint.hits > breakpoint.ignore {
+                stopped = Some(breakpoint.id);
+            }
+        }
+
+        let breakpoint = self.breakpoints.get(&stopped?)?.clone();
+        if breakpoint.temporary {
+            self.breakpoints.remove(&breakpoint.id);
+        }
+
+        // the first hit of an instruction is the one reported
+        if self.hit.is_none() {
+            self.hit = Some(BreakpointHit {
+                id: breakpoint.id,
+                kind: breakpoint.kind,
+                pc,
+                access,
+            });
+        }
+        self.hit.as_ref()
+    }
+
+    // takes the hit set by the last instruction, if any
+    pub fn take_hit(&mut self) -> Option<BreakpointHit> {
+        self.hit.take()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn breakpoints_check() {
+        let mut breakpoints = Breakpoints::new();
+        let execute = breakpoints.add(BreakpointKind::Execute(0x0100));
+        let write = breakpoints.add_temporary(BreakpointKind::Write {
+            start: 0x2000,
+            end: 0x20FF,
+            value: Some(0x24),
+        });
+        breakpoints.set_ignore(execute, 1).unwrap();
+
+        // the first hit is ignored, but still counted
+        assert!(breakpoints.check(0x0100, Access::Execute(0x0100)).is_none());
+        assert!(breakpoints.check(0x0100, Access::Execute(0x0100)).is_some());
+        assert_eq!(breakpoints.take_hit().unwrap().id, execute);
+        assert_eq!(breakpoints.get(execute).unwrap().hits, 2);
+
+        // value conditions, and temporary breakpoints are removed once hit
+        let other = Access::Write {
+            addr: 0x2010,
+            value: 0x00,
+        };
+        let dollar = Access::Write {
+            addr: 0x2010,
+            value: 0x24,
+        };
+        assert!(breakpoints.check(0x0105, other).is_none());
+        assert_eq!(breakpoints.check(0x0105, dollar).unwrap().id, write);
+        assert!(breakpoints.get(write).is_none());
+        breakpoints.take_hit();
+
+        // disabled breakpoints don't count hits
+        breakpoints.set_enabled(execute, false).unwrap();
+        assert!(breakpoints.check(0x0100, Access::Execute(0x0100)).is_none());
+        assert_eq!(breakpoints.get(execute).unwrap().hits, 2);
+
+        let symbols: SymbolTable = [("BUFFER", 0x2000)].into_iter().collect();
+        let hit = BreakpointHit {
+            id: 2,
+            kind: BreakpointKind::PortOut(0x01),
+            pc: 0x2004,
+            access: Access::PortOut {
+                port: 0x01,
+                value: 0x41,
+            },
+        };
+        assert_eq!(
+            hit.describe(&symbols),
+            "Breakpoint 2 (out port 01) at 2004 (BUFFER+4): wrote 41 to port 01"
+        );
+    }
+}
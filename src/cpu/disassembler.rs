@@ -0,0 +1,540 @@
+/*
+ * disassembler.rs - Contains code related to turning the contents of memory
+ * back into Intel 8080 assembly language
+ * See the Intel 8080 Assembly Language Programming Manual for the syntax
+ */
+
+use super::instruction::*;
+use super::memory::*;
+use super::opcodes::opcode_info;
+use super::registers::*;
+use crate::symbols::SymbolTable;
+
+use std::fmt;
+use std::str::FromStr;
+
+// DisassembledInstruction struct - a single instruction read from memory
+#[derive(Debug, Clone, PartialEq)]
+pub struct DisassembledInstruction {
+    pub addr: u16,
+    pub bytes: Vec<u8>, // the opcode followed by its operand bytes
+    pub text: String,   // e.g. "MVI A,3FH"
+}
+
+impl DisassembledInstruction {
+    // returns the length of the instruction in bytes
+    pub fn n_bytes(&self) -> usize {
+        self.bytes.len()
+    }
+
+    // returns the operand that follows the opcode, if there is one
+    pub fn operand(&self) -> Option<u16> {
+        match self.bytes[..] {
+            [_, low] => Some(low as u16),
+            [_, low, high] => Some(u16::from_le_bytes([low, high])),
+            _ => None,
+        }
+    }
+
+    // replaces an address operand with the symbol it refers to, e.g.
+    // "CALL 0105H" becomes "CALL LOOP+2"
+    pub fn with_symbols(mut self, symbols: &SymbolTable) -> Self {
+        if let (Some(operand), 3) = (self.operand(), self.n_bytes()) {
+            if let Some(symbol) = symbols.describe(operand) {
+                self.text = self.text.replacen(&format_word(operand), &symbol, 1);
+            }
+        }
+
+        self
+    }
+}
+
+impl fmt::Display for DisassembledInstruction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let bytes: Vec<String> = self.bytes.iter().map(|b| format!("{b:02X}")).collect();
+
+        write!(f, "{:04X}  {:<9} {}", self.addr, bytes.join(" "), self.text)
+    }
+}
+
+// disassembles the instruction at the given address. memory is only read, so
+// this can be used on a running program
+pub fn disassemble(
+    memory: &Memory,
+    addr: u16,
+    syntax: Syntax,
+) -> Result<DisassembledInstruction, String> {
+    let opcode = read_byte(memory, addr)?;
+    let info = opcode_info(opcode);
+
+    // opcodes that aren't supported are shown as data
+    if !info.valid {
+        return Ok(DisassembledInstruction {
+            addr,
+            bytes: vec![opcode],
+            text: format!("DB {}", format_byte(opcode)),
+        });
+    }
+
+    let instruction = Instruction::decode(RegisterValue::from(opcode))?;
+    let bytes = (0..info.n_bytes as u16)
+        .map(|i| read_byte(memory, addr.wrapping_add(i)))
+        .collect::<Result<Vec<u8>, String>>()?;
+
+    let operand = match bytes[..] {
+        [_, low] => Operand::Byte(low),
+        [_, low, high] => Operand::Word(u16::from_le_bytes([low, high])),
+        _ => Operand::None,
+    };
+
+    Ok(DisassembledInstruction {
+        addr,
+        bytes,
+        text: format_instruction(&instruction, operand, syntax),
+    })
+}
+
+// disassembles every instruction starting in the len bytes from start, which
+// may run up to the end of memory. the last instruction may extend past them
+pub fn disassemble_range(
+    memory: &Memory,
+    start: u16,
+    len: usize,
+    syntax: Syntax,
+) -> Result<Vec<DisassembledInstruction>, String> {
+    let mut instructions = Vec::new();
+    let mut addr = start as usize;
+    let end = (start as usize + len).min(0x10000);
+
+    while addr < end {
+        let instruction = disassemble(memory, addr as u16, syntax)?;
+        addr += instruction.n_bytes();
+        instructions.push(instruction);
+    }
+
+    Ok(instructions)
+}
+
+// finds where to start disassembling to show some instructions before an
+// address, disassembling from far enough back that the instructions line up
+// with it. the address itself if they don't
+pub fn instructions_before(memory: &Memory, addr: u16, count: usize, syntax: Syntax) -> u16 {
+    let start = addr.saturating_sub(u16::try_from(count.saturating_mul(3)).unwrap_or(u16::MAX));
+    let Ok(instructions) = disassemble_range(memory, start, (addr - start) as usize, syntax) else {
+        return addr;
+    };
+
+    match instructions.last() {
+        Some(last) if last.addr.wrapping_add(last.n_bytes() as u16) == addr => {
+            instructions[instructions.len().saturating_sub(count)].addr
+        }
+        _ => addr,
+    }
+}
+
+// Operand enum - the immediate operand of an instruction
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Operand {
+    None,
+    Byte(u8),
+    Word(u16),
+}
+
+impl fmt::Display for Operand {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Operand::None => Ok(()),
+            Operand::Byte(value) => write!(f, "{}", format_byte(*value)),
+            Operand::Word(value) => write!(f, "{}", format_word(*value)),
+        }
+    }
+}
+
+// Syntax enum - the assembly language an instruction is written in. Zilog
+// syntax uses the Z80 mnemonics for the 8080 subset
+#[derive(Debug, Default, PartialEq, Clone, Copy)]
+pub enum Syntax {
+    #[default]
+    Intel,
+    Zilog,
+}
+
+impl FromStr for Syntax {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "intel" => Ok(Self::Intel),
+            "zilog" | "z80" => Ok(Self::Zilog),
+            _ => Err(format!("Unknown syntax: {s} (expected intel or zilog)")),
+        }
+    }
+}
+
+// Form struct - how an instruction is written in each syntax. in the
+// templates, {d} is replaced by the destination, {s} by the source, {c} by the
+// condition and {n} by the immediate operand
+struct Form {
+    intel: &'static str,
+    zilog: &'static str,
+}
+
+const fn form(intel: &'static str, zilog: &'static str) -> Form {
+    Form { intel, zilog }
+}
+
+// returns the form of an instruction. every variant of Instruction has an
+// entry here, so both syntaxes always cover the same instructions
+fn instruction_form(instruction: &Instruction) -> Form {
+    use Instruction::*;
+    use InstructionSource as Src;
+
+    // immediate data is read from the program counter
+    let is_immediate =
+        |source: &Src| matches!(source, Src::Memory(MemorySource::ProgramCounter, _));
+    let is_indirect = |source: &Src| matches!(source, Src::Memory(MemorySource::Register(pair), _) if *pair != Register::HL);
+
+    match instruction {
+        Nop => form("NOP", "NOP"),
+
+        Load(Src::Register(_)) => form("LHLD {n}", "LD HL,({n})"),
+        Load(_) => form("LDA {n}", "LD A,({n})"),
+        Store(Src::Register(_)) => form("SHLD {n}", "LD ({n}),HL"),
+        Store(_) => form("STA {n}", "LD ({n}),A"),
+
+        Increment(source) if is_pair(source) => form("INX {s}", "INC {s}"),
+        Increment(_) => form("INR {s}", "INC {s}"),
+        Decrement(source) if is_pair(source) => form("DCX {s}", "DEC {s}"),
+        Decrement(_) => form("DCR {s}", "DEC {s}"),
+
+        Move(dest, source) if is_immediate(source) && is_pair(dest) => {
+            form("LXI {d},{n}", "LD {d},{n}")
+        }
+        Move(_, source) if is_immediate(source) => form("MVI {d},{n}", "LD {d},{n}"),
+        Move(dest, _) if is_indirect(dest) => form("STAX {d}", "LD {d},A"),
+        Move(_, source) if is_indirect(source) => form("LDAX {s}", "LD A,{s}"),
+        Move(Src::Register(Register::SP), _) => form("SPHL", "LD SP,HL"),
+        Move(..) => form("MOV {d},{s}", "LD {d},{s}"),
+
+        RotateLeft(_) => form("RLC", "RLCA"),
+        RotateLeftThroughCarry(_) => form("RAL", "RLA"),
+        RotateRight(_) => form("RRC", "RRCA"),
+        RotateRightThroughCarry(_) => form("RAR", "RRA"),
+        DecimalAdjust(_) => form("DAA", "DAA"),
+        DoubleByteAdd(_) => form("DAD {s}", "ADD HL,{s}"),
+        Complement(_) => form("CMA", "CPL"),
+        SetCarry => form("STC", "SCF"),
+        ComplementCarry => form("CMC", "CCF"),
+        Halt => form("HLT", "HALT"),
+
+        Add(_, source) if is_immediate(source) => form("ADI {n}", "ADD A,{n}"),
+        Add(..) => form("ADD {s}", "ADD A,{s}"),
+        AddWithCarry(_, source) if is_immediate(source) => form("ACI {n}", "ADC A,{n}"),
+        AddWithCarry(..) => form("ADC {s}", "ADC A,{s}"),
+        Subtract(_, source) if is_immediate(source) => form("SUI {n}", "SUB {n}"),
+        Subtract(..) => form("SUB {s}", "SUB {s}"),
+        SubtractWithBorrow(_, source) if is_immediate(source) => form("SBI {n}", "SBC A,{n}"),
+        SubtractWithBorrow(..) => form("SBB {s}", "SBC A,{s}"),
+        BitwiseAnd(_, source) if is_immediate(source) => form("ANI {n}", "AND {n}"),
+        BitwiseAnd(..) => form("ANA {s}", "AND {s}"),
+        BitwiseXor(_, source) if is_immediate(source) => form("XRI {n}", "XOR {n}"),
+        BitwiseXor(..) => form("XRA {s}", "XOR {s}"),
+        BitwiseOr(_, source) if is_immediate(source) => form("ORI {n}", "OR {n}"),
+        BitwiseOr(..) => form("ORA {s}", "OR {s}"),
+        Comparison(_, source) if is_immediate(source) => form("CPI {n}", "CP {n}"),
+        Comparison(..) => form("CMP {s}", "CP {s}"),
+
+        ReturnConditional(_) => form("R{c}", "RET {c}"),
+        JumpConditional(_) => form("J{c} {n}", "JP {c},{n}"),
+        CallConditional(_) => form("C{c} {n}", "CALL {c},{n}"),
+        Jump => form("JMP {n}", "JP {n}"),
+        Call => form("CALL {n}", "CALL {n}"),
+        Return => form("RET", "RET"),
+        StackPop(_) => form("POP {s}", "POP {s}"),
+        StackPush(_) => form("PUSH {s}", "PUSH {s}"),
+        Reset(_) => form("RST {s}", "RST {s}"),
+        IoOut => form("OUT {n}", "OUT ({n}),A"),
+        IoIn => form("IN {n}", "IN A,({n})"),
+
+        Exchange(_, Src::Memory(..)) => form("XTHL", "EX (SP),HL"),
+        Exchange(Src::Register(Register::PC), _) => form("PCHL", "JP (HL)"),
+        Exchange(..) => form("XCHG", "EX DE,HL"),
+
+        DisableInterrupts => form("DI", "DI"),
+        EnableInterrupts => form("EI", "EI"),
+    }
+}
+
+// formats an instruction in the given syntax
+fn format_instruction(instruction: &Instruction, operand: Operand, syntax: Syntax) -> String {
+    instruction_template(instruction, syntax).replace("{n}", &operand.to_string())
+}
+
+// returns how the instruction with the given opcode is written, with {n} in
+// place of its immediate operand, along with its length in bytes. this is
+// what the assembler encodes instructions with
+pub fn opcode_template(opcode: u8, syntax: Syntax) -> Option<(String, usize)> {
+    let info = opcode_info(opcode);
+    let instruction = Instruction::decode(RegisterValue::from(opcode))
+        .ok()
+        .filter(|_| info.valid)?;
+
+    Some((instruction_template(&instruction, syntax), info.n_bytes))
+}
+
+// fills in the registers and condition of an instruction's form, leaving
+// {n} for the immediate operand
+fn instruction_template(instruction: &Instruction, syntax: Syntax) -> String {
+    use Instruction::*;
+
+    let form = instruction_form(instruction);
+    let template = match syntax {
+        Syntax::Intel => form.intel,
+        Syntax::Zilog => form.zilog,
+    };
+
+    // the single operand of INR, PUSH etc. is treated as the source
+    let (dest, source) = match instruction {
+        Move(dest, source) | Exchange(dest, source) => (Some(dest), Some(source)),
+        Add(_, source)
+        | AddWithCarry(_, source)
+        | Subtract(_, source)
+        | SubtractWithBorrow(_, source)
+        | BitwiseAnd(_, source)
+        | BitwiseXor(_, source)
+        | BitwiseOr(_, source)
+        | Comparison(_, source)
+        | Increment(source)
+        | Decrement(source)
+        | DoubleByteAdd(source)
+        | StackPop(source)
+        | StackPush(source)
+        | Reset(source) => (None, Some(source)),
+        _ => (None, None),
+    };
+
+    let condition = match instruction {
+        ReturnConditional(condition) | JumpConditional(condition) | CallConditional(condition) => {
+            format_condition(condition)
+        }
+        _ => "",
+    };
+
+    let format = |source: Option<&InstructionSource>| {
+        source
+            .map(|source| format_source(source, syntax))
+            .unwrap_or_default()
+    };
+
+    template
+        .replace("{d}", &format(dest))
+        .replace("{s}", &format(source))
+        .replace("{c}", condition)
+}
+
+// returns whether a source is a register pair
+fn is_pair(source: &InstructionSource) -> bool {
+    matches!(source, InstructionSource::Register(register) if register.n_bytes() == 2)
+}
+
+// formats a register, register pair or memory operand
+fn format_source(source: &InstructionSource, syntax: Syntax) -> String {
+    use InstructionSource as Src;
+
+    match (source, syntax) {
+        (Src::Register(register), _) if register.n_bytes() == 2 => {
+            format_pair(*register, syntax).to_string()
+        }
+        (Src::Register(register), _) => register.get_human_readable_name().to_string(),
+
+        // M in Intel syntax, except for STAX/LDAX which name the pair
+        (Src::Memory(MemorySource::Register(Register::HL), _), Syntax::Intel) => String::from("M"),
+        (Src::Memory(MemorySource::Register(pair), _), Syntax::Intel) => {
+            format_pair(*pair, syntax).to_string()
+        }
+        (Src::Memory(MemorySource::Register(pair), _), Syntax::Zilog) => {
+            format!("({})", format_pair(*pair, syntax))
+        }
+        (Src::Memory(..), _) => String::new(),
+
+        (Src::Accumulator, _) => String::from("A"),
+
+        // RST is given the vector number in Intel syntax, and the address in
+        // Zilog syntax
+        (Src::Value(value), Syntax::Intel) => u16::from(*value).to_string(),
+        (Src::Value(value), Syntax::Zilog) => format_byte(u16::from(*value) as u8 * 8),
+
+        (Src::Sum(..), _) => String::new(),
+    }
+}
+
+// register pairs are named after their first register in Intel syntax
+fn format_pair(pair: Register, syntax: Syntax) -> &'static str {
+    match (pair, syntax) {
+        (Register::BC, Syntax::Intel) => "B",
+        (Register::DE, Syntax::Intel) => "D",
+        (Register::HL, Syntax::Intel) => "H",
+        (Register::PSW, Syntax::Zilog) => "AF",
+        (other, _) => other.get_human_readable_name(),
+    }
+}
+
+// conditions are written the same way in both syntaxes
+fn format_condition(condition: &InstructionCondition) -> &'static str {
+    use InstructionCondition::*;
+
+    match condition {
+        NotZero => "NZ",
+        Zero => "Z",
+        NoCarry => "NC",
+        Carry => "C",
+        ParityOdd => "PO",
+        ParityEven => "PE",
+        Plus => "P",
+        Minus => "M",
+    }
+}
+
+// formats a hex number the way the Intel assembler expects it, with an H
+// suffix and a leading 0 if it would otherwise start with a letter
+fn format_hex(digits: String) -> String {
+    match digits.starts_with(|c: char| c.is_ascii_alphabetic()) {
+        true => format!("0{digits}H"),
+        false => format!("{digits}H"),
+    }
+}
+
+pub fn format_byte(value: u8) -> String {
+    format_hex(format!("{value:02X}"))
+}
+
+pub fn format_word(value: u16) -> String {
+    format_hex(format!("{value:04X}"))
+}
+
+fn read_byte(memory: &Memory, addr: u16) -> Result<u8, String> {
+    Ok(u8::try_from(
+        memory.read(RegisterValue::from(addr), MemorySize::Integer8)?,
+    )?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // disassembles a program loaded at 0x100
+    fn disassemble_program(program: &[u8], syntax: Syntax) -> Vec<String> {
+        let mut memory = Memory::new();
+        for (i, value) in program.iter().enumerate() {
+            memory
+                .write(
+                    RegisterValue::from(0x100 + i as u16),
+                    RegisterValue::from(*value),
+                )
+                .unwrap();
+        }
+
+        disassemble_range(&memory, 0x100, program.len(), syntax)
+            .unwrap()
+            .iter()
+            .map(|instruction| instruction.text.clone())
+            .collect()
+    }
+
+    const PROGRAM: [u8; 19] = [
+        0x3E, 0x3F, 0x21, 0x34, 0x12, 0xC2, 0x50, 0x01, 0x7E, 0x12, 0xFE, 0xFF, 0xF5, 0xCF, 0xE9,
+        0x08, 0x3A, 0x00, 0xE0,
+    ];
+
+    #[test]
+    fn disassemble_intel_syntax() {
+        assert_eq!(
+            disassemble_program(&PROGRAM, Syntax::Intel),
+            [
+                "MVI A,3FH",
+                "LXI H,1234H",
+                "JNZ 0150H",
+                "MOV A,M",
+                "STAX D",
+                "CPI 0FFH",
+                "PUSH PSW",
+                "RST 1",
+                "PCHL",
+                "DB 08H",
+                "LDA 0E000H",
+            ]
+        );
+    }
+
+    #[test]
+    fn disassemble_zilog_syntax() {
+        assert_eq!(
+            disassemble_program(&PROGRAM, Syntax::Zilog),
+            [
+                "LD A,3FH",
+                "LD HL,1234H",
+                "JP NZ,0150H",
+                "LD A,(HL)",
+                "LD (DE),A",
+                "CP 0FFH",
+                "PUSH AF",
+                "RST 08H",
+                "JP (HL)",
+                "DB 08H",
+                "LD A,(0E000H)",
+            ]
+        );
+    }
+
+    #[test]
+    fn disassemble_bytes_and_length() {
+        let mut memory = Memory::new();
+        memory
+            .write(RegisterValue::from(0u16), RegisterValue::from(0xCDu8))
+            .unwrap();
+        memory
+            .write(RegisterValue::from(1u16), RegisterValue::from(0x0005u16))
+            .unwrap();
+
+        let instruction = disassemble(&memory, 0, Syntax::Intel).unwrap();
+        assert_eq!(instruction.n_bytes(), 3);
+        assert_eq!(instruction.operand(), Some(0x0005));
+        assert_eq!(instruction.to_string(), "0000  CD 05 00  CALL 0005H");
+
+        // addresses are replaced by symbols
+        let symbols: SymbolTable = [("BDOS", 0x0005)].into_iter().collect();
+        assert_eq!(instruction.with_symbols(&symbols).text, "CALL BDOS");
+    }
+
+    #[test]
+    fn disassemble_range_at_end_of_memory() {
+        // NOP; NOP in the last two bytes of memory
+        let memory = Memory::new();
+        let instructions = disassemble_range(&memory, 0xFFFE, 2, Syntax::Intel).unwrap();
+        let addrs: Vec<u16> = instructions.iter().map(|i| i.addr).collect();
+        assert_eq!(addrs, [0xFFFE, 0xFFFF]);
+
+        assert!(disassemble_range(&memory, 0xFFFE, 0, Syntax::Intel)
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn instructions_before_many() {
+        // memory is all NOPs, so the instructions before an address start
+        // at its count back, or the start of memory for a huge count
+        let memory = Memory::new();
+        assert_eq!(
+            instructions_before(&memory, 0x0100, 4, Syntax::Intel),
+            0x00FC
+        );
+        assert_eq!(
+            instructions_before(&memory, 0x0100, usize::MAX, Syntax::Intel),
+            0
+        );
+        assert_eq!(
+            instructions_before(&memory, 0xFFFF, 30000, Syntax::Intel),
+            0xFFFF - 30000
+        );
+    }
+}
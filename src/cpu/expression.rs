@@ -0,0 +1,478 @@
+/*
+ * expression.rs - Contains code related to debugger expressions, which are
+ * evaluated over the state of a Cpu for breakpoint conditions and watches,
+ * e.g. "A == 0x24 && [HL+1] > 3". they can use:
+ *   numbers       36, 0x24, 24H
+ *   registers     A B C D E H L M BC DE HL SP PC PSW (M is the byte at HL)
+ *   flags         Z CY S P AC, which are 0 or 1
+ *   memory        [addr] or byte[addr] for a byte, word[addr] for a word
+ *   symbols       by name, as their address
+ *   operators     as in C: - ! ~  * / %  + -  << >>  < <= > >=  == !=  &  ^  |
+ *                 &&  ||, with parentheses for grouping
+ */
+use super::disassembler::{format_byte, format_word};
+use super::memory::*;
+use super::registers::*;
+use super::Cpu;
+use crate::symbols::SymbolTable;
+
+use std::fmt;
+
+// the binary operators, from the loosest binding to the tightest
+const BINARY_OPERATORS: [(&str, u8); 18] = [
+    ("||", 1),
+    ("&&", 2),
+    ("|", 3),
+    ("^", 4),
+    ("&", 5),
+    ("==", 6),
+    ("!=", 6),
+    ("<=", 7),
+    (">=", 7),
+    ("<", 7),
+    (">", 7),
+    ("<<", 8),
+    (">>", 8),
+    ("+", 9),
+    ("-", 9),
+    ("*", 10),
+    ("/", 10),
+    ("%", 10),
+];
+
+// Flag enum - a flag an expression can read
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Flag {
+    Zero,
+    Carry,
+    Sign,
+    Parity,
+    AuxCarry,
+}
+
+// Node enum - a node of a parsed expression
+#[derive(Debug, Clone, PartialEq)]
+enum Node {
+    Number(i64),
+    Accumulator,
+    Register(Register),
+    Flag(Flag),
+    Memory(Box<Node>, MemorySize),
+    Unary(char, Box<Node>),
+    Binary(&'static str, Box<Node>, Box<Node>),
+}
+
+// Expression struct - a parsed expression, with symbols already resolved
+#[derive(Debug, Clone, PartialEq)]
+pub struct Expression {
+    pub text: String,
+    root: Node,
+}
+
+impl Expression {
+    // parses an expression, resolving the symbols it uses. errors say what
+    // was expected and the column where it went wrong
+    pub fn parse(text: &str, symbols: &SymbolTable) -> Result<Self, String> {
+        let mut parser = Parser {
+            tokens: tokenize(text)?,
+            pos: 0,
+            symbols,
+        };
+
+        let root = parser.expression(0)?;
+        match parser.peek() {
+            (Token::End, _) => Ok(Self {
+                text: text.trim().to_string(),
+                root,
+            }),
+            (token, column) => Err(format!("Unexpected {token} at column {column}")),
+        }
+    }
+
+    // evaluates the expression over the state of a Cpu
+    pub fn evaluate(&self, cpu: &Cpu) -> Result<i64, String> {
+        evaluate(&self.root, cpu)
+    }
+
+    // evaluates the expression as a condition, which is met if it isn't 0
+    pub fn is_true(&self, cpu: &Cpu) -> Result<bool, String> {
+        Ok(self.evaluate(cpu)? != 0)
+    }
+}
+
+// formats the value of an expression for a debugger, in hex as a byte or
+// a word with the decimal value after it, e.g. 3CH (60)
+pub fn format_value(value: i64) -> String {
+    match value {
+        0..=0xFF => format!("{} ({value})", format_byte(value as u8)),
+        0x100..=0xFFFF => format!("{} ({value})", format_word(value as u16)),
+        _ => value.to_string(),
+    }
+}
+
+impl fmt::Display for Expression {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.text)
+    }
+}
+
+fn evaluate(node: &Node, cpu: &Cpu) -> Result<i64, String> {
+    let flags = cpu.alu.flags();
+
+    Ok(match node {
+        Node::Number(n) => *n,
+        Node::Accumulator => u8::try_from(cpu.alu.accumulator())? as i64,
+        Node::Register(register) => u16::from(cpu.reg_array.read_reg(*register)) as i64,
+        Node::Flag(flag) => i64::from(match flag {
+            Flag::Zero => flags.zero,
+            Flag::Carry => flags.carry,
+            Flag::Sign => flags.sign,
+            Flag::Parity => flags.parity,
+            Flag::AuxCarry => flags.aux_carry,
+        }),
+        Node::Memory(addr, size) => {
+            let addr = evaluate(addr, cpu)? as u16;
+            match size {
+                MemorySize::Integer8 => u16::from(
+                    cpu.memory
+                        .read(RegisterValue::from(addr), MemorySize::Integer8)?,
+                ) as i64,
+                // a word at FFFF wraps around to 0000 for its high byte
+                MemorySize::Integer16 => {
+                    let byte = |addr: u16| -> Result<i64, String> {
+                        let value = cpu
+                            .memory
+                            .read(RegisterValue::from(addr), MemorySize::Integer8)?;
+                        Ok(u16::from(value) as i64)
+                    };
+                    byte(addr)? | byte(addr.wrapping_add(1))? << 8
+                }
+            }
+        }
+        Node::Unary(op, operand) => {
+            let value = evaluate(operand, cpu)?;
+            match op {
+                '-' => value.wrapping_neg(),
+                '!' => i64::from(value == 0),
+                _ => !value,
+            }
+        }
+        Node::Binary(op, left, right) => {
+            let left = evaluate(left, cpu)?;
+
+            // && and || only evaluate their right side when they need to
+            match *op {
+                "&&" if left == 0 => return Ok(0),
+                "||" if left != 0 => return Ok(1),
+                _ => {}
+            }
+
+            let right = evaluate(right, cpu)?;
+            match *op {
+                "&&" | "||" => i64::from(right != 0),
+                "|" => left | right,
+                "^" => left ^ right,
+                "&" => left & right,
+                "==" => i64::from(left == right),
+                "!=" => i64::from(left != right),
+                "<=" => i64::from(left <= right),
+                ">=" => i64::from(left >= right),
+                "<" => i64::from(left < right),
+                ">" => i64::from(left > right),
+                "<<" => left.wrapping_shl(right as u32),
+                ">>" => left.wrapping_shr(right as u32),
+                "+" => left.wrapping_add(right),
+                "-" => left.wrapping_sub(right),
+                "*" => left.wrapping_mul(right),
+                "/" | "%" if right == 0 => return Err(String::from("Division by zero")),
+                "/" => left.wrapping_div(right),
+                _ => left.wrapping_rem(right),
+            }
+        }
+    })
+}
+
+// Token enum - a token of an expression
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Number(i64),
+    Name(String),
+    Operator(&'static str),
+    Open(char),  // ( or [
+    Close(char), // ) or ]
+    End,
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Number(n) => write!(f, "number {n}"),
+            Self::Name(name) => write!(f, "name {name}"),
+            Self::Operator(op) => write!(f, "'{op}'"),
+            Self::Open(c) | Self::Close(c) => write!(f, "'{c}'"),
+            Self::End => write!(f, "the end"),
+        }
+    }
+}
+
+// splits an expression into tokens, each with the column it starts at
+fn tokenize(text: &str) -> Result<Vec<(Token, usize)>, String> {
+    let chars: Vec<char> = text.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+
+    let is_name_char =
+        |c: char| c.is_ascii_alphanumeric() || matches!(c, '_' | '?' | '@' | '$' | '.');
+
+    while i < chars.len() {
+        let c = chars[i];
+        let column = i + 1;
+
+        if c.is_whitespace() {
+            i += 1;
+            continue;
+        }
+
+        let token = if c.is_ascii_digit() {
+            let start = i;
+            while i < chars.len() && chars[i].is_ascii_alphanumeric() {
+                i += 1;
+            }
+            let word: String = chars[start..i].iter().collect();
+            Token::Number(
+                parse_number(&word).ok_or(format!("Invalid number {word} at column {column}"))?,
+            )
+        } else if is_name_char(c) {
+            let start = i;
+            while i < chars.len() && is_name_char(chars[i]) {
+                i += 1;
+            }
+            Token::Name(chars[start..i].iter().collect())
+        } else if matches!(c, '(' | '[') {
+            i += 1;
+            Token::Open(c)
+        } else if matches!(c, ')' | ']') {
+            i += 1;
+            Token::Close(c)
+        } else {
+            // the longest operator that matches
+            let rest: String = chars[i..chars.len().min(i + 2)].iter().collect();
+            let op = BINARY_OPERATORS
+                .iter()
+                .map(|(op, _)| *op)
+                .chain(["!", "~"])
+                .filter(|op| rest.starts_with(op))
+                .max_by_key(|op| op.len())
+                .ok_or(format!("Unexpected '{c}' at column {column}"))?;
+            i += op.len();
+            Token::Operator(op)
+        };
+
+        tokens.push((token, column));
+    }
+
+    tokens.push((Token::End, chars.len() + 1));
+    Ok(tokens)
+}
+
+// parses a number in decimal, or hex as 0x24 or 24H
+fn parse_number(word: &str) -> Option<i64> {
+    if let Some(hex) = word.strip_prefix("0x").or_else(|| word.strip_prefix("0X")) {
+        i64::from_str_radix(hex, 16).ok()
+    } else if let Some(hex) = word.strip_suffix(['h', 'H']) {
+        i64::from_str_radix(hex, 16).ok()
+    } else {
+        word.parse().ok()
+    }
+}
+
+// Parser struct - a precedence climbing parser over the tokens of an
+// expression
+struct Parser<'a> {
+    tokens: Vec<(Token, usize)>,
+    pos: usize,
+    symbols: &'a SymbolTable,
+}
+
+impl Parser<'_> {
+    fn peek(&self) -> (Token, usize) {
+        self.tokens[self.pos].clone()
+    }
+
+    fn next(&mut self) -> (Token, usize) {
+        let token = self.peek();
+        if token.0 != Token::End {
+            self.pos += 1;
+        }
+        token
+    }
+
+    // parses operators that bind at least as tightly as min_precedence
+    fn expression(&mut self, min_precedence: u8) -> Result<Node, String> {
+        let mut left = self.unary()?;
+
+        loop {
+            let (Token::Operator(op), _) = self.peek() else {
+                return Ok(left);
+            };
+            let Some((_, precedence)) = BINARY_OPERATORS.iter().find(|(o, _)| *o == op) else {
+                return Ok(left);
+            };
+            if *precedence < min_precedence {
+                return Ok(left);
+            }
+
+            self.next();
+            let right = self.expression(precedence + 1)?;
+            left = Node::Binary(op, Box::new(left), Box::new(right));
+        }
+    }
+
+    fn unary(&mut self) -> Result<Node, String> {
+        match self.peek() {
+            (Token::Operator(op @ ("-" | "!" | "~")), _) => {
+                self.next();
+                let operand = self.unary()?;
+                Ok(Node::Unary(op.chars().next().unwrap(), Box::new(operand)))
+            }
+            _ => self.primary(),
+        }
+    }
+
+    fn primary(&mut self) -> Result<Node, String> {
+        match self.next() {
+            (Token::Number(n), _) => Ok(Node::Number(n)),
+            (Token::Open('('), _) => {
+                let node = self.expression(0)?;
+                self.close(')')?;
+                Ok(node)
+            }
+            (Token::Open('['), _) => self.memory(MemorySize::Integer8),
+            (Token::Name(name), column) => {
+                let upper = name.to_ascii_uppercase();
+
+                // byte[...] and word[...] read memory
+                if matches!(self.peek().0, Token::Open('[')) {
+                    let size = match upper.as_str() {
+                        "BYTE" => Some(MemorySize::Integer8),
+                        "WORD" => Some(MemorySize::Integer16),
+                        _ => None,
+                    };
+                    if let Some(size) = size {
+                        self.next();
+                        return self.memory(size);
+                    }
+                }
+
+                name_node(&upper)
+                    .or_else(|| {
+                        self.symbols
+                            .address_of(&name)
+                            .map(|addr| Node::Number(addr as i64))
+                    })
+                    .ok_or(format!("Unknown name {name} at column {column}"))
+            }
+            (token, column) => Err(format!(
+                "Expected a value at column {column}, found {token}"
+            )),
+        }
+    }
+
+    // parses the address of a memory read, after the [
+    fn memory(&mut self, size: MemorySize) -> Result<Node, String> {
+        let addr = self.expression(0)?;
+        self.close(']')?;
+        Ok(Node::Memory(Box::new(addr), size))
+    }
+
+    fn close(&mut self, c: char) -> Result<(), String> {
+        match self.next() {
+            (Token::Close(close), _) if close == c => Ok(()),
+            (token, column) => Err(format!("Expected '{c}' at column {column}, found {token}")),
+        }
+    }
+}
+
+// returns the register or flag with a name
+fn name_node(name: &str) -> Option<Node> {
+    use Register::*;
+
+    let register = |register| Some(Node::Register(register));
+    match name {
+        "A" => Some(Node::Accumulator),
+        "B" => register(B),
+        "C" => register(C),
+        "D" => register(D),
+        "E" => register(E),
+        "H" => register(H),
+        "L" => register(L),
+        "M" => Some(Node::Memory(
+            Box::new(Node::Register(HL)),
+            MemorySize::Integer8,
+        )),
+        "BC" => register(BC),
+        "DE" => register(DE),
+        "HL" => register(HL),
+        "SP" => register(SP),
+        "PC" => register(PC),
+        "PSW" => register(PSW),
+        "Z" => Some(Node::Flag(Flag::Zero)),
+        "CY" => Some(Node::Flag(Flag::Carry)),
+        "S" => Some(Node::Flag(Flag::Sign)),
+        "P" => Some(Node::Flag(Flag::Parity)),
+        "AC" => Some(Node::Flag(Flag::AuxCarry)),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn expression_evaluate() {
+        let mut cpu = Cpu::new();
+        cpu.set_register("A", 0x24).unwrap();
+        cpu.set_register("HL", 0x2000).unwrap();
+        cpu.set_register("SP", 0x3000).unwrap();
+        cpu.load_to_memory(vec![0x10, 0x05], 0x2000).unwrap();
+        cpu.load_to_memory(vec![0x34, 0x12], 0x3000).unwrap();
+
+        let symbols: SymbolTable = [("BUFFER", 0x2000)].into_iter().collect();
+        let evaluate = |text: &str| Expression::parse(text, &symbols)?.evaluate(&cpu);
+
+        assert_eq!(evaluate("A == 0x24 && [HL+1] > 3"), Ok(1));
+        assert_eq!(evaluate("word[SP]"), Ok(0x1234));
+        assert_eq!(evaluate("byte[buffer] + M * 2"), Ok(0x30));
+        assert_eq!(evaluate("1 + 2 * 3 << 1 == 14 || Z"), Ok(1));
+        assert_eq!(evaluate("-(0FFH & ~1) % 10"), Ok(-4));
+        assert_eq!(evaluate("CY || !(HL - BUFFER)"), Ok(1));
+        assert_eq!(evaluate("0 && 1 / 0"), Ok(0));
+        assert_eq!(
+            evaluate("1 / (A - 24H)"),
+            Err(String::from("Division by zero"))
+        );
+
+        assert_eq!(
+            evaluate("A == 0x24 && [HL+1 > 3"),
+            Err(String::from("Expected ']' at column 23, found the end"))
+        );
+        assert_eq!(
+            evaluate("A ==").unwrap_err(),
+            "Expected a value at column 5, found the end"
+        );
+        assert_eq!(
+            evaluate("LOOP + 1").unwrap_err(),
+            "Unknown name LOOP at column 1"
+        );
+        assert_eq!(
+            evaluate("12G").unwrap_err(),
+            "Invalid number 12G at column 1"
+        );
+        assert_eq!(evaluate("A = 1").unwrap_err(), "Unexpected '=' at column 3");
+        assert_eq!(
+            evaluate("A 1").unwrap_err(),
+            "Unexpected number 1 at column 3"
+        );
+    }
+}
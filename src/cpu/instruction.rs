@@ -417,10 +417,7 @@ impl Instruction {
 
 #[cfg(test)]
 mod tests {
-    
     use super::*;
-    
-    
 
     macro_rules! instr_decode {
         ($instr:expr) => {
@@ -429,5 +426,27 @@ mod tests {
     }
 
     #[test]
-    fn instruction_decode() {}
+    fn instruction_decode() {
+        // spot-check a handful of opcodes from different groups
+        assert_eq!(instr_decode!(0x00u8), Instruction::Nop);
+        assert_eq!(instr_decode!(0x76u8), Instruction::Halt);
+        assert_eq!(
+            instr_decode!(0x78u8),
+            Instruction::Move(
+                InstructionSource::Accumulator,
+                InstructionSource::Register(Register::B)
+            )
+        );
+        assert_eq!(
+            instr_decode!(0xC2u8),
+            Instruction::JumpConditional(InstructionCondition::NotZero)
+        );
+        assert_eq!(
+            instr_decode!(0xF5u8),
+            Instruction::StackPush(InstructionSource::Register(Register::PSW))
+        );
+
+        // 0x08 is one of the undocumented opcodes, which are unsupported
+        assert!(Instruction::decode(RegisterValue::from(0x08u8)).is_err());
+    }
 }
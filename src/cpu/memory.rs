@@ -38,14 +38,37 @@ pub struct Memory {
     data: [u8; 0x10000], // supports 64KB of memory
 }
 
+// dumps all of memory in hex and ASCII, with runs of lines the same as the
+// one before them shown as a single *, like hexdump does
 impl std::fmt::Debug for Memory {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        let _width_bytes = 16;
+        let dump = self.dump(0x0000, 0xFFFF);
+        let mut previous: Option<&str> = None;
+        let mut repeated = false;
+
+        for line in dump.lines() {
+            // lines are compared without their address
+            let bytes = &line[4..];
+            if previous == Some(bytes) {
+                if !repeated {
+                    writeln!(f, "*")?;
+                    repeated = true;
+                }
+                continue;
+            }
+
+            writeln!(f, "{line}")?;
+            previous = Some(bytes);
+            repeated = false;
+        }
 
-        let mut mem_str = String::new();
-        mem_str.push_str("");
+        Ok(())
+    }
+}
 
-        f.write_str(&mem_str)
+impl Default for Memory {
+    fn default() -> Self {
+        Self::new()
     }
 }
 
@@ -123,6 +146,33 @@ impl Memory {
 
         Ok(())
     }
+
+    // returns a hex and ASCII dump of the bytes from start to end inclusive,
+    // 16 to a line, e.g. "0100  48 49 24 ...  HI$..."
+    pub fn dump(&self, start: u16, end: u16) -> String {
+        let mut dump = String::new();
+        let end = (end as usize).max(start as usize);
+
+        for line_start in (start as usize..=end).step_by(16) {
+            let bytes = &self.data[line_start..=end.min(line_start + 15)];
+
+            let hex: Vec<String> = bytes.iter().map(|b| format!("{b:02X}")).collect();
+            let ascii: String = bytes
+                .iter()
+                .map(|b| match b.is_ascii_graphic() || *b == b' ' {
+                    true => *b as char,
+                    false => '.',
+                })
+                .collect();
+
+            dump.push_str(&format!(
+                "{line_start:04X}  {:<47}  {ascii}\n",
+                hex.join(" ")
+            ));
+        }
+
+        dump
+    }
 }
 
 #[cfg(test)]
@@ -131,25 +181,23 @@ mod tests {
 
     #[test]
     fn memory_read_write_8bit_random() {
-        
-
         let mut memory = Memory::new();
 
         // write a random value to each address, then read it back and make
         // sure it is the same as what was written
         let mut written_values = [RegisterValue::from(0u8); 0x10000];
 
-        for i in 0..0x10000 {
+        for (i, written_value) in written_values.iter_mut().enumerate() {
             let value = rand::random::<u8>();
             let value = RegisterValue::from(value);
 
-            written_values[i] = value;
+            *written_value = value;
             memory.write(RegisterValue::from(i as u16), value).unwrap();
         }
 
-        for i in 0..0x10000 {
+        for (i, written_value) in written_values.iter().enumerate() {
             assert_eq!(
-                written_values[i],
+                *written_value,
                 memory
                     .read(RegisterValue::from(i as u16), MemorySize::Integer8)
                     .unwrap()
@@ -220,4 +268,28 @@ mod tests {
             .read(RegisterValue::from(0xFFFFu16), MemorySize::Integer16)
             .unwrap();
     }
+
+    #[test]
+    fn memory_debug_dump() {
+        let mut memory = Memory::new();
+        memory
+            .write(
+                RegisterValue::from(0x0100u16),
+                RegisterValue::from(0x4948u16),
+            )
+            .unwrap();
+
+        // runs of the same line are collapsed
+        assert_eq!(
+            format!("{memory:?}"),
+            [
+                "0000  00 00 00 00 00 00 00 00 00 00 00 00 00 00 00 00  ................",
+                "*",
+                "0100  48 49 00 00 00 00 00 00 00 00 00 00 00 00 00 00  HI..............",
+                "0110  00 00 00 00 00 00 00 00 00 00 00 00 00 00 00 00  ................",
+                "*\n",
+            ]
+            .join("\n")
+        );
+    }
 }
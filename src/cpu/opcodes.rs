@@ -0,0 +1,600 @@
+/*
+ * opcodes.rs - Contains code related to the opcode table, which describes
+ * every opcode without running it: its mnemonic, length, timing, and the
+ * flags, registers and memory it reads and writes
+ * See the Intel 8080 Microcomputer Systems User's Manual, chapter 4
+ */
+
+use std::fmt;
+use std::ops::BitOr;
+
+// base number of clock cycles used by each opcode
+const CLOCK_CYCLES: [usize; 256] = [
+    4, 10, 7, 5, 5, 5, 7, 4, 4, 10, 7, 5, 5, 5, 7, 4, 4, 10, 7, 5, 5, 5, 7, 4, 4, 10, 7, 5, 5, 5,
+    7, 4, 4, 10, 16, 5, 5, 5, 7, 4, 4, 10, 16, 5, 5, 5, 7, 4, 4, 10, 13, 5, 10, 10, 10, 4, 4, 10,
+    13, 5, 5, 5, 7, 4, 5, 5, 5, 5, 5, 5, 7, 5, 5, 5, 5, 5, 5, 5, 7, 5, 5, 5, 5, 5, 5, 5, 7, 5, 5,
+    5, 5, 5, 5, 5, 7, 5, 5, 5, 5, 5, 5, 5, 7, 5, 5, 5, 5, 5, 5, 5, 7, 5, 7, 7, 7, 7, 7, 7, 7, 7, 5,
+    5, 5, 5, 5, 5, 7, 5, 4, 4, 4, 4, 4, 4, 7, 4, 4, 4, 4, 4, 4, 4, 7, 4, 4, 4, 4, 4, 4, 4, 7, 4, 4,
+    4, 4, 4, 4, 4, 7, 4, 4, 4, 4, 4, 4, 4, 7, 4, 4, 4, 4, 4, 4, 4, 7, 4, 4, 4, 4, 4, 4, 4, 7, 4, 4,
+    4, 4, 4, 4, 4, 7, 4, 5, 10, 10, 10, 11, 11, 7, 11, 5, 10, 10, 10, 11, 17, 7, 11, 5, 10, 10, 10,
+    11, 11, 7, 11, 5, 10, 10, 10, 11, 17, 7, 11, 5, 10, 10, 18, 11, 11, 7, 11, 5, 5, 10, 4, 11, 17,
+    7, 11, 5, 10, 10, 4, 11, 11, 7, 11, 5, 5, 10, 4, 11, 17, 7, 11,
+];
+
+// extra clock cycles used by a conditional call or return when it is taken
+const TAKEN_EXTRA_CYCLES: usize = 6;
+
+// Flags struct - a set of condition flags, as the bits of the F register
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Flags(pub u8);
+
+impl Flags {
+    pub const NONE: Self = Self(0);
+    pub const CARRY: Self = Self(0x01);
+    pub const PARITY: Self = Self(0x04);
+    pub const AUX_CARRY: Self = Self(0x10);
+    pub const ZERO: Self = Self(0x40);
+    pub const SIGN: Self = Self(0x80);
+    pub const ALL: Self = Self(0xD5);
+
+    pub const fn union(self, other: Self) -> Self {
+        Self(self.0 | other.0)
+    }
+
+    pub fn contains(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    pub fn is_empty(self) -> bool {
+        self.0 == 0
+    }
+}
+
+impl BitOr for Flags {
+    type Output = Self;
+
+    fn bitor(self, rhs: Self) -> Self {
+        Self(self.0 | rhs.0)
+    }
+}
+
+// shown as the letters of the flags in the set, e.g. "SZAPC"
+impl fmt::Display for Flags {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let names = [
+            (Self::SIGN, 'S'),
+            (Self::ZERO, 'Z'),
+            (Self::AUX_CARRY, 'A'),
+            (Self::PARITY, 'P'),
+            (Self::CARRY, 'C'),
+        ];
+
+        for (flag, name) in names {
+            if self.contains(flag) {
+                write!(f, "{name}")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+// Registers struct - a set of registers, pairs are their two halves
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Registers(pub u16);
+
+impl Registers {
+    pub const NONE: Self = Self(0);
+    pub const A: Self = Self(0x001);
+    pub const B: Self = Self(0x002);
+    pub const C: Self = Self(0x004);
+    pub const D: Self = Self(0x008);
+    pub const E: Self = Self(0x010);
+    pub const H: Self = Self(0x020);
+    pub const L: Self = Self(0x040);
+    pub const SP: Self = Self(0x080);
+    pub const PC: Self = Self(0x100);
+    pub const BC: Self = Self::B.union(Self::C);
+    pub const DE: Self = Self::D.union(Self::E);
+    pub const HL: Self = Self::H.union(Self::L);
+
+    const NAMES: [(Self, &'static str); 9] = [
+        (Self::A, "A"),
+        (Self::B, "B"),
+        (Self::C, "C"),
+        (Self::D, "D"),
+        (Self::E, "E"),
+        (Self::H, "H"),
+        (Self::L, "L"),
+        (Self::SP, "SP"),
+        (Self::PC, "PC"),
+    ];
+
+    pub const fn union(self, other: Self) -> Self {
+        Self(self.0 | other.0)
+    }
+
+    pub fn contains(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    pub fn is_empty(self) -> bool {
+        self.0 == 0
+    }
+}
+
+impl BitOr for Registers {
+    type Output = Self;
+
+    fn bitor(self, rhs: Self) -> Self {
+        Self(self.0 | rhs.0)
+    }
+}
+
+// shown as the names of the registers in the set, e.g. "A H L"
+impl fmt::Display for Registers {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let names: Vec<&str> = Self::NAMES
+            .iter()
+            .filter(|(register, _)| self.contains(*register))
+            .map(|(_, name)| *name)
+            .collect();
+
+        write!(f, "{}", names.join(" "))
+    }
+}
+
+// Flow enum - where execution goes after an instruction
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Flow {
+    Next, // on to the following instruction
+    Jump,
+    ConditionalJump,
+    Call,
+    ConditionalCall,
+    Return,
+    ConditionalReturn,
+    Restart(u16), // a call to the given vector
+    Indirect,     // PCHL, to an address only known when it runs
+    Halt,
+}
+
+// OpcodeInfo struct - what is known about an opcode without running it
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct OpcodeInfo {
+    pub opcode: u8,
+    pub valid: bool,            // false for opcodes the 8080 doesn't define
+    pub mnemonic: &'static str, // in Intel syntax, e.g. "MOV"
+    pub n_bytes: usize,         // including the operand
+    pub cycles: usize,          // when a conditional call or return isn't taken
+    pub taken_cycles: usize,
+    pub flags_read: Flags,
+    pub flags_written: Flags,
+    pub registers_read: Registers,
+    pub registers_written: Registers,
+    pub reads_memory: bool, // including the stack, but not the operand
+    pub writes_memory: bool,
+    pub flow: Flow,
+}
+
+// the opcode table, built at compile time from the fields of each opcode
+static OPCODES: [OpcodeInfo; 256] = opcode_table();
+
+// returns the information about an opcode
+pub fn opcode_info(opcode: u8) -> &'static OpcodeInfo {
+    &OPCODES[opcode as usize]
+}
+
+// the mnemonics of the ALU operations, by bits 3-5 of their opcodes, for a
+// register or memory and for immediate data
+const ALU: [&str; 8] = ["ADD", "ADC", "SUB", "SBB", "ANA", "XRA", "ORA", "CMP"];
+const ALU_IMMEDIATE: [&str; 8] = ["ADI", "ACI", "SUI", "SBI", "ANI", "XRI", "ORI", "CPI"];
+
+// the mnemonics of the conditional instructions, by their condition
+const RETURNS: [&str; 8] = ["RNZ", "RZ", "RNC", "RC", "RPO", "RPE", "RP", "RM"];
+const JUMPS: [&str; 8] = ["JNZ", "JZ", "JNC", "JC", "JPO", "JPE", "JP", "JM"];
+const CALLS: [&str; 8] = ["CNZ", "CZ", "CNC", "CC", "CPO", "CPE", "CP", "CM"];
+
+// the flags changed by INR and DCR, every one but the carry
+const INCREMENT_FLAGS: Flags = Flags(Flags::ALL.0 & !Flags::CARRY.0);
+
+const fn opcode_table() -> [OpcodeInfo; 256] {
+    let mut table = [describe_opcode(0); 256];
+
+    let mut opcode = 0;
+    while opcode < 256 {
+        table[opcode] = describe_opcode(opcode as u8);
+        opcode += 1;
+    }
+    table
+}
+
+// the register in a 3-bit field of an opcode, and whether it is M (the
+// memory HL points to)
+const fn register_field(field: u8) -> (Registers, bool) {
+    match field & 7 {
+        0 => (Registers::B, false),
+        1 => (Registers::C, false),
+        2 => (Registers::D, false),
+        3 => (Registers::E, false),
+        4 => (Registers::H, false),
+        5 => (Registers::L, false),
+        6 => (Registers::HL, true),
+        _ => (Registers::A, false),
+    }
+}
+
+// the register pair in a 2-bit field of an opcode, SP or the PSW for 3
+const fn pair_field(field: u8, psw: bool) -> Registers {
+    match field & 3 {
+        0 => Registers::BC,
+        1 => Registers::DE,
+        2 => Registers::HL,
+        _ if psw => Registers::A,
+        _ => Registers::SP,
+    }
+}
+
+// the flag tested by the condition in bits 3-5 of an opcode
+const fn condition_field(field: u8) -> Flags {
+    match (field & 7) / 2 {
+        0 => Flags::ZERO,
+        1 => Flags::CARRY,
+        2 => Flags::PARITY,
+        _ => Flags::SIGN,
+    }
+}
+
+// describes an opcode from its fields: a register or condition in bits 3-5
+// (DDD), a register in bits 0-2 (SSS) and a register pair in bits 4-5 (RP)
+const fn describe_opcode(opcode: u8) -> OpcodeInfo {
+    let cycles = CLOCK_CYCLES[opcode as usize];
+    let ddd = (opcode >> 3) & 7;
+    let sss = opcode & 7;
+    let rp = (opcode >> 4) & 3;
+
+    let mut info = OpcodeInfo {
+        opcode,
+        valid: true,
+        mnemonic: "",
+        n_bytes: 1,
+        cycles,
+        taken_cycles: cycles,
+        flags_read: Flags::NONE,
+        flags_written: Flags::NONE,
+        registers_read: Registers::NONE,
+        registers_written: Registers::NONE,
+        reads_memory: false,
+        writes_memory: false,
+        flow: Flow::Next,
+    };
+
+    match opcode {
+        0x00 => info.mnemonic = "NOP",
+        0xF3 => info.mnemonic = "DI",
+        0xFB => info.mnemonic = "EI",
+        0x76 => {
+            info.mnemonic = "HLT";
+            info.flow = Flow::Halt;
+        }
+
+        // MOV DDD,SSS
+        0x40..=0x7F => {
+            let (source, source_memory) = register_field(sss);
+            let (dest, dest_memory) = register_field(ddd);
+            info.mnemonic = "MOV";
+            info.registers_read = source.union(if dest_memory { dest } else { Registers::NONE });
+            info.registers_written = if dest_memory { Registers::NONE } else { dest };
+            info.reads_memory = source_memory;
+            info.writes_memory = dest_memory;
+        }
+
+        // MVI DDD,n
+        0x06 | 0x0E | 0x16 | 0x1E | 0x26 | 0x2E | 0x36 | 0x3E => {
+            let (dest, memory) = register_field(ddd);
+            info.mnemonic = "MVI";
+            info.n_bytes = 2;
+            match memory {
+                true => info.registers_read = dest,
+                false => info.registers_written = dest,
+            }
+            info.writes_memory = memory;
+        }
+
+        // LXI RP,nn
+        0x01 | 0x11 | 0x21 | 0x31 => {
+            info.mnemonic = "LXI";
+            info.n_bytes = 3;
+            info.registers_written = pair_field(rp, false);
+        }
+
+        // STAX and LDAX, through BC or DE
+        0x02 | 0x12 => {
+            info.mnemonic = "STAX";
+            info.registers_read = Registers::A.union(pair_field(rp, false));
+            info.writes_memory = true;
+        }
+        0x0A | 0x1A => {
+            info.mnemonic = "LDAX";
+            info.registers_read = pair_field(rp, false);
+            info.registers_written = Registers::A;
+            info.reads_memory = true;
+        }
+
+        // SHLD, LHLD, STA and LDA nn
+        0x22 => {
+            info.mnemonic = "SHLD";
+            info.n_bytes = 3;
+            info.registers_read = Registers::HL;
+            info.writes_memory = true;
+        }
+        0x2A => {
+            info.mnemonic = "LHLD";
+            info.n_bytes = 3;
+            info.registers_written = Registers::HL;
+            info.reads_memory = true;
+        }
+        0x32 => {
+            info.mnemonic = "STA";
+            info.n_bytes = 3;
+            info.registers_read = Registers::A;
+            info.writes_memory = true;
+        }
+        0x3A => {
+            info.mnemonic = "LDA";
+            info.n_bytes = 3;
+            info.registers_written = Registers::A;
+            info.reads_memory = true;
+        }
+
+        // INX, DCX and DAD RP, which don't affect the flags but for DAD's
+        // carry
+        0x03 | 0x13 | 0x23 | 0x33 | 0x0B | 0x1B | 0x2B | 0x3B => {
+            info.mnemonic = if opcode & 0x08 == 0 { "INX" } else { "DCX" };
+            info.registers_read = pair_field(rp, false);
+            info.registers_written = pair_field(rp, false);
+        }
+        0x09 | 0x19 | 0x29 | 0x39 => {
+            info.mnemonic = "DAD";
+            info.registers_read = pair_field(rp, false).union(Registers::HL);
+            info.registers_written = Registers::HL;
+            info.flags_written = Flags::CARRY;
+        }
+
+        // INR and DCR DDD
+        0x04 | 0x0C | 0x14 | 0x1C | 0x24 | 0x2C | 0x34 | 0x3C | 0x05 | 0x0D | 0x15 | 0x1D
+        | 0x25 | 0x2D | 0x35 | 0x3D => {
+            let (register, memory) = register_field(ddd);
+            info.mnemonic = if opcode & 1 == 0 { "INR" } else { "DCR" };
+            info.registers_read = register;
+            info.registers_written = if memory { Registers::NONE } else { register };
+            info.reads_memory = memory;
+            info.writes_memory = memory;
+            info.flags_written = INCREMENT_FLAGS;
+        }
+
+        // the rotates, and the instructions on A and the carry
+        0x07 | 0x0F | 0x17 | 0x1F | 0x27 | 0x2F => {
+            info.mnemonic = match opcode {
+                0x07 => "RLC",
+                0x0F => "RRC",
+                0x17 => "RAL",
+                0x1F => "RAR",
+                0x27 => "DAA",
+                _ => "CMA",
+            };
+            info.registers_read = Registers::A;
+            info.registers_written = Registers::A;
+            info.flags_read = match opcode {
+                0x17 | 0x1F => Flags::CARRY,
+                0x27 => Flags::CARRY.union(Flags::AUX_CARRY),
+                _ => Flags::NONE,
+            };
+            info.flags_written = match opcode {
+                0x27 => Flags::ALL,
+                0x2F => Flags::NONE,
+                _ => Flags::CARRY,
+            };
+        }
+        0x37 => {
+            info.mnemonic = "STC";
+            info.flags_written = Flags::CARRY;
+        }
+        0x3F => {
+            info.mnemonic = "CMC";
+            info.flags_read = Flags::CARRY;
+            info.flags_written = Flags::CARRY;
+        }
+
+        // ALU operations on A and SSS, or immediate data. CMP leaves A as
+        // it is
+        0x80..=0xBF | 0xC6 | 0xCE | 0xD6 | 0xDE | 0xE6 | 0xEE | 0xF6 | 0xFE => {
+            let immediate = opcode >= 0xC0;
+            let (source, memory) = match immediate {
+                true => (Registers::NONE, false),
+                false => register_field(sss),
+            };
+            info.mnemonic = if immediate {
+                ALU_IMMEDIATE[ddd as usize]
+            } else {
+                ALU[ddd as usize]
+            };
+            info.n_bytes = if immediate { 2 } else { 1 };
+            info.registers_read = Registers::A.union(source);
+            info.registers_written = if ddd == 7 {
+                Registers::NONE
+            } else {
+                Registers::A
+            };
+            info.reads_memory = memory;
+            info.flags_read = if ddd == 1 || ddd == 3 {
+                Flags::CARRY
+            } else {
+                Flags::NONE
+            };
+            info.flags_written = Flags::ALL;
+        }
+
+        // PUSH and POP RP, where pushing and popping the PSW moves the flags
+        0xC5 | 0xD5 | 0xE5 | 0xF5 => {
+            info.mnemonic = "PUSH";
+            info.registers_read = pair_field(rp, true).union(Registers::SP);
+            info.registers_written = Registers::SP;
+            info.writes_memory = true;
+            info.flags_read = if rp == 3 { Flags::ALL } else { Flags::NONE };
+        }
+        0xC1 | 0xD1 | 0xE1 | 0xF1 => {
+            info.mnemonic = "POP";
+            info.registers_read = Registers::SP;
+            info.registers_written = pair_field(rp, true).union(Registers::SP);
+            info.reads_memory = true;
+            info.flags_written = if rp == 3 { Flags::ALL } else { Flags::NONE };
+        }
+
+        // the jumps, calls and returns
+        0xC3 => {
+            info.mnemonic = "JMP";
+            info.n_bytes = 3;
+            info.registers_written = Registers::PC;
+            info.flow = Flow::Jump;
+        }
+        0xC2 | 0xCA | 0xD2 | 0xDA | 0xE2 | 0xEA | 0xF2 | 0xFA => {
+            info.mnemonic = JUMPS[ddd as usize];
+            info.n_bytes = 3;
+            info.flags_read = condition_field(ddd);
+            info.registers_written = Registers::PC;
+            info.flow = Flow::ConditionalJump;
+        }
+        0xCD => {
+            info.mnemonic = "CALL";
+            info.n_bytes = 3;
+            call(&mut info, Flow::Call);
+        }
+        0xC4 | 0xCC | 0xD4 | 0xDC | 0xE4 | 0xEC | 0xF4 | 0xFC => {
+            info.mnemonic = CALLS[ddd as usize];
+            info.n_bytes = 3;
+            info.flags_read = condition_field(ddd);
+            info.taken_cycles = cycles + TAKEN_EXTRA_CYCLES;
+            call(&mut info, Flow::ConditionalCall);
+        }
+        0xC7 | 0xCF | 0xD7 | 0xDF | 0xE7 | 0xEF | 0xF7 | 0xFF => {
+            info.mnemonic = "RST";
+            call(&mut info, Flow::Restart(ddd as u16 * 8));
+        }
+        0xC9 => {
+            info.mnemonic = "RET";
+            info.registers_read = Registers::SP;
+            info.registers_written = Registers::SP.union(Registers::PC);
+            info.reads_memory = true;
+            info.flow = Flow::Return;
+        }
+        0xC0 | 0xC8 | 0xD0 | 0xD8 | 0xE0 | 0xE8 | 0xF0 | 0xF8 => {
+            info.mnemonic = RETURNS[ddd as usize];
+            info.flags_read = condition_field(ddd);
+            info.taken_cycles = cycles + TAKEN_EXTRA_CYCLES;
+            info.registers_read = Registers::SP;
+            info.registers_written = Registers::SP.union(Registers::PC);
+            info.reads_memory = true;
+            info.flow = Flow::ConditionalReturn;
+        }
+        0xE9 => {
+            info.mnemonic = "PCHL";
+            info.registers_read = Registers::HL;
+            info.registers_written = Registers::PC;
+            info.flow = Flow::Indirect;
+        }
+
+        // OUT and IN n
+        0xD3 => {
+            info.mnemonic = "OUT";
+            info.n_bytes = 2;
+            info.registers_read = Registers::A;
+        }
+        0xDB => {
+            info.mnemonic = "IN";
+            info.n_bytes = 2;
+            info.registers_written = Registers::A;
+        }
+
+        // the exchanges, and SPHL
+        0xE3 => {
+            info.mnemonic = "XTHL";
+            info.registers_read = Registers::HL.union(Registers::SP);
+            info.registers_written = Registers::HL;
+            info.reads_memory = true;
+            info.writes_memory = true;
+        }
+        0xEB => {
+            info.mnemonic = "XCHG";
+            info.registers_read = Registers::DE.union(Registers::HL);
+            info.registers_written = Registers::DE.union(Registers::HL);
+        }
+        0xF9 => {
+            info.mnemonic = "SPHL";
+            info.registers_read = Registers::HL;
+            info.registers_written = Registers::SP;
+        }
+
+        // the opcodes the 8080 doesn't define
+        _ => info.valid = false,
+    }
+
+    info
+}
+
+// adds the stack accesses of a call, which pushes the program counter
+const fn call(info: &mut OpcodeInfo, flow: Flow) {
+    info.registers_read = info
+        .registers_read
+        .union(Registers::SP.union(Registers::PC));
+    info.registers_written = info
+        .registers_written
+        .union(Registers::SP.union(Registers::PC));
+    info.writes_memory = true;
+    info.flow = flow;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::instruction::*;
+    use super::super::registers::*;
+    use super::*;
+
+    #[test]
+    fn opcode_table() {
+        // MOV A,M
+        let mov = opcode_info(0x7E);
+        assert_eq!((mov.mnemonic, mov.n_bytes, mov.cycles), ("MOV", 1, 7));
+        assert_eq!(mov.registers_read, Registers::H | Registers::L);
+        assert_eq!(mov.registers_written, Registers::A);
+        assert!(mov.reads_memory && !mov.writes_memory);
+
+        // CNZ addr
+        let cnz = opcode_info(0xC4);
+        assert_eq!((cnz.n_bytes, cnz.cycles, cnz.taken_cycles), (3, 11, 17));
+        assert_eq!(cnz.flags_read, Flags::ZERO);
+        assert_eq!(cnz.flow, Flow::ConditionalCall);
+
+        // ADC B, INX H and POP PSW
+        assert_eq!(opcode_info(0x88).flags_read.to_string(), "C");
+        assert_eq!(opcode_info(0x88).flags_written.to_string(), "SZAPC");
+        assert!(opcode_info(0x23).flags_written.is_empty());
+        assert_eq!(opcode_info(0xF1).registers_written.to_string(), "A SP");
+
+        assert_eq!(opcode_info(0xE9).flow, Flow::Indirect);
+        assert_eq!(opcode_info(0xFF).flow, Flow::Restart(0x38));
+        assert!(!opcode_info(0x08).valid);
+
+        // lengths include the immediate operand
+        assert_eq!(opcode_info(0x01).n_bytes, 3); // LXI B
+        assert_eq!(opcode_info(0xFE).n_bytes, 2); // CPI
+        assert_eq!(opcode_info(0xDB).n_bytes, 2); // IN
+        assert_eq!(opcode_info(0x32).n_bytes, 3); // STA
+        assert_eq!(opcode_info(0x77).n_bytes, 1); // MOV M,A
+
+        // every defined opcode is one the decoder knows, and the other way round
+        for opcode in 0..=255u8 {
+            let decoded = Instruction::decode(RegisterValue::from(opcode));
+            assert_eq!(opcode_info(opcode).valid, decoded.is_ok(), "{opcode:02X}");
+        }
+    }
+}
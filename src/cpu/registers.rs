@@ -31,6 +31,12 @@ pub struct RegisterArray {
     reg_z: u8,
 }
 
+impl Default for RegisterArray {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl RegisterArray {
     // Creates a new instance of RegisterArray, with all values set to 0
     pub fn new() -> Self {
@@ -103,6 +109,7 @@ impl RegisterArray {
 
 // Register enum - contains all possible registers that can be referenced
 #[derive(Clone, Copy, Debug, PartialEq, EnumIter)]
+#[allow(clippy::upper_case_acronyms)]
 pub enum Register {
     PC, // 16-bit program counter
     SP, // 16-bit stack pointer
@@ -133,7 +140,7 @@ impl Register {
         match self {
             PC => "PC",
             SP => "SP",
-            _A => "A",
+            PSW => "PSW",
             B => "B",
             C => "C",
             D => "D",
@@ -293,7 +300,6 @@ mod tests {
 
     #[test]
     fn register_array_read_write() {
-        use rand::prelude::*;
         use Register::*;
 
         let mut reg_array = RegisterArray::new();
@@ -0,0 +1,171 @@
+/*
+ * trace.rs - Contains code related to tracing execution, writing a line for
+ * each instruction executed with the state of the Cpu before it. the format
+ * is stable, so traces from two builds can be diffed to find where they part
+ * ways, e.g.
+ *   0100  31 00 02  LXI SP,0200H      A=00 BC=0000 DE=0000 HL=0000 SP=0000 F=..... CYC=0
+ * the flags are S Z A P C, shown when set
+ */
+use super::disassembler::*;
+use super::registers::*;
+use super::Cpu;
+
+use std::io::Write;
+
+// width the address, bytes and mnemonic are padded to, so the registers line
+// up
+const INSTRUCTION_WIDTH: usize = 34;
+
+// the flags in the order they are shown, and their bits in the F register
+const FLAGS: [(char, u8); 5] = [
+    ('S', 0x80),
+    ('Z', 0x40),
+    ('A', 0x10),
+    ('P', 0x04),
+    ('C', 0x01),
+];
+
+// Trace struct - writes a line for each instruction executed, optionally
+// only in some address ranges or between a start and a stop address
+pub struct Trace {
+    writer: Box<dyn Write + Send>,
+    pub syntax: Syntax,
+    pub ranges: Vec<(u16, u16)>, // inclusive, everything is traced if empty
+    pub start: Option<u16>,      // tracing starts when this address is reached
+    pub stop: Option<u16>,       // and stops when this one is
+    started: bool,
+    stopped: bool,
+}
+
+impl Trace {
+    // creates a new instance of Trace writing to the given writer
+    pub fn new(writer: Box<dyn Write + Send>) -> Self {
+        Self {
+            writer,
+            syntax: Syntax::Intel,
+            ranges: Vec::new(),
+            start: None,
+            stop: None,
+            started: false,
+            stopped: false,
+        }
+    }
+
+    // writes a line for the instruction about to be executed at pc, if it is
+    // traced. the start and stop addresses can turn tracing on and off any
+    // number of times
+    pub fn record(&mut self, cpu: &Cpu, pc: u16) -> Result<(), String> {
+        if Some(pc) == self.start {
+            self.started = true;
+            self.stopped = false;
+        }
+        if Some(pc) == self.stop {
+            self.stopped = true;
+        }
+
+        let triggered = (self.start.is_none() || self.started) && !self.stopped;
+        let in_range = self.ranges.is_empty()
+            || self
+                .ranges
+                .iter()
+                .any(|(start, end)| (*start..=*end).contains(&pc));
+        if !triggered || !in_range {
+            return Ok(());
+        }
+
+        writeln!(self.writer, "{}", trace_line(cpu, pc, self.syntax))
+            .map_err(|e| format!("Could not write trace: {e}"))
+    }
+
+    pub fn flush(&mut self) -> Result<(), String> {
+        self.writer
+            .flush()
+            .map_err(|e| format!("Could not write trace: {e}"))
+    }
+}
+
+// formats the trace line of the instruction at pc
+pub fn trace_line(cpu: &Cpu, pc: u16, syntax: Syntax) -> String {
+    let read = |register| u16::from(cpu.reg_array.read_reg(register));
+    let psw = read(Register::PSW);
+
+    let instruction = match disassemble(&cpu.memory, pc, syntax) {
+        Ok(instruction) => instruction.to_string(),
+        Err(_) => format!("{pc:04X}  ??"),
+    };
+    let flags: String = FLAGS
+        .iter()
+        .map(|(name, bit)| match psw as u8 & bit {
+            0 => '.',
+            _ => *name,
+        })
+        .collect();
+
+    format!(
+        "{instruction:<INSTRUCTION_WIDTH$}A={:02X} BC={:04X} DE={:04X} HL={:04X} SP={:04X} F={flags} CYC={}",
+        psw >> 8,
+        read(Register::BC),
+        read(Register::DE),
+        read(Register::HL),
+        read(Register::SP),
+        cpu.total_cycles
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+
+    // SharedBuf struct - a writer whose contents can be read after it has
+    // been handed to a Trace
+    #[derive(Clone, Default)]
+    struct SharedBuf(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for SharedBuf {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn trace_ranges_and_triggers() {
+        // MVI B,3; LOOP: DCR B; JNZ LOOP; XRA A; HLT
+        let mut cpu = Cpu::new();
+        cpu.load_to_memory(vec![0x06, 0x03, 0x05, 0xC2, 0x02, 0x01, 0xAF, 0x76], 0x0100)
+            .unwrap();
+        cpu.set_pc(0x0100).unwrap();
+
+        let log = SharedBuf::default();
+        let mut trace = Trace::new(Box::new(log.clone()));
+        trace.start = Some(0x0102);
+        trace.stop = Some(0x0107);
+        trace.ranges = vec![(0x0100, 0x0102), (0x0106, 0x0107)];
+        cpu.trace = Some(trace);
+
+        while cpu.is_running() {
+            cpu.execute_next().unwrap();
+        }
+
+        let log = String::from_utf8(log.0.lock().unwrap().clone()).unwrap();
+        let lines: Vec<&str> = log.lines().collect();
+
+        // DCR B three times, then XRA A. MVI is before the start and HLT is
+        // the stop, and JNZ is outside the ranges
+        assert_eq!(lines.len(), 4);
+        assert_eq!(
+            lines[0],
+            "0102  05        DCR B             A=00 BC=0300 DE=0000 HL=0000 SP=0000 F=..... CYC=7"
+        );
+        assert_eq!(
+            lines[2],
+            "0102  05        DCR B             A=00 BC=0100 DE=0000 HL=0000 SP=0000 F=..... CYC=37"
+        );
+        assert!(lines[3].starts_with("0106  AF        XRA A "));
+    }
+}
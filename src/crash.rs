@@ -0,0 +1,258 @@
+/*
+ * crash.rs - Contains code related to crash reports, which describe the state
+ * of a Cpu when an instruction fails, so the cause can be worked out without
+ * running the program again under a debugger
+ */
+use crate::cpu::disassembler::*;
+use crate::cpu::memory::*;
+use crate::cpu::opcodes::{opcode_info, Registers};
+use crate::cpu::registers::*;
+use crate::cpu::*;
+use crate::symbols::SymbolTable;
+
+use std::fmt;
+
+// instructions shown before and after the one that failed
+const WINDOW_BEFORE: usize = 4;
+const WINDOW_AFTER: usize = 3;
+
+// words shown from the top of the stack
+const STACK_WORDS: usize = 8;
+
+// lines of 16 bytes dumped around the address the instruction used
+const DUMP_LINES: u16 = 4;
+
+// the flags, and their bits in the F register
+const FLAGS: [(&str, u8); 5] = [
+    ("S", 0x80),
+    ("Z", 0x40),
+    ("AC", 0x10),
+    ("P", 0x04),
+    ("CY", 0x01),
+];
+
+// CrashReport struct - the state of a Cpu when the instruction at pc failed
+#[derive(Debug, Clone, PartialEq)]
+pub struct CrashReport {
+    pub error: String,
+    pub pc: u16,
+    pub opcode: u8,
+    pub location: String,         // the pc, with the symbol it is in
+    pub registers: String,        // e.g. A=41  BC=0000 ... PC=0105
+    pub flags: String,            // e.g. S0 Z1 AC0 P1 CY0
+    pub disassembly: Vec<String>, // around the pc, which is marked with >
+    pub stack: Vec<String>,       // words from the top of the stack
+    pub history: Vec<String>,     // instructions executed before, oldest first
+    pub dump_addr: u16,           // the address the instruction used
+    pub dump: String,             // memory around dump_addr
+}
+
+impl CrashReport {
+    // describes the state of a Cpu after the instruction at pc returned an
+    // error. the history is only there if the Cpu was recording it
+    pub fn new(cpu: &Cpu, pc: u16, error: &str, symbols: &SymbolTable, syntax: Syntax) -> Self {
+        let read = |register| u16::from(cpu.reg_array.read_reg(register));
+        let psw = read(Register::PSW);
+        let opcode = read_byte(cpu, pc);
+
+        let registers = format!(
+            "A={:02X}  BC={:04X}  DE={:04X}  HL={:04X}  SP={:04X}  PC={:04X}",
+            psw >> 8,
+            read(Register::BC),
+            read(Register::DE),
+            read(Register::HL),
+            read(Register::SP),
+            read(Register::PC)
+        );
+        let flags: Vec<String> = FLAGS
+            .iter()
+            .map(|(name, bit)| format!("{name}{}", u8::from(psw as u8 & bit != 0)))
+            .collect();
+
+        // the instructions around the pc
+        let mut addr = instructions_before(&cpu.memory, pc, WINDOW_BEFORE, syntax);
+        let mut disassembly = Vec::new();
+        while disassembly.len() < WINDOW_BEFORE + 1 + WINDOW_AFTER {
+            let Ok(instruction) = disassemble(&cpu.memory, addr, syntax) else {
+                break;
+            };
+            let next = addr.wrapping_add(instruction.n_bytes() as u16);
+            let mark = if addr == pc { '>' } else { ' ' };
+            disassembly.push(format!("{mark} {}", instruction.with_symbols(symbols)));
+
+            if next < addr {
+                break;
+            }
+            addr = next;
+        }
+
+        let sp = read(Register::SP);
+        let stack = (0..STACK_WORDS as u16)
+            .map_while(|i| {
+                let addr = sp.checked_add(i * 2)?;
+                let value = cpu
+                    .memory
+                    .read(RegisterValue::from(addr), MemorySize::Integer16)
+                    .ok()?;
+                let value = u16::from(value);
+
+                Some(match symbols.describe(value) {
+                    Some(symbol) => format!("{addr:04X}  {value:04X}  {symbol}"),
+                    None => format!("{addr:04X}  {value:04X}"),
+                })
+            })
+            .collect();
+
+        // the history ends with the instruction that failed
+        let history = cpu
+            .history
+            .iter()
+            .flatten()
+            .filter_map(|addr| disassemble(&cpu.memory, *addr, syntax).ok())
+            .map(|instruction| instruction.with_symbols(symbols).to_string())
+            .collect();
+
+        let dump_addr = memory_addr(cpu, pc);
+        let start = (dump_addr & 0xFFF0)
+            .saturating_sub(16)
+            .min(0u16.wrapping_sub(16 * DUMP_LINES));
+        let end = start + (16 * DUMP_LINES - 1);
+
+        Self {
+            error: error.to_string(),
+            pc,
+            opcode,
+            location: symbols.format_addr(pc),
+            registers,
+            flags: flags.join(" "),
+            disassembly,
+            stack,
+            history,
+            dump_addr,
+            dump: cpu.memory.dump(start, end),
+        }
+    }
+}
+
+impl fmt::Display for CrashReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let info = opcode_info(self.opcode);
+        let opcode = match info.valid {
+            true => format!("{:02X} ({})", self.opcode, info.mnemonic),
+            false => format!("{:02X} (undefined)", self.opcode),
+        };
+
+        writeln!(f, "Error at {}: {}", self.location, self.error)?;
+        writeln!(f)?;
+        writeln!(f, "Opcode     {opcode}")?;
+        writeln!(f, "Registers  {}", self.registers)?;
+        writeln!(f, "Flags      {}", self.flags)?;
+
+        let sections = [
+            ("Disassembly", &self.disassembly),
+            ("Stack", &self.stack),
+            ("Last instructions", &self.history),
+        ];
+        for (title, lines) in sections {
+            if lines.is_empty() {
+                continue;
+            }
+            writeln!(f)?;
+            writeln!(f, "{title}:")?;
+            for line in lines {
+                writeln!(f, "  {line}")?;
+            }
+        }
+
+        writeln!(f)?;
+        writeln!(f, "Memory near {:04X}:", self.dump_addr)?;
+        for line in self.dump.lines() {
+            writeln!(f, "  {line}")?;
+        }
+        Ok(())
+    }
+}
+
+// the address in memory the instruction at pc uses: its operand for direct
+// addressing, the stack, BC or DE for LDAX and STAX, or HL. the pc itself if
+// it doesn't use memory
+fn memory_addr(cpu: &Cpu, pc: u16) -> u16 {
+    let read = |register| u16::from(cpu.reg_array.read_reg(register));
+    let info = opcode_info(read_byte(cpu, pc));
+
+    if !info.reads_memory && !info.writes_memory {
+        return pc;
+    }
+
+    match info.mnemonic {
+        "LDA" | "STA" | "LHLD" | "SHLD" => u16::from_le_bytes([
+            read_byte(cpu, pc.wrapping_add(1)),
+            read_byte(cpu, pc.wrapping_add(2)),
+        ]),
+        "LDAX" | "STAX" if info.registers_read.contains(Registers::B) => read(Register::BC),
+        "LDAX" | "STAX" => read(Register::DE),
+        _ if info.registers_read.contains(Registers::SP) => read(Register::SP),
+        _ => read(Register::HL),
+    }
+}
+
+fn read_byte(cpu: &Cpu, addr: u16) -> u8 {
+    cpu.memory
+        .read(RegisterValue::from(addr), MemorySize::Integer8)
+        .ok()
+        .and_then(|value| u8::try_from(value).ok())
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::VecDeque;
+
+    #[test]
+    fn crash_report() {
+        // START: LXI SP,0200H; LXI H,1234H; CALL SUB; SUB: SHLD 0FFFFH,
+        // which writes past the end of memory
+        let mut cpu = Cpu::new();
+        cpu.load_to_memory(
+            vec![
+                0x31, 0x00, 0x02, 0x21, 0x34, 0x12, 0xCD, 0x09, 0x01, 0x22, 0xFF, 0xFF,
+            ],
+            0x0100,
+        )
+        .unwrap();
+        cpu.set_pc(0x0100).unwrap();
+        cpu.history = Some(VecDeque::new());
+
+        let symbols: SymbolTable = [("START", 0x0100), ("SUB", 0x0109)].into_iter().collect();
+        let error = loop {
+            let pc = u16::from(cpu.reg_array.read_reg(Register::PC));
+            if let Err(e) = cpu.execute_next() {
+                break CrashReport::new(&cpu, pc, &e, &symbols, Syntax::Intel);
+            }
+        };
+
+        assert_eq!((error.pc, error.opcode), (0x0109, 0x22));
+        assert_eq!(error.dump_addr, 0xFFFF);
+        assert_eq!(
+            error.disassembly[1..5],
+            [
+                "  0100  31 00 02  LXI SP,SUB+F7",
+                "  0103  21 34 12  LXI H,1234H",
+                "  0106  CD 09 01  CALL SUB",
+                "> 0109  22 FF FF  SHLD 0FFFFH",
+            ]
+        );
+        assert_eq!(error.stack[0], "01FE  0109  SUB");
+        assert_eq!(error.history.len(), 4);
+
+        let report = error.to_string();
+        assert!(report.starts_with("Error at 0109 (SUB): "));
+        assert!(report.contains("Opcode     22 (SHLD)\n"));
+        assert!(report.contains("Registers  A=00  BC=0000  DE=0000  HL=1234  SP=01FE"));
+        assert!(report.contains("Memory near FFFF:\n  FFC0  00"));
+        assert!(report
+            .trim_end()
+            .ends_with("FFF0  00 00 00 00 00 00 00 00 00 00 00 00 00 00 00 00  ................"));
+    }
+}
@@ -0,0 +1,1148 @@
+/*
+ * dap.rs - Contains code related to the debug adapter, which lets editors such
+ * as VS Code debug a program running on a Cpu through the Debug Adapter
+ * Protocol, over a TCP connection or stdin and stdout
+ * See https://microsoft.github.io/debug-adapter-protocol/specification
+ */
+use crate::cpu::breakpoints::*;
+use crate::cpu::disassembler::*;
+use crate::cpu::expression::{format_value, Expression};
+use crate::cpu::memory::*;
+use crate::cpu::registers::*;
+use crate::cpu::*;
+use crate::json::Json;
+use crate::listing::LineTable;
+use crate::symbols::SymbolTable;
+
+use std::collections::{BTreeMap, HashMap};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::path::Path;
+use std::sync::mpsc;
+use std::sync::{Arc, Mutex};
+
+// the Cpu is the only thread
+const THREAD_ID: i64 = 1;
+
+// variablesReference of each scope
+const REGISTERS_REFERENCE: i64 = 1;
+const FLAGS_REFERENCE: i64 = 2;
+
+// cycles run between checks for requests (such as pause) while running
+const RUN_SLICE: usize = 20_000;
+
+// how long a step over or step out can run before it stops anyway
+const STEP_BUDGET: Budget = Budget::Cycles(50_000_000);
+
+// stack words looked at for return addresses
+const STACK_DEPTH: usize = 64;
+
+// the flags, and their bits in the F register
+const FLAGS: [(&str, u8); 5] = [
+    ("S", 0x80),
+    ("Z", 0x40),
+    ("AC", 0x10),
+    ("P", 0x04),
+    ("CY", 0x01),
+];
+
+// SourceFile struct - a source file whose lines have been matched to the
+// listing, so breakpoints can be set and stack frames shown in it
+struct SourceFile {
+    path: String,
+    listing_lines: BTreeMap<usize, usize>, // source line -> index in the listing
+    source_lines: BTreeMap<usize, usize>,  // index in the listing -> source line
+}
+
+// DebugAdapter struct - a debug session on a Cpu
+pub struct DebugAdapter {
+    pub symbols: SymbolTable,
+    pub syntax: Syntax,
+    pub lines: Option<LineTable>, // listing the program was assembled with
+    pub listing_path: Option<String>, // shown as the source when none is mapped
+    sources: Vec<SourceFile>,
+    source_breakpoints: HashMap<String, Vec<usize>>, // breakpoint ids by path
+    instruction_breakpoints: Vec<usize>,
+    seq: i64,
+    running: bool,
+    stop_on_entry: bool,
+    output: Arc<Mutex<Vec<u8>>>, // console output not yet sent
+}
+
+impl Default for DebugAdapter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl DebugAdapter {
+    // creates a new instance of DebugAdapter
+    pub fn new() -> Self {
+        Self {
+            symbols: SymbolTable::new(),
+            syntax: Syntax::Intel,
+            lines: None,
+            listing_path: None,
+            sources: Vec::new(),
+            source_breakpoints: HashMap::new(),
+            instruction_breakpoints: Vec::new(),
+            seq: 0,
+            running: false,
+            stop_on_entry: false,
+            output: Arc::new(Mutex::new(Vec::new())),
+        }
+    }
+
+    // serves a client until it disconnects. requests are read on another
+    // thread, so a running program can be paused. the console output of the
+    // program (port 0) is sent to the client as output events
+    pub fn serve(
+        &mut self,
+        cpu: &mut Cpu,
+        input: impl Read + Send + 'static,
+        output: &mut dyn Write,
+    ) -> Result<(), String> {
+        self.capture_output(cpu);
+
+        let (sender, receiver) = mpsc::channel();
+        std::thread::spawn(move || {
+            let mut input = BufReader::new(input);
+            while let Ok(Some(message)) = read_message(&mut input) {
+                if sender.send(message).is_err() {
+                    break;
+                }
+            }
+        });
+
+        loop {
+            let request = match self.running {
+                true => match receiver.try_recv() {
+                    Ok(request) => request,
+                    Err(mpsc::TryRecvError::Empty) => {
+                        self.run_slice(cpu, output)?;
+                        continue;
+                    }
+                    Err(mpsc::TryRecvError::Disconnected) => return Ok(()),
+                },
+                false => match receiver.recv() {
+                    Ok(request) => request,
+                    Err(_) => return Ok(()),
+                },
+            };
+
+            if !self.handle(cpu, &request, output)? {
+                return Ok(());
+            }
+        }
+    }
+
+    // sends the console output of the program (port 0) to the client
+    fn capture_output(&mut self, cpu: &mut Cpu) {
+        let console = Arc::clone(&self.output);
+        cpu.set_port_handler_fn(move |port, value| {
+            if let (Ok(0), Ok(value)) = (u8::try_from(port), u8::try_from(value)) {
+                console.lock().unwrap().push(value);
+            }
+        });
+    }
+
+    // runs the program for a while, sending an event if it stops
+    fn run_slice(&mut self, cpu: &mut Cpu, output: &mut dyn Write) -> Result<(), String> {
+        let result = cpu.run(RUN_SLICE);
+        self.send_output(output)?;
+
+        if !matches!(result, Ok(StopReason::BudgetExhausted)) {
+            self.running = false;
+            self.stopped(cpu, result, "step", output)?;
+        }
+        Ok(())
+    }
+
+    // handles a request, returns whether the session carries on
+    pub fn handle(
+        &mut self,
+        cpu: &mut Cpu,
+        request: &Json,
+        output: &mut dyn Write,
+    ) -> Result<bool, String> {
+        let command = request["command"].as_str().unwrap_or_default();
+        let args = &request["arguments"];
+
+        let result = match command {
+            "initialize" => Ok(capabilities()),
+            "launch" | "attach" => {
+                self.stop_on_entry = args["stopOnEntry"].as_bool().unwrap_or(command == "attach");
+                Ok(Json::Null)
+            }
+            "setBreakpoints" => self.set_breakpoints(cpu, args),
+            "setInstructionBreakpoints" => self.set_instruction_breakpoints(cpu, args),
+            "setExceptionBreakpoints" => Ok(Json::object([("breakpoints", Json::Array(vec![]))])),
+            "configurationDone" => Ok(Json::Null),
+            "threads" => Ok(Json::object([(
+                "threads",
+                Json::Array(vec![Json::object([
+                    ("id", Json::from(THREAD_ID)),
+                    ("name", Json::from("8080")),
+                ])]),
+            )])),
+            "stackTrace" => self.stack_trace(cpu),
+            "scopes" => Ok(scopes()),
+            "variables" => Ok(self.variables(cpu, args["variablesReference"].as_i64())),
+            "setVariable" => self.set_variable(cpu, args),
+            "readMemory" => read_memory(cpu, args),
+            "evaluate" => self.evaluate(cpu, args["expression"].as_str().unwrap_or_default()),
+            "disassemble" => self.disassemble(cpu, args),
+            "continue" => {
+                self.running = true;
+                cpu.running = true;
+                Ok(Json::object([("allThreadsContinued", Json::from(true))]))
+            }
+            "next" | "stepIn" | "stepOut" | "pause" => Ok(Json::Null),
+            "disconnect" | "terminate" => Ok(Json::Null),
+            _ => Err(format!("Unsupported request {command}")),
+        };
+
+        self.respond(request, result, output)?;
+
+        // events that follow the responses
+        match command {
+            "initialize" => self.send_event("initialized", Json::Null, output)?,
+            "configurationDone" => match self.stop_on_entry {
+                true => self.send_stopped("entry", None, Vec::new(), output)?,
+                false => {
+                    self.running = true;
+                    cpu.running = true;
+                }
+            },
+            "next" | "stepIn" | "stepOut" => {
+                cpu.running = true;
+                let result = match command {
+                    "next" => cpu.step_over(STEP_BUDGET),
+                    "stepOut" => cpu.step_out(STEP_BUDGET),
+                    _ => cpu.run_until(Budget::Instructions(1), |_, _| true),
+                };
+                self.send_output(output)?;
+                self.stopped(cpu, result, "step", output)?;
+            }
+            "pause" => {
+                self.running = false;
+                self.send_stopped("pause", None, Vec::new(), output)?;
+            }
+            "disconnect" | "terminate" => return Ok(false),
+            _ => {}
+        }
+
+        Ok(true)
+    }
+
+    // sends the event for why the program stopped
+    fn stopped(
+        &mut self,
+        cpu: &Cpu,
+        result: Result<StopReason, String>,
+        reason: &str,
+        output: &mut dyn Write,
+    ) -> Result<(), String> {
+        match result {
+            Ok(StopReason::Breakpoint(hit)) => {
+                let reason = match hit.kind {
+                    BreakpointKind::Execute(_) => "breakpoint",
+                    _ => "data breakpoint",
+                };
+                let description = hit.describe(&self.symbols);
+                self.send_stopped(reason, Some(description), vec![hit.id], output)
+            }
+            Ok(StopReason::Halted) => {
+                self.send_output(output)?;
+                self.send_event(
+                    "exited",
+                    Json::object([("exitCode", Json::from(0u8))]),
+                    output,
+                )?;
+                self.send_event("terminated", Json::Null, output)
+            }
+            Ok(_) => self.send_stopped(reason, None, Vec::new(), output),
+            Err(e) => {
+                let pc = read_register(cpu, "PC");
+                let description = format!("Error at {}: {e}", self.symbols.format_addr(pc));
+                self.send_stopped("exception", Some(description), Vec::new(), output)
+            }
+        }
+    }
+
+    fn send_stopped(
+        &mut self,
+        reason: &str,
+        description: Option<String>,
+        breakpoints: Vec<usize>,
+        output: &mut dyn Write,
+    ) -> Result<(), String> {
+        let mut body = Json::object([
+            ("reason", Json::from(reason)),
+            ("threadId", Json::from(THREAD_ID)),
+            ("allThreadsStopped", Json::from(true)),
+        ]);
+        if let Some(description) = description {
+            body.set("description", Json::from(description.clone()));
+            body.set("text", Json::from(description));
+        }
+        if !breakpoints.is_empty() {
+            body.set(
+                "hitBreakpointIds",
+                Json::Array(breakpoints.into_iter().map(Json::from).collect()),
+            );
+        }
+
+        self.send_event("stopped", body, output)
+    }
+
+    // sends the console output collected since the last time
+    fn send_output(&mut self, output: &mut dyn Write) -> Result<(), String> {
+        let text = std::mem::take(&mut *self.output.lock().unwrap());
+        if text.is_empty() {
+            return Ok(());
+        }
+
+        let body = Json::object([
+            ("category", Json::from("stdout")),
+            (
+                "output",
+                Json::from(String::from_utf8_lossy(&text).into_owned()),
+            ),
+        ]);
+        self.send_event("output", body, output)
+    }
+
+    fn respond(
+        &mut self,
+        request: &Json,
+        result: Result<Json, String>,
+        output: &mut dyn Write,
+    ) -> Result<(), String> {
+        self.seq += 1;
+        let mut response = Json::object([
+            ("seq", Json::from(self.seq)),
+            ("type", Json::from("response")),
+            ("request_seq", request["seq"].clone()),
+            ("command", request["command"].clone()),
+            ("success", Json::from(result.is_ok())),
+        ]);
+
+        match result {
+            Ok(Json::Null) => {}
+            Ok(body) => response.set("body", body),
+            Err(e) => response.set("message", Json::from(e)),
+        }
+
+        write_message(output, &response)
+    }
+
+    fn send_event(
+        &mut self,
+        event: &str,
+        body: Json,
+        output: &mut dyn Write,
+    ) -> Result<(), String> {
+        self.seq += 1;
+        let mut message = Json::object([
+            ("seq", Json::from(self.seq)),
+            ("type", Json::from("event")),
+            ("event", Json::from(event)),
+        ]);
+        if !body.is_null() {
+            message.set("body", body);
+        }
+
+        write_message(output, &message)
+    }
+
+    // sets the breakpoints of a source file, replacing those it had. the
+    // listing itself can be used as the source, otherwise the file is
+    // matched to the listing. lines without code break at the next line
+    // with some
+    fn set_breakpoints(&mut self, cpu: &mut Cpu, args: &Json) -> Result<Json, String> {
+        let path = args["source"]["path"]
+            .as_str()
+            .ok_or("Breakpoints need a source path")?
+            .to_string();
+
+        for id in self.source_breakpoints.remove(&path).unwrap_or_default() {
+            let _ = cpu.breakpoints.remove(id);
+        }
+
+        let mut ids = Vec::new();
+        let mut breakpoints = Vec::new();
+        for requested in args["breakpoints"].as_array().unwrap_or_default() {
+            let Some(line) = requested["line"].as_i64() else {
+                continue;
+            };
+            let addr = self.line_address(&path, line as usize);
+
+            let mut breakpoint = Json::object([("verified", Json::from(addr.is_some()))]);
+            match addr {
+                Some(addr) => {
+                    let id = cpu.breakpoints.add(BreakpointKind::Execute(addr));
+                    ids.push(id);
+                    breakpoint.set("id", Json::from(id));
+                    breakpoint.set("line", Json::from(line));
+                    breakpoint.set("instructionReference", Json::from(format_reference(addr)));
+                    self.set_condition(cpu, id, requested, &mut breakpoint);
+                }
+                None => breakpoint.set("message", Json::from("No code at this line")),
+            }
+            breakpoints.push(breakpoint);
+        }
+
+        self.source_breakpoints.insert(path, ids);
+        Ok(Json::object([("breakpoints", Json::Array(breakpoints))]))
+    }
+
+    // sets the breakpoints given by address, replacing the ones there were
+    fn set_instruction_breakpoints(&mut self, cpu: &mut Cpu, args: &Json) -> Result<Json, String> {
+        for id in self.instruction_breakpoints.drain(..) {
+            let _ = cpu.breakpoints.remove(id);
+        }
+
+        let mut breakpoints = Vec::new();
+        for breakpoint in args["breakpoints"].as_array().unwrap_or_default() {
+            let addr = breakpoint["instructionReference"]
+                .as_str()
+                .and_then(parse_reference)
+                .map(|addr| addr.wrapping_add(breakpoint["offset"].as_i64().unwrap_or(0) as u16));
+
+            breakpoints.push(match addr {
+                Some(addr) => {
+                    let id = cpu.breakpoints.add(BreakpointKind::Execute(addr));
+                    self.instruction_breakpoints.push(id);
+                    let mut result =
+                        Json::object([("id", Json::from(id)), ("verified", Json::from(true))]);
+                    self.set_condition(cpu, id, breakpoint, &mut result);
+                    result
+                }
+                None => Json::object([("verified", Json::from(false))]),
+            });
+        }
+
+        Ok(Json::object([("breakpoints", Json::Array(breakpoints))]))
+    }
+
+    // gives a breakpoint the condition requested for it, if any. one that
+    // can't be parsed leaves the breakpoint unverified, with the error
+    fn set_condition(&self, cpu: &mut Cpu, id: usize, requested: &Json, breakpoint: &mut Json) {
+        let Some(text) = requested["condition"]
+            .as_str()
+            .filter(|text| !text.trim().is_empty())
+        else {
+            return;
+        };
+
+        match Expression::parse(text, &self.symbols) {
+            Ok(condition) => {
+                let _ = cpu.breakpoints.set_condition(id, Some(condition));
+            }
+            Err(e) => {
+                let _ = cpu.breakpoints.remove(id);
+                breakpoint.set("verified", Json::from(false));
+                breakpoint.set("message", Json::from(e));
+            }
+        }
+    }
+
+    // returns the address of the code generated by a line of a source file
+    fn line_address(&mut self, path: &str, line: usize) -> Option<u16> {
+        let index = match self.is_listing(path) {
+            true => self
+                .lines
+                .as_ref()?
+                .lines
+                .iter()
+                .position(|l| l.number >= line)?,
+            false => *self.source_file(path)?.listing_lines.get(&line)?,
+        };
+
+        self.lines.as_ref()?.lines[index..]
+            .iter()
+            .take(16)
+            .find_map(|listing_line| listing_line.addr)
+    }
+
+    // returns whether a path is the listing file
+    fn is_listing(&self, path: &str) -> bool {
+        let canonical = |path: &str| std::fs::canonicalize(path).ok();
+
+        self.listing_path.as_deref().is_some_and(|listing| {
+            listing == path || canonical(listing).is_some_and(|l| Some(l) == canonical(path))
+        })
+    }
+
+    // returns a source file matched to the listing, reading it the first
+    // time it is asked for
+    fn source_file(&mut self, path: &str) -> Option<&SourceFile> {
+        if let Some(i) = self.sources.iter().position(|source| source.path == path) {
+            return Some(&self.sources[i]);
+        }
+
+        let text = std::fs::read(path).ok()?;
+        let listing_lines = self
+            .lines
+            .as_ref()?
+            .map_source(&String::from_utf8_lossy(&text));
+        let source_lines = listing_lines.iter().map(|(s, l)| (*l, *s)).collect();
+
+        self.sources.push(SourceFile {
+            path: path.to_string(),
+            listing_lines,
+            source_lines,
+        });
+        self.sources.last()
+    }
+
+    // returns the source file and line of the code at an address
+    fn source_position(&self, addr: u16) -> Option<(String, usize)> {
+        let lines = self.lines.as_ref()?;
+        let index = lines.line_index(addr)?;
+
+        for source in &self.sources {
+            if let Some(line) = source.source_lines.get(&index) {
+                return Some((source.path.clone(), *line));
+            }
+        }
+
+        let listing = self.listing_path.clone()?;
+        Some((listing, lines.lines[index].number))
+    }
+
+    // the current instruction, then the calls found on the stack
+    fn stack_trace(&self, cpu: &Cpu) -> Result<Json, String> {
+        let pc = read_register(cpu, "PC");
+        let addrs: Vec<u16> = std::iter::once(pc)
+            .chain(cpu.call_stack(STACK_DEPTH))
+            .collect();
+
+        let frames: Vec<Json> = addrs
+            .iter()
+            .enumerate()
+            .map(|(i, addr)| {
+                let name = match self.symbols.describe(*addr) {
+                    Some(name) => name,
+                    None => format!("{addr:04X}"),
+                };
+
+                let mut frame = Json::object([
+                    ("id", Json::from(i)),
+                    ("name", Json::from(name)),
+                    ("line", Json::from(0u8)),
+                    ("column", Json::from(0u8)),
+                    (
+                        "instructionPointerReference",
+                        Json::from(format_reference(*addr)),
+                    ),
+                ]);
+
+                if let Some((path, line)) = self.source_position(*addr) {
+                    let name = Path::new(&path)
+                        .file_name()
+                        .map(|name| name.to_string_lossy().into_owned());
+                    frame.set(
+                        "source",
+                        Json::object([("name", Json::from(name)), ("path", Json::from(path))]),
+                    );
+                    frame.set("line", Json::from(line));
+                    frame.set("column", Json::from(1u8));
+                }
+                frame
+            })
+            .collect();
+
+        Ok(Json::object([
+            ("totalFrames", Json::from(frames.len())),
+            ("stackFrames", Json::Array(frames)),
+        ]))
+    }
+
+    // the registers or the flags
+    fn variables(&self, cpu: &Cpu, reference: Option<i64>) -> Json {
+        let variable = |name: &str, value: String, memory: Option<u16>| {
+            let mut variable = Json::object([
+                ("name", Json::from(name)),
+                ("value", Json::from(value)),
+                ("variablesReference", Json::from(0u8)),
+            ]);
+            if let Some(addr) = memory {
+                variable.set("memoryReference", Json::from(format_reference(addr)));
+            }
+            variable
+        };
+
+        let variables = match reference {
+            Some(REGISTERS_REFERENCE) => ["A", "BC", "DE", "HL", "SP", "PC"]
+                .iter()
+                .map(|name| {
+                    let value = read_register(cpu, name);
+                    match *name {
+                        "A" => variable(name, format_byte(value as u8), None),
+                        _ => {
+                            let text = match self.symbols.describe(value) {
+                                Some(symbol) => format!("{} ({symbol})", format_word(value)),
+                                None => format_word(value),
+                            };
+                            variable(name, text, Some(value))
+                        }
+                    }
+                })
+                .collect(),
+            Some(FLAGS_REFERENCE) => FLAGS
+                .iter()
+                .map(|(name, bit)| {
+                    let set = read_f(cpu) & bit != 0;
+                    variable(name, String::from(if set { "1" } else { "0" }), None)
+                })
+                .collect(),
+            _ => Vec::new(),
+        };
+
+        Json::object([("variables", Json::Array(variables))])
+    }
+
+    // changes a register or a flag
+    fn set_variable(&mut self, cpu: &mut Cpu, args: &Json) -> Result<Json, String> {
+        let name = args["name"].as_str().unwrap_or_default();
+        let value = parse_number(args["value"].as_str().unwrap_or_default())?;
+
+        match args["variablesReference"].as_i64() {
+            Some(REGISTERS_REFERENCE) => cpu.set_register(name, value)?,
+            Some(FLAGS_REFERENCE) => {
+                let (_, bit) = FLAGS
+                    .iter()
+                    .find(|(flag, _)| *flag == name)
+                    .ok_or_else(|| format!("Unknown flag {name}"))?;
+                let f = match value {
+                    0 => read_f(cpu) & !bit,
+                    1 => read_f(cpu) | bit,
+                    _ => return Err(String::from("Flags are 0 or 1")),
+                };
+                cpu.set_register("PSW", (read_register(cpu, "A") << 8) | f as u16)?;
+            }
+            _ => return Err(String::from("Unknown variables reference")),
+        }
+
+        let variables = self.variables(cpu, args["variablesReference"].as_i64());
+        let value = variables["variables"]
+            .as_array()
+            .unwrap_or_default()
+            .iter()
+            .find(|variable| variable["name"].as_str() == Some(name))
+            .map(|variable| variable["value"].clone())
+            .unwrap_or(Json::Null);
+
+        Ok(Json::object([("value", value)]))
+    }
+
+    // evaluates an expression over the state of the Cpu, for the debug
+    // console, watches and hovers. values that fit in a byte are shown as
+    // one, and addresses can be opened in the memory view
+    fn evaluate(&self, cpu: &Cpu, expression: &str) -> Result<Json, String> {
+        let value = Expression::parse(expression, &self.symbols)?.evaluate(cpu)?;
+
+        let mut body = Json::object([
+            ("result", Json::from(format_value(value))),
+            ("variablesReference", Json::from(0u8)),
+        ]);
+        if (0x100..=0xFFFF).contains(&value) {
+            body.set(
+                "memoryReference",
+                Json::from(format_reference(value as u16)),
+            );
+        }
+        Ok(body)
+    }
+
+    // disassembles instructions around an address. instructions before it
+    // are found by disassembling from far enough back
+    fn disassemble(&self, cpu: &Cpu, args: &Json) -> Result<Json, String> {
+        let addr = args["memoryReference"]
+            .as_str()
+            .and_then(parse_reference)
+            .ok_or("Invalid memory reference")?
+            .wrapping_add(args["offset"].as_i64().unwrap_or(0) as u16);
+        // offsets and counts beyond the size of memory are cut down to it
+        let offset = args["instructionOffset"]
+            .as_i64()
+            .unwrap_or(0)
+            .clamp(-0x10000, 0x10000);
+        let count = args["instructionCount"]
+            .as_i64()
+            .unwrap_or(0)
+            .clamp(0, 0x10000) as usize;
+
+        let mut start = addr;
+        if offset < 0 {
+            let back = offset.unsigned_abs() as usize;
+            let from = (addr as usize).saturating_sub(back * 3) as u16;
+            let before = disassemble_range(&cpu.memory, from, (addr - from) as usize, self.syntax)?;
+            let skip = before.len().saturating_sub(back);
+            start = before
+                .get(skip)
+                .map_or(addr, |instruction| instruction.addr);
+        } else {
+            for _ in 0..offset {
+                start = start
+                    .wrapping_add(disassemble(&cpu.memory, start, self.syntax)?.n_bytes() as u16);
+            }
+        }
+
+        let mut instructions = Vec::new();
+        for _ in 0..count {
+            let instruction =
+                disassemble(&cpu.memory, start, self.syntax)?.with_symbols(&self.symbols);
+            let bytes: Vec<String> = instruction
+                .bytes
+                .iter()
+                .map(|b| format!("{b:02X}"))
+                .collect();
+
+            let mut json = Json::object([
+                ("address", Json::from(format_reference(start))),
+                ("instructionBytes", Json::from(bytes.join(" "))),
+                ("instruction", Json::from(instruction.text.clone())),
+            ]);
+            if let Some(symbol) = self.symbols.describe(start) {
+                json.set("symbol", Json::from(symbol));
+            }
+            if let Some((path, line)) = self.source_position(start) {
+                json.set("location", Json::object([("path", Json::from(path))]));
+                json.set("line", Json::from(line));
+            }
+
+            instructions.push(json);
+            start = start.wrapping_add(instruction.n_bytes() as u16);
+        }
+
+        Ok(Json::object([("instructions", Json::Array(instructions))]))
+    }
+}
+
+// reads a message, framed by a Content-Length header. returns None at the end
+// of the input
+pub fn read_message(input: &mut dyn BufRead) -> Result<Option<Json>, String> {
+    let mut length = None;
+
+    loop {
+        let mut header = String::new();
+        if input.read_line(&mut header).map_err(|e| e.to_string())? == 0 {
+            return Ok(None);
+        }
+
+        let header = header.trim();
+        if header.is_empty() {
+            break;
+        }
+
+        if let Some((name, value)) = header.split_once(':') {
+            if name.trim().eq_ignore_ascii_case("Content-Length") {
+                length = Some(
+                    value
+                        .trim()
+                        .parse::<usize>()
+                        .map_err(|_| format!("Invalid Content-Length {value}"))?,
+                );
+            }
+        }
+    }
+
+    let mut content = vec![0; length.ok_or("Message without a Content-Length")?];
+    input.read_exact(&mut content).map_err(|e| e.to_string())?;
+
+    Json::parse(&String::from_utf8_lossy(&content)).map(Some)
+}
+
+// writes a message, framed by a Content-Length header
+pub fn write_message(output: &mut dyn Write, message: &Json) -> Result<(), String> {
+    let content = message.to_string();
+
+    write!(output, "Content-Length: {}\r\n\r\n{content}", content.len())
+        .and_then(|_| output.flush())
+        .map_err(|e| e.to_string())
+}
+
+fn capabilities() -> Json {
+    Json::object(
+        [
+            "supportsConfigurationDoneRequest",
+            "supportsInstructionBreakpoints",
+            "supportsConditionalBreakpoints",
+            "supportsSetVariable",
+            "supportsReadMemoryRequest",
+            "supportsDisassembleRequest",
+            "supportsSteppingGranularity",
+            "supportsEvaluateForHovers",
+            "supportsTerminateRequest",
+        ]
+        .map(|capability| (capability, Json::from(true))),
+    )
+}
+
+fn scopes() -> Json {
+    let scope = |name: &str, reference: i64| {
+        Json::object([
+            ("name", Json::from(name)),
+            ("presentationHint", Json::from("registers")),
+            ("variablesReference", Json::from(reference)),
+            ("expensive", Json::from(false)),
+        ])
+    };
+
+    Json::object([(
+        "scopes",
+        Json::Array(vec![
+            scope("Registers", REGISTERS_REFERENCE),
+            scope("Flags", FLAGS_REFERENCE),
+        ]),
+    )])
+}
+
+// reads memory, which is sent base64 encoded
+fn read_memory(cpu: &Cpu, args: &Json) -> Result<Json, String> {
+    let addr = args["memoryReference"]
+        .as_str()
+        .and_then(parse_reference)
+        .ok_or("Invalid memory reference")?
+        .wrapping_add(args["offset"].as_i64().unwrap_or(0) as u16);
+    let count = (args["count"].as_i64().unwrap_or(0).max(0) as usize).min(0x10000 - addr as usize);
+
+    let data = (0..count)
+        .map(|i| {
+            let value = cpu.memory.read(
+                RegisterValue::from(addr.wrapping_add(i as u16)),
+                MemorySize::Integer8,
+            )?;
+            Ok(u8::try_from(value)?)
+        })
+        .collect::<Result<Vec<u8>, String>>()?;
+
+    Ok(Json::object([
+        ("address", Json::from(format_reference(addr))),
+        ("data", Json::from(base64(&data))),
+    ]))
+}
+
+// encodes bytes as base64
+fn base64(data: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+    let mut encoded = String::new();
+    for chunk in data.chunks(3) {
+        let bits = chunk
+            .iter()
+            .enumerate()
+            .fold(0u32, |bits, (i, b)| bits | (*b as u32) << (16 - 8 * i));
+
+        for i in 0..4 {
+            match i <= chunk.len() {
+                true => encoded.push(ALPHABET[(bits >> (18 - 6 * i) & 0x3F) as usize] as char),
+                false => encoded.push('='),
+            }
+        }
+    }
+
+    encoded
+}
+
+// memory references and instruction pointers are addresses like "0x0100"
+fn format_reference(addr: u16) -> String {
+    format!("0x{addr:04X}")
+}
+
+fn parse_reference(reference: &str) -> Option<u16> {
+    parse_number(reference).ok()
+}
+
+// parses a number given as 0x1234, 1234H or decimal
+fn parse_number(text: &str) -> Result<u16, String> {
+    let text = text.trim();
+    let invalid = || format!("Invalid number {text}");
+
+    if let Some(hex) = text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+        u16::from_str_radix(hex, 16).map_err(|_| invalid())
+    } else if let Some(hex) = text.strip_suffix(['h', 'H']) {
+        u16::from_str_radix(hex, 16).map_err(|_| invalid())
+    } else {
+        text.parse().map_err(|_| invalid())
+    }
+}
+
+fn read_register(cpu: &Cpu, name: &str) -> u16 {
+    match name {
+        "A" => u16::from(cpu.alu.accumulator()),
+        "BC" => u16::from(cpu.reg_array.read_reg(Register::BC)),
+        "DE" => u16::from(cpu.reg_array.read_reg(Register::DE)),
+        "HL" => u16::from(cpu.reg_array.read_reg(Register::HL)),
+        "SP" => u16::from(cpu.reg_array.read_reg(Register::SP)),
+        _ => u16::from(cpu.reg_array.read_reg(Register::PC)),
+    }
+}
+
+// the F register, worked out from the flags
+fn read_f(cpu: &Cpu) -> u8 {
+    let flags = cpu.alu.flags();
+
+    [
+        (flags.carry, 0x01),
+        (flags.parity, 0x04),
+        (flags.aux_carry, 0x10),
+        (flags.zero, 0x40),
+        (flags.sign, 0x80),
+    ]
+    .iter()
+    .filter(|(set, _)| *set)
+    .fold(0x02, |f, (_, bit)| f | bit)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // frames requests the way a client would
+    fn requests(requests: &[(&str, Json)]) -> Vec<u8> {
+        let mut input = Vec::new();
+        for (i, (command, args)) in requests.iter().enumerate() {
+            let request = Json::object([
+                ("seq", Json::from(i + 1)),
+                ("type", Json::from("request")),
+                ("command", Json::from(*command)),
+                ("arguments", args.clone()),
+            ]);
+            write_message(&mut input, &request).unwrap();
+        }
+        input
+    }
+
+    #[test]
+    fn dap_session() {
+        let source = "        ORG 100H\nSTART:  MVI A,41H\n        OUT 0\n        CALL SUB\n        HLT\nSUB:    INR A\n        RET\n";
+        let assembly = crate::assembler::assemble(source).unwrap();
+
+        let mut cpu = Cpu::new();
+        assembly.load_into(&mut cpu).unwrap();
+        cpu.set_pc(0x0100).unwrap();
+
+        let mut adapter = DebugAdapter::new();
+        adapter.lines = Some(LineTable::parse(&assembly.listing));
+        adapter.listing_path = Some(String::from("prog.prn"));
+        adapter.symbols = assembly.symbols.iter().map(|(n, a)| (n, *a)).collect();
+
+        let input = requests(&[
+            (
+                "initialize",
+                Json::object([("adapterID", Json::from("i8080"))]),
+            ),
+            ("launch", Json::object([("stopOnEntry", Json::from(false))])),
+            (
+                "setBreakpoints",
+                Json::object([
+                    ("source", Json::object([("path", Json::from("prog.prn"))])),
+                    (
+                        "breakpoints",
+                        Json::Array(vec![Json::object([("line", Json::from(6u8))])]),
+                    ),
+                ]),
+            ),
+            ("configurationDone", Json::Null),
+        ]);
+
+        // the requests as the client's thread would read them
+        let mut output = Vec::new();
+        let mut reader = &input[..];
+        adapter.capture_output(&mut cpu);
+        while let Some(request) = read_message(&mut reader).unwrap() {
+            assert!(adapter.handle(&mut cpu, &request, &mut output).unwrap());
+        }
+        while adapter.running {
+            adapter.run_slice(&mut cpu, &mut output).unwrap();
+        }
+
+        let mut messages = Vec::new();
+        let mut reader = &output[..];
+        while let Some(message) = read_message(&mut reader).unwrap() {
+            messages.push(message);
+        }
+
+        let find = |kind: &str, name: &str| {
+            messages
+                .iter()
+                .find(|m| {
+                    m["type"].as_str() == Some(kind)
+                        && (m["command"].as_str() == Some(name)
+                            || m["event"].as_str() == Some(name))
+                })
+                .unwrap_or_else(|| panic!("no {kind} {name}"))
+        };
+
+        assert_eq!(
+            find("response", "initialize")["body"]["supportsDisassembleRequest"],
+            Json::from(true)
+        );
+        assert_eq!(
+            find("response", "setBreakpoints")["body"]["breakpoints"]
+                .as_array()
+                .unwrap()[0]["instructionReference"]
+                .as_str(),
+            Some("0x0108")
+        );
+        assert_eq!(
+            find("event", "output")["body"]["output"].as_str(),
+            Some("A")
+        );
+        assert_eq!(
+            find("event", "stopped")["body"]["reason"].as_str(),
+            Some("breakpoint")
+        );
+
+        // stopped in SUB, called from 0104
+        assert_eq!(
+            adapter.stack_trace(&cpu).unwrap()["stackFrames"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|f| f["name"].as_str().unwrap().to_string())
+                .collect::<Vec<_>>(),
+            ["SUB", "START+4"]
+        );
+        assert_eq!(
+            adapter.evaluate(&cpu, "a").unwrap()["result"].as_str(),
+            Some("41H (65)")
+        );
+        assert_eq!(
+            adapter.evaluate(&cpu, "[SUB]").unwrap()["result"].as_str(),
+            Some("3CH (60)")
+        );
+        assert_eq!(
+            adapter.evaluate(&cpu, "A == 0x41 && [SUB] > 3").unwrap()["result"].as_str(),
+            Some("01H (1)")
+        );
+        assert_eq!(
+            adapter.evaluate(&cpu, "[SUB").unwrap_err(),
+            "Expected ']' at column 5, found the end"
+        );
+
+        let memory = read_memory(
+            &cpu,
+            &Json::object([
+                ("memoryReference", Json::from("0x0100")),
+                ("count", Json::from(4u8)),
+            ]),
+        )
+        .unwrap();
+        assert_eq!(memory["data"].as_str(), Some("PkHTAA=="));
+    }
+
+    #[test]
+    fn dap_breakpoints_disassembly_and_stepping() {
+        let source = "        ORG 100H\nSTART:  MVI A,41H\n        OUT 0\n        CALL SUB\n        HLT\nSUB:    INR A\n        RET\n";
+        let assembly = crate::assembler::assemble(source).unwrap();
+
+        let mut cpu = Cpu::new();
+        assembly.load_into(&mut cpu).unwrap();
+        cpu.set_pc(0x0100).unwrap();
+
+        let mut adapter = DebugAdapter::new();
+        adapter.lines = Some(LineTable::parse(&assembly.listing));
+        adapter.listing_path = Some(String::from("prog.prn"));
+        adapter.capture_output(&mut cpu);
+
+        let set_breakpoints = |adapter: &mut DebugAdapter, cpu: &mut Cpu, breakpoints| {
+            let args = Json::object([
+                ("source", Json::object([("path", Json::from("prog.prn"))])),
+                ("breakpoints", Json::Array(breakpoints)),
+            ]);
+            adapter.set_breakpoints(cpu, &args).unwrap()["breakpoints"]
+                .as_array()
+                .unwrap()
+                .to_vec()
+        };
+        let line = |line: u8, condition: &str| {
+            Json::object([
+                ("line", Json::from(line)),
+                ("condition", Json::from(condition)),
+            ])
+        };
+
+        // a line without code and a bad condition leave their breakpoints
+        // unverified, and setting them again replaces the old ones
+        let breakpoints = set_breakpoints(
+            &mut adapter,
+            &mut cpu,
+            vec![line(3, ""), line(100, ""), line(6, "A ==")],
+        );
+        assert_eq!(
+            breakpoints
+                .iter()
+                .map(|b| b["verified"].clone())
+                .collect::<Vec<_>>(),
+            [Json::from(true), Json::from(false), Json::from(false)]
+        );
+        assert_eq!(
+            breakpoints[1]["message"].as_str(),
+            Some("No code at this line")
+        );
+        assert_eq!(cpu.breakpoints.len(), 1);
+        assert!(set_breakpoints(&mut adapter, &mut cpu, Vec::new()).is_empty());
+        assert_eq!(cpu.breakpoints.len(), 0);
+
+        // disassembling from two instructions before SUB, and with offsets
+        // far outside of memory
+        let disassemble = |reference: &str, offset: i64, count: u8| {
+            let args = Json::object([
+                ("memoryReference", Json::from(reference)),
+                ("instructionOffset", Json::from(offset)),
+                ("instructionCount", Json::from(count)),
+            ]);
+            adapter.disassemble(&cpu, &args).unwrap()["instructions"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|i| i["address"].as_str().unwrap().to_string())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(disassemble("0x0108", -2, 3), ["0x0104", "0x0107", "0x0108"]);
+        assert_eq!(disassemble("0x0002", -1_000_000_000_000, 1), ["0x0000"]);
+        assert_eq!(disassemble("0x0100", 1_000_000_000_000, 1).len(), 1);
+
+        // stepping into the call, out of it and over the HLT
+        let mut output = Vec::new();
+        let mut pcs = Vec::new();
+        for (i, command) in ["stepIn", "next", "stepIn", "stepOut", "next"]
+            .iter()
+            .enumerate()
+        {
+            let request = Json::object([
+                ("seq", Json::from(i + 1)),
+                ("type", Json::from("request")),
+                ("command", Json::from(*command)),
+                ("arguments", Json::Null),
+            ]);
+            adapter.handle(&mut cpu, &request, &mut output).unwrap();
+            pcs.push(read_register(&cpu, "PC"));
+        }
+        assert_eq!(pcs, [0x0102, 0x0104, 0x0108, 0x0107, 0x0108]);
+
+        let mut events = Vec::new();
+        let mut reader = &output[..];
+        while let Some(message) = read_message(&mut reader).unwrap() {
+            if let Some(event) = message["event"].as_str() {
+                events.push(event.to_string());
+            }
+        }
+        assert_eq!(
+            events,
+            [
+                "stopped",
+                "output",
+                "stopped",
+                "stopped",
+                "stopped",
+                "exited",
+                "terminated"
+            ]
+        );
+    }
+}
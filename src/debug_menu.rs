@@ -4,7 +4,9 @@
  * Also referenced: https://github.com/imgui-rs/imgui-examples/blob/main/examples/support/mod.rs
  */
 pub mod cpu_output;
+pub mod disassembly_view;
 pub mod registers_view;
+pub mod source_view;
 
 use glium::Surface;
 use imgui::{Context, Ui};
@@ -16,6 +18,8 @@ use imgui_winit_support::{HiDpiMode, WinitPlatform};
 use std::time::Instant;
 
 // initializes an ImGui window
+// (EventLoop::run is deprecated in favour of run_app, but still works fine)
+#[allow(deprecated)]
 pub fn init_imgui(title: &str, ui_f: impl Fn(&mut Ui)) {
     let mut imgui = Context::create();
 
@@ -4,7 +4,6 @@
  */
 
 use imgui::*;
-use crate::cpu::*;
 
 pub fn add_cpu_output(ui: &Ui, out_str: &str) {
     ui.window("Output")
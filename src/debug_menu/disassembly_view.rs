@@ -0,0 +1,40 @@
+/*
+ * disassembly_view.rs - Debug menu window that displays the instructions
+ * starting at the program counter
+ */
+
+use i8080::cpu::disassembler::*;
+use i8080::cpu::*;
+use i8080::symbols::SymbolTable;
+use imgui::*;
+use registers::*;
+
+// number of instructions shown
+const N_INSTRUCTIONS: usize = 16;
+
+pub fn add_disassembly_view(ui: &Ui, cpu: &Cpu, syntax: Syntax, symbols: &SymbolTable) {
+    ui.window("Disassembly View")
+        .size([300.0, 300.0], Condition::FirstUseEver)
+        .build(|| {
+            let mut addr = u16::from(cpu.reg_array.read_reg(Register::PC));
+
+            for i in 0..N_INSTRUCTIONS {
+                let Ok(instruction) = disassemble(&cpu.memory, addr, syntax) else {
+                    break;
+                };
+                let instruction = instruction.with_symbols(symbols);
+
+                if let Some(name) = symbols.name_at(addr) {
+                    ui.text_disabled(format!("{name}:"));
+                }
+
+                // the instruction at the program counter is highlighted
+                match i {
+                    0 => ui.text_colored([1.0, 1.0, 0.0, 1.0], instruction.to_string()),
+                    _ => ui.text(instruction.to_string()),
+                }
+
+                addr = addr.wrapping_add(instruction.n_bytes() as u16);
+            }
+        });
+}
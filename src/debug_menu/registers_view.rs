@@ -3,8 +3,8 @@
  * the current register values.
  */
 
+use i8080::cpu::*;
 use imgui::*;
-use crate::cpu::*;
 use registers::*;
 use strum::IntoEnumIterator;
 
@@ -17,13 +17,9 @@ pub fn add_registers_view(ui: &Ui, reg_array: &RegisterArray) {
                 | TableFlags::BORDERS_H
                 | TableFlags::BORDERS_V;
 
-            if let Some(_t) = ui.begin_table_with_sizing(
-                "Registers View",
-                2,
-                flags,
-                [300.0, 100.0],
-                0.0,
-            ) {
+            if let Some(_t) =
+                ui.begin_table_with_sizing("Registers View", 2, flags, [300.0, 100.0], 0.0)
+            {
                 ui.table_setup_column("Register");
                 ui.table_setup_column("Value");
 
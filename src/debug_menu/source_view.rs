@@ -0,0 +1,39 @@
+/*
+ * source_view.rs - Debug menu window that displays the source lines around
+ * the program counter, from an assembler listing
+ */
+
+use i8080::cpu::*;
+use i8080::listing::*;
+use imgui::*;
+use registers::*;
+
+// number of lines shown before and after the current line
+const LINES_BEFORE: usize = 8;
+const LINES_AFTER: usize = 16;
+
+pub fn add_source_view(ui: &Ui, cpu: &Cpu, lines: &LineTable) {
+    ui.window("Source View")
+        .size([400.0, 400.0], Condition::FirstUseEver)
+        .position([310.0, 0.0], Condition::FirstUseEver)
+        .build(|| {
+            let pc = u16::from(cpu.reg_array.read_reg(Register::PC));
+            let Some(current) = lines.line_index(pc) else {
+                ui.text_disabled("The program counter is outside of the listing");
+                return;
+            };
+
+            let start = current.saturating_sub(LINES_BEFORE);
+            let end = (current + LINES_AFTER).min(lines.lines.len());
+
+            for (i, line) in lines.lines[start..end].iter().enumerate() {
+                let text = format!("{:>5}  {}", line.number, line.text);
+
+                // the line being executed is highlighted
+                match start + i == current {
+                    true => ui.text_colored([1.0, 1.0, 0.0, 1.0], text),
+                    false => ui.text(text),
+                }
+            }
+        });
+}
@@ -0,0 +1,995 @@
+/*
+ * isis.rs - Contains code related to emulating the ISIS-II system calls, so
+ * that Intel's development tools (PL/M-80, ASM80, LINK, LOCATE) can be run
+ * against directories on the host
+ * See the ISIS-II System User's Guide (Intel 9800306), chapter 7
+ */
+pub mod omf;
+
+use crate::cpu::memory::*;
+use crate::cpu::registers::*;
+use crate::cpu::*;
+use crate::terminal::Console;
+
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{ErrorKind, Read, Seek, SeekFrom, Write};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+// address of the ISIS-II system call entry point
+pub const ISIS_ENTRY: u16 = 0x0040;
+
+// address of the monitor's MEMCK routine, which tools call to size memory
+pub const MEMCK_ENTRY: u16 = 0xF81B;
+
+// highest address available to programs, as reported by MEMCK
+pub const MEMORY_TOP: u16 = 0xF7FF;
+
+// number of disk drives, :F0: to :F9:
+pub const DRIVES: usize = 10;
+
+// connection numbers (AFTNs) that are always open
+pub const CONSOLE_OUT_AFTN: u16 = 0;
+pub const CONSOLE_IN_AFTN: u16 = 1;
+
+// the most files a program may have open at once, besides the console
+const MAX_OPEN_FILES: usize = 6;
+
+// ISIS-II status codes
+const OK: u16 = 0;
+const ERR_BAD_AFTN: u16 = 2;
+const ERR_TOO_MANY_FILES: u16 = 3;
+const ERR_BAD_FILE_NAME: u16 = 4;
+const ERR_BAD_DEVICE: u16 = 5;
+const ERR_WRITE_ON_INPUT: u16 = 6;
+const ERR_READ_ON_OUTPUT: u16 = 8;
+const ERR_RENAME_EXISTS: u16 = 11;
+const ERR_NO_SUCH_FILE: u16 = 13;
+const ERR_WRITE_PROTECTED: u16 = 14;
+const ERR_SEEK_NON_DISK: u16 = 19;
+const ERR_SEEK_BEFORE_START: u16 = 20;
+
+// access modes passed to OPEN
+const ACCESS_READ: u16 = 1;
+const ACCESS_WRITE: u16 = 2;
+const ACCESS_UPDATE: u16 = 3;
+
+// size of a block, as used by SEEK
+const BLOCK_SIZE: u64 = 128;
+
+// IsisDevice enum - the devices that can be named at the start of an ISIS-II
+// file name, e.g. :F1:FOO.PLM or :CO:
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum IsisDevice {
+    Disk(u8), // :F0: to :F9:
+    TeletypeIn,
+    TeletypeOut,
+    LinePrinter,
+    ByteBucket,
+    ConsoleIn,
+    ConsoleOut,
+}
+
+impl IsisDevice {
+    // returns the device with the given two letter name
+    pub fn from_name(name: &str) -> Option<Self> {
+        use IsisDevice::*;
+
+        match name.as_bytes() {
+            [b'F', n @ b'0'..=b'9'] => Some(Disk(n - b'0')),
+            b"TI" => Some(TeletypeIn),
+            b"TO" => Some(TeletypeOut),
+            b"LP" => Some(LinePrinter),
+            b"BB" => Some(ByteBucket),
+            b"CI" => Some(ConsoleIn),
+            b"CO" => Some(ConsoleOut),
+            _ => None,
+        }
+    }
+
+    // returns the device number reported by SPATH
+    pub fn number(&self) -> u8 {
+        use IsisDevice::*;
+
+        match self {
+            Disk(n) => *n,
+            TeletypeIn => 10,
+            TeletypeOut => 11,
+            LinePrinter => 24,
+            ByteBucket => 26,
+            ConsoleIn => 27,
+            ConsoleOut => 28,
+        }
+    }
+}
+
+// IsisPath struct - a parsed ISIS-II file name, name and extension are upper
+// case and empty for non-disk devices
+#[derive(Debug, PartialEq, Clone)]
+pub struct IsisPath {
+    pub device: IsisDevice,
+    pub name: String, // up to 6 characters
+    pub ext: String,  // up to 3 characters
+}
+
+impl IsisPath {
+    // parses a file name, which ends at the first character that can't be
+    // part of it (usually a space or carriage return). returns the status
+    // code to report for a bad name
+    pub fn parse(s: &[u8]) -> Result<Self, u16> {
+        let s: Vec<u8> = s
+            .iter()
+            .skip_while(|c| **c == b' ')
+            .take_while(|c| c.is_ascii_alphanumeric() || **c == b':' || **c == b'.')
+            .map(|c| c.to_ascii_uppercase())
+            .collect();
+        let s = String::from_utf8_lossy(&s);
+
+        let (device, file) = match s.strip_prefix(':') {
+            Some(rest) => {
+                let (device, file) = rest.split_once(':').ok_or(ERR_BAD_DEVICE)?;
+                (IsisDevice::from_name(device).ok_or(ERR_BAD_DEVICE)?, file)
+            }
+
+            None => (IsisDevice::Disk(0), &s[..]),
+        };
+
+        let (name, ext) = file.split_once('.').unwrap_or((file, ""));
+
+        match device {
+            IsisDevice::Disk(_) => {
+                let valid = |s: &str, len| s.len() <= len && !s.contains([':', '.']);
+
+                if name.is_empty() || !valid(name, 6) || !valid(ext, 3) {
+                    return Err(ERR_BAD_FILE_NAME);
+                }
+            }
+
+            _ if !file.is_empty() => return Err(ERR_BAD_FILE_NAME),
+            _ => {}
+        }
+
+        Ok(Self {
+            device,
+            name: name.to_string(),
+            ext: ext.to_string(),
+        })
+    }
+
+    // returns the name as it would be stored on the host, e.g. FOO.PLM
+    pub fn file_name(&self) -> String {
+        if self.ext.is_empty() {
+            self.name.clone()
+        } else {
+            format!("{}.{}", self.name, self.ext)
+        }
+    }
+}
+
+// Connection enum - what an open AFTN is connected to
+enum Connection {
+    Disk { file: File, access: u16 },
+    ConsoleIn,
+    ConsoleOut,
+    LinePrinter,
+    ByteBucket,
+}
+
+// Isis struct - holds the state of the emulated ISIS-II
+pub struct Isis {
+    pub drives: [Option<PathBuf>; DRIVES], // host directories for :F0: to :F9:
+    pub console: Option<Box<dyn Console>>, // console input, host stdin if None
+    pub list: Option<Box<dyn Write + Send>>, // :LP:, discarded if None
+    connections: HashMap<u16, Connection>,
+    console_line: Vec<u8>, // line-edited console input
+    console_pos: usize,
+    console_pending: Vec<u8>, // the start of a line read before the input ran out
+    console_cr: bool,         // the last line ended with CR, so an LF after it is skipped
+    console_in_file: Option<File>, // set by CONSOL
+    console_out_file: Option<File>,
+}
+
+impl Default for Isis {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Isis {
+    // creates a new instance of Isis with no drives, and only the console open
+    pub fn new() -> Self {
+        let mut connections = HashMap::new();
+        connections.insert(CONSOLE_OUT_AFTN, Connection::ConsoleOut);
+        connections.insert(CONSOLE_IN_AFTN, Connection::ConsoleIn);
+
+        Self {
+            drives: Default::default(),
+            console: None,
+            list: None,
+            connections,
+            console_line: Vec::new(),
+            console_pos: 0,
+            console_pending: Vec::new(),
+            console_cr: false,
+            console_in_file: None,
+            console_out_file: None,
+        }
+    }
+
+    // sets the command line that started the program, which it reads back
+    // from the console
+    pub fn set_command_line(&mut self, command_line: &str) {
+        self.console_line = format!("{}\r\n", command_line.trim_end()).into_bytes();
+        self.console_pos = 0;
+    }
+
+    // returns the host path of a file on a disk device, matching the name
+    // case-insensitively, or the path it would be created at
+    fn host_path(&self, path: &IsisPath) -> Result<PathBuf, u16> {
+        let IsisDevice::Disk(drive) = path.device else {
+            return Err(ERR_BAD_DEVICE);
+        };
+        let dir = self.drives[drive as usize].as_ref().ok_or(ERR_BAD_DEVICE)?;
+
+        let file_name = path.file_name();
+        let existing = fs::read_dir(dir).ok().and_then(|entries| {
+            entries
+                .flatten()
+                .find(|entry| {
+                    entry
+                        .file_name()
+                        .to_string_lossy()
+                        .eq_ignore_ascii_case(&file_name)
+                })
+                .map(|entry| entry.path())
+        });
+
+        Ok(existing.unwrap_or_else(|| dir.join(file_name)))
+    }
+
+    // handles a call to the ISIS-II entry point: the system call number is
+    // passed in C, and the address of its parameter block in DE. every call
+    // ends by writing a status code through the last parameter
+    pub fn call(&mut self, cpu: &mut Cpu) -> Result<(), String> {
+        let function = u8::try_from(cpu.reg_array.read_reg(Register::C))?;
+        let params = u16::from(cpu.reg_array.read_reg(Register::DE));
+
+        // reads the nth parameter from the parameter block
+        let param = |cpu: &Cpu, n: u16| read_word(&cpu.memory, params.wrapping_add(n * 2));
+
+        let (status, status_param) = match function {
+            // OPEN (aftn_ptr, file_ptr, access, echo, status_ptr)
+            0 => {
+                let file_ptr = param(cpu, 1)?;
+                let access = param(cpu, 2)?;
+                let result = self.open(&cpu.memory, file_ptr, access)?;
+
+                if let Ok(aftn) = result {
+                    let ptr = param(cpu, 0)?;
+                    write_word(&mut cpu.memory, ptr, aftn)?;
+                }
+
+                (result.err().unwrap_or(OK), 4)
+            }
+
+            // CLOSE (aftn, status_ptr)
+            1 => {
+                let aftn = param(cpu, 0)?;
+
+                // the console can't be closed, but closing it isn't an error
+                let status = match aftn {
+                    CONSOLE_OUT_AFTN | CONSOLE_IN_AFTN => OK,
+                    _ => match self.connections.remove(&aftn) {
+                        Some(_) => OK,
+                        None => ERR_BAD_AFTN,
+                    },
+                };
+
+                (status, 1)
+            }
+
+            // DELETE (file_ptr, status_ptr)
+            2 => {
+                let path = read_path(&cpu.memory, param(cpu, 0)?)?;
+                let status = match path.and_then(|path| self.host_path(&path)) {
+                    // the host refusing to delete a file is reported as the
+                    // file being write protected
+                    Ok(host_path) if host_path.exists() => match fs::remove_file(&host_path) {
+                        Ok(()) => OK,
+                        Err(_) => ERR_WRITE_PROTECTED,
+                    },
+                    Ok(_) => ERR_NO_SUCH_FILE,
+                    Err(status) => status,
+                };
+
+                (status, 1)
+            }
+
+            // READ (aftn, buffer_ptr, count, actual_ptr, status_ptr)
+            3 => {
+                let aftn = param(cpu, 0)?;
+                let buffer = param(cpu, 1)?;
+                let count = param(cpu, 2)?;
+
+                let result = self.read(cpu, aftn, count as usize)?;
+
+                // the call is made again once there is console input
+                if cpu.is_waiting_for_input() {
+                    return Ok(());
+                }
+
+                let status = match result {
+                    Ok(data) => {
+                        let ptr = param(cpu, 3)?;
+                        write_word(&mut cpu.memory, ptr, data.len() as u16)?;
+                        cpu.load_to_memory(data, buffer)?;
+                        OK
+                    }
+                    Err(status) => status,
+                };
+
+                (status, 4)
+            }
+
+            // WRITE (aftn, buffer_ptr, count, status_ptr)
+            4 => {
+                let aftn = param(cpu, 0)?;
+                let buffer = param(cpu, 1)?;
+                let count = param(cpu, 2)?;
+
+                let data = (0..count)
+                    .map(|i| read_byte(&cpu.memory, buffer.wrapping_add(i)))
+                    .collect::<Result<Vec<u8>, String>>()?;
+
+                (self.write(cpu, aftn, &data)?, 3)
+            }
+
+            // SEEK (aftn, mode, block_ptr, byte_ptr, status_ptr)
+            5 => {
+                let aftn = param(cpu, 0)?;
+                let mode = param(cpu, 1)?;
+                let (block_ptr, byte_ptr) = (param(cpu, 2)?, param(cpu, 3)?);
+
+                let block = read_word(&cpu.memory, block_ptr)? as u64;
+                let byte = read_word(&cpu.memory, byte_ptr)? as u64;
+
+                let status = match self.seek(aftn, mode, block * BLOCK_SIZE + byte)? {
+                    Ok(position) => {
+                        // mode 0 reports the current position
+                        if mode == 0 {
+                            write_word(&mut cpu.memory, block_ptr, (position / BLOCK_SIZE) as u16)?;
+                            write_word(&mut cpu.memory, byte_ptr, (position % BLOCK_SIZE) as u16)?;
+                        }
+                        OK
+                    }
+                    Err(status) => status,
+                };
+
+                (status, 4)
+            }
+
+            // LOAD (file_ptr, bias, switch, entry_ptr, status_ptr)
+            6 => {
+                let bias = param(cpu, 1)?;
+                let switch = param(cpu, 2)?;
+                let path = read_path(&cpu.memory, param(cpu, 0)?)?;
+
+                let status = match path.and_then(|path| self.host_path(&path)) {
+                    Ok(host_path) if host_path.exists() => {
+                        let data = fs::read(&host_path).map_err(|e| e.to_string())?;
+                        let entry = omf::load_object(cpu, &data, bias)?.unwrap_or(0);
+
+                        // switch 0 returns to the caller with the entry point,
+                        // otherwise control is passed to the loaded program
+                        if switch == 0 {
+                            let ptr = param(cpu, 3)?;
+                            write_word(&mut cpu.memory, ptr, entry)?;
+                        } else {
+                            cpu.set_pc(entry)?;
+                        }
+
+                        OK
+                    }
+                    Ok(_) => ERR_NO_SUCH_FILE,
+                    Err(status) => status,
+                };
+
+                (status, 4)
+            }
+
+            // RENAME (old_ptr, new_ptr, status_ptr)
+            7 => {
+                let old_path = read_path(&cpu.memory, param(cpu, 0)?)?;
+                let new_path = read_path(&cpu.memory, param(cpu, 1)?)?;
+
+                let paths = old_path
+                    .and_then(|path| self.host_path(&path))
+                    .and_then(|old| Ok((old, self.host_path(&new_path?)?)));
+
+                let status = match paths {
+                    Ok((old, _)) if !old.exists() => ERR_NO_SUCH_FILE,
+                    Ok((_, new)) if new.exists() => ERR_RENAME_EXISTS,
+                    Ok((old, new)) => match fs::rename(old, new) {
+                        Ok(()) => OK,
+                        Err(_) => ERR_WRITE_PROTECTED,
+                    },
+                    Err(status) => status,
+                };
+
+                (status, 2)
+            }
+
+            // CONSOL (input_ptr, output_ptr, status_ptr)
+            8 => {
+                let input = read_path(&cpu.memory, param(cpu, 0)?)?;
+                let output = read_path(&cpu.memory, param(cpu, 1)?)?;
+
+                (self.consol(input, output)?, 2)
+            }
+
+            // EXIT ()
+            9 => {
+                cpu.running = false;
+                return Ok(());
+            }
+
+            // ATTRIB (file_ptr, attrib, on_off, status_ptr), attributes
+            // aren't kept on the host so this does nothing
+            10 => (OK, 3),
+
+            // RESCAN (aftn, status_ptr), rewinds the current console line
+            11 => {
+                let status = match param(cpu, 0)? {
+                    CONSOLE_IN_AFTN => {
+                        self.console_pos = 0;
+                        OK
+                    }
+                    _ => ERR_BAD_AFTN,
+                };
+
+                (status, 1)
+            }
+
+            // ERROR (error_number, status_ptr)
+            12 => {
+                let error = param(cpu, 0)?;
+                let caller = u16::from(cpu.reg_array.read_reg(Register::PC)).wrapping_sub(3);
+
+                let message = format!("\r\nERROR {error} USER PC {caller:04X}\r\n");
+                self.write(cpu, CONSOLE_OUT_AFTN, message.as_bytes())?;
+
+                (OK, 1)
+            }
+
+            // SPATH (file_ptr, info_ptr, status_ptr)
+            14 => {
+                let info_ptr = param(cpu, 1)?;
+
+                let status = match read_path(&cpu.memory, param(cpu, 0)?)? {
+                    Ok(path) => {
+                        write_path_info(&mut cpu.memory, info_ptr, &path)?;
+                        OK
+                    }
+                    Err(status) => status,
+                };
+
+                (status, 2)
+            }
+
+            _ => {
+                return Err(format!("Unsupported ISIS-II system call: {function}"));
+            }
+        };
+
+        let status_ptr = param(cpu, status_param)?;
+        write_word(&mut cpu.memory, status_ptr, status)
+    }
+
+    // opens a file, returning its AFTN or the status code of the failure
+    fn open(
+        &mut self,
+        memory: &Memory,
+        file_ptr: u16,
+        access: u16,
+    ) -> Result<Result<u16, u16>, String> {
+        let path = match read_path(memory, file_ptr)? {
+            Ok(path) => path,
+            Err(status) => return Ok(Err(status)),
+        };
+
+        let connection = match path.device {
+            IsisDevice::Disk(_) => {
+                let host_path = match self.host_path(&path) {
+                    Ok(host_path) => host_path,
+                    Err(status) => return Ok(Err(status)),
+                };
+
+                let file = match access {
+                    ACCESS_WRITE => File::create(&host_path),
+                    ACCESS_UPDATE => OpenOptions::new()
+                        .read(true)
+                        .write(true)
+                        .create(true)
+                        .truncate(false)
+                        .open(&host_path),
+                    _ if !host_path.exists() => return Ok(Err(ERR_NO_SUCH_FILE)),
+                    _ => File::open(&host_path),
+                };
+
+                let file = file.map_err(|e| format!("Could not open {}: {e}", path.file_name()))?;
+                Connection::Disk { file, access }
+            }
+
+            // the console is already open, so opening it again hands out
+            // the existing connection
+            IsisDevice::ConsoleIn | IsisDevice::TeletypeIn => return Ok(Ok(CONSOLE_IN_AFTN)),
+            IsisDevice::ConsoleOut | IsisDevice::TeletypeOut => return Ok(Ok(CONSOLE_OUT_AFTN)),
+            IsisDevice::LinePrinter => Connection::LinePrinter,
+            IsisDevice::ByteBucket => Connection::ByteBucket,
+        };
+
+        if self.connections.len() - 2 >= MAX_OPEN_FILES {
+            return Ok(Err(ERR_TOO_MANY_FILES));
+        }
+
+        let aftn = (2..)
+            .find(|aftn| !self.connections.contains_key(aftn))
+            .unwrap();
+        self.connections.insert(aftn, connection);
+
+        Ok(Ok(aftn))
+    }
+
+    // reads up to count bytes from an AFTN
+    fn read(
+        &mut self,
+        cpu: &mut Cpu,
+        aftn: u16,
+        count: usize,
+    ) -> Result<Result<Vec<u8>, u16>, String> {
+        let data = match self.connections.get_mut(&aftn) {
+            Some(Connection::Disk { file, access }) => {
+                if *access == ACCESS_WRITE {
+                    return Ok(Err(ERR_READ_ON_OUTPUT));
+                }
+
+                let mut data = Vec::with_capacity(count);
+                file.take(count as u64)
+                    .read_to_end(&mut data)
+                    .map_err(|e| e.to_string())?;
+                data
+            }
+
+            Some(Connection::ConsoleIn) => {
+                // console input is read a line at a time
+                if self.console_pos >= self.console_line.len() && !self.read_console_line(cpu)? {
+                    cpu.wait_for_input()?;
+                    return Ok(Ok(Vec::new()));
+                }
+
+                let end = (self.console_pos + count).min(self.console_line.len());
+                let data = self.console_line[self.console_pos..end].to_vec();
+                self.console_pos = end;
+                data
+            }
+
+            Some(Connection::ByteBucket) => Vec::new(),
+
+            Some(Connection::ConsoleOut | Connection::LinePrinter) => {
+                return Ok(Err(ERR_READ_ON_OUTPUT));
+            }
+
+            None => return Ok(Err(ERR_BAD_AFTN)),
+        };
+
+        Ok(Ok(data))
+    }
+
+    // reads the next line of console input, from the CONSOL file if there is
+    // one. false if the input ran out partway, the line is finished once
+    // there is more
+    fn read_console_line(&mut self, cpu: &mut Cpu) -> Result<bool, String> {
+        let mut line = std::mem::take(&mut self.console_pending);
+        let mut buf = [0u8];
+
+        loop {
+            let n_read = match (self.console_in_file.as_mut(), self.console.as_mut()) {
+                (Some(file), _) => file.read(&mut buf),
+                (None, Some(console)) => console.read(&mut buf),
+                (None, None) => std::io::stdin().read(&mut buf),
+            };
+            let n_read = match n_read {
+                Err(e) if e.kind() == ErrorKind::WouldBlock => {
+                    self.console_pending = line;
+                    return Ok(false);
+                }
+                n_read => n_read.map_err(|e| e.to_string())?,
+            };
+
+            // a line ends with CR (what a terminal sends for Enter), LF or
+            // both, and is always passed on ending with CR LF
+            let after_cr = std::mem::replace(&mut self.console_cr, false);
+            match (n_read, buf[0]) {
+                (0, _) => break,
+                (_, b'\n') if after_cr => {}
+                (_, b'\r' | b'\n') => {
+                    self.console_cr = buf[0] == b'\r';
+                    line.extend(b"\r\n");
+                    break;
+                }
+                (_, c) => line.push(c),
+            }
+        }
+
+        // injected input isn't echoed by a terminal, so echo it here
+        if self.console.is_some() && self.console_in_file.is_none() {
+            self.write(cpu, CONSOLE_OUT_AFTN, &line.clone())?;
+        }
+
+        self.console_line = line;
+        self.console_pos = 0;
+
+        Ok(true)
+    }
+
+    // writes bytes to an AFTN, returns the status code
+    fn write(&mut self, cpu: &mut Cpu, aftn: u16, data: &[u8]) -> Result<u16, String> {
+        let result = match self.connections.get_mut(&aftn) {
+            Some(Connection::Disk { file, access }) => {
+                if *access == ACCESS_READ {
+                    return Ok(ERR_WRITE_ON_INPUT);
+                }
+
+                file.write_all(data)
+            }
+
+            Some(Connection::ConsoleOut) => match self.console_out_file.as_mut() {
+                Some(file) => file.write_all(data),
+                None => {
+                    for value in data {
+                        cpu.write_to_port(RegisterValue::from(0u8), RegisterValue::from(*value))?;
+                    }
+                    Ok(())
+                }
+            },
+
+            Some(Connection::LinePrinter) => match self.list.as_mut() {
+                Some(list) => list.write_all(data),
+                None => Ok(()),
+            },
+
+            Some(Connection::ByteBucket) => Ok(()),
+
+            Some(Connection::ConsoleIn) => return Ok(ERR_WRITE_ON_INPUT),
+
+            None => return Ok(ERR_BAD_AFTN),
+        };
+
+        result.map_err(|e| format!("Could not write to AFTN {aftn}: {e}"))?;
+
+        Ok(OK)
+    }
+
+    // moves the position of a disk file, returns the new position. mode 0
+    // reports the position, 1 moves back, 2 is absolute, 3 moves forward and
+    // 4 moves to the end of the file
+    fn seek(&mut self, aftn: u16, mode: u16, offset: u64) -> Result<Result<u64, u16>, String> {
+        let file = match self.connections.get_mut(&aftn) {
+            Some(Connection::Disk { file, .. }) => file,
+            Some(_) => return Ok(Err(ERR_SEEK_NON_DISK)),
+            None => return Ok(Err(ERR_BAD_AFTN)),
+        };
+
+        let current = file.stream_position().map_err(|e| e.to_string())?;
+
+        let target = match mode {
+            0 => return Ok(Ok(current)),
+            1 => match current.checked_sub(offset) {
+                Some(target) => SeekFrom::Start(target),
+                None => {
+                    file.seek(SeekFrom::Start(0)).map_err(|e| e.to_string())?;
+                    return Ok(Err(ERR_SEEK_BEFORE_START));
+                }
+            },
+            2 => SeekFrom::Start(offset),
+            3 => SeekFrom::Start(current + offset),
+            _ => SeekFrom::End(0),
+        };
+
+        let position = file.seek(target).map_err(|e| e.to_string())?;
+
+        Ok(Ok(position))
+    }
+
+    // redirects the console input and output to the given files
+    fn consol(
+        &mut self,
+        input: Result<IsisPath, u16>,
+        output: Result<IsisPath, u16>,
+    ) -> Result<u16, String> {
+        let (input, output) = match (input, output) {
+            (Ok(input), Ok(output)) => (input, output),
+            (Err(status), _) | (_, Err(status)) => return Ok(status),
+        };
+
+        self.console_in_file = match input.device {
+            IsisDevice::ConsoleIn | IsisDevice::TeletypeIn => None,
+            _ => match self.host_path(&input) {
+                Ok(path) if path.exists() => Some(File::open(path).map_err(|e| e.to_string())?),
+                Ok(_) => return Ok(ERR_NO_SUCH_FILE),
+                Err(status) => return Ok(status),
+            },
+        };
+
+        self.console_out_file = match output.device {
+            IsisDevice::ConsoleOut | IsisDevice::TeletypeOut => None,
+            _ => match self.host_path(&output) {
+                Ok(path) => Some(File::create(path).map_err(|e| e.to_string())?),
+                Err(status) => return Ok(status),
+            },
+        };
+
+        Ok(OK)
+    }
+}
+
+// installs the ISIS-II system call handler (and MEMCK) on the given Cpu,
+// returns the ISIS-II state so that it can be inspected afterwards
+pub fn add_isis(cpu: &mut Cpu, isis: Isis) -> Arc<Mutex<Isis>> {
+    let isis = Arc::new(Mutex::new(isis));
+    let isis_thr = Arc::clone(&isis);
+
+    cpu.add_subroutine_handler(ISIS_ENTRY, move |cpu| isis_thr.lock().unwrap().call(cpu));
+
+    // MEMCK returns the top of memory in A (low byte) and B (high byte)
+    cpu.add_subroutine_handler(MEMCK_ENTRY, |cpu| {
+        let (higher, lower) = ((MEMORY_TOP >> 8) as u8, (MEMORY_TOP & 0xFF) as u8);
+
+        cpu.alu.write_accumulator(RegisterValue::from(lower))?;
+        cpu.reg_array
+            .write_reg(Register::B, RegisterValue::from(higher))?;
+        cpu.reg_array
+            .write_reg(Register::HL, RegisterValue::from(MEMORY_TOP))
+    });
+
+    isis
+}
+
+// loads a program stored as an absolute object module and sets the Cpu up to
+// start it, with the given command line waiting on the console
+pub fn load_program(
+    cpu: &mut Cpu,
+    isis: &Arc<Mutex<Isis>>,
+    object: &[u8],
+    command_line: &str,
+) -> Result<(), String> {
+    let entry = omf::load_object(cpu, object, 0)?
+        .ok_or_else(|| String::from("Object file has no entry point"))?;
+
+    isis.lock().unwrap().set_command_line(command_line);
+
+    // a return from the program lands on EXIT
+    cpu.load_to_memory(vec![0x0E, 0x09, 0xCD, 0x40, 0x00], 0x0000)?;
+    cpu.reg_array.write_reg(
+        Register::SP,
+        RegisterValue::from(MEMORY_TOP.wrapping_add(1)),
+    )?;
+    cpu.push_to_stack(RegisterValue::from(0u16))?;
+    cpu.set_pc(entry)?;
+    cpu.running = true;
+
+    Ok(())
+}
+
+// reads and parses the file name at the given address
+fn read_path(memory: &Memory, addr: u16) -> Result<Result<IsisPath, u16>, String> {
+    // a file name can't be longer than a device, a name and an extension
+    let s = (0..16)
+        .map(|i| read_byte(memory, addr.wrapping_add(i)))
+        .collect::<Result<Vec<u8>, String>>()?;
+
+    Ok(IsisPath::parse(&s))
+}
+
+// writes the 12 byte SPATH information block for a path: device number,
+// name, extension (both padded with 0s), device type and drive type
+fn write_path_info(memory: &mut Memory, addr: u16, path: &IsisPath) -> Result<(), String> {
+    use IsisDevice::*;
+
+    let mut info = [0u8; 12];
+    info[0] = path.device.number();
+    info[1..1 + path.name.len()].copy_from_slice(path.name.as_bytes());
+    info[7..7 + path.ext.len()].copy_from_slice(path.ext.as_bytes());
+
+    // device type: 0 for input, 1 for output, 3 for both
+    info[10] = match path.device {
+        ConsoleIn | TeletypeIn => 0,
+        ConsoleOut | TeletypeOut | LinePrinter => 1,
+        Disk(_) | ByteBucket => 3,
+    };
+
+    for (i, value) in info.iter().enumerate() {
+        memory.write(
+            RegisterValue::from(addr.wrapping_add(i as u16)),
+            RegisterValue::from(*value),
+        )?;
+    }
+
+    Ok(())
+}
+
+// reads a single byte from memory
+fn read_byte(memory: &Memory, addr: u16) -> Result<u8, String> {
+    let value = memory.read(RegisterValue::from(addr), MemorySize::Integer8)?;
+
+    Ok(u8::try_from(value)?)
+}
+
+// reads a 16-bit word from memory
+fn read_word(memory: &Memory, addr: u16) -> Result<u16, String> {
+    let value = memory.read(RegisterValue::from(addr), MemorySize::Integer16)?;
+
+    Ok(u16::from(value))
+}
+
+// writes a 16-bit word to memory
+fn write_word(memory: &mut Memory, addr: u16, value: u16) -> Result<(), String> {
+    memory.write(RegisterValue::from(addr), RegisterValue::from(value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn isis_path_parse() {
+        let path = IsisPath::parse(b":f1:prog.plm ").unwrap();
+        assert_eq!(path.device, IsisDevice::Disk(1));
+        assert_eq!(path.file_name(), "PROG.PLM");
+
+        let path = IsisPath::parse(b"  ASM80\r").unwrap();
+        assert_eq!(path.device, IsisDevice::Disk(0));
+        assert_eq!(path.file_name(), "ASM80");
+
+        assert_eq!(
+            IsisPath::parse(b":CO: ").unwrap().device,
+            IsisDevice::ConsoleOut
+        );
+        assert_eq!(IsisPath::parse(b":XX:FOO"), Err(ERR_BAD_DEVICE));
+        assert_eq!(IsisPath::parse(b"TOOLONG.PLM"), Err(ERR_BAD_FILE_NAME));
+    }
+
+    #[test]
+    fn isis_open_read_write() {
+        let root = std::env::temp_dir().join(format!("i8080-isis-{}", std::process::id()));
+        fs::create_dir_all(&root).unwrap();
+        fs::write(root.join("in.txt"), b"HELLO").unwrap();
+
+        let mut isis = Isis::new();
+        isis.drives[1] = Some(root.clone());
+
+        let output = Arc::new(Mutex::new(Vec::new()));
+        let output_thr = output.clone();
+
+        let mut cpu = Cpu::new();
+        let isis = add_isis(&mut cpu, isis);
+        cpu.set_port_handler_fn(move |_, value| {
+            output_thr
+                .lock()
+                .unwrap()
+                .push(u8::try_from(value).unwrap());
+        });
+
+        // parameter blocks at 0x2000, file name at 0x2100, buffer at 0x2200
+        //   OPEN (aftn @ 0x2300, ":F1:IN.TXT ", read, 0, status @ 0x2302)
+        //   READ (aftn, 0x2200, 16, actual @ 0x2304, status)
+        //   WRITE (0, 0x2200, 5, status)
+        //   EXIT
+        let mut params = Vec::new();
+        for word in [0x2300u16, 0x2100, 1, 0, 0x2302] {
+            params.extend(word.to_le_bytes());
+        }
+        cpu.load_to_memory(params, 0x2000).unwrap();
+        cpu.load_to_memory(b":F1:IN.TXT ".to_vec(), 0x2100).unwrap();
+
+        let program = vec![
+            0x0E, 0x00, 0x11, 0x00, 0x20, 0xCD, 0x40, 0x00, // OPEN
+            0x2A, 0x00, 0x23, 0x22, 0x10, 0x20, // LHLD aftn; SHLD read block
+            0x0E, 0x03, 0x11, 0x10, 0x20, 0xCD, 0x40, 0x00, // READ
+            0x0E, 0x04, 0x11, 0x20, 0x20, 0xCD, 0x40, 0x00, // WRITE
+            0x0E, 0x09, 0xCD, 0x40, 0x00, // EXIT
+        ];
+        let mut read_params = Vec::new();
+        for word in [0u16, 0x2200, 16, 0x2304, 0x2302] {
+            read_params.extend(word.to_le_bytes());
+        }
+        let mut write_params = Vec::new();
+        for word in [CONSOLE_OUT_AFTN, 0x2200, 5, 0x2302] {
+            write_params.extend(word.to_le_bytes());
+        }
+        cpu.load_to_memory(read_params, 0x2010).unwrap();
+        cpu.load_to_memory(write_params, 0x2020).unwrap();
+        cpu.load_to_memory(program, 0x3680).unwrap();
+        cpu.set_pc(0x3680).unwrap();
+
+        while cpu.is_running() {
+            cpu.execute_next().unwrap();
+        }
+
+        assert_eq!(read_word(&cpu.memory, 0x2300).unwrap(), 2);
+        assert_eq!(read_word(&cpu.memory, 0x2302).unwrap(), OK);
+        assert_eq!(read_word(&cpu.memory, 0x2304).unwrap(), 5);
+        assert_eq!(*output.lock().unwrap(), b"HELLO");
+        assert_eq!(isis.lock().unwrap().connections.len(), 3);
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn isis_delete_failure() {
+        let root = std::env::temp_dir().join(format!("i8080-isis-del-{}", std::process::id()));
+        fs::create_dir_all(root.join("DIR.X")).unwrap();
+
+        let mut isis = Isis::new();
+        isis.drives[1] = Some(root.clone());
+
+        let mut cpu = Cpu::new();
+        add_isis(&mut cpu, isis);
+        cpu.set_port_handler_fn(|_, _| {});
+
+        // DELETE (":F1:DIR.X ", status @ 0x2302); EXIT. the host won't
+        // delete a directory as a file, which the program sees in the status
+        let mut params = Vec::new();
+        for word in [0x2100u16, 0x2302] {
+            params.extend(word.to_le_bytes());
+        }
+        cpu.load_to_memory(params, 0x2000).unwrap();
+        cpu.load_to_memory(b":F1:DIR.X ".to_vec(), 0x2100).unwrap();
+        cpu.load_to_memory(
+            vec![
+                0x0E, 0x02, 0x11, 0x00, 0x20, 0xCD, 0x40, 0x00, // DELETE
+                0x0E, 0x09, 0xCD, 0x40, 0x00, // EXIT
+            ],
+            0x3680,
+        )
+        .unwrap();
+        cpu.set_pc(0x3680).unwrap();
+
+        while cpu.is_running() {
+            cpu.execute_next().unwrap();
+        }
+
+        assert_eq!(read_word(&cpu.memory, 0x2302).unwrap(), ERR_WRITE_PROTECTED);
+        assert!(root.join("DIR.X").is_dir());
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn isis_console_line_endings() {
+        // Enter on a terminal sends CR, files and pipes usually end lines
+        // with LF or CR LF
+        let mut isis = Isis::new();
+        isis.console = Some(Box::new(std::io::Cursor::new(
+            b"ONE\rTWO\r\nTHREE\n".to_vec(),
+        )));
+        let mut cpu = Cpu::new();
+        cpu.set_port_handler_fn(|_, _| {});
+
+        for expected in [&b"ONE\r\n"[..], b"TWO\r\n", b"THREE\r\n", b""] {
+            isis.read_console_line(&mut cpu).unwrap();
+            assert_eq!(isis.console_line, expected);
+        }
+
+        // injected input that runs out partway through a line waits for the
+        // rest of it
+        let input = crate::remote::ConsoleInput::default();
+        isis.console = Some(Box::new(input.clone()));
+        input.push(b"FO");
+        assert_eq!(isis.read_console_line(&mut cpu), Ok(false));
+        input.push(b"UR\r");
+        assert_eq!(isis.read_console_line(&mut cpu), Ok(true));
+        assert_eq!(isis.console_line, b"FOUR\r\n");
+    }
+}
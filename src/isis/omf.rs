@@ -0,0 +1,116 @@
+/*
+ * omf.rs - Contains code related to loading absolute object modules in the
+ * Intel OMF-80 format, which is what ISIS-II programs are stored as
+ * See the 8080/8085 Object Module Format specification (Intel 9800983)
+ */
+use crate::cpu::*;
+
+// OMF-80 record types that matter when loading an absolute module
+const RECORD_MODULE_END: u8 = 0x04;
+const RECORD_CONTENT: u8 = 0x06;
+const RECORD_EOF: u8 = 0x0E;
+
+// module type in the module end record of a main module (with an entry point)
+const MAIN_MODULE: u8 = 0x01;
+
+// loads every content record of an absolute object module into memory, offset
+// by bias, and returns the entry point of a main module
+pub fn load_object(cpu: &mut Cpu, data: &[u8], bias: u16) -> Result<Option<u16>, String> {
+    let mut pos = 0;
+    let mut entry = None;
+
+    while pos < data.len() {
+        // each record is a type byte, a 16-bit length, then `length` bytes of
+        // which the last is the checksum
+        let header = data
+            .get(pos..pos + 3)
+            .ok_or_else(|| format!("Truncated object record at offset {pos:#X}"))?;
+        let record_type = header[0];
+        let length = u16::from_le_bytes([header[1], header[2]]) as usize;
+
+        let record = data
+            .get(pos..pos + 3 + length)
+            .ok_or_else(|| format!("Truncated object record at offset {pos:#X}"))?;
+        let content = &record[3..record.len().saturating_sub(1)];
+
+        // all bytes of the record, including the checksum, sum to 0
+        let sum = record.iter().fold(0u8, |sum, b| sum.wrapping_add(*b));
+        if length == 0 || sum != 0 {
+            return Err(format!("Bad checksum in object record at offset {pos:#X}"));
+        }
+
+        match record_type {
+            // segment id, 16-bit offset, then the data to load at the offset
+            RECORD_CONTENT => {
+                if content.len() < 3 {
+                    return Err(format!("Short content record at offset {pos:#X}"));
+                }
+
+                let offset = u16::from_le_bytes([content[1], content[2]]);
+                cpu.load_to_memory(content[3..].to_vec(), offset.wrapping_add(bias))?;
+            }
+
+            // module type, segment id, then the 16-bit entry point offset
+            RECORD_MODULE_END if content.len() >= 4 && content[0] == MAIN_MODULE => {
+                let offset = u16::from_le_bytes([content[2], content[3]]);
+                entry = Some(offset.wrapping_add(bias));
+            }
+
+            RECORD_EOF => break,
+
+            // header, symbol and debug records don't affect what is loaded
+            _ => {}
+        }
+
+        pos += 3 + length;
+    }
+
+    Ok(entry)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpu::memory::*;
+    use crate::cpu::registers::*;
+
+    // builds a record with the correct length and checksum
+    fn record(record_type: u8, content: &[u8]) -> Vec<u8> {
+        let length = (content.len() + 1) as u16;
+
+        let mut record = vec![record_type];
+        record.extend(length.to_le_bytes());
+        record.extend(content);
+
+        let sum = record.iter().fold(0u8, |sum, b| sum.wrapping_add(*b));
+        record.push(sum.wrapping_neg());
+        record
+    }
+
+    #[test]
+    fn omf_load_object() {
+        let mut data = record(0x02, &[0x04, b'T', b'E', b'S', b'T', 0x00, 0x00]);
+        data.extend(record(
+            RECORD_CONTENT,
+            &[0x00, 0x00, 0x38, 0x3E, 0x42, 0x76],
+        ));
+        data.extend(record(RECORD_MODULE_END, &[MAIN_MODULE, 0x00, 0x00, 0x38]));
+        data.extend(record(RECORD_EOF, &[]));
+
+        let mut cpu = Cpu::new();
+        let entry = load_object(&mut cpu, &data, 0).unwrap();
+
+        assert_eq!(entry, Some(0x3800));
+        assert_eq!(
+            cpu.memory
+                .read(RegisterValue::from(0x3801u16), MemorySize::Integer8)
+                .unwrap(),
+            RegisterValue::from(0x42u8)
+        );
+
+        // a corrupted checksum is rejected
+        let last = data.len() - 1;
+        data[last] ^= 0xFF;
+        assert!(load_object(&mut Cpu::new(), &data, 0).is_err());
+    }
+}
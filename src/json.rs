@@ -0,0 +1,427 @@
+/*
+ * json.rs - Contains code related to JSON, which the debug adapter and other
+ * tools driving the emulator from outside speak. only what they need is
+ * supported: numbers are f64, and objects keep the order of their keys
+ */
+use std::fmt;
+use std::ops::Index;
+
+// value returned when indexing something that isn't there
+static NULL: Json = Json::Null;
+
+// Json enum - a JSON value
+#[derive(Debug, Clone, PartialEq)]
+pub enum Json {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<Json>),
+    Object(Vec<(String, Json)>),
+}
+
+impl Json {
+    // parses a JSON document
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut parser = Parser {
+            chars: text.chars().collect(),
+            pos: 0,
+        };
+
+        let value = parser.value()?;
+        parser.skip_whitespace();
+
+        match parser.pos == parser.chars.len() {
+            true => Ok(value),
+            false => Err(parser.error("Unexpected text after the value")),
+        }
+    }
+
+    // builds an object from pairs of keys and values
+    pub fn object<K: Into<String>>(pairs: impl IntoIterator<Item = (K, Json)>) -> Self {
+        Self::Object(
+            pairs
+                .into_iter()
+                .map(|(key, value)| (key.into(), value))
+                .collect(),
+        )
+    }
+
+    // returns the value of a key of an object
+    pub fn get(&self, key: &str) -> Option<&Json> {
+        match self {
+            Self::Object(pairs) => pairs.iter().find(|(k, _)| k == key).map(|(_, v)| v),
+            _ => None,
+        }
+    }
+
+    // sets a key of an object, replacing any value it had
+    pub fn set(&mut self, key: &str, value: Json) {
+        if let Self::Object(pairs) = self {
+            match pairs.iter_mut().find(|(k, _)| k == key) {
+                Some((_, v)) => *v = value,
+                None => pairs.push((key.to_string(), value)),
+            }
+        }
+    }
+
+    pub fn is_null(&self) -> bool {
+        *self == Self::Null
+    }
+
+    pub fn as_bool(&self) -> Option<bool> {
+        match self {
+            Self::Bool(b) => Some(*b),
+            _ => None,
+        }
+    }
+
+    pub fn as_f64(&self) -> Option<f64> {
+        match self {
+            Self::Number(n) => Some(*n),
+            _ => None,
+        }
+    }
+
+    // returns a number that is a whole number
+    pub fn as_i64(&self) -> Option<i64> {
+        self.as_f64()
+            .filter(|n| n.fract() == 0.0 && n.abs() < 9.0e15)
+            .map(|n| n as i64)
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Self::String(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    pub fn as_array(&self) -> Option<&[Json]> {
+        match self {
+            Self::Array(values) => Some(values),
+            _ => None,
+        }
+    }
+}
+
+// missing keys and non-objects index as null, so lookups can be chained
+impl Index<&str> for Json {
+    type Output = Json;
+
+    fn index(&self, key: &str) -> &Json {
+        self.get(key).unwrap_or(&NULL)
+    }
+}
+
+impl From<bool> for Json {
+    fn from(b: bool) -> Self {
+        Self::Bool(b)
+    }
+}
+
+impl From<&str> for Json {
+    fn from(s: &str) -> Self {
+        Self::String(s.to_string())
+    }
+}
+
+impl From<String> for Json {
+    fn from(s: String) -> Self {
+        Self::String(s)
+    }
+}
+
+impl From<Vec<Json>> for Json {
+    fn from(values: Vec<Json>) -> Self {
+        Self::Array(values)
+    }
+}
+
+impl<T: Into<Json>> From<Option<T>> for Json {
+    fn from(value: Option<T>) -> Self {
+        value.map_or(Self::Null, Into::into)
+    }
+}
+
+macro_rules! json_from_number {
+    ( $( $t:ty ),* ) => {
+        $(
+            impl From<$t> for Json {
+                fn from(n: $t) -> Self {
+                    Self::Number(n as f64)
+                }
+            }
+        )*
+    };
+}
+
+json_from_number!(u8, u16, u32, u64, usize, i32, i64, f64);
+
+// writes the value as compact JSON, on a single line
+impl fmt::Display for Json {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Null => write!(f, "null"),
+            Self::Bool(b) => write!(f, "{b}"),
+            Self::Number(n) if n.is_finite() => write!(f, "{n}"),
+            Self::Number(_) => write!(f, "null"),
+            Self::String(s) => write_string(f, s),
+            Self::Array(values) => {
+                write!(f, "[")?;
+                for (i, value) in values.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ",")?;
+                    }
+                    write!(f, "{value}")?;
+                }
+                write!(f, "]")
+            }
+            Self::Object(pairs) => {
+                write!(f, "{{")?;
+                for (i, (key, value)) in pairs.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ",")?;
+                    }
+                    write_string(f, key)?;
+                    write!(f, ":{value}")?;
+                }
+                write!(f, "}}")
+            }
+        }
+    }
+}
+
+// writes a string with quotes, escaping what needs to be
+fn write_string(f: &mut fmt::Formatter<'_>, s: &str) -> fmt::Result {
+    write!(f, "\"")?;
+    for c in s.chars() {
+        match c {
+            '"' => write!(f, "\\\"")?,
+            '\\' => write!(f, "\\\\")?,
+            '\n' => write!(f, "\\n")?,
+            '\r' => write!(f, "\\r")?,
+            '\t' => write!(f, "\\t")?,
+            c if (c as u32) < 0x20 => write!(f, "\\u{:04x}", c as u32)?,
+            c => write!(f, "{c}")?,
+        }
+    }
+    write!(f, "\"")
+}
+
+// Parser struct - a recursive descent parser over the characters of a
+// document
+struct Parser {
+    chars: Vec<char>,
+    pos: usize,
+}
+
+impl Parser {
+    fn error(&self, message: &str) -> String {
+        format!("{message} at character {}", self.pos + 1)
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self.chars.get(self.pos).is_some_and(|c| c.is_whitespace()) {
+            self.pos += 1;
+        }
+    }
+
+    fn peek(&mut self) -> Option<char> {
+        self.skip_whitespace();
+        self.chars.get(self.pos).copied()
+    }
+
+    fn expect(&mut self, c: char) -> Result<(), String> {
+        match self.peek() == Some(c) {
+            true => {
+                self.pos += 1;
+                Ok(())
+            }
+            false => Err(self.error(&format!("Expected '{c}'"))),
+        }
+    }
+
+    fn value(&mut self) -> Result<Json, String> {
+        match self.peek() {
+            Some('{') => self.object(),
+            Some('[') => self.array(),
+            Some('"') => Ok(Json::String(self.string()?)),
+            Some('t') => self.keyword("true", Json::Bool(true)),
+            Some('f') => self.keyword("false", Json::Bool(false)),
+            Some('n') => self.keyword("null", Json::Null),
+            Some(c) if c == '-' || c.is_ascii_digit() => self.number(),
+            Some(_) => Err(self.error("Unexpected character")),
+            None => Err(self.error("Unexpected end of input")),
+        }
+    }
+
+    fn keyword(&mut self, word: &str, value: Json) -> Result<Json, String> {
+        let end = self.pos + word.len();
+        match self.chars.get(self.pos..end) {
+            Some(chars) if chars.iter().copied().eq(word.chars()) => {
+                self.pos = end;
+                Ok(value)
+            }
+            _ => Err(self.error("Unexpected character")),
+        }
+    }
+
+    fn number(&mut self) -> Result<Json, String> {
+        let start = self.pos;
+        while self
+            .chars
+            .get(self.pos)
+            .is_some_and(|c| c.is_ascii_digit() || matches!(c, '-' | '+' | '.' | 'e' | 'E'))
+        {
+            self.pos += 1;
+        }
+
+        let text: String = self.chars[start..self.pos].iter().collect();
+        text.parse()
+            .map(Json::Number)
+            .map_err(|_| format!("Invalid number {text} at character {}", start + 1))
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        self.expect('"')?;
+        let mut s = String::new();
+
+        loop {
+            let c = *self
+                .chars
+                .get(self.pos)
+                .ok_or_else(|| self.error("Unterminated string"))?;
+            self.pos += 1;
+
+            match c {
+                '"' => return Ok(s),
+                '\\' => {
+                    let escape = *self
+                        .chars
+                        .get(self.pos)
+                        .ok_or_else(|| self.error("Unterminated string"))?;
+                    self.pos += 1;
+
+                    s.push(match escape {
+                        'n' => '\n',
+                        'r' => '\r',
+                        't' => '\t',
+                        'b' => '\x08',
+                        'f' => '\x0C',
+                        'u' => self.unicode_escape()?,
+                        c => c,
+                    });
+                }
+                c => s.push(c),
+            }
+        }
+    }
+
+    // reads the digits of a \u escape, and the second half of a surrogate
+    // pair if there is one
+    fn unicode_escape(&mut self) -> Result<char, String> {
+        let mut code = self.hex_digits()?;
+
+        if (0xD800..0xDC00).contains(&code)
+            && self.chars.get(self.pos..self.pos + 2) == Some(&['\\', 'u'])
+        {
+            self.pos += 2;
+            let low = self.hex_digits()?;
+            code = 0x10000 + ((code - 0xD800) << 10) + (low.wrapping_sub(0xDC00) & 0x3FF);
+        }
+
+        Ok(char::from_u32(code).unwrap_or(char::REPLACEMENT_CHARACTER))
+    }
+
+    // reads the 4 hex digits of a \u escape
+    fn hex_digits(&mut self) -> Result<u32, String> {
+        let digits: String = self
+            .chars
+            .get(self.pos..self.pos + 4)
+            .unwrap_or_default()
+            .iter()
+            .collect();
+
+        let value =
+            u32::from_str_radix(&digits, 16).map_err(|_| self.error("Invalid \\u escape"))?;
+        self.pos += 4;
+        Ok(value)
+    }
+
+    fn array(&mut self) -> Result<Json, String> {
+        self.expect('[')?;
+        let mut values = Vec::new();
+
+        if self.peek() == Some(']') {
+            self.pos += 1;
+            return Ok(Json::Array(values));
+        }
+
+        loop {
+            values.push(self.value()?);
+            match self.peek() {
+                Some(',') => self.pos += 1,
+                Some(']') => {
+                    self.pos += 1;
+                    return Ok(Json::Array(values));
+                }
+                _ => return Err(self.error("Expected ',' or ']'")),
+            }
+        }
+    }
+
+    fn object(&mut self) -> Result<Json, String> {
+        self.expect('{')?;
+        let mut pairs = Vec::new();
+
+        if self.peek() == Some('}') {
+            self.pos += 1;
+            return Ok(Json::Object(pairs));
+        }
+
+        loop {
+            self.skip_whitespace();
+            let key = self.string()?;
+            self.expect(':')?;
+            pairs.push((key, self.value()?));
+
+            match self.peek() {
+                Some(',') => self.pos += 1,
+                Some('}') => {
+                    self.pos += 1;
+                    return Ok(Json::Object(pairs));
+                }
+                _ => return Err(self.error("Expected ',' or '}'")),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn json_round_trip() {
+        let text = r#"{"seq":1,"type":"request","arguments":{"lines":[3,-4.5e1],"path":"a\"b\\c\né😀","ok":true,"none":null}}"#;
+        let json = Json::parse(text).unwrap();
+
+        assert_eq!(json["seq"].as_i64(), Some(1));
+        assert_eq!(
+            json["arguments"]["lines"].as_array().unwrap()[1],
+            Json::Number(-45.0)
+        );
+        assert_eq!(json["arguments"]["path"].as_str(), Some("a\"b\\c\né😀"));
+        assert!(json["arguments"]["none"].is_null());
+        assert!(json["missing"]["deeper"].is_null());
+        assert_eq!(Json::parse(&json.to_string()).unwrap(), json);
+
+        let mut object = Json::object([("a", Json::from(1u8)), ("b", Json::from("x"))]);
+        object.set("a", Json::from(false));
+        assert_eq!(object.to_string(), r#"{"a":false,"b":"x"}"#);
+
+        assert!(Json::parse("[1,]").is_err());
+        assert!(Json::parse("{\"a\":1} x").is_err());
+    }
+}
@@ -1 +1,21 @@
-
+/*
+ * lib.rs - Library root, exposes the emulator core so it can be driven from
+ * other Rust code (and tests) without going through the binary
+ */
+pub mod analyzer;
+pub mod assembler;
+pub mod compare;
+pub mod cp_m;
+pub mod cpu;
+pub mod crash;
+pub mod dap;
+pub mod isis;
+pub mod json;
+pub mod linker;
+pub mod listing;
+pub mod loader;
+pub mod monitor;
+pub mod remote;
+pub mod symbols;
+pub mod terminal;
+pub mod tui;
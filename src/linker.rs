@@ -0,0 +1,765 @@
+/*
+ * linker.rs - Contains code related to linking relocatable object modules in
+ * the Microsoft REL format (as written by M80) directly into memory
+ * See the Microsoft Utility Software Manual, appendix on the REL format
+ */
+use crate::cpu::memory::*;
+use crate::cpu::registers::*;
+use crate::cpu::*;
+
+use std::collections::BTreeMap;
+use std::ops::Range;
+
+// special link item control values
+const ENTRY_SYMBOL: u8 = 0;
+const SELECT_COMMON: u8 = 1;
+const PROGRAM_NAME: u8 = 2;
+const LIBRARY_SEARCH: u8 = 3;
+const EXTENSION: u8 = 4;
+const COMMON_SIZE: u8 = 5;
+const CHAIN_EXTERNAL: u8 = 6;
+const ENTRY_POINT: u8 = 7;
+const EXTERNAL_MINUS_OFFSET: u8 = 8;
+const EXTERNAL_PLUS_OFFSET: u8 = 9;
+const DATA_SIZE: u8 = 10;
+const SET_LOCATION: u8 = 11;
+const CHAIN_ADDRESS: u8 = 12;
+const PROGRAM_SIZE: u8 = 13;
+const END_PROGRAM: u8 = 14;
+const END_FILE: u8 = 15;
+
+// Segment enum - what an address is relative to
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Segment {
+    Absolute,
+    Program,
+    Data,
+    Common,
+}
+
+impl Segment {
+    fn from_bits(bits: u32) -> Self {
+        match bits {
+            0b00 => Segment::Absolute,
+            0b01 => Segment::Program,
+            0b10 => Segment::Data,
+            _ => Segment::Common,
+        }
+    }
+}
+
+// RelItem enum - a single item of a REL module
+#[derive(Debug, Clone, PartialEq)]
+pub enum RelItem {
+    Byte(u8),
+    Word(Segment, u16), // a relocatable 16-bit value
+    Special {
+        control: u8,
+        a: Option<(Segment, u16)>,
+        b: Option<String>,
+    },
+}
+
+// RelModule struct - the items of one module, up to its end program item
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RelModule {
+    pub items: Vec<RelItem>,
+}
+
+impl RelModule {
+    // returns the name given by the program name item
+    pub fn name(&self) -> Option<&str> {
+        self.specials().find_map(|(control, _, b)| match control {
+            PROGRAM_NAME => b,
+            _ => None,
+        })
+    }
+
+    // returns the value of the A field of the first special item of a type
+    fn a_field(&self, control: u8) -> Option<u16> {
+        self.specials()
+            .find(|(c, ..)| *c == control)
+            .and_then(|(_, a, _)| a)
+            .map(|(_, value)| value)
+    }
+
+    fn specials(&self) -> impl Iterator<Item = (u8, Option<(Segment, u16)>, Option<&str>)> {
+        self.items.iter().filter_map(|item| match item {
+            RelItem::Special { control, a, b } => Some((*control, *a, b.as_deref())),
+            _ => None,
+        })
+    }
+}
+
+// BitReader struct - reads a REL file a number of bits at a time, most
+// significant bit first
+struct BitReader<'a> {
+    data: &'a [u8],
+    pos: usize, // in bits
+}
+
+impl BitReader<'_> {
+    fn read(&mut self, n_bits: usize) -> Result<u32, String> {
+        let mut value = 0;
+
+        for _ in 0..n_bits {
+            let byte = self
+                .data
+                .get(self.pos / 8)
+                .ok_or("REL file ends in the middle of an item")?;
+            let bit = (byte >> (7 - self.pos % 8)) & 1;
+
+            value = (value << 1) | bit as u32;
+            self.pos += 1;
+        }
+
+        Ok(value)
+    }
+
+    // reads a 16-bit value, stored low byte first
+    fn read_word(&mut self) -> Result<u16, String> {
+        let low = self.read(8)? as u16;
+        let high = self.read(8)? as u16;
+
+        Ok((high << 8) | low)
+    }
+
+    fn align_to_byte(&mut self) {
+        self.pos = self.pos.div_ceil(8) * 8;
+    }
+}
+
+// parses a REL file into its modules
+pub fn parse_rel(data: &[u8]) -> Result<Vec<RelModule>, String> {
+    let mut reader = BitReader { data, pos: 0 };
+    let mut modules = Vec::new();
+    let mut module = RelModule::default();
+
+    loop {
+        if reader.pos / 8 >= data.len() {
+            // a file that ends without an end file item is cut short
+            return match module.items.is_empty() {
+                true => Ok(modules),
+                false => Err(String::from("REL file ends in the middle of a module")),
+            };
+        }
+
+        // 0: an absolute byte, 1: a relocatable word or special item
+        if reader.read(1)? == 0 {
+            module.items.push(RelItem::Byte(reader.read(8)? as u8));
+            continue;
+        }
+
+        let segment = Segment::from_bits(reader.read(2)?);
+        if segment != Segment::Absolute {
+            module
+                .items
+                .push(RelItem::Word(segment, reader.read_word()?));
+            continue;
+        }
+
+        let control = reader.read(4)? as u8;
+
+        // items 5 to 14 have an A field (an address), items 0 to 7 a B field
+        // (a name of up to 7 characters)
+        let a = match control {
+            COMMON_SIZE..=END_PROGRAM => {
+                let segment = Segment::from_bits(reader.read(2)?);
+                Some((segment, reader.read_word()?))
+            }
+            _ => None,
+        };
+
+        let b = match control {
+            ENTRY_SYMBOL..=ENTRY_POINT => {
+                let len = reader.read(3)?;
+                let name = (0..len)
+                    .map(|_| reader.read(8).map(|c| (c as u8 & 0x7F) as char))
+                    .collect::<Result<String, String>>()?;
+                Some(name)
+            }
+            _ => None,
+        };
+
+        match control {
+            END_FILE => {
+                return match module.items.is_empty() {
+                    true => Ok(modules),
+                    false => Err(String::from("End of file inside a module")),
+                };
+            }
+
+            END_PROGRAM => {
+                module.items.push(RelItem::Special { control, a, b });
+                modules.push(std::mem::take(&mut module));
+                reader.align_to_byte();
+            }
+
+            _ => module.items.push(RelItem::Special { control, a, b }),
+        }
+    }
+}
+
+// LinkedModule struct - where a module was placed
+#[derive(Debug, Clone, PartialEq)]
+pub struct LinkedModule {
+    pub name: String,
+    pub program_base: u16,
+    pub program_size: u16,
+    pub data_base: u16,
+    pub data_size: u16,
+}
+
+// LinkedProgram struct - the result of linking modules into memory
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct LinkedProgram {
+    pub modules: Vec<LinkedModule>,
+    pub commons: BTreeMap<String, (u16, u16)>, // base and size of each block
+    pub symbols: BTreeMap<String, u16>,
+    pub start: Option<u16>,
+    pub end: u32,                     // one past the highest address used, up to 10000H
+    pub absolute: Vec<(String, u16)>, // modules with absolute code, and where it starts
+}
+
+impl LinkedProgram {
+    // returns the symbol table, one "ADDR NAME" line per symbol in the
+    // format of a .SYM file
+    pub fn symbol_table(&self) -> String {
+        self.symbols
+            .iter()
+            .map(|(name, value)| format!("{value:04X} {name}\n"))
+            .collect()
+    }
+}
+
+// links every module of the given REL files into memory. each module's
+// program segment is followed by its data segment, starting at base, and
+// common blocks come after all of the modules
+pub fn link_into(cpu: &mut Cpu, files: &[Vec<u8>], base: u16) -> Result<LinkedProgram, String> {
+    let mut modules = Vec::new();
+    for file in files {
+        modules.extend(parse_rel(file)?);
+    }
+
+    let mut program = LinkedProgram::default();
+    let mut location = base as u32;
+
+    // lay out the segments, every size is given before any code
+    for (i, module) in modules.iter().enumerate() {
+        let program_size = module.a_field(PROGRAM_SIZE).unwrap_or(0);
+        let data_size = module.a_field(DATA_SIZE).unwrap_or(0);
+
+        let program_base = location;
+        let data_base = program_base + program_size as u32;
+        location = data_base + data_size as u32;
+
+        program.modules.push(LinkedModule {
+            name: module
+                .name()
+                .map(String::from)
+                .unwrap_or(format!("MODULE{}", i + 1)),
+            program_base: program_base as u16,
+            program_size,
+            data_base: data_base as u16,
+            data_size,
+        });
+    }
+
+    // common blocks of the same name are shared, and as big as the largest
+    let mut common_sizes: Vec<(String, u16)> = Vec::new();
+    for (control, a, b) in modules.iter().flat_map(|module| module.specials()) {
+        if let (COMMON_SIZE, Some((_, size)), Some(name)) = (control, a, b) {
+            match common_sizes.iter_mut().find(|(n, _)| n == name) {
+                Some((_, max)) => *max = (*max).max(size),
+                None => common_sizes.push((name.to_string(), size)),
+            }
+        }
+    }
+
+    for (name, size) in common_sizes {
+        program.commons.insert(name, (location as u16, size));
+        location += size as u32;
+    }
+
+    if location > 0x10000 {
+        return Err(format!(
+            "Linked program of {:X} bytes does not fit in memory",
+            location - base as u32
+        ));
+    }
+    program.end = location;
+
+    // load the code, the entry points of every module are needed before
+    // externals can be resolved
+    let mut externals = Vec::new();
+    let mut offsets = Vec::new();
+
+    for (module, placed) in modules.iter().zip(&program.modules) {
+        let mut loader = ModuleLoader {
+            cpu: &mut *cpu,
+            linked: base as u32..program.end,
+            placed,
+            commons: &program.commons,
+            common: None,
+            location: (Segment::Program, 0),
+            absolute: None,
+        };
+
+        for item in &module.items {
+            match item {
+                RelItem::Byte(value) => loader.load(&[*value])?,
+
+                RelItem::Word(segment, value) => {
+                    let value = loader.resolve(*segment, *value)?;
+                    loader.load(&value.to_le_bytes())?;
+                }
+
+                RelItem::Special { control, a, b } => {
+                    let name = b.clone().unwrap_or_default();
+                    let resolve_a = |loader: &ModuleLoader| match a {
+                        Some((segment, value)) => loader.resolve(*segment, *value),
+                        None => Err(format!("Link item {control} has no address")),
+                    };
+
+                    match *control {
+                        SELECT_COMMON => loader.common = Some(name),
+                        SET_LOCATION => loader.location = a.unwrap_or((Segment::Program, 0)),
+
+                        ENTRY_POINT => {
+                            let value = resolve_a(&loader)?;
+
+                            if program.symbols.insert(name.clone(), value).is_some() {
+                                return Err(format!("Duplicate entry point: {name}"));
+                            }
+                        }
+
+                        CHAIN_EXTERNAL => externals.push((resolve_a(&loader)?, name)),
+
+                        // every address in the chain is replaced by the
+                        // current location
+                        CHAIN_ADDRESS => {
+                            let location = loader.current_addr()?;
+                            resolve_chain(loader.cpu, resolve_a(&loader)?, location)?;
+                        }
+
+                        // the offset applies to the next word loaded, which
+                        // is part of an external chain, so it is added once
+                        // the chain has been resolved
+                        EXTERNAL_PLUS_OFFSET | EXTERNAL_MINUS_OFFSET => {
+                            let offset = resolve_a(&loader)?;
+                            let offset = match *control {
+                                EXTERNAL_PLUS_OFFSET => offset,
+                                _ => offset.wrapping_neg(),
+                            };
+                            offsets.push((loader.current_addr()?, offset));
+                        }
+
+                        END_PROGRAM => {
+                            let start = resolve_a(&loader)?;
+
+                            // only a main module gives a start address
+                            if a.is_some_and(|(segment, value)| {
+                                segment != Segment::Absolute || value != 0
+                            }) {
+                                if program.start.is_some() {
+                                    return Err(String::from(
+                                        "More than one module has a start address",
+                                    ));
+                                }
+                                program.start = Some(start);
+                            }
+                        }
+
+                        LIBRARY_SEARCH => {
+                            return Err(format!("Library searches aren't supported: {name}"))
+                        }
+
+                        // names, sizes and extensions were handled above
+                        ENTRY_SYMBOL | PROGRAM_NAME | EXTENSION | COMMON_SIZE | DATA_SIZE
+                        | PROGRAM_SIZE => {}
+
+                        _ => return Err(format!("Unknown link item: {control}")),
+                    }
+                }
+            }
+        }
+
+        if let Some(addr) = loader.absolute {
+            program.absolute.push((placed.name.clone(), addr));
+        }
+    }
+
+    // each external chain runs through the code referring to the symbol
+    for (head, name) in externals {
+        let value = *program
+            .symbols
+            .get(&name)
+            .ok_or_else(|| format!("Undefined external: {name}"))?;
+
+        resolve_chain(cpu, head, value)?;
+    }
+
+    for (addr, offset) in offsets {
+        let value = read_word(cpu, addr)?.wrapping_add(offset);
+        write_word(cpu, addr, value)?;
+    }
+
+    if let Some(start) = program.start {
+        cpu.set_pc(start)?;
+    }
+
+    Ok(program)
+}
+
+// ModuleLoader struct - the state of loading a single module
+struct ModuleLoader<'a> {
+    cpu: &'a mut Cpu,
+    linked: Range<u32>, // where the relocatable segments of every module are
+    placed: &'a LinkedModule,
+    commons: &'a BTreeMap<String, (u16, u16)>,
+    common: Option<String>, // the selected common block
+    location: (Segment, u16),
+    absolute: Option<u16>, // the first address absolute code was loaded at
+}
+
+impl ModuleLoader<'_> {
+    // converts an address relative to a segment to an absolute address
+    fn resolve(&self, segment: Segment, value: u16) -> Result<u16, String> {
+        let base = match segment {
+            Segment::Absolute => 0,
+            Segment::Program => self.placed.program_base,
+            Segment::Data => self.placed.data_base,
+            Segment::Common => {
+                let name = self.common.as_deref().unwrap_or("");
+                self.commons
+                    .get(name)
+                    .map(|(base, _)| *base)
+                    .ok_or_else(|| format!("Unknown common block: /{name}/"))?
+            }
+        };
+
+        Ok(base.wrapping_add(value))
+    }
+
+    fn current_addr(&self) -> Result<u16, String> {
+        self.resolve(self.location.0, self.location.1)
+    }
+
+    // loads bytes at the location counter. they have to fit in the segment
+    // selected, and absolute bytes can't land on the relocatable segments,
+    // so no module overwrites another
+    fn load(&mut self, bytes: &[u8]) -> Result<(), String> {
+        let addr = self.current_addr()?;
+        let (segment, offset) = self.location;
+        let end = offset as u32 + bytes.len() as u32;
+        let name = &self.placed.name;
+
+        let size = match segment {
+            Segment::Absolute => {
+                if (addr as u32) < self.linked.end
+                    && addr as u32 + bytes.len() as u32 > self.linked.start
+                {
+                    return Err(format!(
+                        "{name} loads absolute code at {addr:04X}, overlapping the linked program"
+                    ));
+                }
+                self.absolute.get_or_insert(addr);
+                None
+            }
+            Segment::Program => Some((
+                self.placed.program_size,
+                String::from("its program segment"),
+            )),
+            Segment::Data => Some((self.placed.data_size, String::from("its data segment"))),
+            Segment::Common => {
+                let common = self.common.as_deref().unwrap_or("");
+                self.commons
+                    .get(common)
+                    .map(|(_, size)| (*size, format!("common block /{common}/")))
+            }
+        };
+        if let Some((size, what)) = size {
+            if end > size as u32 {
+                return Err(format!(
+                    "{name} loads past the end of {what}, at {addr:04X}"
+                ));
+            }
+        }
+
+        self.cpu.load_to_memory(bytes.to_vec(), addr)?;
+        self.location.1 = self.location.1.wrapping_add(bytes.len() as u16);
+
+        Ok(())
+    }
+}
+
+// replaces every word in a chain with a value. each word holds the address
+// of the next, and the chain ends with 0
+fn resolve_chain(cpu: &mut Cpu, head: u16, value: u16) -> Result<(), String> {
+    let mut addr = head;
+
+    for _ in 0..0x10000 {
+        if addr == 0 {
+            return Ok(());
+        }
+
+        let next = read_word(cpu, addr)?;
+        write_word(cpu, addr, value)?;
+        addr = next;
+    }
+
+    Err(format!("Chain starting at {head:04X} doesn't end"))
+}
+
+fn read_word(cpu: &Cpu, addr: u16) -> Result<u16, String> {
+    Ok(u16::from(
+        cpu.memory
+            .read(RegisterValue::from(addr), MemorySize::Integer16)?,
+    ))
+}
+
+fn write_word(cpu: &mut Cpu, addr: u16, value: u16) -> Result<(), String> {
+    cpu.memory
+        .write(RegisterValue::from(addr), RegisterValue::from(value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // BitWriter struct - builds REL files for the tests
+    #[derive(Default)]
+    struct BitWriter {
+        data: Vec<u8>,
+        n_bits: usize,
+    }
+
+    impl BitWriter {
+        fn bits(&mut self, value: u32, n_bits: usize) -> &mut Self {
+            for i in (0..n_bits).rev() {
+                if self.n_bits.is_multiple_of(8) {
+                    self.data.push(0);
+                }
+
+                let bit = ((value >> i) & 1) as u8;
+                *self.data.last_mut().unwrap() |= bit << (7 - self.n_bits % 8);
+                self.n_bits += 1;
+            }
+            self
+        }
+
+        fn byte(&mut self, value: u8) -> &mut Self {
+            self.bits(0, 1).bits(value as u32, 8)
+        }
+
+        fn word(&mut self, segment: u32, value: u16) -> &mut Self {
+            self.bits(1, 1).bits(segment, 2);
+            self.bits((value & 0xFF) as u32, 8)
+                .bits((value >> 8) as u32, 8)
+        }
+
+        fn special(&mut self, control: u8, a: Option<(u32, u16)>, b: Option<&str>) -> &mut Self {
+            self.bits(0b100, 3).bits(control as u32, 4);
+
+            if let Some((segment, value)) = a {
+                self.bits(segment, 2);
+                self.bits((value & 0xFF) as u32, 8)
+                    .bits((value >> 8) as u32, 8);
+            }
+
+            if let Some(name) = b {
+                self.bits(name.len() as u32, 3);
+                for c in name.bytes() {
+                    self.bits(c as u32, 8);
+                }
+            }
+
+            if control == END_PROGRAM {
+                self.n_bits = self.n_bits.div_ceil(8) * 8;
+            }
+            self
+        }
+    }
+
+    #[test]
+    fn link_modules() {
+        // MAIN: CALL EXT; LXI H,BUF; JMP START, with BUF in its data segment
+        let mut main = BitWriter::default();
+        main.special(PROGRAM_NAME, None, Some("MAIN"))
+            .special(DATA_SIZE, Some((0, 2)), None)
+            .special(PROGRAM_SIZE, Some((1, 9)), None)
+            .byte(0xCD)
+            .byte(0x00)
+            .byte(0x00)
+            .byte(0x21)
+            .word(0b10, 0x0001)
+            .byte(0xC3)
+            .word(0b01, 0x0000)
+            .special(CHAIN_EXTERNAL, Some((1, 0x0001)), Some("EXT"))
+            .special(ENTRY_POINT, Some((1, 0x0000)), Some("START"))
+            .special(END_PROGRAM, Some((1, 0x0000)), None);
+
+        // LIB: EXT: RET
+        let mut lib = BitWriter::default();
+        lib.special(PROGRAM_NAME, None, Some("LIB"))
+            .special(PROGRAM_SIZE, Some((1, 1)), None)
+            .byte(0xC9)
+            .special(ENTRY_POINT, Some((1, 0x0000)), Some("EXT"))
+            .special(END_PROGRAM, Some((0, 0)), None)
+            .special(END_FILE, None, None);
+
+        let mut cpu = Cpu::new();
+        let linked = link_into(&mut cpu, &[main.data.clone(), lib.data.clone()], 0x100).unwrap();
+
+        assert_eq!(linked.modules[0].name, "MAIN");
+        assert_eq!(linked.modules[0].data_base, 0x0109);
+        assert_eq!(linked.modules[1].program_base, 0x010B);
+        assert_eq!(linked.symbols.get("EXT"), Some(&0x010B));
+        assert_eq!(linked.start, Some(0x0100));
+        assert_eq!(linked.end, 0x010C);
+        assert!(linked.symbol_table().contains("0100 START\n"));
+
+        let code: Vec<u8> = (0x100..0x10C)
+            .map(|addr: u16| {
+                u8::try_from(
+                    cpu.memory
+                        .read(RegisterValue::from(addr), MemorySize::Integer8)
+                        .unwrap(),
+                )
+                .unwrap()
+            })
+            .collect();
+        assert_eq!(
+            code,
+            [0xCD, 0x0B, 0x01, 0x21, 0x0A, 0x01, 0xC3, 0x00, 0x01, 0x00, 0x00, 0xC9]
+        );
+
+        // a module without its external is an error
+        assert_eq!(
+            link_into(&mut Cpu::new(), &[main.data], 0x100),
+            Err(String::from("Undefined external: EXT"))
+        );
+
+        // a program can end at the very end of memory, but not past it
+        let linked = link_into(&mut Cpu::new(), &[lib.data.clone()], 0xFFFF).unwrap();
+        assert_eq!(linked.end, 0x10000);
+        assert_eq!(
+            link_into(
+                &mut Cpu::new(),
+                &[lib.data.clone(), lib.data.clone()],
+                0xFFFF
+            ),
+            Err(String::from(
+                "Linked program of 2 bytes does not fit in memory"
+            ))
+        );
+    }
+
+    #[test]
+    fn link_errors() {
+        // EXT: RET, which two modules define
+        let mut lib = BitWriter::default();
+        lib.special(PROGRAM_NAME, None, Some("LIB"))
+            .special(PROGRAM_SIZE, Some((1, 1)), None)
+            .byte(0xC9)
+            .special(ENTRY_POINT, Some((1, 0x0000)), Some("EXT"))
+            .special(END_PROGRAM, Some((0, 0)), None);
+        assert_eq!(
+            link_into(
+                &mut Cpu::new(),
+                &[lib.data.clone(), lib.data.clone()],
+                0x100
+            ),
+            Err(String::from("Duplicate entry point: EXT"))
+        );
+
+        // CALL EXT; CALL OTHER, where OTHER is never defined
+        let mut main = BitWriter::default();
+        main.special(PROGRAM_NAME, None, Some("MAIN"))
+            .special(PROGRAM_SIZE, Some((1, 6)), None)
+            .byte(0xCD)
+            .byte(0x00)
+            .byte(0x00)
+            .byte(0xCD)
+            .byte(0x00)
+            .byte(0x00)
+            .special(CHAIN_EXTERNAL, Some((1, 0x0001)), Some("EXT"))
+            .special(CHAIN_EXTERNAL, Some((1, 0x0004)), Some("OTHER"))
+            .special(END_PROGRAM, Some((1, 0x0000)), None);
+        assert_eq!(
+            link_into(&mut Cpu::new(), &[main.data, lib.data.clone()], 0x100),
+            Err(String::from("Undefined external: OTHER"))
+        );
+
+        // a module loading more than its program size would run into the
+        // next module
+        let mut long = BitWriter::default();
+        long.special(PROGRAM_NAME, None, Some("LONG"))
+            .special(PROGRAM_SIZE, Some((1, 1)), None)
+            .byte(0x00)
+            .byte(0x00)
+            .special(END_PROGRAM, Some((0, 0)), None);
+        assert_eq!(
+            link_into(&mut Cpu::new(), &[long.data, lib.data.clone()], 0x100),
+            Err(String::from(
+                "LONG loads past the end of its program segment, at 0101"
+            ))
+        );
+
+        // and the same for data and common blocks
+        let mut data = BitWriter::default();
+        data.special(PROGRAM_NAME, None, Some("DATA"))
+            .special(DATA_SIZE, Some((0, 1)), None)
+            .special(SET_LOCATION, Some((2, 0x0001)), None)
+            .byte(0x00)
+            .special(END_PROGRAM, Some((0, 0)), None);
+        assert_eq!(
+            link_into(&mut Cpu::new(), &[data.data], 0x100),
+            Err(String::from(
+                "DATA loads past the end of its data segment, at 0101"
+            ))
+        );
+
+        let mut common = BitWriter::default();
+        common
+            .special(PROGRAM_NAME, None, Some("COMMON"))
+            .special(COMMON_SIZE, Some((0, 2)), Some("BUF"))
+            .special(SELECT_COMMON, None, Some("BUF"))
+            .special(SET_LOCATION, Some((3, 0x0000)), None)
+            .byte(0x12)
+            .byte(0x34)
+            .byte(0x56)
+            .special(END_PROGRAM, Some((0, 0)), None);
+        assert_eq!(
+            link_into(&mut Cpu::new(), &[common.data], 0x100),
+            Err(String::from(
+                "COMMON loads past the end of common block /BUF/, at 0102"
+            ))
+        );
+
+        // absolute code can go anywhere but over the linked modules
+        let mut absolute = BitWriter::default();
+        absolute
+            .special(PROGRAM_NAME, None, Some("ABS"))
+            .special(SET_LOCATION, Some((0, 0x0000)), None)
+            .byte(0xC3)
+            .special(SET_LOCATION, Some((0, 0x0100)), None)
+            .byte(0x76)
+            .special(END_PROGRAM, Some((0, 0)), None);
+        assert_eq!(
+            link_into(&mut Cpu::new(), &[absolute.data, lib.data.clone()], 0x100),
+            Err(String::from(
+                "ABS loads absolute code at 0100, overlapping the linked program"
+            ))
+        );
+
+        let mut absolute = BitWriter::default();
+        absolute
+            .special(PROGRAM_NAME, None, Some("ABS"))
+            .special(SET_LOCATION, Some((0, 0x0000)), None)
+            .byte(0xC3)
+            .special(END_PROGRAM, Some((0, 0)), None);
+        let program = link_into(&mut Cpu::new(), &[absolute.data, lib.data], 0x100).unwrap();
+        assert_eq!(program.absolute, vec![(String::from("ABS"), 0x0000)]);
+    }
+}
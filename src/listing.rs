@@ -0,0 +1,281 @@
+/*
+ * listing.rs - Contains code related to assembler listing files (.PRN and
+ * .LST), which are read into a table mapping addresses to source lines so a
+ * program can be followed through its source
+ */
+use crate::cpu::registers::*;
+use crate::cpu::*;
+
+use std::collections::BTreeMap;
+use std::path::Path;
+
+// relocation marks M80 puts after addresses and bytes
+const RELOCATION_MARKS: [char; 4] = ['\'', '"', '!', '*'];
+
+// mnemonics that would otherwise be taken for bytes
+const HEX_MNEMONICS: [&str; 2] = ["DB", "CC"];
+
+// how far ahead in a listing a source line is looked for, past any lines
+// the assembler added (macro expansions, headers)
+const SOURCE_SEARCH_LINES: usize = 200;
+
+// ListingLine struct - a single line of a listing
+#[derive(Debug, Clone, PartialEq)]
+pub struct ListingLine {
+    pub number: usize,     // line of the listing file, from 1
+    pub addr: Option<u16>, // only for lines that generate code
+    pub n_bytes: usize,    // number of bytes of code generated
+    pub text: String,      // the source text
+}
+
+impl ListingLine {
+    // returns whether the code generated by the line includes an address
+    pub fn contains(&self, addr: u16) -> bool {
+        self.addr.is_some_and(|start| {
+            let end = start as usize + self.n_bytes;
+            (start as usize..end).contains(&(addr as usize))
+        })
+    }
+}
+
+// LineTable struct - the lines of a listing, and the line generating the code
+// at each address
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct LineTable {
+    pub lines: Vec<ListingLine>,
+    addrs: BTreeMap<u16, usize>, // start address of each line's code
+}
+
+impl LineTable {
+    // creates a new empty instance of LineTable
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // loads a listing file
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let data =
+            std::fs::read(path).map_err(|e| format!("Could not read {}: {e}", path.display()))?;
+
+        Ok(Self::parse(&String::from_utf8_lossy(&data)))
+    }
+
+    // parses a listing. the listings of ASM, MAC, RMAC and M80 are read, as
+    // well as those written by the asm command. lines start with the address
+    // and bytes of the code they generate, followed by the source text
+    pub fn parse(text: &str) -> Self {
+        let mut table = Self::new();
+
+        // CP/M text files end with ^Z, and pages are separated by form feeds
+        let text = text.split('\x1A').next().unwrap_or_default();
+
+        for (i, line) in text.lines().enumerate() {
+            let line = line.replace('\x0C', "");
+            let (addr, n_bytes, source) = parse_line(&line);
+
+            // code that doesn't fit on a line carries on to the next lines,
+            // without any source
+            if let (Some(addr), true) = (addr, source.trim().is_empty()) {
+                if let Some(last) = table.lines.last_mut().filter(|last| {
+                    last.addr
+                        .is_some_and(|start| start as usize + last.n_bytes == addr as usize)
+                }) {
+                    last.n_bytes += n_bytes;
+                    continue;
+                }
+            }
+
+            let addr = addr.filter(|_| n_bytes > 0);
+            if let Some(addr) = addr {
+                table.addrs.insert(addr, table.lines.len());
+            }
+
+            table.lines.push(ListingLine {
+                number: i + 1,
+                addr,
+                n_bytes,
+                text: source.to_string(),
+            });
+        }
+
+        table
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.addrs.is_empty()
+    }
+
+    // returns the index in lines of the line that generated the code at an
+    // address
+    pub fn line_index(&self, addr: u16) -> Option<usize> {
+        let (_, index) = self.addrs.range(..=addr).next_back()?;
+        self.lines[*index].contains(addr).then_some(*index)
+    }
+
+    // returns the line that generated the code at an address
+    pub fn line_at(&self, addr: u16) -> Option<&ListingLine> {
+        self.line_index(addr).map(|index| &self.lines[index])
+    }
+
+    // returns the source line of the instruction about to be executed
+    pub fn current_line(&self, cpu: &Cpu) -> Option<&ListingLine> {
+        self.line_at(u16::from(cpu.reg_array.read_reg(Register::PC)))
+    }
+
+    // matches the lines of the source file the listing was made from to the
+    // lines of the listing, in order and by their text. returns the index in
+    // lines of each source line found, by source line number (from 1)
+    pub fn map_source(&self, source: &str) -> BTreeMap<usize, usize> {
+        let normalize = |s: &str| s.split_whitespace().collect::<Vec<&str>>().join(" ");
+
+        let mut map = BTreeMap::new();
+        let mut next = 0;
+
+        let source = source.split('\x1A').next().unwrap_or_default();
+        for (i, line) in source.lines().enumerate() {
+            let text = normalize(line);
+            if text.is_empty() {
+                continue;
+            }
+
+            if let Some(offset) = self.lines[next..]
+                .iter()
+                .take(SOURCE_SEARCH_LINES)
+                .position(|listing_line| normalize(&listing_line.text) == text)
+            {
+                map.insert(i + 1, next + offset);
+                next += offset + 1;
+            }
+        }
+
+        map
+    }
+}
+
+// splits a line of a listing into the address, number of bytes and source
+fn parse_line(line: &str) -> (Option<u16>, usize, &str) {
+    // listings from the asm command have a fixed layout, with the line
+    // number in the columns after the code
+    if let Some((code, source)) = split_numbered_line(line) {
+        let mut tokens = code.split_whitespace();
+        let addr = tokens.next().and_then(parse_addr);
+        let n_bytes = tokens.next().map_or(0, |bytes| bytes.len() / 2);
+
+        return (addr, n_bytes, source);
+    }
+
+    let mut rest = line;
+    let Some(addr) = next_token(&mut rest).and_then(parse_addr) else {
+        return (None, 0, line);
+    };
+
+    // values of EQU and SET are marked with =
+    let mut n_bytes = 0;
+    let mut source = rest;
+
+    while let Some(token) = next_token(&mut rest) {
+        if token == "=" {
+            return (None, 0, rest.trim_start());
+        }
+
+        let bytes = token.trim_end_matches(RELOCATION_MARKS);
+        let is_bytes = !bytes.is_empty()
+            && bytes.len() <= 8
+            && bytes.len().is_multiple_of(2)
+            && bytes.chars().all(|c| c.is_ascii_hexdigit())
+            && !HEX_MNEMONICS.contains(&bytes.to_ascii_uppercase().as_str());
+
+        if !is_bytes {
+            break;
+        }
+
+        // M80 shows a 16-bit value as one token, and bytes as separate ones
+        n_bytes += bytes.len() / 2;
+        source = rest;
+    }
+
+    (Some(addr), n_bytes, source.trim_start())
+}
+
+// splits a line from the asm command into the code and the source, if it is
+// laid out as one: 13 columns of code, then a right-aligned line number
+fn split_numbered_line(line: &str) -> Option<(&str, &str)> {
+    let number = line.get(14..19)?.trim_start();
+    let marker = line.get(19..21)?;
+
+    let is_numbered = !number.is_empty()
+        && number.chars().all(|c| c.is_ascii_digit())
+        && matches!(marker, "  " | "+ ");
+
+    is_numbered.then(|| (&line[..13], &line[21..]))
+}
+
+// removes the next whitespace-separated token from the start of a string
+fn next_token<'a>(s: &mut &'a str) -> Option<&'a str> {
+    let trimmed = s.trim_start();
+    let end = trimmed.find(char::is_whitespace).unwrap_or(trimmed.len());
+
+    *s = &trimmed[end..];
+    (end > 0).then(|| &trimmed[..end])
+}
+
+// parses the address at the start of a line, which is always 4 hex digits
+fn parse_addr(token: &str) -> Option<u16> {
+    let digits = token.trim_end_matches(RELOCATION_MARKS);
+
+    match digits.len() {
+        4 => u16::from_str_radix(digits, 16).ok(),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn listing_parse_formats() {
+        // CP/M ASM
+        let asm = LineTable::parse(
+            " 0005 =        BDOS\tEQU\t5\r\n 0100          \tORG\t100H\r\n 0100 0E09     START:\tMVI\tC,9\r\n 0102 CD0500   \tCALL\tBDOS\r\n 0105 48492400 MSG:\tDB\t'HI$',0\r\n 0109 00\r\n\x1A",
+        );
+        assert_eq!(asm.line_at(0x0100).unwrap().text, "START:\tMVI\tC,9");
+        assert_eq!(asm.line_at(0x0104).unwrap().text, "CALL\tBDOS");
+        assert_eq!(asm.line_at(0x0109).unwrap().n_bytes, 5);
+        assert_eq!(asm.line_at(0x010A), None);
+        assert_eq!(asm.lines[0].addr, None);
+
+        // M80, with relocatable addresses
+        let m80 = LineTable::parse(
+            "\x0CMACRO-80 3.44\t09-Dec-81\tPAGE\t1\n\n  0000'   0E 09         START:  MVI C,9\n  0002'   CD 0000*              CALL BDOS\n  0005'   00                    DB 0\n",
+        );
+        assert_eq!(m80.line_at(0x0004).unwrap().text, "CALL BDOS");
+        assert_eq!(m80.line_at(0x0005).unwrap().text, "DB 0");
+    }
+
+    #[test]
+    fn listing_current_line() {
+        let assembly = crate::assembler::assemble(
+            "        ORG 100H\nSTART:  MVI C,9\n        LXI D,1234H\n        HLT\n",
+        )
+        .unwrap();
+        let table = LineTable::parse(&assembly.listing);
+
+        let mut cpu = Cpu::new();
+        assembly.load_into(&mut cpu).unwrap();
+        cpu.set_pc(0x0100).unwrap();
+        cpu.execute_next().unwrap();
+
+        assert_eq!(
+            table.current_line(&cpu).unwrap().text,
+            "        LXI D,1234H"
+        );
+        assert_eq!(table.current_line(&cpu).unwrap().number, 3);
+
+        let source =
+            "; comment\n\n        ORG 100H\nSTART:  MVI C,9\n        LXI\tD,1234H\n        HLT\n";
+        let map = table.map_source(source);
+        assert_eq!(table.lines[map[&5]].addr, Some(0x0102));
+        assert!(!map.contains_key(&1));
+    }
+}
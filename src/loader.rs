@@ -0,0 +1,373 @@
+/*
+ * loader.rs - Contains code related to loading program images in the Intel
+ * HEX and Motorola S-record formats, as well as raw binaries
+ * See the Intel Hexadecimal Object File Format Specification, and the
+ * Motorola S-record format in the M68000 Family Reference Manual, appendix C
+ */
+use crate::cpu::*;
+
+// ImageFormat enum - the formats a program image can be stored in
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum ImageFormat {
+    Binary,
+    IntelHex,
+    SRecord,
+}
+
+// Segment struct - a contiguous run of data from an image
+#[derive(Debug, Clone, PartialEq)]
+pub struct Segment {
+    pub addr: u16,
+    pub data: Vec<u8>,
+}
+
+// Image struct - the contents of an Intel HEX or S-record file
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Image {
+    pub segments: Vec<Segment>,
+    pub start: Option<u16>, // given by a start address record
+}
+
+impl Image {
+    // writes every segment into the memory of a Cpu
+    pub fn load_into(&self, cpu: &mut Cpu) -> Result<(), String> {
+        for segment in &self.segments {
+            cpu.load_to_memory(segment.data.clone(), segment.addr)?;
+        }
+
+        Ok(())
+    }
+
+    // adds data at an address, extending the last segment if it follows on
+    fn add(&mut self, addr: u32, data: &[u8]) -> Result<(), String> {
+        if addr as usize + data.len() > 0x10000 {
+            return Err(format!(
+                "Data at {addr:X} is outside of the 64KB address space"
+            ));
+        }
+
+        let addr = addr as u16;
+
+        match self.segments.last_mut() {
+            Some(segment) if segment.addr as usize + segment.data.len() == addr as usize => {
+                segment.data.extend(data)
+            }
+            _ => self.segments.push(Segment {
+                addr,
+                data: data.to_vec(),
+            }),
+        }
+
+        Ok(())
+    }
+
+    // sets the start address, which has to be within the address space
+    fn set_start(&mut self, start: u32) -> Result<(), String> {
+        let start =
+            u16::try_from(start).map_err(|_| format!("Start address {start:X} is out of range"))?;
+        self.start = Some(start);
+
+        Ok(())
+    }
+}
+
+// works out the format of an image from its start. Intel HEX files start
+// with a whole record on a line of its own, as a binary can start with a
+// colon and hex digits by chance. S-records start with an S and the record
+// type
+pub fn detect_format(data: &[u8]) -> ImageFormat {
+    let start = data
+        .iter()
+        .position(|c| !c.is_ascii_whitespace())
+        .unwrap_or(data.len());
+
+    match &data[start..] {
+        data @ [b':', ..] if starts_with_hex_record(data) => ImageFormat::IntelHex,
+        [b'S' | b's', b'0'..=b'9', ..] if data.is_ascii() => ImageFormat::SRecord,
+        _ => ImageFormat::Binary,
+    }
+}
+
+// loads an image of any format into the memory of a Cpu. binaries are placed
+// at the given address. the program counter is set to the start address of
+// the image if it has one, which is returned
+pub fn load_image(cpu: &mut Cpu, data: &[u8], binary_addr: u16) -> Result<Option<u16>, String> {
+    load_image_as(cpu, data, detect_format(data), binary_addr)
+}
+
+// loads an image like load_image, in the given format rather than the one
+// it looks like
+pub fn load_image_as(
+    cpu: &mut Cpu,
+    data: &[u8],
+    format: ImageFormat,
+    binary_addr: u16,
+) -> Result<Option<u16>, String> {
+    let image = match format {
+        ImageFormat::Binary => {
+            if binary_addr as usize + data.len() > 0x10000 {
+                return Err(format!(
+                    "Image of {} bytes does not fit in memory",
+                    data.len()
+                ));
+            }
+
+            cpu.load_to_memory(data.to_vec(), binary_addr)?;
+            return Ok(None);
+        }
+
+        ImageFormat::IntelHex => parse_intel_hex(&String::from_utf8_lossy(data))?,
+        ImageFormat::SRecord => parse_srecord(&String::from_utf8_lossy(data))?,
+    };
+
+    image.load_into(cpu)?;
+
+    if let Some(start) = image.start {
+        cpu.set_pc(start)?;
+    }
+
+    Ok(image.start)
+}
+
+// parses an Intel HEX file. along with data and end-of-file records, the
+// segment and linear address records are accepted as long as everything
+// stays within 64KB
+pub fn parse_intel_hex(text: &str) -> Result<Image, String> {
+    let mut image = Image::default();
+    let mut base = 0u32; // from extended address records
+
+    for (i, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+
+        let error = |message: &str| format!("Line {}: {message}", i + 1);
+
+        let bytes = decode_hex_record(line).map_err(error)?;
+        let addr = u16::from_be_bytes([bytes[1], bytes[2]]) as u32;
+        let data = &bytes[4..bytes.len() - 1];
+
+        match bytes[3] {
+            0x00 => image.add(base + addr, data).map_err(|e| error(&e))?,
+
+            // 8-bit tools put the start address in the end-of-file record
+            0x01 => {
+                if addr != 0 && image.start.is_none() {
+                    image.set_start(addr).map_err(|e| error(&e))?;
+                }
+                break;
+            }
+
+            0x02 if data.len() == 2 => base = (u16::from_be_bytes([data[0], data[1]]) as u32) << 4,
+            0x04 if data.len() == 2 => base = (u16::from_be_bytes([data[0], data[1]]) as u32) << 16,
+
+            // CS:IP, or a linear address
+            0x03 if data.len() == 4 => {
+                let cs = u16::from_be_bytes([data[0], data[1]]) as u32;
+                let ip = u16::from_be_bytes([data[2], data[3]]) as u32;
+                image.set_start((cs << 4) + ip).map_err(|e| error(&e))?;
+            }
+            0x05 if data.len() == 4 => {
+                let start = u32::from_be_bytes([data[0], data[1], data[2], data[3]]);
+                image.set_start(start).map_err(|e| error(&e))?;
+            }
+
+            record_type => return Err(error(&format!("Invalid record type {record_type:02X}"))),
+        }
+    }
+
+    Ok(image)
+}
+
+// decodes an Intel HEX record: length, 16-bit address, type, data, checksum
+fn decode_hex_record(line: &str) -> Result<Vec<u8>, &'static str> {
+    let bytes = line
+        .strip_prefix(':')
+        .ok_or("Record doesn't start with ':'")
+        .and_then(|hex| decode_hex(hex).ok_or("Invalid hex digits"))?;
+
+    if bytes.len() < 5 || bytes.len() != bytes[0] as usize + 5 {
+        return Err("Record length doesn't match its contents");
+    }
+
+    // all bytes of the record, including the checksum, sum to 0
+    if bytes.iter().fold(0u8, |sum, b| sum.wrapping_add(*b)) != 0 {
+        return Err("Bad checksum");
+    }
+
+    Ok(bytes)
+}
+
+// whether data starts with a valid Intel HEX record, followed by the end of
+// the line or of the data
+fn starts_with_hex_record(data: &[u8]) -> bool {
+    let end = data
+        .iter()
+        .position(|c| matches!(c, b'\r' | b'\n'))
+        .unwrap_or(data.len());
+
+    std::str::from_utf8(&data[..end]).is_ok_and(|line| decode_hex_record(line.trim_end()).is_ok())
+}
+
+// parses a Motorola S-record file. S1 to S3 records hold data with 16, 24 or
+// 32-bit addresses, and S7 to S9 end the file with the start address
+pub fn parse_srecord(text: &str) -> Result<Image, String> {
+    let mut image = Image::default();
+
+    for (i, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+
+        let error = |message: &str| format!("Line {}: {message}", i + 1);
+
+        let mut chars = line.chars();
+        if !matches!(chars.next(), Some('S' | 's')) {
+            return Err(error("Record doesn't start with 'S'"));
+        }
+
+        let record_type = chars
+            .next()
+            .and_then(|c| c.to_digit(10))
+            .ok_or_else(|| error("Invalid record type"))?;
+        let bytes = decode_hex(chars.as_str()).ok_or_else(|| error("Invalid hex digits"))?;
+
+        // count (of the bytes that follow), address, data, checksum
+        if bytes.is_empty() || bytes.len() != bytes[0] as usize + 1 {
+            return Err(error("Record length doesn't match its contents"));
+        }
+
+        // the checksum is the ones' complement of the sum of the other bytes
+        if bytes.iter().fold(0u8, |sum, b| sum.wrapping_add(*b)) != 0xFF {
+            return Err(error("Bad checksum"));
+        }
+
+        let addr_len = match record_type {
+            0 | 1 | 5 | 9 => 2,
+            2 | 6 | 8 => 3,
+            3 | 7 => 4,
+            _ => return Err(error(&format!("Invalid record type S{record_type}"))),
+        };
+
+        if bytes.len() < addr_len + 2 {
+            return Err(error("Record is too short"));
+        }
+
+        let addr = bytes[1..=addr_len]
+            .iter()
+            .fold(0u32, |addr, b| (addr << 8) | *b as u32);
+        let data = &bytes[addr_len + 1..bytes.len() - 1];
+
+        match record_type {
+            1..=3 => image.add(addr, data).map_err(|e| error(&e))?,
+            7..=9 => {
+                image.set_start(addr).map_err(|e| error(&e))?;
+                break;
+            }
+
+            // the header and record counts don't affect what is loaded
+            _ => {}
+        }
+    }
+
+    Ok(image)
+}
+
+// decodes pairs of hex digits into bytes
+fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) || !hex.is_ascii() {
+        return None;
+    }
+
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpu::memory::*;
+    use crate::cpu::registers::*;
+
+    #[test]
+    fn loader_intel_hex() {
+        let hex = ":03010000C3000237\n:010200007687\n:00010001FE\n";
+
+        assert_eq!(detect_format(hex.as_bytes()), ImageFormat::IntelHex);
+
+        let image = parse_intel_hex(hex).unwrap();
+        assert_eq!(image.segments.len(), 2);
+        assert_eq!(image.segments[1].addr, 0x0200);
+        assert_eq!(image.start, Some(0x0100));
+
+        let mut cpu = Cpu::new();
+        assert_eq!(
+            load_image(&mut cpu, hex.as_bytes(), 0).unwrap(),
+            Some(0x0100)
+        );
+        assert_eq!(
+            cpu.memory
+                .read(RegisterValue::from(0x0200u16), MemorySize::Integer8),
+            Ok(RegisterValue::from(0x76u8))
+        );
+        assert_eq!(
+            cpu.reg_array.read_reg(Register::PC),
+            RegisterValue::from(0x0100u16)
+        );
+
+        // a corrupted checksum is rejected
+        let bad = hex.replacen("0237", "0238", 1);
+        assert_eq!(
+            parse_intel_hex(&bad),
+            Err(String::from("Line 1: Bad checksum"))
+        );
+
+        // a binary starting with a colon and hex digits, or with a record
+        // that doesn't check out or end its line, isn't taken for Intel HEX
+        assert_eq!(detect_format(b":10\x00\xC3\x00\x01"), ImageFormat::Binary);
+        assert_eq!(detect_format(bad.as_bytes()), ImageFormat::Binary);
+        assert_eq!(
+            detect_format(b":00000001FF\xC3\x00\x01"),
+            ImageFormat::Binary
+        );
+        assert_eq!(detect_format(b":00000001FF"), ImageFormat::IntelHex);
+
+        // and a file can be loaded as a binary whatever it looks like
+        assert_eq!(
+            load_image_as(&mut cpu, hex.as_bytes(), ImageFormat::Binary, 0x1000),
+            Ok(None)
+        );
+        assert_eq!(
+            cpu.memory
+                .read(RegisterValue::from(0x1000u16), MemorySize::Integer8),
+            Ok(RegisterValue::from(b':'))
+        );
+    }
+
+    #[test]
+    fn loader_srecord() {
+        let srec = "S00600004844521B\nS1060100C3000233\nS9030100FB\n";
+
+        assert_eq!(detect_format(srec.as_bytes()), ImageFormat::SRecord);
+
+        let image = parse_srecord(srec).unwrap();
+        assert_eq!(
+            image.segments,
+            [Segment {
+                addr: 0x0100,
+                data: vec![0xC3, 0x00, 0x02]
+            }]
+        );
+        assert_eq!(image.start, Some(0x0100));
+
+        let bad = srec.replace("S9030100FB", "S9030100FC");
+        assert!(parse_srecord(&bad).is_err());
+
+        // anything else is a raw binary
+        assert_eq!(detect_format(&[0xC3, 0x00, 0x01]), ImageFormat::Binary);
+    }
+}
@@ -1,57 +1,310 @@
 use clap::Parser;
 mod arguments;
-mod cpu;
-mod cp_m;
+mod commands;
 mod debug_menu;
 
-use cpu::*;
 use debug_menu::*;
+use i8080::cp_m;
+use i8080::cpu::disassembler::Syntax;
+use i8080::cpu::registers::*;
+use i8080::cpu::trace::Trace;
+use i8080::cpu::*;
+use i8080::crash::CrashReport;
+use i8080::dap::DebugAdapter;
+use i8080::isis;
+use i8080::listing::LineTable;
+use i8080::loader;
+use i8080::monitor::Monitor;
+use i8080::remote::{ConsoleInput, Remote};
+use i8080::symbols::SymbolTable;
+use i8080::terminal::*;
+use i8080::tui::Tui;
+use std::collections::{BTreeSet, VecDeque};
+use std::io::Write;
 use std::sync::{Arc, Mutex};
 use std::{fs, thread};
 
 fn main() {
     let args = arguments::Args::parse();
 
-    let program = fs::read(args.program).unwrap();
+    let mut symbols = SymbolTable::new();
+    for path in &args.sym {
+        symbols.extend(&SymbolTable::load(std::path::Path::new(path)).unwrap());
+    }
+
+    if let Some(command) = args.command {
+        if let Err(e) = commands::run_command(command, args.syntax, &symbols) {
+            eprintln!("{e}");
+            std::process::exit(1);
+        }
+
+        return;
+    }
+
+    let program = args
+        .program
+        .as_ref()
+        .map(|program| or_exit(read_file(program)));
+
+    let setup = Setup {
+        images: args
+            .load
+            .iter()
+            .map(|image| (image.clone(), or_exit(read_file(&image.path))))
+            .collect(),
+        entry: args.entry,
+        sp: args.sp,
+        registers: args.reg.clone(),
+    };
+
+    // ISIS-II programs see their own name at the start of the command line
+    let command_line = match &args.program {
+        Some(program) => {
+            let stem = std::path::Path::new(program)
+                .file_stem()
+                .unwrap_or_default();
+            format!("{} {}", stem.to_string_lossy().to_uppercase(), args.args)
+        }
+        None => args.args.clone(),
+    };
+
+    let console_input = ConsoleInput::default();
+
+    // keyboard input translated for the terminal the program expects
+    let console = || -> Option<Box<dyn Console>> {
+        // stdin carries the debug adapter's requests instead
+        if args.dap.as_deref() == Some("-") {
+            return Some(Box::new(std::io::empty()));
+        }
+
+        // the program reads what the remote control or terminal UI injects
+        if args.remote.is_some() || args.tui {
+            return Some(Box::new(console_input.clone()));
+        }
+
+        let terminal = args.terminal?;
+        Some(Box::new(InputTranslator::new(Keyboard::new(), terminal)))
+    };
+
+    let isis_state = args.isis.then(|| {
+        let mut isis_state = isis::Isis::new();
+        isis_state.console = console();
+        isis_state.drives[0] = Some(args.drive.as_deref().unwrap_or(".").into());
+
+        for drive in &args.isis_drive {
+            isis_state.drives[drive.drive] = Some(drive.dir.clone().into());
+        }
+
+        isis_state
+    });
+
+    let mut devices = cp_m::CpmDevices::from_paths(
+        args.list.as_deref(),
+        args.punch.as_deref(),
+        args.reader.as_deref(),
+    )
+    .unwrap();
+    devices.console = console();
+
+    // the CCP always needs a drive to load programs from, default to the
+    // current directory
+    let mut bdos = cp_m::Bdos::new(devices);
+    bdos.drive = match (args.drive, args.ccp) {
+        (Some(drive), _) => Some(cp_m::drive::HostDrive::new(drive)),
+        (None, true) => Some(cp_m::drive::HostDrive::new(".")),
+        (None, false) => None,
+    };
+    bdos.trace = args.bdos_trace.map(|path| {
+        let writer: Box<dyn Write + Send> = match path.as_str() {
+            "-" => Box::new(std::io::stderr()),
+            path => Box::new(fs::File::create(path).unwrap()),
+        };
+
+        let mut trace = cp_m::trace::BdosTrace::new(writer);
+        trace.symbols = symbols.clone();
+        trace
+    });
 
     let cpu_output_str = Arc::new(Mutex::new(String::new())); // string containing the output of
                                                               // the cpu through port 0
     let cpu_output_str_thr = cpu_output_str.clone(); // clone to be passed to the thread
 
-    let cpu = Cpu::new();
+    let output_translator = Mutex::new(args.terminal.map(OutputTranslator::new));
+
+    // the program reads keys as they are typed, and echoes them itself
+    let _raw_mode = args.terminal.and_then(|_| RawMode::enable_cbreak());
+
+    let mut cpu = Cpu::new();
+    cpu.trace = args.trace.map(|path| {
+        let writer: Box<dyn Write + Send> = match path.as_str() {
+            "-" => Box::new(std::io::stdout()),
+            path => Box::new(std::io::BufWriter::new(fs::File::create(path).unwrap())),
+        };
+
+        let mut trace = Trace::new(writer);
+        trace.syntax = args.syntax;
+        trace.ranges = args.trace_range;
+        trace.start = args.trace_start;
+        trace.stop = args.trace_stop;
+        trace
+    });
     let cpu = Arc::new(Mutex::new(cpu));
     let cpu_thr = cpu.clone();
 
+    let coverage = args.coverage.clone();
+    let lines = args
+        .prn
+        .as_ref()
+        .map(|path| LineTable::load(std::path::Path::new(path)).unwrap());
+
+    let frontend = if args.monitor {
+        let mut monitor = Monitor::new();
+        monitor.symbols = symbols.clone();
+        monitor.syntax = args.syntax;
+        Frontend::Monitor(monitor)
+    } else if args.tui {
+        let mut tui = Tui::new();
+        tui.symbols = symbols.clone();
+        tui.syntax = args.syntax;
+        tui.output = cpu_output_str.clone();
+        tui.console = console_input.clone();
+        Frontend::Tui(tui)
+    } else if let Some(addr) = args.dap.clone() {
+        let mut adapter = DebugAdapter::new();
+        adapter.symbols = symbols.clone();
+        adapter.syntax = args.syntax;
+        adapter.lines = lines.clone();
+        adapter.listing_path = args.prn.clone();
+        Frontend::DebugAdapter(adapter, addr)
+    } else if let Some(addr) = args.remote.clone() {
+        let mut remote = Remote::new();
+        remote.symbols = symbols.clone();
+        remote.console = console_input.clone();
+        Frontend::Remote(remote, addr)
+    } else {
+        Frontend::None
+    };
+    let symbols = Arc::new(symbols);
+    let symbols_thr = symbols.clone();
+
+    let quiet = args.tui;
+    let syntax = args.syntax;
     let sim_handler = move || {
         let cpu_arc = Arc::clone(&cpu_thr);
         let mut cpu = cpu_arc.lock().unwrap();
 
-        cp_m::add_cpm_bdos(&mut cpu);
-        cpu.set_pc(0x100).unwrap();
-
-        cpu.load_to_memory(program, 0x100).unwrap();
         cpu.set_port_handler_fn(move |port, value| {
             let port = u8::try_from(port).unwrap();
             let value = u8::try_from(value).unwrap();
 
-            match port {
-                0 => {
-                    let character = value as char;
+            if port == 0 {
+                let character = value as char;
 
-                    let cpu_output_str = Arc::clone(&cpu_output_str_thr);
-                    let mut out_str = cpu_output_str.lock().unwrap();
-                    (*out_str).push(character);
+                let cpu_output_str = Arc::clone(&cpu_output_str_thr);
+                let mut out_str = cpu_output_str.lock().unwrap();
+                (*out_str).push(character);
 
-                    // also print
-                    print!("{character}");
+                // the terminal UI shows the output in a pane instead
+                if quiet {
+                    return;
                 }
 
-                _ => {}
+                // also print, translating the control codes of the terminal
+                let mut stdout = std::io::stdout();
+                match output_translator.lock().unwrap().as_mut() {
+                    Some(translator) => stdout.write_all(&translator.translate(value)).unwrap(),
+                    None => print!("{character}"),
+                }
+                stdout.flush().unwrap();
             }
         });
 
-        while cpu.is_running() {
-            cpu.execute_next().unwrap();
+        if let Some(isis_state) = isis_state {
+            let isis_state = isis::add_isis(&mut cpu, isis_state);
+            or_exit(isis::load_program(
+                &mut cpu,
+                &isis_state,
+                &program.unwrap(),
+                &command_line,
+            ));
+            or_exit(setup.apply(&mut cpu));
+
+            run(
+                &mut cpu,
+                &symbols_thr,
+                syntax,
+                coverage.as_deref(),
+                frontend,
+            );
+
+            println!();
+            return;
+        }
+
+        // bare metal programs start at the reset vector, with nothing else
+        // in memory
+        if args.bare {
+            if let Some(program) = program {
+                let org = args.org.unwrap_or(0);
+                let format = program_format(&program, args.binary);
+                let start =
+                    or_exit(loader::load_image_as(&mut cpu, &program, format, org)).unwrap_or(org);
+                or_exit(cpu.set_pc(start));
+            }
+            or_exit(setup.apply(&mut cpu));
+
+            run(
+                &mut cpu,
+                &symbols_thr,
+                syntax,
+                coverage.as_deref(),
+                frontend,
+            );
+
+            println!();
+            return;
+        }
+
+        let bdos = cp_m::add_cpm_bdos(&mut cpu, bdos);
+
+        if args.ccp {
+            or_exit(setup.apply(&mut cpu));
+            cp_m::ccp::run_ccp(&mut cpu, &bdos).unwrap();
+            if let Some(trace) = cpu.trace.as_mut() {
+                trace.flush().unwrap();
+            }
+        } else {
+            let program = program.unwrap_or_default();
+
+            // Intel HEX and S-record images are placed at the addresses they
+            // give, and may set their own start address
+            let format = program_format(&program, args.binary);
+            match (format, args.org) {
+                (loader::ImageFormat::Binary, None) => {
+                    or_exit(cp_m::load_transient(&mut cpu, program, &args.args))
+                }
+                (_, org) => {
+                    or_exit(cp_m::load_transient(&mut cpu, Vec::new(), &args.args));
+
+                    let org = org.unwrap_or(cp_m::TPA_START);
+                    let start = or_exit(loader::load_image_as(&mut cpu, &program, format, org))
+                        .unwrap_or(org);
+                    or_exit(cpu.set_pc(start));
+                }
+            }
+            or_exit(setup.apply(&mut cpu));
+
+            run(
+                &mut cpu,
+                &symbols_thr,
+                syntax,
+                coverage.as_deref(),
+                frontend,
+            );
+        }
+
+        if let Some(trace) = bdos.lock().unwrap().trace.as_mut() {
+            trace.write_summary().unwrap();
         }
 
         println!();
@@ -62,15 +315,167 @@ fn main() {
 
         init_imgui("Intel 8080 Emulator", |ui| {
             let cpu_arc = Arc::clone(&cpu);
-            let mut cpu = cpu_arc.lock().unwrap();
+            let cpu = cpu_arc.lock().unwrap();
 
             let cpu_output_str = Arc::clone(&cpu_output_str);
             let out_str = cpu_output_str.lock().unwrap();
 
-            cpu_output::add_cpu_output(ui, &*out_str);
+            cpu_output::add_cpu_output(ui, &out_str);
             registers_view::add_registers_view(ui, &cpu.reg_array);
+            disassembly_view::add_disassembly_view(ui, &cpu, args.syntax, &symbols);
+
+            if let Some(lines) = &lines {
+                source_view::add_source_view(ui, &cpu, lines);
+            }
         });
     } else {
         sim_handler();
     }
 }
+
+// Frontend enum - what the loaded program is handed to
+enum Frontend {
+    None,
+    Monitor(Monitor),
+    Tui(Tui),
+    DebugAdapter(DebugAdapter, String), // and the address to serve it on
+    Remote(Remote, String),
+}
+
+// runs the Cpu until it stops, or hands it to the monitor, terminal UI, debug
+// adapter or remote control if there is one. an error is reported with a
+// crash report describing the state of the Cpu. the addresses of the
+// instructions run are written to the coverage file if there is one
+fn run(
+    cpu: &mut Cpu,
+    symbols: &SymbolTable,
+    syntax: Syntax,
+    coverage: Option<&str>,
+    frontend: Frontend,
+) {
+    if coverage.is_some() {
+        cpu.coverage = Some(BTreeSet::new());
+    }
+
+    let result = match frontend {
+        Frontend::None => {
+            cpu.history = Some(VecDeque::new());
+
+            let mut result = Ok(());
+            while cpu.is_running() {
+                let pc = u16::from(cpu.reg_array.read_reg(Register::PC));
+
+                if let Err(e) = cpu.execute_next() {
+                    result = Err(CrashReport::new(cpu, pc, &e, symbols, syntax).to_string());
+                    break;
+                }
+            }
+            result
+        }
+        Frontend::Monitor(mut monitor) => {
+            monitor.run(cpu, &mut std::io::stdin().lock(), &mut std::io::stdout())
+        }
+        Frontend::Tui(mut tui) => tui.run(cpu),
+        Frontend::DebugAdapter(mut adapter, addr) => match addr.as_str() {
+            "-" => adapter.serve(cpu, std::io::stdin(), &mut std::io::stdout()),
+            addr => accept(addr).and_then(|mut stream| {
+                let input = stream.try_clone().map_err(|e| e.to_string())?;
+                adapter.serve(cpu, input, &mut stream)
+            }),
+        },
+        Frontend::Remote(mut remote, addr) => match addr.as_str() {
+            "-" => remote.serve(cpu, &mut std::io::stdin().lock(), &mut std::io::stdout()),
+            addr => accept(addr).and_then(|mut stream| {
+                let input = stream.try_clone().map_err(|e| e.to_string())?;
+                remote.serve(cpu, &mut std::io::BufReader::new(input), &mut stream)
+            }),
+        },
+    };
+
+    // the trace is written through a buffer
+    let flushed = cpu.trace.as_mut().map_or(Ok(()), |trace| trace.flush());
+    if let Err(e) = result.and(flushed) {
+        eprintln!("\n{e}");
+        std::process::exit(1);
+    }
+
+    if let (Some(path), Some(addrs)) = (coverage, &cpu.coverage) {
+        let text: String = addrs.iter().map(|addr| format!("{addr:04X}\n")).collect();
+        fs::write(path, text).unwrap();
+    }
+}
+
+// returns the result of setting up the machine, or reports the error and
+// exits if it failed
+fn or_exit<T>(result: Result<T, String>) -> T {
+    result.unwrap_or_else(|e| {
+        eprintln!("{e}");
+        std::process::exit(1)
+    })
+}
+
+fn read_file(path: &str) -> Result<Vec<u8>, String> {
+    fs::read(path).map_err(|e| format!("Could not read {path}: {e}"))
+}
+
+// the format the program is loaded in, a binary if --binary is given
+fn program_format(program: &[u8], binary: bool) -> loader::ImageFormat {
+    match binary {
+        true => loader::ImageFormat::Binary,
+        false => loader::detect_format(program),
+    }
+}
+
+// waits for a client to connect to an address. a port alone is on localhost
+fn accept(addr: &str) -> Result<std::net::TcpStream, String> {
+    let addr = match addr.parse::<u16>() {
+        Ok(port) => format!("127.0.0.1:{port}"),
+        Err(_) => addr.to_string(),
+    };
+
+    let listener = std::net::TcpListener::bind(&addr).map_err(|e| format!("{addr}: {e}"))?;
+    eprintln!("Waiting for a client on {addr}");
+
+    let (stream, _) = listener.accept().map_err(|e| e.to_string())?;
+    Ok(stream)
+}
+
+// Setup struct - changes made to the machine after the program is loaded
+struct Setup {
+    images: Vec<(arguments::ImageArg, Vec<u8>)>,
+    entry: Option<u16>,
+    sp: Option<u16>,
+    registers: Vec<arguments::RegisterArg>,
+}
+
+impl Setup {
+    // loads the extra images, then sets the entry point, stack pointer and
+    // registers given on the command line
+    fn apply(&self, cpu: &mut Cpu) -> Result<(), String> {
+        for (image, data) in &self.images {
+            let addr = match (image.addr, loader::detect_format(data)) {
+                (Some(addr), _) => addr,
+                (None, loader::ImageFormat::Binary) => {
+                    return Err(format!("{} needs a load address, as {0}@ADDR", image.path))
+                }
+                (None, _) => 0,
+            };
+
+            loader::load_image(cpu, data, addr)?;
+        }
+
+        if let Some(entry) = self.entry {
+            cpu.set_pc(entry)?;
+        }
+
+        if let Some(sp) = self.sp {
+            cpu.set_register("SP", sp)?;
+        }
+
+        for register in &self.registers {
+            cpu.set_register(&register.name, register.value)?;
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,654 @@
+/*
+ * monitor.rs - Contains code related to the monitor, a line-based debugger
+ * for terminals with the commands of CP/M's DDT and SID:
+ *   D[s][,e]     dump memory            L[s][,e]     list (disassemble)
+ *   A[s]         assemble inline        G[s][,b...]  go, with breakpoints
+ *   T[n] / U[n]  trace / untrace steps  S[s][,b...]  set memory
+ *   X[r]         examine registers      I[file]      name a file for R
+ *   R[offset]    read the file          W[file],s,e  write memory to a file
+ *   Q            quit
+ * numbers are in hex, and .NAME is the address of a symbol
+ */
+use crate::assembler;
+use crate::cpu::breakpoints::*;
+use crate::cpu::disassembler::*;
+use crate::cpu::registers::*;
+use crate::cpu::*;
+use crate::loader;
+use crate::symbols::SymbolTable;
+
+use std::io::{BufRead, Write};
+
+// bytes shown by D without an end address
+const DUMP_BYTES: u16 = 0xC0;
+
+// instructions shown by L without an end address
+const LIST_INSTRUCTIONS: usize = 12;
+
+// the registers X can change, and the names set_register knows them by
+const REGISTERS: [(&str, &str); 6] = [
+    ("A", "A"),
+    ("B", "BC"),
+    ("D", "DE"),
+    ("H", "HL"),
+    ("S", "SP"),
+    ("P", "PC"),
+];
+
+// the flags X can change, and their bits in the F register
+const FLAGS: [(&str, u8); 5] = [
+    ("C", 0x01),
+    ("Z", 0x40),
+    ("M", 0x80),
+    ("E", 0x04),
+    ("I", 0x10),
+];
+
+// Monitor struct - the state of the monitor between commands
+#[derive(Debug, Clone, Default)]
+pub struct Monitor {
+    pub symbols: SymbolTable,
+    pub syntax: Syntax,
+    dump_addr: u16,       // where D carries on from
+    list_addr: u16,       // where L carries on from
+    file: Option<String>, // named by I, read by R
+}
+
+impl Monitor {
+    // creates a new instance of Monitor
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // reads and executes commands until Q or the end of the input. errors
+    // are reported, and don't end the session
+    pub fn run(
+        &mut self,
+        cpu: &mut Cpu,
+        input: &mut dyn BufRead,
+        output: &mut dyn Write,
+    ) -> Result<(), String> {
+        let pc = read_pc(cpu);
+        self.dump_addr = pc;
+        self.list_addr = pc;
+
+        loop {
+            write!(output, "-").map_err(|e| e.to_string())?;
+            output.flush().map_err(|e| e.to_string())?;
+
+            let Some(line) = read_line(input)? else {
+                return Ok(());
+            };
+
+            match self.execute(cpu, &line, input, output) {
+                Ok(true) => {}
+                Ok(false) => return Ok(()),
+                Err(e) => writeln!(output, "?{e}").map_err(|e| e.to_string())?,
+            }
+        }
+    }
+
+    // executes a command, returns whether to carry on. commands that need
+    // more input (A, S and X) read lines from input
+    pub fn execute(
+        &mut self,
+        cpu: &mut Cpu,
+        line: &str,
+        input: &mut dyn BufRead,
+        output: &mut dyn Write,
+    ) -> Result<bool, String> {
+        let line = line.trim();
+        let Some(command) = line.chars().next() else {
+            return Ok(true);
+        };
+        let rest = line[command.len_utf8()..].trim();
+
+        let text = match command.to_ascii_uppercase() {
+            'D' => self.dump(cpu, rest)?,
+            'L' => self.list(cpu, rest)?,
+            'A' => self.assemble(cpu, rest, input, output)?,
+            'G' => self.go(cpu, rest)?,
+            'T' => self.trace(cpu, rest, true)?,
+            'U' => self.trace(cpu, rest, false)?,
+            'S' => self.set_memory(cpu, rest, input, output)?,
+            'X' => self.examine(cpu, rest, input, output)?,
+            'I' => {
+                self.file = Some(rest.to_string()).filter(|file| !file.is_empty());
+                String::new()
+            }
+            'R' => self.read_file(cpu, rest)?,
+            'W' => self.write_file(cpu, rest)?,
+            'Q' => return Ok(false),
+            _ => return Err(format!("Unknown command {command}")),
+        };
+
+        write!(output, "{text}").map_err(|e| e.to_string())?;
+        Ok(true)
+    }
+
+    // D[s][,e] - dumps memory in hex and ASCII
+    fn dump(&mut self, cpu: &Cpu, args: &str) -> Result<String, String> {
+        let (start, end) = match self.parse_args(args)?[..] {
+            [] => (self.dump_addr, None),
+            [start] => (start, None),
+            [start, end] => (start, Some(end)),
+            _ => return Err(String::from("Expected D[s][,e]")),
+        };
+        let end = end.unwrap_or(start.saturating_add(DUMP_BYTES - 1));
+
+        self.dump_addr = end.wrapping_add(1);
+        Ok(cpu.memory.dump(start, end))
+    }
+
+    // L[s][,e] - lists the instructions in memory
+    fn list(&mut self, cpu: &Cpu, args: &str) -> Result<String, String> {
+        let (start, end) = match self.parse_args(args)?[..] {
+            [] => (self.list_addr, None),
+            [start] => (start, None),
+            [start, end] => (start, Some(end)),
+            _ => return Err(String::from("Expected L[s][,e]")),
+        };
+
+        let mut text = String::new();
+        let mut addr = start;
+        for i in 0.. {
+            let done = match end {
+                Some(end) => addr > end || (i > 0 && addr <= start),
+                None => i == LIST_INSTRUCTIONS,
+            };
+            if done {
+                break;
+            }
+
+            let instruction = disassemble(&cpu.memory, addr, self.syntax)?;
+            if let Some(name) = self.symbols.name_at(addr) {
+                text.push_str(&format!("{name}:\n"));
+            }
+            addr = addr.wrapping_add(instruction.n_bytes() as u16);
+            text.push_str(&format!("{}\n", instruction.with_symbols(&self.symbols)));
+        }
+
+        self.list_addr = addr;
+        Ok(text)
+    }
+
+    // A[s] - assembles lines into memory until an empty line or "."
+    fn assemble(
+        &mut self,
+        cpu: &mut Cpu,
+        args: &str,
+        input: &mut dyn BufRead,
+        output: &mut dyn Write,
+    ) -> Result<String, String> {
+        let mut addr = match self.parse_args(args)?[..] {
+            [] => self.list_addr,
+            [addr] => addr,
+            _ => return Err(String::from("Expected A[s]")),
+        };
+
+        loop {
+            write!(output, "{addr:04X} ").map_err(|e| e.to_string())?;
+            output.flush().map_err(|e| e.to_string())?;
+
+            let line = match read_line(input)? {
+                Some(line) if !matches!(line.trim(), "" | ".") => line,
+                _ => break,
+            };
+
+            // mistakes are reported, and the line can be typed again
+            let line = self.substitute_symbols(line.trim());
+            match assembler::assemble(&format!("\tORG {addr:05X}H\n\t{line}\n")) {
+                Ok(assembly) => {
+                    let bytes = assembly.to_binary();
+                    cpu.load_to_memory(bytes.clone(), addr)?;
+                    addr = addr.wrapping_add(bytes.len() as u16);
+                }
+                Err(e) => writeln!(output, "?{e}").map_err(|e| e.to_string())?,
+            }
+        }
+
+        self.list_addr = addr;
+        Ok(String::new())
+    }
+
+    // G[s][,b...] - runs from s (or the program counter) until the program
+    // halts or reaches a breakpoint. the addresses after s are temporary
+    // breakpoints, removed when the program stops
+    fn go(&mut self, cpu: &mut Cpu, args: &str) -> Result<String, String> {
+        let mut args = args.splitn(2, ',');
+        let start = args.next().unwrap_or_default();
+        let breakpoints = self.parse_args(args.next().unwrap_or_default())?;
+
+        if !start.trim().is_empty() {
+            cpu.set_pc(self.parse_value(start)?)?;
+        }
+
+        let ids: Vec<usize> = breakpoints
+            .iter()
+            .map(|addr| {
+                cpu.breakpoints
+                    .add_temporary(BreakpointKind::Execute(*addr))
+            })
+            .collect();
+
+        cpu.running = true;
+        let result = cpu.run(usize::MAX);
+
+        for id in ids {
+            let _ = cpu.breakpoints.remove(id);
+        }
+
+        self.stopped(cpu, result?)
+    }
+
+    // T[n] and U[n] - executes n instructions (1 by default), showing the
+    // state before each one when tracing
+    fn trace(&mut self, cpu: &mut Cpu, args: &str, show: bool) -> Result<String, String> {
+        let n = match self.parse_args(args)?[..] {
+            [] => 1,
+            [n] => n as usize,
+            _ => return Err(String::from("Expected T[n] or U[n]")),
+        };
+
+        let mut text = String::new();
+        cpu.running = true;
+
+        let result = match show {
+            true => {
+                let mut result = StopReason::BudgetExhausted;
+                for _ in 0..n {
+                    text.push_str(&format!("{}\n", self.state(cpu)?));
+
+                    result = cpu.run_until(Budget::Instructions(1), |_, _| false)?;
+                    if result != StopReason::BudgetExhausted {
+                        break;
+                    }
+                }
+                result
+            }
+            false => cpu.run_until(Budget::Instructions(n), |_, _| false)?,
+        };
+
+        text.push_str(&self.stopped(cpu, result)?);
+        Ok(text)
+    }
+
+    // describes why the program stopped, and where
+    fn stopped(&mut self, cpu: &Cpu, result: StopReason) -> Result<String, String> {
+        let pc = read_pc(cpu);
+        self.list_addr = pc;
+
+        Ok(match result {
+            StopReason::Breakpoint(hit) if matches!(hit.kind, BreakpointKind::Execute(_)) => {
+                format!("*{}\n", self.symbols.format_addr(pc))
+            }
+            StopReason::Breakpoint(hit) => {
+                format!(
+                    "{}\n*{}\n",
+                    hit.describe(&self.symbols),
+                    self.symbols.format_addr(pc)
+                )
+            }
+            StopReason::Halted => format!("Halted at {}\n", self.symbols.format_addr(pc)),
+            StopReason::WaitingForInput => {
+                format!("Waiting for input at {}\n", self.symbols.format_addr(pc))
+            }
+            StopReason::Completed | StopReason::BudgetExhausted => {
+                format!("*{}\n", self.symbols.format_addr(pc))
+            }
+        })
+    }
+
+    // S[s][,b...] - sets memory to the bytes given, or otherwise shows each
+    // byte in turn and reads a new value for it. an empty line leaves a byte
+    // as it is, and "." stops
+    fn set_memory(
+        &mut self,
+        cpu: &mut Cpu,
+        args: &str,
+        input: &mut dyn BufRead,
+        output: &mut dyn Write,
+    ) -> Result<String, String> {
+        let args = self.parse_args(args)?;
+        let Some((&start, bytes)) = args.split_first() else {
+            return Err(String::from("Expected S[s][,b...]"));
+        };
+
+        if !bytes.is_empty() {
+            let bytes = bytes
+                .iter()
+                .map(|b| u8::try_from(*b).map_err(|_| format!("{b:X} is not a byte")))
+                .collect::<Result<Vec<u8>, String>>()?;
+            cpu.load_to_memory(bytes, start)?;
+            return Ok(String::new());
+        }
+
+        let mut addr = start;
+        loop {
+            let byte = read_byte(cpu, addr)?;
+            write!(output, "{addr:04X} {byte:02X} ").map_err(|e| e.to_string())?;
+            output.flush().map_err(|e| e.to_string())?;
+
+            let value = match read_line(input)? {
+                Some(line) if line.trim() == "." => break,
+                Some(line) => line.trim().to_string(),
+                None => break,
+            };
+
+            if !value.is_empty() {
+                let value = self.parse_value(&value)?;
+                let value = u8::try_from(value).map_err(|_| format!("{value:X} is not a byte"))?;
+                cpu.load_to_memory(vec![value], addr)?;
+            }
+            addr = addr.wrapping_add(1);
+        }
+
+        Ok(String::new())
+    }
+
+    // X[r] - shows the registers and flags, or shows one of them and reads
+    // a new value for it. an empty line leaves it as it is
+    fn examine(
+        &mut self,
+        cpu: &mut Cpu,
+        args: &str,
+        input: &mut dyn BufRead,
+        output: &mut dyn Write,
+    ) -> Result<String, String> {
+        if args.is_empty() {
+            return Ok(format!("{}\n", self.state(cpu)?));
+        }
+
+        let name = args.to_ascii_uppercase();
+        let current = if let Some((_, register)) = REGISTERS.iter().find(|(n, _)| *n == name) {
+            match *register {
+                "A" => format!("{:02X}", read_register(cpu, register)),
+                _ => format!("{:04X}", read_register(cpu, register)),
+            }
+        } else if let Some((_, bit)) = FLAGS.iter().find(|(n, _)| *n == name) {
+            format!("{}", u8::from(read_f(cpu) & bit != 0))
+        } else {
+            return Err(format!("Unknown register {args}"));
+        };
+
+        write!(output, "{name}={current} ").map_err(|e| e.to_string())?;
+        output.flush().map_err(|e| e.to_string())?;
+
+        let value = match read_line(input)? {
+            Some(line) if !matches!(line.trim(), "" | ".") => self.parse_value(&line)?,
+            _ => return Ok(String::new()),
+        };
+
+        if let Some((_, register)) = REGISTERS.iter().find(|(n, _)| *n == name) {
+            cpu.set_register(register, value)?;
+        } else if let Some((_, bit)) = FLAGS.iter().find(|(n, _)| *n == name) {
+            let f = match value {
+                0 => read_f(cpu) & !bit,
+                1 => read_f(cpu) | bit,
+                _ => return Err(String::from("Flags are 0 or 1")),
+            };
+            let a = read_register(cpu, "A");
+            cpu.set_register("PSW", (a << 8) | f as u16)?;
+        }
+
+        Ok(String::new())
+    }
+
+    // the registers, flags and next instruction, in the form DDT shows them:
+    // C0Z1M0E1I0 A=00 B=0000 D=0000 H=0000 S=0100 P=0100 LXI SP,0100H
+    pub fn state(&self, cpu: &Cpu) -> Result<String, String> {
+        let f = read_f(cpu);
+        let flags: String = FLAGS
+            .iter()
+            .map(|(name, bit)| format!("{name}{}", u8::from(f & bit != 0)))
+            .collect();
+
+        let registers: Vec<String> = REGISTERS
+            .iter()
+            .map(|(name, register)| match *name {
+                "A" => format!("A={:02X}", read_register(cpu, register)),
+                _ => format!("{name}={:04X}", read_register(cpu, register)),
+            })
+            .collect();
+
+        let instruction = disassemble(&cpu.memory, read_pc(cpu), self.syntax)?;
+        Ok(format!(
+            "{flags} {} {}",
+            registers.join(" "),
+            instruction.with_symbols(&self.symbols).text
+        ))
+    }
+
+    // R[offset] - reads the file named by I. binaries are read at 100 plus
+    // the offset, and Intel HEX and S-record images at their own addresses
+    // plus the offset
+    fn read_file(&mut self, cpu: &mut Cpu, args: &str) -> Result<String, String> {
+        let offset = match self.parse_args(args)?[..] {
+            [] => 0,
+            [offset] => offset,
+            _ => return Err(String::from("Expected R[offset]")),
+        };
+        let file = self.file.as_ref().ok_or("No file named, use I first")?;
+        let data = std::fs::read(file).map_err(|e| format!("Could not read {file}: {e}"))?;
+
+        let mut image = match loader::detect_format(&data) {
+            loader::ImageFormat::Binary => loader::Image {
+                segments: vec![loader::Segment { addr: 0x0100, data }],
+                start: None,
+            },
+            loader::ImageFormat::IntelHex => {
+                loader::parse_intel_hex(&String::from_utf8_lossy(&data))?
+            }
+            loader::ImageFormat::SRecord => loader::parse_srecord(&String::from_utf8_lossy(&data))?,
+        };
+
+        for segment in &mut image.segments {
+            segment.addr = segment.addr.wrapping_add(offset);
+        }
+        image.load_into(cpu)?;
+
+        if let Some(start) = image.start {
+            cpu.set_pc(start)?;
+        }
+
+        let next = image
+            .segments
+            .iter()
+            .map(|segment| segment.addr as usize + segment.data.len())
+            .max()
+            .unwrap_or_default();
+        self.dump_addr = image.segments.first().map_or(0, |segment| segment.addr);
+        self.list_addr = read_pc(cpu);
+
+        Ok(format!("NEXT  PC\n{:04X} {:04X}\n", next, read_pc(cpu)))
+    }
+
+    // W[file],s,e - writes memory from s to e inclusive to a file
+    fn write_file(&self, cpu: &Cpu, args: &str) -> Result<String, String> {
+        let (file, range) = args.split_once(',').ok_or("Expected W[file],s,e")?;
+        let (start, end) = match self.parse_args(range)?[..] {
+            [start, end] if start <= end => (start, end),
+            _ => return Err(String::from("Expected W[file],s,e")),
+        };
+
+        let data = (start..=end)
+            .map(|addr| read_byte(cpu, addr))
+            .collect::<Result<Vec<u8>, String>>()?;
+        std::fs::write(file.trim(), &data).map_err(|e| format!("Could not write {file}: {e}"))?;
+
+        Ok(format!("{:04X} bytes written\n", data.len()))
+    }
+
+    // replaces each .NAME of a known symbol in a line with its address
+    fn substitute_symbols(&self, line: &str) -> String {
+        let mut result = String::new();
+        let mut rest = line;
+
+        while let Some(i) = rest.find('.') {
+            result.push_str(&rest[..i]);
+
+            let name_len = rest[i + 1..]
+                .find(|c: char| !(c.is_ascii_alphanumeric() || matches!(c, '?' | '@' | '_' | '$')))
+                .unwrap_or(rest.len() - i - 1);
+            let name = &rest[i + 1..i + 1 + name_len];
+
+            match self.symbols.address_of(name).filter(|_| !name.is_empty()) {
+                Some(addr) => result.push_str(&format!("{addr:05X}H")),
+                None => result.push_str(&rest[i..i + 1 + name_len]),
+            }
+            rest = &rest[i + 1 + name_len..];
+        }
+
+        result.push_str(rest);
+        result
+    }
+
+    // parses comma-separated values
+    fn parse_args(&self, args: &str) -> Result<Vec<u16>, String> {
+        if args.trim().is_empty() {
+            return Ok(Vec::new());
+        }
+
+        args.split(',').map(|arg| self.parse_value(arg)).collect()
+    }
+
+    // parses a value, in hex or as .NAME for the address of a symbol
+    fn parse_value(&self, value: &str) -> Result<u16, String> {
+        let value = value.trim();
+
+        match value.strip_prefix('.') {
+            Some(name) => self
+                .symbols
+                .address_of(name)
+                .ok_or_else(|| format!("Unknown symbol {name}")),
+            None => u16::from_str_radix(value, 16).map_err(|_| format!("Invalid number {value}")),
+        }
+    }
+}
+
+// reads a line, without the line ending. returns None at the end of input
+fn read_line(input: &mut dyn BufRead) -> Result<Option<String>, String> {
+    let mut line = String::new();
+
+    match input.read_line(&mut line).map_err(|e| e.to_string())? {
+        0 => Ok(None),
+        _ => Ok(Some(line.trim_end_matches(['\r', '\n']).to_string())),
+    }
+}
+
+fn read_byte(cpu: &Cpu, addr: u16) -> Result<u8, String> {
+    let value = cpu.memory.read(
+        RegisterValue::from(addr),
+        crate::cpu::memory::MemorySize::Integer8,
+    )?;
+    Ok(u8::try_from(value)?)
+}
+
+fn read_pc(cpu: &Cpu) -> u16 {
+    u16::from(cpu.reg_array.read_reg(Register::PC))
+}
+
+// reads a register by the name set_register knows it by
+fn read_register(cpu: &Cpu, name: &str) -> u16 {
+    match name {
+        "A" => u16::from(cpu.alu.accumulator()),
+        "BC" => u16::from(cpu.reg_array.read_reg(Register::BC)),
+        "DE" => u16::from(cpu.reg_array.read_reg(Register::DE)),
+        "HL" => u16::from(cpu.reg_array.read_reg(Register::HL)),
+        "SP" => u16::from(cpu.reg_array.read_reg(Register::SP)),
+        _ => read_pc(cpu),
+    }
+}
+
+// the F register, worked out from the flags
+fn read_f(cpu: &Cpu) -> u8 {
+    let flags = cpu.alu.flags();
+
+    [
+        (flags.carry, 0x01),
+        (flags.parity, 0x04),
+        (flags.aux_carry, 0x10),
+        (flags.zero, 0x40),
+        (flags.sign, 0x80),
+    ]
+    .iter()
+    .filter(|(set, _)| *set)
+    .fold(0x02, |f, (_, bit)| f | bit)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // runs the commands, returns the output
+    fn session(monitor: &mut Monitor, cpu: &mut Cpu, commands: &str) -> String {
+        let mut output = Vec::new();
+        monitor
+            .run(cpu, &mut commands.as_bytes(), &mut output)
+            .unwrap();
+        String::from_utf8(output).unwrap()
+    }
+
+    #[test]
+    fn monitor_commands() {
+        let mut cpu = Cpu::new();
+        let mut monitor = Monitor::new();
+        monitor.symbols = [("LOOP", 0x0105)].into_iter().collect();
+
+        let output = session(
+            &mut monitor,
+            &mut cpu,
+            "A100\nLXI H,2000H\nMVI A,41H\nINR A\nMOV M,A\nJMP .LOOP\n\nL100,105\nG100,.LOOP\nT2\nXA\n5A\nX\nD2000,2001\nS2001,42,43\nS2002\n\n44\n.\nD2000,2003\nZ\nQ\nD\n",
+        );
+
+        let expected = [
+            "-0100 0103 0105 0106 0107 010A -0100  21 00 20  LXI H,2000H",
+            "0103  3E 41     MVI A,41H",
+            "LOOP:",
+            "0105  3C        INR A",
+            "-*0105 (LOOP)",
+            "-C0Z0M0E0I0 A=41 B=0000 D=0000 H=2000 S=0000 P=0105 INR A",
+            "C0Z0M0E1I0 A=42 B=0000 D=0000 H=2000 S=0000 P=0106 MOV M,A",
+            "*0107 (LOOP+2)",
+            "-A=42 -C0Z0M0E1I0 A=5A B=0000 D=0000 H=2000 S=0000 P=0107 JMP LOOP",
+            "-2000  42 00                                            B.",
+            "--2002 43 2003 00 2004 00 -2000  42 42 43 44                                      BBCD",
+            "-?Unknown command Z",
+            "-",
+        ];
+        assert_eq!(output, expected.join("\n"));
+    }
+
+    #[test]
+    fn monitor_errors() {
+        let mut cpu = Cpu::new();
+        let mut monitor = Monitor::new();
+
+        // malformed commands are reported and the session carries on, as do
+        // bad lines typed into A and bad values given to S and X
+        let output = session(
+            &mut monitor,
+            &mut cpu,
+            "D1,2,3\nLXYZ\nG.NOWHERE\nT1,2\nS\nS100,1FF\nXQ\nXC\n2\nR\nW\nWOUT,5,4\nA100\nMVI A\nNOP\n.\n?\nQ\n",
+        );
+
+        let expected = [
+            "-?Expected D[s][,e]",
+            "-?Invalid number XYZ",
+            "-?Unknown symbol NOWHERE",
+            "-?Expected T[n] or U[n]",
+            "-?Expected S[s][,b...]",
+            "-?1FF is not a byte",
+            "-?Unknown register Q",
+            "-C=0 ?Flags are 0 or 1",
+            "-?No file named, use I first",
+            "-?Expected W[file],s,e",
+            "-?Expected W[file],s,e",
+            "-0100 ?<source>:2: Invalid operands for MVI: A",
+            "0100 0101 -?Unknown command ?",
+            "-",
+        ];
+        assert_eq!(output, expected.join("\n"));
+        assert_eq!(read_byte(&cpu, 0x0100), Ok(0x00));
+    }
+}
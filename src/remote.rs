@@ -0,0 +1,599 @@
+/*
+ * remote.rs - Contains code related to remote control, which lets scripts
+ * and test frameworks in other languages drive a Cpu over a line-based JSON
+ * protocol. each request is a line with a "cmd" and its arguments, e.g.
+ *   {"id":1,"cmd":"load","path":"prog.com","addr":"0x100"}
+ *   {"id":2,"cmd":"run","until":"DONE"}
+ * and each response is a line with the same "id", "ok" and the results, or
+ * "error" saying what went wrong
+ */
+use crate::cpu::expression::Expression;
+use crate::cpu::memory::*;
+use crate::cpu::registers::*;
+use crate::cpu::*;
+use crate::json::Json;
+use crate::loader;
+use crate::symbols::SymbolTable;
+use crate::terminal::Console;
+
+use std::collections::VecDeque;
+use std::io::{BufRead, Read, Write};
+use std::sync::{Arc, Mutex};
+
+// how long run can go when no budget is given, so a program that never
+// halts can't hang the script
+const RUN_BUDGET: Budget = Budget::Cycles(100_000_000);
+
+// the registers reported and saved, by the names set_register knows them by
+const REGISTERS: [(&str, Register); 12] = [
+    ("B", Register::B),
+    ("C", Register::C),
+    ("D", Register::D),
+    ("E", Register::E),
+    ("H", Register::H),
+    ("L", Register::L),
+    ("BC", Register::BC),
+    ("DE", Register::DE),
+    ("HL", Register::HL),
+    ("SP", Register::SP),
+    ("PC", Register::PC),
+    ("PSW", Register::PSW),
+];
+
+// the registers that make up the saved state
+const STATE_REGISTERS: [&str; 6] = ["PSW", "BC", "DE", "HL", "SP", "PC"];
+
+// the flags, and their bits in the F register
+const FLAGS: [(&str, u8); 5] = [
+    ("S", 0x80),
+    ("Z", 0x40),
+    ("AC", 0x10),
+    ("P", 0x04),
+    ("CY", 0x01),
+];
+
+// ConsoleInput struct - console input injected by the input command, which
+// the program reads instead of the host's stdin. reads would block once it
+// runs out, which pauses the program until more is queued
+#[derive(Clone, Default)]
+pub struct ConsoleInput(Arc<Mutex<VecDeque<u8>>>);
+
+impl ConsoleInput {
+    // queues bytes for the program to read
+    pub fn push(&self, data: &[u8]) {
+        self.0.lock().unwrap().extend(data);
+    }
+}
+
+impl Console for ConsoleInput {
+    fn key_ready(&mut self) -> bool {
+        !self.0.lock().unwrap().is_empty()
+    }
+}
+
+impl Read for ConsoleInput {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let mut queue = self.0.lock().unwrap();
+        if queue.is_empty() && !buf.is_empty() {
+            return Err(std::io::ErrorKind::WouldBlock.into());
+        }
+
+        let n = buf.len().min(queue.len());
+
+        for (byte, value) in buf.iter_mut().zip(queue.drain(..n)) {
+            *byte = value;
+        }
+        Ok(n)
+    }
+}
+
+// Remote struct - a remote control session on a Cpu
+pub struct Remote {
+    pub symbols: SymbolTable,
+    pub console: ConsoleInput,
+    output: Arc<Mutex<Vec<u8>>>, // console output not yet fetched
+    initial: Option<Json>,       // state reset goes back to
+}
+
+impl Default for Remote {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Remote {
+    // creates a new instance of Remote
+    pub fn new() -> Self {
+        Self {
+            symbols: SymbolTable::new(),
+            console: ConsoleInput::default(),
+            output: Arc::new(Mutex::new(Vec::new())),
+            initial: None,
+        }
+    }
+
+    // serves requests until the input ends or a quit command. the console
+    // output of the program (port 0) is kept for the output command, and
+    // reset goes back to the state the Cpu is in now
+    pub fn serve(
+        &mut self,
+        cpu: &mut Cpu,
+        input: &mut dyn BufRead,
+        output: &mut dyn Write,
+    ) -> Result<(), String> {
+        self.capture_output(cpu);
+        self.initial = Some(save_state(cpu)?);
+
+        for line in input.lines() {
+            let line = line.map_err(|e| e.to_string())?;
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            let (response, quit) = match Json::parse(&line) {
+                Ok(request) => {
+                    let result = self.execute(cpu, &request);
+                    (
+                        respond(&request, result),
+                        request["cmd"].as_str() == Some("quit"),
+                    )
+                }
+                Err(e) => (respond(&Json::Null, Err(e)), false),
+            };
+
+            writeln!(output, "{response}")
+                .and_then(|_| output.flush())
+                .map_err(|e| e.to_string())?;
+
+            if quit {
+                break;
+            }
+        }
+
+        Ok(())
+    }
+
+    // keeps the console output of the program (port 0) for the output command
+    fn capture_output(&mut self, cpu: &mut Cpu) {
+        let console = Arc::clone(&self.output);
+        cpu.set_port_handler_fn(move |port, value| {
+            if let (Ok(0), Ok(value)) = (u8::try_from(port), u8::try_from(value)) {
+                console.lock().unwrap().push(value);
+            }
+        });
+    }
+
+    // executes a request, returns the results to add to the response
+    pub fn execute(&mut self, cpu: &mut Cpu, request: &Json) -> Result<Json, String> {
+        match request["cmd"].as_str().ok_or("Requests need a cmd")? {
+            "load" => self.load(cpu, request),
+            "reset" => {
+                let initial = self.initial.as_ref().ok_or("Nothing to reset to")?;
+                restore_state(cpu, initial)?;
+                self.output.lock().unwrap().clear();
+                Ok(Json::object::<&str>([]))
+            }
+            "step" => {
+                let count = request["count"].as_i64().unwrap_or(1).max(0) as usize;
+                self.run(cpu, Budget::Instructions(count), None)
+            }
+            "run" => {
+                let budget = match (request["cycles"].as_i64(), request["instructions"].as_i64()) {
+                    (Some(cycles), _) => Budget::Cycles(cycles.max(0) as usize),
+                    (None, Some(n)) => Budget::Instructions(n.max(0) as usize),
+                    (None, None) => RUN_BUDGET,
+                };
+                let until = match request["until"].is_null() {
+                    true => None,
+                    false => Some(self.address(&request["until"])?),
+                };
+                self.run(cpu, budget, until)
+            }
+            "read" => {
+                let addr = self.address(&request["addr"])?;
+                let len = request["len"].as_i64().unwrap_or(1).clamp(0, 0x10000) as usize;
+                let data = (0..len)
+                    .map(|i| read_byte(cpu, addr.wrapping_add(i as u16)).map(Json::from))
+                    .collect::<Result<Vec<Json>, String>>()?;
+
+                Ok(Json::object([
+                    ("addr", Json::from(addr)),
+                    ("data", Json::Array(data)),
+                ]))
+            }
+            "write" => {
+                let addr = self.address(&request["addr"])?;
+                let data = bytes(&request["data"])?;
+                for (i, value) in data.iter().enumerate() {
+                    cpu.memory.write(
+                        RegisterValue::from(addr.wrapping_add(i as u16)),
+                        RegisterValue::from(*value),
+                    )?;
+                }
+
+                Ok(Json::object([("len", Json::from(data.len()))]))
+            }
+            "registers" => Ok(registers(cpu)),
+            "eval" => {
+                let text = request["expr"].as_str().ok_or("eval needs an expr")?;
+                let value = Expression::parse(text, &self.symbols)?.evaluate(cpu)?;
+                Ok(Json::object([("value", Json::from(value))]))
+            }
+            "set" => {
+                let Json::Object(values) = &request["registers"] else {
+                    return Err(String::from("set needs registers, e.g. {\"A\":1}"));
+                };
+                for (name, value) in values {
+                    cpu.set_register(name, self.address(value)?)?;
+                }
+
+                Ok(registers(cpu))
+            }
+            "input" => {
+                let data = bytes(&request["data"])?;
+                self.console.push(&data);
+                Ok(Json::object([("len", Json::from(data.len()))]))
+            }
+            "output" => {
+                let output = std::mem::take(&mut *self.output.lock().unwrap());
+                Ok(Json::object([(
+                    "output",
+                    Json::from(String::from_utf8_lossy(&output).into_owned()),
+                )]))
+            }
+            "save" => {
+                let state = save_state(cpu)?;
+                match request["path"].as_str() {
+                    Some(path) => {
+                        std::fs::write(path, state.to_string())
+                            .map_err(|e| format!("{path}: {e}"))?;
+                        Ok(Json::object([("path", Json::from(path))]))
+                    }
+                    None => Ok(Json::object([("state", state)])),
+                }
+            }
+            "restore" => {
+                let state = match request["path"].as_str() {
+                    Some(path) => Json::parse(
+                        &std::fs::read_to_string(path).map_err(|e| format!("{path}: {e}"))?,
+                    )?,
+                    None => request["state"].clone(),
+                };
+                restore_state(cpu, &state)?;
+                Ok(registers(cpu))
+            }
+            "quit" => Ok(Json::object::<&str>([])),
+            cmd => Err(format!("Unknown cmd {cmd}")),
+        }
+    }
+
+    // loads an image from a file or given as data. binaries go at addr,
+    // 0100H by default, and the program counter is set to the start of the
+    // image. reset then goes back to the state after the load
+    fn load(&mut self, cpu: &mut Cpu, request: &Json) -> Result<Json, String> {
+        let data = match request["path"].as_str() {
+            Some(path) => std::fs::read(path).map_err(|e| format!("{path}: {e}"))?,
+            None => bytes(&request["data"])?,
+        };
+        let addr = match request["addr"].is_null() {
+            true => 0x0100,
+            false => self.address(&request["addr"])?,
+        };
+
+        let start = loader::load_image(cpu, &data, addr)?.unwrap_or(addr);
+        cpu.set_pc(start)?;
+        cpu.running = true;
+        self.initial = Some(save_state(cpu)?);
+
+        Ok(Json::object([
+            ("start", Json::from(start)),
+            ("len", Json::from(data.len())),
+        ]))
+    }
+
+    // runs with a budget, or until an address is reached
+    fn run(&mut self, cpu: &mut Cpu, budget: Budget, until: Option<u16>) -> Result<Json, String> {
+        let cycles = cpu.total_cycles;
+        cpu.running = true;
+
+        let reason = match until {
+            Some(addr) => cpu.run_to(addr, budget)?,
+            None => cpu.run_until(budget, |_, _| false)?,
+        };
+
+        let mut result = Json::object([
+            (
+                "reason",
+                Json::from(match reason {
+                    StopReason::Breakpoint(_) => "breakpoint",
+                    StopReason::Halted => "halted",
+                    StopReason::Completed => "completed",
+                    StopReason::BudgetExhausted => "budget",
+                    StopReason::WaitingForInput => "input",
+                }),
+            ),
+            ("pc", Json::from(read_pc(cpu))),
+            ("cycles", Json::from(cpu.total_cycles - cycles)),
+        ]);
+        if let StopReason::Breakpoint(hit) = reason {
+            result.set("breakpoint", Json::from(hit.describe(&self.symbols)));
+        }
+        Ok(result)
+    }
+
+    // an address or value is a number, a string with a number in hex
+    // (0x100 or 100H) or decimal, or the name of a symbol
+    fn address(&self, value: &Json) -> Result<u16, String> {
+        if let Some(n) = value.as_i64() {
+            return u16::try_from(n).map_err(|_| format!("{n} doesn't fit in 16 bits"));
+        }
+
+        let text = value.as_str().ok_or("Expected an address or value")?.trim();
+        if let Some(addr) = self.symbols.address_of(text) {
+            return Ok(addr);
+        }
+
+        let invalid = || format!("Invalid address {text}");
+        if let Some(hex) = text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+            u16::from_str_radix(hex, 16).map_err(|_| invalid())
+        } else if let Some(hex) = text.strip_suffix(['h', 'H']) {
+            u16::from_str_radix(hex, 16).map_err(|_| invalid())
+        } else {
+            text.parse().map_err(|_| invalid())
+        }
+    }
+}
+
+// builds a response to a request, echoing its id
+fn respond(request: &Json, result: Result<Json, String>) -> Json {
+    let mut response = Json::object([("id", request["id"].clone())]);
+
+    match result {
+        Ok(Json::Object(pairs)) => {
+            response.set("ok", Json::from(true));
+            for (key, value) in pairs {
+                response.set(&key, value);
+            }
+        }
+        Ok(_) => response.set("ok", Json::from(true)),
+        Err(e) => {
+            response.set("ok", Json::from(false));
+            response.set("error", Json::from(e));
+        }
+    }
+
+    response
+}
+
+// bytes are given as an array of numbers, or a string whose characters are
+// used as they are
+fn bytes(value: &Json) -> Result<Vec<u8>, String> {
+    match value {
+        Json::String(s) => Ok(s.as_bytes().to_vec()),
+        Json::Array(values) => values
+            .iter()
+            .map(|value| {
+                value
+                    .as_i64()
+                    .and_then(|n| u8::try_from(n).ok())
+                    .ok_or_else(|| format!("Invalid byte {value}"))
+            })
+            .collect(),
+        _ => Err(String::from(
+            "Expected data as an array of bytes or a string",
+        )),
+    }
+}
+
+// the registers, A and the flags by name, and the cycles run so far
+fn registers(cpu: &Cpu) -> Json {
+    let psw = u16::from(cpu.reg_array.read_reg(Register::PSW));
+
+    let mut registers = vec![("A", Json::from(psw >> 8))];
+    registers.extend(REGISTERS.iter().map(|(name, register)| {
+        (
+            *name,
+            Json::from(u16::from(cpu.reg_array.read_reg(*register))),
+        )
+    }));
+
+    let flags = FLAGS
+        .iter()
+        .map(|(name, bit)| (*name, Json::from(psw as u8 & bit != 0)));
+
+    Json::object([
+        ("registers", Json::object(registers)),
+        ("flags", Json::object(flags)),
+        ("cycles", Json::from(cpu.total_cycles)),
+        ("halted", Json::from(!cpu.running)),
+    ])
+}
+
+// the state of a Cpu: the registers, the cycles run, whether it has halted
+// and the memory, as a hex string
+pub fn save_state(cpu: &Cpu) -> Result<Json, String> {
+    let mut memory = String::with_capacity(0x20000);
+    for addr in 0..=0xFFFF {
+        memory.push_str(&format!("{:02X}", read_byte(cpu, addr)?));
+    }
+
+    let registers = REGISTERS
+        .iter()
+        .filter(|(name, _)| STATE_REGISTERS.contains(name))
+        .map(|(name, register)| {
+            (
+                *name,
+                Json::from(u16::from(cpu.reg_array.read_reg(*register))),
+            )
+        });
+
+    Ok(Json::object([
+        ("registers", Json::object(registers)),
+        ("interrupts_enabled", Json::from(cpu.interrupts_enabled)),
+        ("halted", Json::from(!cpu.running)),
+        ("cycles", Json::from(cpu.total_cycles)),
+        ("memory", Json::from(memory)),
+    ]))
+}
+
+// puts a Cpu back into a state made by save_state
+pub fn restore_state(cpu: &mut Cpu, state: &Json) -> Result<(), String> {
+    let memory = state["memory"].as_str().ok_or("State without memory")?;
+    if memory.len() != 0x20000 || !memory.is_ascii() {
+        return Err(String::from("State memory must be 64KB in hex"));
+    }
+
+    let data = (0..memory.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&memory[i..i + 2], 16))
+        .collect::<Result<Vec<u8>, _>>()
+        .map_err(|_| String::from("State memory must be 64KB in hex"))?;
+    cpu.load_to_memory(data, 0)?;
+
+    for name in STATE_REGISTERS {
+        let value = state["registers"][name]
+            .as_i64()
+            .and_then(|value| u16::try_from(value).ok())
+            .ok_or_else(|| format!("State without {name}"))?;
+        cpu.set_register(name, value)?;
+    }
+
+    cpu.interrupts_enabled = state["interrupts_enabled"].as_bool().unwrap_or(true);
+    cpu.running = !state["halted"].as_bool().unwrap_or(false);
+    cpu.total_cycles = state["cycles"].as_i64().unwrap_or(0).max(0) as usize;
+    Ok(())
+}
+
+fn read_byte(cpu: &Cpu, addr: u16) -> Result<u8, String> {
+    let value = cpu
+        .memory
+        .read(RegisterValue::from(addr), MemorySize::Integer8)?;
+    Ok(u8::try_from(value)?)
+}
+
+fn read_pc(cpu: &Cpu) -> u16 {
+    u16::from(cpu.reg_array.read_reg(Register::PC))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn remote_session() {
+        // LXI H,0200H; LOOP: MOV A,M; ORA A; JZ DONE; OUT 0; INX H;
+        // JMP LOOP; DONE: HLT, which outputs the string at 0200H
+        let requests = [
+            r#"{"id":1,"cmd":"load","data":[33,0,2,126,183,202,14,1,211,0,35,195,3,1,118]}"#,
+            r#"{"id":2,"cmd":"write","addr":"200H","data":"HI"}"#,
+            r#"{"id":3,"cmd":"step","count":2}"#,
+            r#"{"id":4,"cmd":"run","until":"0x10E"}"#,
+            r#"{"id":5,"cmd":"output"}"#,
+            r#"{"id":6,"cmd":"set","registers":{"A":"0x41","HL":"DATA"}}"#,
+            r#"{"id":7,"cmd":"read","addr":512,"len":3}"#,
+            r#"{"id":8,"cmd":"reset"}"#,
+            r#"{"id":9,"cmd":"registers"}"#,
+            r#"{"id":10,"cmd":"bogus"}"#,
+            r#"{"id":11,"cmd":"eval","expr":"PC == 0x100 && word[DATA] == 0"}"#,
+            r#"{"id":12,"cmd":"eval","expr":"HL +"}"#,
+            r#"{"id":13,"cmd":"quit"}"#,
+            r#"{"id":14,"cmd":"registers"}"#,
+        ];
+
+        let mut remote = Remote::new();
+        remote.symbols = [("DATA", 0x0200)].into_iter().collect();
+
+        let mut cpu = Cpu::new();
+        let mut output = Vec::new();
+        let input = requests.join("\n");
+        remote
+            .serve(&mut cpu, &mut input.as_bytes(), &mut output)
+            .unwrap();
+
+        let responses: Vec<Json> = String::from_utf8(output)
+            .unwrap()
+            .lines()
+            .map(|line| Json::parse(line).unwrap())
+            .collect();
+
+        // nothing is answered after quit
+        assert_eq!(responses.len(), 13);
+        assert_eq!(responses[0]["start"].as_i64(), Some(0x0100));
+        assert_eq!(responses[2]["pc"].as_i64(), Some(0x0104));
+        assert_eq!(responses[3]["reason"].as_str(), Some("completed"));
+        assert_eq!(responses[4]["output"].as_str(), Some("HI"));
+        assert_eq!(responses[5]["registers"]["A"].as_i64(), Some(0x41));
+        assert_eq!(responses[5]["registers"]["HL"].as_i64(), Some(0x0200));
+        assert_eq!(responses[6]["data"], Json::parse("[72,73,0]").unwrap());
+
+        // reset goes back to just after the load, before HI was written
+        assert_eq!(responses[8]["registers"]["PC"].as_i64(), Some(0x0100));
+        assert_eq!(responses[8]["cycles"].as_i64(), Some(0));
+        assert_eq!(read_byte(&cpu, 0x0200).unwrap(), 0);
+
+        assert_eq!(responses[9]["ok"], Json::from(false));
+        assert_eq!(responses[9]["error"].as_str(), Some("Unknown cmd bogus"));
+        assert_eq!(responses[10]["value"].as_i64(), Some(1));
+        assert_eq!(
+            responses[11]["error"].as_str(),
+            Some("Expected a value at column 5, found the end")
+        );
+    }
+
+    #[test]
+    fn remote_errors() {
+        // bad requests are answered with an error and the session carries on
+        let requests = [
+            r#"{"id":1,"cmd":"step""#,
+            r#"[1,2]"#,
+            r#"{"id":3}"#,
+            r#"{"id":4,"cmd":5}"#,
+            r#"{"id":5,"cmd":"Step"}"#,
+            r#"{"id":6,"cmd":"read","addr":"0x10000"}"#,
+            r#"{"id":7,"cmd":"write","addr":"NOWHERE","data":[1]}"#,
+            r#"{"id":8,"cmd":"input","data":[256]}"#,
+            r#"{"id":9,"cmd":"set","registers":{"Q":1}}"#,
+            r#"{"id":10,"cmd":"eval"}"#,
+            r#"{"id":11,"cmd":"registers"}"#,
+        ];
+
+        let mut remote = Remote::new();
+        let mut cpu = Cpu::new();
+        let mut output = Vec::new();
+        let input = requests.join("\n");
+        remote
+            .serve(&mut cpu, &mut input.as_bytes(), &mut output)
+            .unwrap();
+
+        let responses: Vec<Json> = String::from_utf8(output)
+            .unwrap()
+            .lines()
+            .map(|line| Json::parse(line).unwrap())
+            .collect();
+        assert_eq!(responses.len(), requests.len());
+
+        let errors: Vec<(Option<i64>, &str)> = responses[..10]
+            .iter()
+            .map(|response| {
+                assert_eq!(response["ok"], Json::from(false));
+                (response["id"].as_i64(), response["error"].as_str().unwrap())
+            })
+            .collect();
+        assert_eq!(
+            errors,
+            [
+                (None, "Expected ',' or '}' at character 21"),
+                (None, "Requests need a cmd"),
+                (Some(3), "Requests need a cmd"),
+                (Some(4), "Requests need a cmd"),
+                (Some(5), "Unknown cmd Step"),
+                (Some(6), "Invalid address 0x10000"),
+                (Some(7), "Invalid address NOWHERE"),
+                (Some(8), "Invalid byte 256"),
+                (Some(9), "Unknown register: Q"),
+                (Some(10), "eval needs an expr"),
+            ]
+        );
+        assert_eq!(responses[10]["ok"], Json::from(true));
+    }
+}
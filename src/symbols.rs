@@ -0,0 +1,305 @@
+/*
+ * symbols.rs - Contains code related to symbol tables, which give names to
+ * addresses. they can be loaded from the .SYM files written by RMAC, M80 and
+ * LINK, the .MAP files written by L80, or simple "label = addr" files
+ */
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::path::Path;
+
+// furthest an address can be past a symbol and still be shown relative to it
+const MAX_OFFSET: u16 = 0x100;
+
+// SymbolFormat enum - the formats a symbol table can be stored in
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum SymbolFormat {
+    Sym,         // "0100 START" pairs, several to a line
+    Map,         // "START 0100" pairs, several to a line
+    Assignments, // "START = 0100H" or "START EQU 0100H", one to a line
+}
+
+// SymbolTable struct - symbols, looked up by address or by name
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SymbolTable {
+    addrs: BTreeMap<u16, Vec<String>>,
+    names: HashMap<String, u16>, // upper case
+}
+
+impl SymbolTable {
+    // creates a new empty instance of SymbolTable
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // loads a symbol table from a file, the format is worked out from the
+    // extension or otherwise from the contents
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let data =
+            std::fs::read(path).map_err(|e| format!("Could not read {}: {e}", path.display()))?;
+        let text = String::from_utf8_lossy(&data);
+
+        let extension = path
+            .extension()
+            .map(|extension| extension.to_string_lossy().to_lowercase());
+        let format = match extension.as_deref() {
+            Some("sym") => SymbolFormat::Sym,
+            Some("map") => SymbolFormat::Map,
+            _ => detect_format(&text),
+        };
+
+        Self::parse(&text, format).map_err(|e| format!("{}: {e}", path.display()))
+    }
+
+    // parses a symbol table in the given format
+    pub fn parse(text: &str, format: SymbolFormat) -> Result<Self, String> {
+        let mut table = Self::new();
+
+        // CP/M text files end with ^Z
+        let text = text.split('\x1A').next().unwrap_or_default();
+
+        for (i, line) in text.lines().enumerate() {
+            let tokens: Vec<&str> = line.split_whitespace().collect();
+
+            match format {
+                SymbolFormat::Sym => {
+                    for pair in tokens.chunks(2) {
+                        match pair {
+                            [addr, name] => match parse_hex(addr) {
+                                Some(addr) => table.insert(name, addr),
+                                None => {
+                                    return Err(format!("Line {}: Invalid address {addr}", i + 1))
+                                }
+                            },
+                            _ => return Err(format!("Line {}: Address without a name", i + 1)),
+                        }
+                    }
+                }
+
+                // L80 also prints the segment sizes and memory left, which
+                // aren't pairs of a name and an address
+                SymbolFormat::Map => {
+                    if line.contains(['<', '[']) {
+                        continue;
+                    }
+
+                    let mut tokens = tokens.iter().peekable();
+                    while let Some(name) = tokens.next() {
+                        let addr = tokens
+                            .peek()
+                            .and_then(|addr| parse_hex(addr.trim_end_matches(['\'', '"', '*'])));
+
+                        if let (Some(addr), true) = (addr, is_name(name)) {
+                            table.insert(name, addr);
+                            tokens.next();
+                        }
+                    }
+                }
+
+                SymbolFormat::Assignments => {
+                    let line = line.split(';').next().unwrap_or_default();
+                    if line.trim().is_empty() {
+                        continue;
+                    }
+
+                    let (name, value) = match tokens[..] {
+                        [name, "=", value] => (name, value),
+                        [name, equ, value] if equ.eq_ignore_ascii_case("EQU") => (name, value),
+                        _ => match line.split_once('=') {
+                            Some((name, value)) => (name.trim(), value.trim()),
+                            None => return Err(format!("Line {}: Expected label = addr", i + 1)),
+                        },
+                    };
+
+                    let name = name.trim_end_matches(':');
+                    let addr = parse_value(value)
+                        .ok_or_else(|| format!("Line {}: Invalid address {value}", i + 1))?;
+                    table.insert(name, addr);
+                }
+            }
+        }
+
+        Ok(table)
+    }
+
+    // adds a symbol, names are case-insensitive
+    pub fn insert(&mut self, name: &str, addr: u16) {
+        let name = name.to_ascii_uppercase();
+
+        // a symbol that is redefined moves to its new address
+        if let Some(old) = self.names.insert(name.clone(), addr) {
+            if let Some(names) = self.addrs.get_mut(&old) {
+                names.retain(|n| *n != name);
+                if names.is_empty() {
+                    self.addrs.remove(&old);
+                }
+            }
+        }
+
+        self.addrs.entry(addr).or_default().push(name);
+    }
+
+    // adds every symbol of another table
+    pub fn extend(&mut self, other: &SymbolTable) {
+        for (name, addr) in &other.names {
+            self.insert(name, *addr);
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.names.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.names.is_empty()
+    }
+
+    // returns the address of a symbol
+    pub fn address_of(&self, name: &str) -> Option<u16> {
+        self.names.get(&name.to_ascii_uppercase()).copied()
+    }
+
+    // returns the first symbol defined at an address
+    pub fn name_at(&self, addr: u16) -> Option<&str> {
+        self.addrs
+            .get(&addr)
+            .and_then(|names| names.first())
+            .map(String::as_str)
+    }
+
+    // returns the closest symbol at or before an address, and how far past
+    // it the address is
+    pub fn lookup(&self, addr: u16) -> Option<(&str, u16)> {
+        let (symbol_addr, names) = self.addrs.range(..=addr).next_back()?;
+
+        let offset = addr - symbol_addr;
+        (offset < MAX_OFFSET).then(|| (names[0].as_str(), offset))
+    }
+
+    // describes an address relative to a symbol, e.g. "LOOP+2"
+    pub fn describe(&self, addr: u16) -> Option<String> {
+        match self.lookup(addr)? {
+            (name, 0) => Some(name.to_string()),
+            (name, offset) => Some(format!("{name}+{offset:X}")),
+        }
+    }
+
+    // formats an address with its symbol if there is one, e.g. "0105 (LOOP+2)"
+    pub fn format_addr(&self, addr: u16) -> String {
+        match self.describe(addr) {
+            Some(symbol) => format!("{addr:04X} ({symbol})"),
+            None => format!("{addr:04X}"),
+        }
+    }
+
+    // iterates over the symbols in order of address
+    pub fn iter(&self) -> impl Iterator<Item = (&str, u16)> {
+        self.addrs
+            .iter()
+            .flat_map(|(addr, names)| names.iter().map(|name| (name.as_str(), *addr)))
+    }
+}
+
+impl<S: AsRef<str>> FromIterator<(S, u16)> for SymbolTable {
+    fn from_iter<I: IntoIterator<Item = (S, u16)>>(iter: I) -> Self {
+        let mut table = Self::new();
+        for (name, addr) in iter {
+            table.insert(name.as_ref(), addr);
+        }
+        table
+    }
+}
+
+// writes the table in the .SYM format
+impl fmt::Display for SymbolTable {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (name, addr) in self.iter() {
+            writeln!(f, "{addr:04X} {name}")?;
+        }
+        Ok(())
+    }
+}
+
+// works out the format of a symbol table from its contents
+pub fn detect_format(text: &str) -> SymbolFormat {
+    let first = text.split_whitespace().next().unwrap_or_default();
+
+    if text.lines().any(|line| {
+        let line = line.to_ascii_uppercase();
+        line.contains('=') || line.split_whitespace().nth(1) == Some("EQU")
+    }) {
+        SymbolFormat::Assignments
+    } else if parse_hex(first).is_some() {
+        SymbolFormat::Sym
+    } else {
+        SymbolFormat::Map
+    }
+}
+
+// parses an address of up to 4 hex digits, with no prefix or suffix
+fn parse_hex(s: &str) -> Option<u16> {
+    match s.len() {
+        1..=4 => u16::from_str_radix(s, 16).ok(),
+        _ => None,
+    }
+}
+
+// parses an address given as hex, with an optional 0x prefix or H suffix
+fn parse_value(s: &str) -> Option<u16> {
+    let digits = s.strip_prefix("0x").unwrap_or(s);
+    let digits = digits.strip_suffix(['h', 'H']).unwrap_or(digits);
+    u16::from_str_radix(digits, 16).ok()
+}
+
+// returns whether a token can be a symbol name
+fn is_name(s: &str) -> bool {
+    s.chars()
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || matches!(c, '?' | '@' | '_' | '.' | '$'))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn symbols_parse() {
+        let sym = SymbolTable::parse(
+            "0100 START\t0105 LOOP\r\n0005 BDOS\r\n\x1A\x1A",
+            SymbolFormat::Sym,
+        )
+        .unwrap();
+        let map = SymbolTable::parse(
+            "Data    0103    01A0    <   157>\n\nSTART   0100    LOOP    0105'\nBDOS    0005\n",
+            SymbolFormat::Map,
+        )
+        .unwrap();
+        let assignments = SymbolTable::parse(
+            "start = 0100H ; entry\nLOOP EQU 105H\nBDOS=0x0005\n",
+            SymbolFormat::Assignments,
+        )
+        .unwrap();
+
+        assert_eq!(sym, map);
+        assert_eq!(sym, assignments);
+        assert_eq!(sym.len(), 3);
+
+        assert_eq!(detect_format("0100 START"), SymbolFormat::Sym);
+        assert_eq!(detect_format("START 0100"), SymbolFormat::Map);
+        assert_eq!(detect_format("START = 100H"), SymbolFormat::Assignments);
+
+        assert!(SymbolTable::parse("0100", SymbolFormat::Sym).is_err());
+    }
+
+    #[test]
+    fn symbols_lookup() {
+        let table: SymbolTable = [("START", 0x0100), ("LOOP", 0x0105)].into_iter().collect();
+
+        assert_eq!(table.address_of("loop"), Some(0x0105));
+        assert_eq!(table.name_at(0x0100), Some("START"));
+        assert_eq!(table.describe(0x0107), Some(String::from("LOOP+2")));
+        assert_eq!(table.describe(0x00FF), None);
+        assert_eq!(table.describe(0x0300), None);
+        assert_eq!(table.format_addr(0x0100), "0100 (START)");
+        assert_eq!(table.to_string(), "0100 START\n0105 LOOP\n");
+    }
+}
@@ -0,0 +1,533 @@
+/*
+ * terminal.rs - Contains code related to translating between the terminals
+ * that CP/M software expects (ADM-3A, VT52, Kaypro) and the ANSI terminal
+ * the emulator runs in
+ */
+use std::collections::VecDeque;
+use std::io::{Cursor, Empty, IsTerminal, Read};
+use std::process::{Command, Stdio};
+use std::str::FromStr;
+use std::sync::mpsc::{self, Receiver, Sender, TryRecvError};
+use std::time::{Duration, Instant};
+
+const ESC: u8 = 0x1B;
+
+// how long after ESC the rest of a cursor key's sequence may take to arrive,
+// an ESC with nothing after it by then was typed on its own
+const ESC_TIMEOUT: Duration = Duration::from_millis(50);
+
+// TerminalType enum - the terminals whose control codes can be translated
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum TerminalType {
+    Adm3a,
+    Vt52,
+    Kaypro, // an ADM-3A with some extra escape sequences
+}
+
+impl FromStr for TerminalType {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "adm3a" | "adm-3a" => Ok(Self::Adm3a),
+            "vt52" => Ok(Self::Vt52),
+            "kaypro" => Ok(Self::Kaypro),
+            _ => Err(format!(
+                "Unknown terminal type: {s} (expected adm3a, vt52 or kaypro)"
+            )),
+        }
+    }
+}
+
+// OutputTranslator struct - converts the output of a program written for a
+// given terminal to ANSI escape sequences, one character at a time
+pub struct OutputTranslator {
+    terminal: TerminalType,
+    pending: Vec<u8>, // an incomplete escape sequence
+}
+
+impl OutputTranslator {
+    // creates a new instance of OutputTranslator for the given terminal
+    pub fn new(terminal: TerminalType) -> Self {
+        Self {
+            terminal,
+            pending: Vec::new(),
+        }
+    }
+
+    // translates a character written by the program, returns what should be
+    // written to the host terminal (nothing while an escape sequence is
+    // incomplete)
+    pub fn translate(&mut self, value: u8) -> Vec<u8> {
+        use TerminalType::*;
+
+        if self.pending.is_empty() {
+            return match (self.terminal, value) {
+                (_, ESC) => {
+                    self.pending.push(ESC);
+                    Vec::new()
+                }
+
+                (Adm3a | Kaypro, 0x0B) => ansi("[A"), // cursor up
+                (Adm3a | Kaypro, 0x0C) => ansi("[C"), // cursor right
+                (Adm3a | Kaypro, 0x1A) => ansi("[H\x1B[2J"), // clear screen
+                (Adm3a | Kaypro, 0x1E) => ansi("[H"), // home
+                (Kaypro, 0x17) => ansi("[J"),         // clear to end of screen
+                (Kaypro, 0x18) => ansi("[K"),         // clear to end of line
+                _ => vec![value],
+            };
+        }
+
+        self.pending.push(value);
+
+        match self.escape() {
+            Some(translated) => {
+                self.pending.clear();
+                translated
+            }
+            None => Vec::new(),
+        }
+    }
+
+    // translates the pending escape sequence, returns None if it isn't complete
+    // yet. sequences that aren't recognised are passed through unchanged
+    fn escape(&self) -> Option<Vec<u8>> {
+        use TerminalType::*;
+
+        let translated = match (self.terminal, &self.pending[1..]) {
+            // cursor addressing, the row and column are offset by 32
+            (Adm3a | Kaypro, [b'=']) | (Adm3a | Kaypro, [b'=', _]) => return None,
+            (Vt52, [b'Y']) | (Vt52, [b'Y', _]) => return None,
+            (Adm3a | Kaypro, [b'=', row, col]) | (Vt52, [b'Y', row, col]) => {
+                format!("\x1B[{};{}H", row.wrapping_sub(31), col.wrapping_sub(31)).into_bytes()
+            }
+
+            // Kaypro attributes, 0 is reverse video, 1 half intensity, 2 blink,
+            // 3 underline and 4 the cursor
+            (Kaypro, [b'B' | b'C']) => return None,
+            (Kaypro, [on_off @ (b'B' | b'C'), attribute]) => {
+                let on = *on_off == b'B';
+
+                match (attribute, on) {
+                    (b'0', true) => ansi("[7m"),
+                    (b'0', false) => ansi("[27m"),
+                    (b'1', true) => ansi("[2m"),
+                    (b'1', false) => ansi("[22m"),
+                    (b'2', true) => ansi("[5m"),
+                    (b'2', false) => ansi("[25m"),
+                    (b'3', true) => ansi("[4m"),
+                    (b'3', false) => ansi("[24m"),
+                    (b'4', true) => ansi("[?25h"),
+                    (b'4', false) => ansi("[?25l"),
+                    _ => Vec::new(),
+                }
+            }
+            (Kaypro, [b'E']) => ansi("[L"), // insert line
+            (Kaypro, [b'R']) => ansi("[M"), // delete line
+            (Kaypro, [b'T']) => ansi("[K"), // clear to end of line
+            (Kaypro, [b'Y']) => ansi("[J"), // clear to end of screen
+
+            // VT52, along with the extensions of the Heathkit H19
+            (Vt52, [b'A']) => ansi("[A"),
+            (Vt52, [b'B']) => ansi("[B"),
+            (Vt52, [b'C']) => ansi("[C"),
+            (Vt52, [b'D']) => ansi("[D"),
+            (Vt52, [b'H']) => ansi("[H"),
+            (Vt52, [b'I']) => ansi("M"), // reverse line feed
+            (Vt52, [b'J']) => ansi("[J"),
+            (Vt52, [b'K']) => ansi("[K"),
+            (Vt52, [b'E']) => ansi("[H\x1B[2J"),
+            (Vt52, [b'L']) => ansi("[L"),
+            (Vt52, [b'M']) => ansi("[M"),
+            (Vt52, [b'p']) => ansi("[7m"),
+            (Vt52, [b'q']) => ansi("[27m"),
+            (Vt52, [b'F' | b'G' | b'=' | b'>' | b'Z']) => Vec::new(),
+            (Vt52, [b'x' | b'y']) => return None,
+            (Vt52, [b'x' | b'y', _]) => Vec::new(),
+
+            _ => self.pending.clone(),
+        };
+
+        Some(translated)
+    }
+}
+
+// InputTranslator struct - wraps the keyboard input, converting the ANSI
+// sequences sent by the cursor keys to the codes of the given terminal, and
+// the LF the host terminal sends for Enter to the CR CP/M software expects
+pub struct InputTranslator<R: Read> {
+    input: R,
+    terminal: TerminalType,
+    translated: VecDeque<u8>, // translated input that hasn't been read yet
+}
+
+impl<R: Console> InputTranslator<R> {
+    // creates a new instance of InputTranslator reading from the given input
+    pub fn new(input: R, terminal: TerminalType) -> Self {
+        Self {
+            input,
+            terminal,
+            translated: VecDeque::new(),
+        }
+    }
+
+    // reads a single byte of untranslated input
+    fn next_byte(&mut self) -> std::io::Result<Option<u8>> {
+        let mut buf = [0u8];
+
+        match self.input.read(&mut buf)? {
+            0 => Ok(None),
+            _ => Ok(Some(buf[0])),
+        }
+    }
+
+    // returns whether another byte of input arrives within ESC_TIMEOUT
+    fn key_follows(&mut self) -> bool {
+        let start = Instant::now();
+
+        while !self.input.key_ready() {
+            if start.elapsed() >= ESC_TIMEOUT {
+                return false;
+            }
+            std::thread::sleep(Duration::from_millis(5));
+        }
+        true
+    }
+
+    // reads and translates the next key, returns false at the end of the input
+    fn translate_key(&mut self) -> std::io::Result<bool> {
+        let Some(value) = self.next_byte()? else {
+            return Ok(false);
+        };
+
+        if value == b'\n' {
+            self.translated.push_back(b'\r');
+            return Ok(true);
+        }
+
+        if value != ESC {
+            self.translated.push_back(value);
+            return Ok(true);
+        }
+
+        // the escape key on its own sends just ESC, which is passed on
+        // rather than waiting for the next key
+        if !self.key_follows() {
+            self.translated.push_back(ESC);
+            return Ok(true);
+        }
+
+        let second = match self.next_byte()? {
+            Some(second @ (b'[' | b'O')) => second,
+            Some(other) => {
+                self.translated.extend([ESC, other]);
+                return Ok(true);
+            }
+            None => {
+                self.translated.push_back(ESC);
+                return Ok(true);
+            }
+        };
+
+        // control sequences end with a byte from 0x40 to 0x7E
+        let mut sequence = vec![second];
+        while let Some(value) = self.next_byte()? {
+            sequence.push(value);
+
+            if (0x40..=0x7E).contains(&value) && sequence.len() > 1 {
+                break;
+            }
+        }
+
+        let key = match sequence.last() {
+            Some(b'A') => Some((0x0B, b'A')), // up
+            Some(b'B') => Some((0x0A, b'B')), // down
+            Some(b'C') => Some((0x0C, b'C')), // right
+            Some(b'D') => Some((0x08, b'D')), // left
+            _ => None,
+        };
+
+        match (key, self.terminal) {
+            (Some((code, _)), TerminalType::Adm3a | TerminalType::Kaypro) => {
+                self.translated.push_back(code)
+            }
+            (Some((_, letter)), TerminalType::Vt52) => self.translated.extend([ESC, letter]),
+
+            // the delete key deletes the character under the cursor in most
+            // CP/M editors
+            (None, _) if sequence == b"[3~" => self.translated.push_back(0x7F),
+
+            // other keys have no equivalent, and are dropped
+            (None, _) => {}
+        }
+
+        Ok(true)
+    }
+}
+
+impl<R: Console> Read for InputTranslator<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        // keys that translate to nothing are skipped
+        while self.translated.is_empty() {
+            if !self.translate_key()? {
+                return Ok(0);
+            }
+        }
+
+        let len = buf.len().min(self.translated.len());
+        for (dest, value) in buf.iter_mut().zip(self.translated.drain(..len)) {
+            *dest = value;
+        }
+
+        Ok(len)
+    }
+}
+
+// Console trait - console input that can also say whether a key is waiting,
+// so a program can poll the keyboard without blocking. input that is always
+// there, or already at its end, is always ready
+pub trait Console: Read + Send {
+    // returns whether a read would return without blocking
+    fn key_ready(&mut self) -> bool {
+        true
+    }
+}
+
+impl Console for &'static [u8] {}
+impl<T: AsRef<[u8]> + Send> Console for Cursor<T> {}
+impl Console for Empty {}
+
+impl<R: Console> Console for InputTranslator<R> {
+    fn key_ready(&mut self) -> bool {
+        !self.translated.is_empty() || self.input.key_ready()
+    }
+}
+
+// Keyboard struct - the host's stdin, read a byte at a time on another thread
+// so that whether a key has been typed can be checked without blocking. the
+// thread is only started by the first read, and only reads a byte when asked
+// to, so nothing is taken from stdin that the program hasn't asked for
+#[derive(Default)]
+pub struct Keyboard {
+    requests: Option<Sender<()>>,
+    keys: Option<Receiver<u8>>,
+    requested: bool,     // a byte has been asked for, and not received yet
+    pending: Option<u8>, // a byte received by key_ready, and not read yet
+}
+
+impl Keyboard {
+    // creates a new instance of Keyboard, without starting its thread yet
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // asks the thread for the next byte of stdin, if it hasn't been already
+    fn request(&mut self) -> &Receiver<u8> {
+        if self.keys.is_none() {
+            let (request_sender, requests) = mpsc::channel::<()>();
+            let (key_sender, keys) = mpsc::channel();
+
+            std::thread::spawn(move || {
+                let mut buf = [0u8];
+                while requests.recv().is_ok() {
+                    // the end of stdin closes the channel
+                    match std::io::stdin().read(&mut buf) {
+                        Ok(1) if key_sender.send(buf[0]).is_ok() => {}
+                        _ => return,
+                    }
+                }
+            });
+
+            self.requests = Some(request_sender);
+            self.keys = Some(keys);
+        }
+
+        if !self.requested {
+            self.requested = true;
+            // a send only fails once the thread has stopped at the end of
+            // stdin, which the receiver reports as well
+            let _ = self.requests.as_ref().unwrap().send(());
+        }
+
+        self.keys.as_ref().unwrap()
+    }
+}
+
+impl Read for Keyboard {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        let key = match self.pending.take() {
+            Some(key) => Some(key),
+            None => self.request().recv().ok(),
+        };
+        self.requested = false;
+
+        match key {
+            Some(key) => {
+                buf[0] = key;
+                Ok(1)
+            }
+            None => Ok(0),
+        }
+    }
+}
+
+impl Console for Keyboard {
+    fn key_ready(&mut self) -> bool {
+        if self.pending.is_some() {
+            return true;
+        }
+
+        match self.request().try_recv() {
+            Ok(key) => {
+                self.pending = Some(key);
+                self.requested = false;
+                true
+            }
+            Err(TryRecvError::Empty) => false,
+            Err(TryRecvError::Disconnected) => true,
+        }
+    }
+}
+
+// RawMode struct - puts the host terminal into raw mode (no line editing or
+// echo) so that keys reach the program as they are typed, and restores the
+// previous settings when dropped
+pub struct RawMode {
+    saved: String, // settings as printed by `stty -g`
+}
+
+impl RawMode {
+    // enables raw mode, where every key including Ctrl-C is read as it is and
+    // output isn't processed. returns None if stdin isn't a terminal or it
+    // couldn't be changed
+    pub fn enable() -> Option<Self> {
+        Self::set(&["raw", "-echo"])
+    }
+
+    // enables cbreak mode, which only turns off line editing and echo. Enter
+    // still reads as LF, Ctrl-C still interrupts and LF is still output as
+    // CR LF. returns None if stdin isn't a terminal or it couldn't be changed
+    pub fn enable_cbreak() -> Option<Self> {
+        Self::set(&["-icanon", "-echo"])
+    }
+
+    fn set(args: &[&str]) -> Option<Self> {
+        if !std::io::stdin().is_terminal() {
+            return None;
+        }
+
+        let saved = stty(&["-g"])?;
+        stty(args)?;
+
+        Some(Self {
+            saved: saved.trim().to_string(),
+        })
+    }
+}
+
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        stty(&[&self.saved]);
+    }
+}
+
+// returns the size of the terminal connected to stdin, as (rows, columns)
+pub fn size() -> Option<(usize, usize)> {
+    let size = stty(&["size"])?;
+    let (rows, columns) = size.trim().split_once(' ')?;
+    Some((rows.parse().ok()?, columns.parse().ok()?))
+}
+
+// runs stty on the terminal connected to stdin, returns its output
+fn stty(args: &[&str]) -> Option<String> {
+    let output = Command::new("stty")
+        .args(args)
+        .stdin(Stdio::inherit())
+        .output()
+        .ok()?;
+
+    match output.status.success() {
+        true => Some(String::from_utf8_lossy(&output.stdout).into_owned()),
+        false => None,
+    }
+}
+
+// returns an ANSI escape sequence
+fn ansi(sequence: &str) -> Vec<u8> {
+    let mut bytes = vec![ESC];
+    bytes.extend(sequence.as_bytes());
+    bytes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // translates a whole string of output
+    fn translate(terminal: TerminalType, output: &[u8]) -> Vec<u8> {
+        let mut translator = OutputTranslator::new(terminal);
+        output
+            .iter()
+            .flat_map(|c| translator.translate(*c))
+            .collect()
+    }
+
+    #[test]
+    fn terminal_output_translation() {
+        // clear screen, then row 5 column 10
+        assert_eq!(
+            translate(TerminalType::Adm3a, b"\x1A\x1B=%*HI"),
+            b"\x1B[H\x1B[2J\x1B[6;11HHI"
+        );
+        assert_eq!(
+            translate(TerminalType::Vt52, b"\x1BY  \x1BK"),
+            b"\x1B[1;1H\x1B[K"
+        );
+        assert_eq!(
+            translate(TerminalType::Kaypro, b"\x1BB0X\x1BC0"),
+            b"\x1B[7mX\x1B[27m"
+        );
+
+        // unknown sequences are passed through
+        assert_eq!(translate(TerminalType::Adm3a, b"\x1B!"), b"\x1B!");
+    }
+
+    #[test]
+    fn terminal_input_translation() {
+        let keys = &b"a\x1B[A\x1B[D\x1Bx\x1B[3~\n"[..];
+
+        let mut translated = Vec::new();
+        InputTranslator::new(keys, TerminalType::Kaypro)
+            .read_to_end(&mut translated)
+            .unwrap();
+        assert_eq!(translated, b"a\x0B\x08\x1Bx\x7F\r");
+
+        let mut translated = Vec::new();
+        InputTranslator::new(keys, TerminalType::Vt52)
+            .read_to_end(&mut translated)
+            .unwrap();
+        assert_eq!(translated, b"a\x1BA\x1BD\x1Bx\x7F\r");
+
+        // ESC with nothing after it is passed on by itself, without waiting
+        // for the next key
+        let keys = crate::remote::ConsoleInput::default();
+        let mut translator = InputTranslator::new(keys.clone(), TerminalType::Adm3a);
+        let mut buf = [0u8];
+
+        keys.push(b"\x1B");
+        assert_eq!(translator.read(&mut buf).unwrap(), 1);
+        assert_eq!(buf, [ESC]);
+
+        keys.push(b"q");
+        assert_eq!(translator.read(&mut buf).unwrap(), 1);
+        assert_eq!(buf, [b'q']);
+    }
+}
@@ -0,0 +1,718 @@
+/*
+ * tui.rs - Contains code related to the terminal UI, a full-screen debugger
+ * drawn with ANSI escape sequences, for machines without a display. it has
+ * panes for the disassembly around the program counter, the registers and
+ * flags, the stack, a memory dump and the program's console output
+ */
+use crate::cpu::breakpoints::*;
+use crate::cpu::disassembler::*;
+use crate::cpu::expression::{format_value, Expression};
+use crate::cpu::memory::*;
+use crate::cpu::registers::*;
+use crate::cpu::*;
+use crate::remote::ConsoleInput;
+use crate::symbols::SymbolTable;
+use crate::terminal::{self, RawMode};
+
+use std::io::{Read, Write};
+use std::sync::mpsc::{self, Receiver};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+// cycles run between checks for keys while the program runs
+const RUN_SLICE: usize = 20_000;
+
+// how often the screen is redrawn while the program runs
+const REDRAW_INTERVAL: Duration = Duration::from_millis(50);
+
+// how long a step over or step out can run before it stops anyway
+const STEP_BUDGET: Budget = Budget::Cycles(50_000_000);
+
+// width of the registers and stack panes, to the right of the disassembly
+const RIGHT_WIDTH: usize = 30;
+
+const HELP: &str = "s step  n next  o out  r run  p break  b breakpoint  c condition  \u{2191}\u{2193} move  g goto  m memory  w watch  i input  q quit";
+
+// the flags, and their bits in the F register
+const FLAGS: [(&str, u8); 5] = [
+    ("S", 0x80),
+    ("Z", 0x40),
+    ("AC", 0x10),
+    ("P", 0x04),
+    ("CY", 0x01),
+];
+
+// Key enum - a key read from the terminal
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Key {
+    Char(char),
+    Up,
+    Down,
+    PageUp,
+    PageDown,
+    Enter,
+    Backspace,
+    Escape,
+    Interrupt, // ctrl-C
+}
+
+// Tui struct - the terminal UI debugger
+pub struct Tui {
+    pub symbols: SymbolTable,
+    pub syntax: Syntax,
+    pub output: Arc<Mutex<String>>, // console output of the program
+    pub console: ConsoleInput,      // console input typed with i
+    cursor: Option<u16>,            // disassembly cursor, None follows the pc
+    memory_addr: u16,
+    watches: Vec<Expression>,
+    running: bool,
+    message: String, // shown above the help line
+}
+
+impl Default for Tui {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Tui {
+    // creates a new instance of Tui
+    pub fn new() -> Self {
+        Self {
+            symbols: SymbolTable::new(),
+            syntax: Syntax::Intel,
+            output: Arc::new(Mutex::new(String::new())),
+            console: ConsoleInput::default(),
+            cursor: None,
+            memory_addr: 0x0100,
+            watches: Vec::new(),
+            running: false,
+            message: String::from("Stopped"),
+        }
+    }
+
+    // runs the debugger until q is pressed. the terminal is put into raw mode
+    // and switched to its alternate screen, and both are undone on the way
+    // out
+    pub fn run(&mut self, cpu: &mut Cpu) -> Result<(), String> {
+        let _raw_mode = RawMode::enable().ok_or("The terminal UI needs a terminal")?;
+
+        // keys are read on another thread, so a running program can be
+        // broken into
+        let (sender, keys) = mpsc::channel();
+        std::thread::spawn(move || {
+            let mut buf = [0u8; 64];
+            while let Ok(n @ 1..) = std::io::stdin().read(&mut buf) {
+                if buf[..n].iter().any(|byte| sender.send(*byte).is_err()) {
+                    break;
+                }
+            }
+        });
+
+        let mut stdout = std::io::stdout();
+        write!(stdout, "\x1b[?1049h\x1b[?25l").map_err(|e| e.to_string())?;
+        let result = self.event_loop(cpu, &keys, &mut stdout);
+        write!(stdout, "\x1b[?25h\x1b[?1049l")
+            .and_then(|_| stdout.flush())
+            .map_err(|e| e.to_string())?;
+
+        result
+    }
+
+    fn event_loop(
+        &mut self,
+        cpu: &mut Cpu,
+        keys: &Receiver<u8>,
+        stdout: &mut dyn Write,
+    ) -> Result<(), String> {
+        let mut last_draw = None;
+
+        loop {
+            if last_draw.is_none_or(|time: Instant| time.elapsed() >= REDRAW_INTERVAL) {
+                self.draw(cpu, stdout)?;
+                last_draw = Some(Instant::now());
+            }
+
+            if self.running {
+                match read_key(keys, false) {
+                    Some(key) => {
+                        if !self.key(cpu, key, keys, stdout)? {
+                            return Ok(());
+                        }
+                    }
+                    None => self.run_slice(cpu),
+                }
+
+                // show where the program stopped straight away
+                if !self.running {
+                    last_draw = None;
+                }
+                continue;
+            }
+
+            let Some(key) = read_key(keys, true) else {
+                return Ok(());
+            };
+            if !self.key(cpu, key, keys, stdout)? {
+                return Ok(());
+            }
+            last_draw = None;
+        }
+    }
+
+    // handles a key, returns whether the debugger carries on
+    pub fn key(
+        &mut self,
+        cpu: &mut Cpu,
+        key: Key,
+        keys: &Receiver<u8>,
+        stdout: &mut dyn Write,
+    ) -> Result<bool, String> {
+        // while the program runs, keys only break into it
+        if self.running {
+            if matches!(key, Key::Char('p') | Key::Escape | Key::Interrupt) {
+                self.running = false;
+                self.cursor = None;
+                self.message = format!("Break at {}", self.symbols.format_addr(read_pc(cpu)));
+            }
+            return Ok(!matches!(key, Key::Char('q')));
+        }
+
+        match key {
+            Key::Char('q') => return Ok(false),
+            Key::Char('s') => {
+                let result = cpu.run_until(Budget::Instructions(1), |_, _| true);
+                self.stopped(cpu, result);
+            }
+            Key::Char('n') => {
+                let result = cpu.step_over(STEP_BUDGET);
+                self.stopped(cpu, result);
+            }
+            Key::Char('o') => {
+                let result = cpu.step_out(STEP_BUDGET);
+                self.stopped(cpu, result);
+            }
+            Key::Char('r') => {
+                cpu.running = true;
+                self.running = true;
+                self.message = String::from("Running, p to break");
+            }
+            Key::Char('b') => {
+                let addr = self.cursor.unwrap_or(read_pc(cpu));
+                self.message = match cpu.breakpoints.at(addr).map(|breakpoint| breakpoint.id) {
+                    Some(id) => {
+                        cpu.breakpoints.remove(id)?;
+                        format!("Breakpoint {id} removed")
+                    }
+                    None => {
+                        let id = cpu.breakpoints.add(BreakpointKind::Execute(addr));
+                        format!("Breakpoint {id} at {}", self.symbols.format_addr(addr))
+                    }
+                };
+            }
+            Key::Char('c') => {
+                let addr = self.cursor.unwrap_or(read_pc(cpu));
+                let Some(id) = cpu.breakpoints.at(addr).map(|breakpoint| breakpoint.id) else {
+                    self.message = format!("No breakpoint at {}", self.symbols.format_addr(addr));
+                    return Ok(true);
+                };
+                if let Some(text) = self.prompt(cpu, "Condition", keys, stdout)? {
+                    self.message = match text.trim() {
+                        "" => {
+                            cpu.breakpoints.set_condition(id, None)?;
+                            format!("Breakpoint {id} is unconditional")
+                        }
+                        text => match Expression::parse(text, &self.symbols) {
+                            Ok(condition) => {
+                                cpu.breakpoints.set_condition(id, Some(condition))?;
+                                format!("Breakpoint {id} only when {text}")
+                            }
+                            Err(e) => e,
+                        },
+                    };
+                }
+            }
+            Key::Up => {
+                let addr = self.cursor.unwrap_or(read_pc(cpu));
+                self.cursor = Some(previous_instruction(cpu, addr, self.syntax));
+            }
+            Key::Down => {
+                let addr = self.cursor.unwrap_or(read_pc(cpu));
+                let n_bytes =
+                    disassemble(&cpu.memory, addr, self.syntax).map_or(1, |i| i.n_bytes());
+                self.cursor = Some(addr.wrapping_add(n_bytes as u16));
+            }
+            Key::Escape => self.cursor = None,
+            Key::PageUp => self.memory_addr = self.memory_addr.wrapping_sub(0x40),
+            Key::PageDown => self.memory_addr = self.memory_addr.wrapping_add(0x40),
+            Key::Char('g') => {
+                if let Some(addr) = self.prompt_address(cpu, "Go to", keys, stdout)? {
+                    self.cursor = Some(addr);
+                }
+            }
+            Key::Char('m') => {
+                if let Some(addr) = self.prompt_address(cpu, "Memory at", keys, stdout)? {
+                    self.memory_addr = addr;
+                }
+            }
+            Key::Char('w') => {
+                if let Some(text) = self.prompt(cpu, "Watch", keys, stdout)? {
+                    match text.trim() {
+                        "" => self.watches.clear(),
+                        text => match Expression::parse(text, &self.symbols) {
+                            Ok(watch) => self.watches.push(watch),
+                            Err(e) => self.message = e,
+                        },
+                    }
+                }
+            }
+            Key::Char('i') => {
+                if let Some(line) = self.prompt(cpu, "Input", keys, stdout)? {
+                    // Enter on a terminal sends CR, which the CP/M and
+                    // ISIS-II console readers both take as the end of a line
+                    self.console.push(line.as_bytes());
+                    self.console.push(b"\r");
+                    self.message = format!("{} characters queued", line.len() + 1);
+
+                    // a program waiting for the input carries on
+                    if cpu.is_waiting_for_input() {
+                        self.running = true;
+                    }
+                }
+            }
+            _ => {}
+        }
+
+        Ok(true)
+    }
+
+    // runs the program for a while
+    fn run_slice(&mut self, cpu: &mut Cpu) {
+        let result = cpu.run(RUN_SLICE);
+        if !matches!(result, Ok(StopReason::BudgetExhausted)) {
+            self.stopped(cpu, result);
+        }
+    }
+
+    // stops running, and says why
+    fn stopped(&mut self, cpu: &Cpu, result: Result<StopReason, String>) {
+        self.running = false;
+        self.cursor = None;
+
+        self.message = match result {
+            Ok(StopReason::Breakpoint(hit)) => hit.describe(&self.symbols),
+            Ok(StopReason::Halted) => String::from("Halted"),
+            Ok(StopReason::BudgetExhausted) => String::from("Gave up, r to carry on"),
+            Ok(StopReason::Completed) => String::from("Stopped"),
+            Ok(StopReason::WaitingForInput) => String::from("Waiting for input, i to type it"),
+            Err(e) => format!("Error at {}: {e}", self.symbols.format_addr(read_pc(cpu))),
+        };
+    }
+
+    // reads a line typed on the message line, None if it is cancelled with
+    // escape
+    fn prompt(
+        &mut self,
+        cpu: &Cpu,
+        label: &str,
+        keys: &Receiver<u8>,
+        stdout: &mut dyn Write,
+    ) -> Result<Option<String>, String> {
+        let mut line = String::new();
+
+        loop {
+            self.message = format!("{label}: {line}_");
+            self.draw(cpu, stdout)?;
+
+            match read_key(keys, true) {
+                Some(Key::Enter) => break,
+                Some(Key::Escape | Key::Interrupt) | None => {
+                    self.message = String::new();
+                    return Ok(None);
+                }
+                Some(Key::Backspace) => {
+                    line.pop();
+                }
+                Some(Key::Char(c)) => line.push(c),
+                Some(_) => {}
+            }
+        }
+
+        self.message = String::new();
+        Ok(Some(line))
+    }
+
+    // reads an address, in hex or as a symbol
+    fn prompt_address(
+        &mut self,
+        cpu: &Cpu,
+        label: &str,
+        keys: &Receiver<u8>,
+        stdout: &mut dyn Write,
+    ) -> Result<Option<u16>, String> {
+        let Some(text) = self.prompt(cpu, label, keys, stdout)? else {
+            return Ok(None);
+        };
+        let text = text.trim();
+
+        let addr = self.symbols.address_of(text).or_else(|| {
+            let hex = text.strip_suffix(['h', 'H']).unwrap_or(text);
+            u16::from_str_radix(hex, 16).ok()
+        });
+        if addr.is_none() {
+            self.message = format!("Invalid address {text}");
+        }
+        Ok(addr)
+    }
+
+    fn draw(&self, cpu: &Cpu, stdout: &mut dyn Write) -> Result<(), String> {
+        let (rows, columns) = terminal::size().unwrap_or((24, 80));
+
+        write!(stdout, "\x1b[H{}", self.render(cpu, rows, columns))
+            .and_then(|_| stdout.flush())
+            .map_err(|e| e.to_string())
+    }
+
+    // draws the screen: a title line, the disassembly with the registers,
+    // watches and stack beside it, the memory dump, the console output, then the message
+    // and help lines
+    pub fn render(&self, cpu: &Cpu, rows: usize, columns: usize) -> String {
+        let rows = rows.max(16);
+        let columns = columns.max(RIGHT_WIDTH + 30);
+
+        let memory_rows = (rows / 5).max(3);
+        let console_rows = (rows / 5).max(3);
+        let top_rows = rows - 3 - (memory_rows + 1) - (console_rows + 1);
+        let left_width = columns - RIGHT_WIDTH - 1;
+
+        let mut screen = Vec::new();
+
+        let state = match (self.running, cpu.running) {
+            (true, _) => "running",
+            (false, true) => "stopped",
+            (false, false) => "halted",
+        };
+        let title = format!(
+            " i8080  PC={}  cycles={}  {state}",
+            self.symbols.format_addr(read_pc(cpu)),
+            cpu.total_cycles
+        );
+        screen.push(style(&fit(&title, columns), "7"));
+
+        // the disassembly, and the registers and stack to the right of it
+        let disassembly = self.disassembly(cpu, left_width, top_rows - 1);
+        let mut right = vec![(header("Registers", RIGHT_WIDTH), "")];
+        right.extend(registers(cpu).into_iter().map(|line| (line, "")));
+        if !self.watches.is_empty() {
+            right.push((header("Watch", RIGHT_WIDTH), ""));
+            right.extend(self.watches.iter().map(|watch| {
+                let value = watch.evaluate(cpu).map_or_else(|e| e, format_value);
+                (format!("{watch} = {value}"), "")
+            }));
+        }
+        right.push((header("Stack", RIGHT_WIDTH), ""));
+        right.extend(self.stack(cpu, top_rows).into_iter().map(|line| (line, "")));
+
+        screen.push(format!(
+            "{}\u{2502}{}",
+            header("Disassembly", left_width),
+            right[0].0
+        ));
+        for i in 1..top_rows {
+            let (left, left_style) = disassembly.get(i - 1).cloned().unwrap_or_default();
+            let (line, right_style) = right.get(i).cloned().unwrap_or_default();
+            screen.push(format!(
+                "{}\u{2502}{}",
+                style(&fit(&left, left_width), left_style),
+                style(&fit(&line, RIGHT_WIDTH), right_style)
+            ));
+        }
+
+        screen.push(header(&format!("Memory {:04X}", self.memory_addr), columns));
+        let end = self
+            .memory_addr
+            .saturating_add((memory_rows * 16 - 1) as u16);
+        let dump = cpu.memory.dump(self.memory_addr, end);
+        let mut dump: Vec<&str> = dump.lines().collect();
+        dump.resize(memory_rows, "");
+        screen.extend(dump.iter().map(|line| fit(line, columns)));
+
+        screen.push(header("Console", columns));
+        let output = self.output.lock().unwrap();
+        let output: String = output
+            .chars()
+            .filter(|c| *c == '\n' || !c.is_control())
+            .collect();
+        let lines: Vec<&str> = output.split('\n').collect();
+        let mut console: Vec<&str> = lines[lines.len().saturating_sub(console_rows)..].to_vec();
+        console.resize(console_rows, "");
+        screen.extend(console.iter().map(|line| fit(line, columns)));
+
+        screen.push(style(&fit(&self.message, columns), "1"));
+        screen.push(style(&fit(HELP, columns), "7"));
+
+        screen.join("\r\n")
+    }
+
+    // the instructions around the cursor or the pc, marking the pc with >
+    // and breakpoints with *, with the cursor highlighted. labels get lines
+    // of their own
+    fn disassembly(&self, cpu: &Cpu, width: usize, rows: usize) -> Vec<(String, &'static str)> {
+        let pc = read_pc(cpu);
+        let focus = self.cursor.unwrap_or(pc);
+        let mut addr = instructions_before(&cpu.memory, focus, rows / 4, self.syntax);
+        let mut lines = Vec::new();
+
+        while lines.len() < rows {
+            let Ok(instruction) = disassemble(&cpu.memory, addr, self.syntax) else {
+                break;
+            };
+            let instruction = instruction.with_symbols(&self.symbols);
+
+            if let Some(name) = self.symbols.name_at(addr) {
+                lines.push((format!("    {name}:"), "2"));
+            }
+
+            let pc_mark = if addr == pc { '>' } else { ' ' };
+            let breakpoint_mark = match cpu.breakpoints.at(addr) {
+                Some(breakpoint) if breakpoint.enabled => '*',
+                Some(_) => 'o',
+                None => ' ',
+            };
+            let line = format!("{pc_mark}{breakpoint_mark}  {instruction}");
+
+            let line_style = match (Some(addr) == self.cursor, addr == pc) {
+                (true, _) => "7",
+                (false, true) => "1;33",
+                (false, false) => "",
+            };
+            lines.push((fit(&line, width), line_style));
+
+            addr = addr.wrapping_add(instruction.n_bytes() as u16);
+        }
+
+        lines.truncate(rows);
+        lines
+    }
+
+    // the words on the stack, with the symbols they point at
+    fn stack(&self, cpu: &Cpu, rows: usize) -> Vec<String> {
+        let sp = u16::from(cpu.reg_array.read_reg(Register::SP));
+
+        (0..rows)
+            .map_while(|i| {
+                let addr = sp.checked_add(i as u16 * 2)?;
+                let value = cpu
+                    .memory
+                    .read(RegisterValue::from(addr), MemorySize::Integer16)
+                    .ok()?;
+                let value = u16::from(value);
+
+                Some(match self.symbols.describe(value) {
+                    Some(symbol) => format!("{addr:04X}  {value:04X}  {symbol}"),
+                    None => format!("{addr:04X}  {value:04X}"),
+                })
+            })
+            .collect()
+    }
+}
+
+// the registers and flags, on three lines
+fn registers(cpu: &Cpu) -> Vec<String> {
+    let read = |register| u16::from(cpu.reg_array.read_reg(register));
+    let psw = read(Register::PSW);
+
+    let flags: Vec<String> = FLAGS
+        .iter()
+        .map(|(name, bit)| format!("{name}{}", u8::from(psw as u8 & bit != 0)))
+        .collect();
+
+    vec![
+        format!(
+            "A={:02X}  BC={:04X}  DE={:04X}",
+            psw >> 8,
+            read(Register::BC),
+            read(Register::DE)
+        ),
+        format!(
+            "HL={:04X} SP={:04X}  PC={:04X}",
+            read(Register::HL),
+            read(Register::SP),
+            read(Register::PC)
+        ),
+        flags.join(" "),
+    ]
+}
+
+// finds the instruction before an address, the closest one that ends there
+fn previous_instruction(cpu: &Cpu, addr: u16, syntax: Syntax) -> u16 {
+    (1..=3)
+        .map(|back| addr.wrapping_sub(back))
+        .find(|start| {
+            disassemble(&cpu.memory, *start, syntax)
+                .is_ok_and(|i| start.wrapping_add(i.n_bytes() as u16) == addr)
+        })
+        .unwrap_or(addr.wrapping_sub(1))
+}
+
+// reads a key, waiting for one if asked to. None if there is no key, or no
+// more input. escape sequences for the cursor keys are put back together
+fn read_key(keys: &Receiver<u8>, wait: bool) -> Option<Key> {
+    let byte = match wait {
+        true => keys.recv().ok()?,
+        false => keys.try_recv().ok()?,
+    };
+
+    // the rest of an escape sequence arrives straight after the escape
+    let next = || keys.recv_timeout(Duration::from_millis(30)).ok();
+
+    Some(match byte {
+        0x1B => match next() {
+            Some(b'[' | b'O') => match next() {
+                Some(b'A') => Key::Up,
+                Some(b'B') => Key::Down,
+                Some(b'5') => {
+                    next();
+                    Key::PageUp
+                }
+                Some(b'6') => {
+                    next();
+                    Key::PageDown
+                }
+                _ => Key::Escape,
+            },
+            _ => Key::Escape,
+        },
+        b'\r' | b'\n' => Key::Enter,
+        0x08 | 0x7F => Key::Backspace,
+        0x03 => Key::Interrupt,
+        b'k' => Key::Up,
+        b'j' => Key::Down,
+        byte => Key::Char(byte as char),
+    })
+}
+
+// a pane title, drawn across the width of the pane
+fn header(title: &str, width: usize) -> String {
+    fit(
+        &format!("\u{2500}\u{2500} {title} {}", "\u{2500}".repeat(width)),
+        width,
+    )
+}
+
+// pads or cuts a line to a width
+fn fit(text: &str, width: usize) -> String {
+    let mut line: String = text.chars().take(width).collect();
+    let len = line.chars().count();
+    line.extend(std::iter::repeat_n(' ', width - len));
+    line
+}
+
+// wraps text in an SGR sequence, e.g. "7" for reverse video
+fn style(text: &str, sgr: &str) -> String {
+    match sgr {
+        "" => text.to_string(),
+        sgr => format!("\x1b[{sgr}m{text}\x1b[0m"),
+    }
+}
+
+fn read_pc(cpu: &Cpu) -> u16 {
+    u16::from(cpu.reg_array.read_reg(Register::PC))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // the screen without its SGR sequences
+    fn plain(screen: &str) -> Vec<String> {
+        let mut text = String::new();
+        let mut chars = screen.chars();
+        while let Some(c) = chars.next() {
+            match c {
+                '\x1b' => {
+                    chars.find(|c| *c == 'm');
+                }
+                '\r' => {}
+                c => text.push(c),
+            }
+        }
+        text.lines().map(String::from).collect()
+    }
+
+    #[test]
+    fn tui_render_and_keys() {
+        // START: LXI SP,0200H; CALL SUB; HLT; SUB: MVI A,41H; OUT 0; RET
+        let mut cpu = Cpu::new();
+        cpu.load_to_memory(
+            vec![
+                0x31, 0x00, 0x02, 0xCD, 0x07, 0x01, 0x76, 0x3E, 0x41, 0xD3, 0x00, 0xC9,
+            ],
+            0x0100,
+        )
+        .unwrap();
+        cpu.set_pc(0x0100).unwrap();
+
+        let mut tui = Tui::new();
+        tui.symbols = [("START", 0x0100), ("SUB", 0x0107)].into_iter().collect();
+        *tui.output.lock().unwrap() = String::from("HELLO\r\nWORLD");
+
+        let (sender, keys) = mpsc::channel();
+        let mut stdout = Vec::new();
+        for key in [
+            Key::Down,
+            Key::Down,
+            Key::Down,
+            Key::Char('b'),
+            Key::Char('r'),
+        ] {
+            assert!(tui.key(&mut cpu, key, &keys, &mut stdout).unwrap());
+        }
+
+        // running stops at the breakpoint toggled on SUB
+        while tui.running {
+            tui.run_slice(&mut cpu);
+        }
+        assert_eq!(read_pc(&cpu), 0x0107);
+        assert_eq!(
+            tui.message,
+            "Breakpoint 1 (execute 0107 (SUB)) at 0107 (SUB)"
+        );
+
+        tui.key(&mut cpu, Key::Char('s'), &keys, &mut stdout)
+            .unwrap();
+
+        // prompts read the keys typed after them
+        for byte in b"[SUB] + 1\r" {
+            sender.send(*byte).unwrap();
+        }
+        tui.key(&mut cpu, Key::Char('w'), &keys, &mut stdout)
+            .unwrap();
+        let screen = plain(&tui.render(&cpu, 24, 80));
+
+        assert_eq!(screen.len(), 24);
+        assert!(screen.iter().all(|line| line.chars().count() == 80));
+        assert!(screen[0].starts_with(" i8080  PC=0109 (SUB+2)  cycles="));
+        assert_eq!(
+            screen[2],
+            format!(
+                "{:49}\u{2502}{:30}",
+                "    0106  76        HLT", "A=41  BC=0000  DE=0000"
+            )
+        );
+        assert!(screen[3].starts_with("    SUB:"));
+        assert!(screen[6].ends_with(&format!("{:30}", "[SUB] + 1 = 3FH (63)")));
+        assert!(screen[4].starts_with(" *  0107  3E 41     MVI A,41H"));
+        assert!(screen[5].starts_with(">   0109  D3 00     OUT 00H"));
+        assert!(screen
+            .iter()
+            .any(|line| line.contains("01FE  0106  START+6")));
+        assert!(screen
+            .iter()
+            .any(|line| line.starts_with("0100  31 00 02 CD 07 01 76")));
+        assert!(screen.iter().any(|line| line.trim_end() == "WORLD"));
+        assert!(!tui
+            .key(&mut cpu, Key::Char('q'), &keys, &mut stdout)
+            .unwrap());
+    }
+}